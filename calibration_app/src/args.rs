@@ -0,0 +1,770 @@
+use std::fmt::Debug;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use lib_cv::board::{BoardConfig, CharucoBoardConfig, LengthUnit};
+use lib_cv::calibration::{
+    CALIBRATION_PARAMS_FILE, ContrastEnhancement, DEFAULT_MIN_SCENES, PickedCalibrationParams,
+    PoseBins, SceneLimits, StereoFlags, load_camera_parameters, predefined_dictionary_from_name,
+};
+use lib_cv::frame_selection::AutoSelectParams;
+use lib_cv::utils::GridLayout;
+use log::{info, warn};
+use opencv::objdetect::PredefinedDictionaryType;
+
+use crate::frame_view::{CornerThresholds, DisplayScale};
+use crate::live::LiveSource;
+use crate::poses::PoseTally;
+
+/// Расширения файлов, которые считаются видео, если --video указывает на папку
+const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "avi", "mov", "mkv", "webm"];
+
+const AFTER_HELP: &str = "\
+Выбранные кадры сохраняются в --picked-dir/{session}/img_{cam}_{frame}.png,
+где {session} - --session (по умолчанию дата и время запуска),
+{cam} - номер камеры (ячейки --layout слева направо, сверху вниз) начиная с 1, а {frame} - номер кадра видео.
+Сохраняются только квадранты, где найдено не меньше --min-corners углов; каждый
+выбранный кадр записывается в picked_manifest.json. Калибровка группирует
+изображения по манифесту (без него - по шаблону имён) и записывает
+calibration_params_{дата_время}.yml в --output-dir, а его копию - в calibration_params.yml,
+так что последний результат всегда лежит по одному пути. Манифест помнит доску: если кадры в --picked-dir
+выбраны с другой доской, программа откажется с ними работать.
+Положение камер уточняется только по кадрам, где доска видна одновременно в камере 1
+и другой камере: на каждом квадранте показано число общих с камерой 1 углов, а строка
+Pairs - сколько выбранных кадров имеют не меньше 10 общих углов для каждой пары.
+Если у пары меньше --min-pair-frames таких кадров, перед калибровкой выводится предупреждение.
+Если в этом запуске не выбрано ни одного кадра, калибровка по прежним кадрам
+начнётся только после повторного нажатия Esc.
+
+Окно выбора кадров показывает мозаику камер текущего кадра, слева - пороги углов,
+переход к кадру по номеру и кнопки Auto-pick (автоматический выбор N кадров, как --auto)
+и калибровки, справа - миниатюры выбранных кадров с числом углов в каждой камере:
+щелчок по миниатюре переходит к кадру, кнопка под ней удаляет его из выбранных.
+Мозаика показывается уменьшенной (--display-scale, по умолчанию - вписать в экран 1080p),
++/- меняют масштаб; разметка рисуется до уменьшения, сохраняются исходные изображения.
+Под кадром показаны наклон доски в камере 1 и расстояние до неё, а ниже - счётчик
+поз выбранных в этом запуске кадров по ячейкам наклона (--pose-tilt-bins) и расстояния
+(--pose-distance-bins): пустые ячейки подсказывают, каких поз не хватает. Поза точнее,
+если есть прежняя калибровка (--pose-intrinsics или calibration_params.yml в --output-dir).
+
+После калибровки открывается окно результатов: RMS каждой камеры и стереопар и
+расстояния между камерами; кнопка Дисторсия (при --live - сразу) показывает выбранный
+кадр каждой камеры до и после устранения дисторсии (края доски должны стать прямыми).
+Enter/y сохраняет calibration_params.yml,
+calibration_report.txt и board.toml, n/Backspace отбрасывает результат и возвращает
+к выбору кадров, Esc завершает работу без сохранения. Вместе с параметрами сохраняются
+карты покрытия coverage_camera_{cam}.png: где на кадре камеры находились углы доски.
+Синие края карты означают, что дисторсия у границ изображения оценена плохо.
+
+Клавиша r в окне результатов открывает просмотр перепроекции: на каждой выбранной сцене
+найденные углы доски (зелёные) и углы, перепроецированные через калибровку (красные).
+Сцены со средней ошибкой выше --max-view-error отмечаются в заголовке окна.
+Стрелки листают сцены, Delete/x удаляет сцену из выбранных (после этого нужна повторная
+калибровка), e сохраняет изображения всех сцен в --output-dir/review, Esc - назад.
+
+С --auto N окно не открывается: кадры оцениваются по числу углов в каждой камере,
+резкости и новизне положения доски, N лучших сохраняются и сразу калибруются.
+
+Кадры извлекаются в --parsed-dir только один раз: frames_manifest.json помнит видео,
+и повторный запуск с тем же видео сразу переходит к выбору. Кадры другого видео
+в папке считаются ошибкой; --force-reparse удаляет их и извлекает заново.
+Если папки --parsed-dir нет (или задано --frames seek), кадры не извлекаются вовсе:
+нужный кадр декодируется из видео при переходе к нему, несколько последних кадров
+хранятся в памяти. Так не тратится место на диске, но дальние переходы медленнее.
+
+При стереокалибровке пар внутренние параметры камер по умолчанию фиксированы;
+--refine-intrinsics уточняет их вместе с положением камер. Это стоит включать, только
+если RMS отдельных камер высокий и выбрано много сцен, общих для пар камер.
+
+Извлечение кадров и калибровка показывают окно прогресса (с --auto - полосу
+прогресса в терминале). Esc в окне прогресса отменяет операцию: отменённое
+извлечение повторится при следующем запуске, а calibration_params.yml останется прежним.
+
+С --live кадры берутся с подключённых камер: --live 0 1 - устройства 0 и 1,
+--live \"v4l2src ! videoconvert ! appsink\" - конвейер GStreamer. Предпросмотр
+обновляется непрерывно, пробел сохраняет самый свежий синхронный набор кадров всех
+камер под следующим свободным номером кадра, остальные клавиши - как при выборе из видео.
+
+С --review-video PATH окно не открывается: каждый --review-step-й кадр размечается
+так же, как при выборе (углы, маркеры, число углов в каждой камере), подписывается
+номером кадра и записывается в видео с кодеком --review-codec. Обзор можно оставить
+на ночь, а потом выбирать кадры на участках, где доска видна хорошо.
+
+Несколько записей (доска близко, далеко, под углом) калибруются вместе: --video a.mp4 b.mp4
+или --video папка_с_записями. Кадры каждой записи извлекаются в свою подпапку --parsed-dir,
+номера кадров записи i сдвигаются на i*1000000, а выбранный кадр помечается в манифесте
+именем записи. --auto N делит N между записями пропорционально числу пригодных кадров.
+
+--headless --config calib_job.toml - то же, что --auto, но все параметры берутся
+из задания, окна не создаются вовсе (можно запускать на сервере без X), а любая
+ошибка завершает программу с ненулевым кодом. Пример задания:
+  videos = [\"near.mp4\", \"far.mp4\"]
+  board_config = \"board.toml\"
+  layout = \"2x2\"
+  output_dir = \"calibration\"
+  [auto]
+  count = 30
+  min_sharpness = 60.0
+Необязательны parsed_dir, picked_dir (по умолчанию подпапки output_dir) и пороги
+auto.min_corners, auto.min_sharpness, auto.min_pose_distance. Кроме
+calibration_report.txt сохраняется calibration_report.json.
+
+Видео, папки и раскладку можно задать и переменными окружения CALIBRATION_VIDEO,
+CALIBRATION_PARSED_DIR, CALIBRATION_PICKED_DIR, CALIBRATION_OUTPUT_DIR и
+CALIBRATION_LAYOUT (например, CALIBRATION_LAYOUT=1x3); флаги командной строки важнее.
+
+На тёмных записях маркеры часто не находятся: --clahe выравнивает контраст каждой
+камеры перед поиском доски (--clahe-clip-limit, --clahe-tiles; в окне - флажок слева).
+Разметка рисуется, а кадры сохраняются без обработки, так что калибровка уточняет
+углы по исходным пикселям.
+
+Геометрию доски удобнее брать из файла .toml, который generate_calibration_pattern
+сохраняет рядом с изображением паттерна: --board-config charuco_pattern.toml.
+На каждом квадранте подписано число найденных углов и маркеров. Если маркеры первого
+кадра явно декодируются другим словарём, чем --dictionary, об этом предупреждает окно.
+
+Управление:
+  стрелки влево/вправо, a/d   - предыдущий/следующий кадр
+  стрелки вниз/вверх, s/w     - на 10 кадров назад/вперёд
+  PageDown/PageUp             - на 100 кадров назад/вперёд
+  g                           - перейти к кадру по номеру (поле ввода слева)
+  пробел                      - сохранить квадранты кадра, где найдена доска
+  Delete, x                   - удалить последний выбранный кадр
+  e                           - сохранить размеченную мозаику
+  Esc                         - закончить выбор и откалибровать
+  q                           - выйти без калибровки";
+
+/// Выбор кадров с доской ChArUco из общего видео нескольких камер и их калибровка
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, after_help = AFTER_HELP)]
+pub struct Args {
+    /// Видео с камерами, объединёнными в сетку (см. --layout). Можно задать несколько
+    /// записей или папку с записями: сцены всех записей калибруются вместе. Не нужно с --live
+    #[arg(
+        long,
+        env = "CALIBRATION_VIDEO",
+        num_args = 1..,
+        required_unless_present_any = ["live", "config"]
+    )]
+    pub video: Vec<PathBuf>,
+
+    /// Снимать кадры с подключённых камер вместо видео: номера устройств или конвейеры
+    /// GStreamer. Кадр единственного устройства делится на ячейки --layout, при нескольких
+    /// устройствах каждое - отдельная камера, и в --layout должно быть столько же ячеек
+    #[arg(long, num_args = 1.., value_name = "SOURCE", conflicts_with_all = ["video", "auto"])]
+    pub live: Vec<String>,
+
+    /// Папка для извлечённых из видео кадров
+    #[arg(
+        long,
+        env = "CALIBRATION_PARSED_DIR",
+        default_value = "calibration/parsed"
+    )]
+    pub parsed_dir: PathBuf,
+
+    /// Как получать кадры видео при ручном выборе: extract - заранее извлечь все кадры
+    /// в --parsed-dir, seek - декодировать нужный кадр из видео по запросу.
+    /// По умолчанию seek, если папки --parsed-dir ещё нет. --auto и --review-video
+    /// всегда извлекают кадры
+    #[arg(long, value_enum)]
+    pub frames: Option<FramesMode>,
+
+    /// Извлечь кадры заново, даже если в --parsed-dir уже есть кадры этого или другого видео
+    #[arg(long)]
+    pub force_reparse: bool,
+
+    /// Папка для выбранных калибровочных изображений img_{cam}_{frame}.png
+    #[arg(
+        long,
+        env = "CALIBRATION_PICKED_DIR",
+        default_value = "calibration/picked"
+    )]
+    pub picked_dir: PathBuf,
+
+    /// Имя сессии: кадры этого запуска сохраняются в подпапку --picked-dir с этим именем,
+    /// чтобы повторный выбор того же кадра в другом запуске не перезаписал прежние файлы.
+    /// По умолчанию - дата и время запуска (UTC)
+    #[arg(long, value_parser = parse_session)]
+    pub session: Option<String>,
+
+    /// Папка для файла calibration_params.yml
+    #[arg(long, env = "CALIBRATION_OUTPUT_DIR", default_value = "calibration")]
+    pub output_dir: PathBuf,
+
+    /// Файл с геометрией доски (board.toml), который сохраняет generate_calibration_pattern.
+    /// Явно заданные параметры доски должны с ним совпадать
+    #[arg(long)]
+    pub board_config: Option<PathBuf>,
+
+    /// Количество квадратов доски по горизонтали [по умолчанию: 10]
+    #[arg(long)]
+    pub squares_x: Option<i32>,
+
+    /// Количество квадратов доски по вертикали [по умолчанию: 5]
+    #[arg(long)]
+    pub squares_y: Option<i32>,
+
+    /// Длина стороны квадрата доски (в единицах --board-unit) [по умолчанию: 13.0]
+    #[arg(long)]
+    pub square_length: Option<f32>,
+
+    /// Длина стороны маркера (в тех же единицах, что и квадрат) [по умолчанию: 9.1]
+    #[arg(long)]
+    pub marker_length: Option<f32>,
+
+    /// Словарь маркеров ArUco [по умолчанию: DICT_4X4_50]
+    #[arg(long, value_parser = parse_dictionary)]
+    pub dictionary: Option<PredefinedDictionaryType>,
+
+    /// Единица длин квадрата и маркера: mm, cm, m или px. В ней же сохраняются расстояния
+    /// между камерами и координаты облаков [по умолчанию: mm]
+    #[arg(long)]
+    pub board_unit: Option<LengthUnit>,
+
+    /// Раскладка камер в кадре видео: РЯДЫxСТОЛБЦЫ, например 1x2 для двух камер
+    #[arg(long, env = "CALIBRATION_LAYOUT", default_value_t = GridLayout::default())]
+    pub layout: GridLayout,
+
+    /// Минимум углов ChArUco в ячейке камеры; камеры ниже порога не сохраняются
+    #[arg(long, default_value_t = 6)]
+    pub min_corners: usize,
+
+    /// Количество углов, начиная с которого квадрант считается хорошим (зелёная рамка)
+    #[arg(long, default_value_t = 20)]
+    pub good_corners: usize,
+
+    /// Выравнивать контраст (CLAHE по яркости) каждой камеры перед поиском доски. Помогает
+    /// на тёмных записях; разметка рисуется и кадры сохраняются без обработки
+    #[arg(long)]
+    pub clahe: bool,
+
+    /// Ограничение усиления контраста для --clahe
+    #[arg(long, default_value_t = ContrastEnhancement::default().clip_limit)]
+    pub clahe_clip_limit: f64,
+
+    /// Число плиток по каждой стороне изображения для --clahe
+    #[arg(long, default_value_t = ContrastEnhancement::default().tile_grid,
+          value_parser = clap::value_parser!(i32).range(1..))]
+    pub clahe_tiles: i32,
+
+    /// Сколько кадров с не менее чем 10 общими с камерой 1 углами нужно каждой паре камер;
+    /// при меньшем числе перед калибровкой выводится предупреждение
+    #[arg(long, default_value_t = 5)]
+    pub min_pair_frames: usize,
+
+    /// Наибольшее число сцен для калибровки: если выбрано больше, используются лучшие по
+    /// числу углов, покрытию кадра и размеру маркеров. По умолчанию - все сцены
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_scenes: Option<u64>,
+
+    /// Наименьшее число выбранных сцен у каждой камеры: при меньшем калибровка не начинается
+    #[arg(long, default_value_t = DEFAULT_MIN_SCENES)]
+    pub min_scenes: usize,
+
+    /// Наименьшее число сцен, в которых доску нашли все камеры: только такие сцены
+    /// идут в калибровку
+    #[arg(long, default_value_t = DEFAULT_MIN_SCENES)]
+    pub min_complete_scenes: usize,
+
+    /// Порог средней ошибки перепроекции камеры на сцене (пикс.), выше которого сцена
+    /// отмечается при просмотре перепроекции
+    #[arg(long, default_value_t = 1.0)]
+    pub max_view_error: f64,
+
+    /// Уточнять внутренние параметры камер при стереокалибровке (CALIB_USE_INTRINSIC_GUESS)
+    /// вместо их фиксации. Помогает, когда калибровка отдельных камер приблизительная,
+    /// но требует много разнообразных сцен, видимых обеими камерами пары
+    #[arg(long)]
+    pub refine_intrinsics: bool,
+
+    /// Пакетный режим без единого окна по заданию --config: извлечь кадры, выбрать лучшие,
+    /// откалибровать и сохранить отчёты. При ошибке программа завершается с ненулевым кодом
+    #[arg(long, requires = "config", conflicts_with_all = ["live", "review_video"])]
+    pub headless: bool,
+
+    /// Файл задания (TOML) для --headless: видео, доска, раскладка, параметры автовыбора
+    /// и папка результатов. Значения задания заменяют соответствующие флаги
+    #[arg(long, value_name = "PATH", requires = "headless")]
+    pub config: Option<PathBuf>,
+
+    /// Автоматический режим без окна: выбрать N лучших кадров и сразу откалибровать
+    #[arg(long, value_name = "N")]
+    pub auto: Option<usize>,
+
+    /// Минимальная резкость (дисперсия лапласиана) каждой камеры в автоматическом режиме
+    #[arg(long, default_value_t = 50.0)]
+    pub auto_min_sharpness: f64,
+
+    /// Минимальное отличие положения доски (в долях кадра) между выбранными кадрами
+    #[arg(long, default_value_t = 0.08)]
+    pub auto_min_pose_distance: f64,
+
+    /// Записать обзорное видео с разметкой доски по всей записи вместо выбора кадров
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["live", "auto"])]
+    pub review_video: Option<PathBuf>,
+
+    /// Шаг по кадрам для --review-video: размечается каждый N-й кадр
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub review_step: u64,
+
+    /// Кодек (FourCC) для --review-video, например mp4v или avc1
+    #[arg(long, default_value = "mp4v", value_parser = parse_fourcc)]
+    pub review_codec: [char; 4],
+
+    /// Масштаб показа мозаики: fit - вписать в экран 1080p, или доля исходного размера
+    /// (0, 1]. Меняется клавишами +/-. Поиск доски и сохранение идут в исходном разрешении
+    #[arg(long, default_value = "fit", value_parser = parse_display_scale)]
+    pub display_scale: DisplayScale,
+
+    /// Границы ячеек наклона доски (градусы) в счётчике поз выбранных кадров
+    #[arg(long, value_delimiter = ',', default_values_t = [15.0, 30.0, 45.0])]
+    pub pose_tilt_bins: Vec<f64>,
+
+    /// Границы ячеек расстояния до доски (в единицах доски) в счётчике поз
+    #[arg(long, value_delimiter = ',', default_values_t = [300.0, 600.0, 1000.0])]
+    pub pose_distance_bins: Vec<f64>,
+
+    /// Параметры камер прежней калибровки для оценки позы доски. По умолчанию -
+    /// calibration_params.yml в --output-dir, если он есть, иначе приблизительная камера
+    /// по размеру кадра
+    #[arg(long, value_name = "PATH")]
+    pub pose_intrinsics: Option<PathBuf>,
+}
+
+fn parse_dictionary(name: &str) -> Result<PredefinedDictionaryType, String> {
+    predefined_dictionary_from_name(name).ok_or_else(|| {
+        format!(
+            "Неизвестный словарь {}, ожидается например DICT_4X4_50",
+            name
+        )
+    })
+}
+
+/// Способ получения кадров видео при ручном выборе
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FramesMode {
+    /// Извлечь все кадры в PNG заранее
+    Extract,
+    /// Декодировать кадры из видео по мере навигации
+    Seek,
+}
+
+fn parse_session(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(format!("Недопустимое имя сессии {:?}", name));
+    }
+    Ok(name.to_string())
+}
+
+fn parse_display_scale(value: &str) -> Result<DisplayScale, String> {
+    if value == "fit" {
+        return Ok(DisplayScale::Fit);
+    }
+    match value.parse::<f64>() {
+        Ok(scale) if scale > 0.0 && scale <= 1.0 => Ok(DisplayScale::Fixed(scale)),
+        _ => Err(format!(
+            "Масштаб показа должен быть fit или числом из (0, 1], а не {:?}",
+            value
+        )),
+    }
+}
+
+fn parse_fourcc(code: &str) -> Result<[char; 4], String> {
+    let chars: Vec<char> = code.chars().collect();
+    chars
+        .try_into()
+        .map_err(|_| format!("Кодек должен состоять из 4 символов, а не {:?}", code))
+}
+
+/// Подставляет явно заданное значение параметра доски или проверяет, что оно совпадает с файлом
+fn merge_board_value<T: PartialEq + Debug>(
+    name: &str,
+    from_file: Option<T>,
+    from_flag: Option<T>,
+    default: T,
+) -> Result<T, String> {
+    match (from_file, from_flag) {
+        (Some(file), Some(flag)) if file != flag => Err(format!(
+            "--{} = {:?} противоречит файлу доски ({:?})",
+            name, flag, file
+        )),
+        (Some(value), _) | (None, Some(value)) => Ok(value),
+        (None, None) => Ok(default),
+    }
+}
+
+impl Args {
+    /// Создаёт все рабочие папки, если их ещё нет
+    pub fn prepare_dirs(&self) -> Result<(), String> {
+        let mut dirs = vec![&self.picked_dir, &self.output_dir];
+        // Без извлечения папка кадров не нужна, а её появление переключило бы следующий
+        // запуск в режим извлечения
+        if !self.seek_frames() {
+            dirs.push(&self.parsed_dir);
+        }
+        for dir in dirs {
+            create_dir_all(dir)
+                .map_err(|e| format!("Не удалось создать папку {}: {}", dir.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Записи --video по порядку: папки заменяются видеофайлами в них (по имени)
+    pub fn videos(&self) -> Result<Vec<PathBuf>, String> {
+        let mut videos = Vec::new();
+        for path in &self.video {
+            if !path.is_dir() {
+                videos.push(path.clone());
+                continue;
+            }
+            let entries = std::fs::read_dir(path)
+                .map_err(|e| format!("Не удалось прочитать папку {}: {}", path.display(), e))?;
+            let mut found: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| {
+                            VIDEO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+                        })
+                })
+                .collect();
+            if found.is_empty() {
+                return Err(format!("В папке {} нет видео", path.display()));
+            }
+            found.sort();
+            videos.extend(found);
+        }
+        Ok(videos)
+    }
+
+    /// Папка извлечённых кадров записи `clip_i` из `clips`: при одной записи - сама
+    /// --parsed-dir, иначе её подпапка для каждой записи
+    pub fn clip_parsed_dir(&self, video: &Path, clip_i: usize, clips: usize) -> PathBuf {
+        if clips == 1 {
+            return self.parsed_dir.clone();
+        }
+        let stem = video
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.parsed_dir.join(format!("{:02}_{}", clip_i + 1, stem))
+    }
+
+    /// Имя сессии этого запуска (см. --session)
+    pub fn session(&self) -> &str {
+        self.session.as_deref().unwrap_or_default()
+    }
+
+    /// Декодировать кадры из видео по запросу вместо извлечения в --parsed-dir
+    pub fn seek_frames(&self) -> bool {
+        if self.auto.is_some() || self.review_video.is_some() || !self.live.is_empty() {
+            return false;
+        }
+        match self.frames {
+            Some(mode) => mode == FramesMode::Seek,
+            None => !self.parsed_dir.exists(),
+        }
+    }
+
+    pub fn corner_thresholds(&self) -> CornerThresholds {
+        CornerThresholds {
+            min_corners: self.min_corners,
+            good_corners: self.good_corners.max(self.min_corners),
+        }
+    }
+
+    /// Выравнивание контраста перед поиском доски, если оно включено
+    pub fn contrast_enhancement(&self) -> Option<ContrastEnhancement> {
+        self.clahe.then_some(ContrastEnhancement {
+            clip_limit: self.clahe_clip_limit,
+            tile_grid: self.clahe_tiles,
+        })
+    }
+
+    /// Ограничения на число сцен калибровки
+    pub fn scene_limits(&self) -> SceneLimits {
+        SceneLimits {
+            min_per_camera: self.min_scenes,
+            min_complete: self.min_complete_scenes,
+            max_scenes: self.max_scenes.map(|k| k as usize),
+        }
+    }
+
+    /// Пустой счётчик поз доски с ячейками --pose-tilt-bins/--pose-distance-bins
+    /// и камерой 1 из --pose-intrinsics (или прежней калибровки в --output-dir)
+    pub fn pose_tally(&self) -> PoseTally {
+        let sorted = |edges: &[f64]| {
+            let mut edges = edges.to_vec();
+            edges.sort_by(f64::total_cmp);
+            edges
+        };
+        let bins = PoseBins {
+            tilt: sorted(&self.pose_tilt_bins),
+            distance: sorted(&self.pose_distance_bins),
+        };
+        let path = self.pose_intrinsics.clone().or_else(|| {
+            Some(self.output_dir.join(CALIBRATION_PARAMS_FILE)).filter(|path| path.exists())
+        });
+        let camera = path.and_then(|path| match load_camera_parameters(&path) {
+            Ok(mut cameras) => {
+                info!("Поза доски оценивается по камере 1 из {}", path.display());
+                Some(cameras.swap_remove(0))
+            }
+            Err(e) => {
+                warn!(
+                    "Параметры камер {} не загружены, поза доски будет приблизительной: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        });
+        PoseTally::new(bins, camera)
+    }
+
+    /// Флаги стереокалибровки пар камер
+    pub fn stereo_flags(&self) -> StereoFlags {
+        if self.refine_intrinsics {
+            StereoFlags::REFINE_INTRINSICS
+        } else {
+            StereoFlags::FIX_INTRINSIC
+        }
+    }
+
+    /// Параметры калибровки по кадрам из --picked-dir, выбранным с доской `board_config`
+    pub fn picked_calibration_params(
+        &self,
+        board_config: &CharucoBoardConfig,
+    ) -> PickedCalibrationParams {
+        PickedCalibrationParams {
+            stereo_flags: self.stereo_flags(),
+            limits: self.scene_limits(),
+            contrast: self.contrast_enhancement(),
+            unit: board_config.unit,
+        }
+    }
+
+    /// Источники кадров режима --live (пусто, если он выключен)
+    pub fn live_sources(&self) -> Result<Vec<LiveSource>, String> {
+        if self.live.len() > 1 && self.live.len() != self.layout.cells() {
+            return Err(format!(
+                "--live: {} устройств, а в --layout {} ячеек",
+                self.live.len(),
+                self.layout.cells()
+            ));
+        }
+        Ok(self.live.iter().map(|s| LiveSource::parse(s)).collect())
+    }
+
+    /// Параметры автоматического режима, если он включён
+    pub fn auto_select_params(&self) -> Option<AutoSelectParams> {
+        self.auto.map(|count| self.auto_params(count))
+    }
+
+    /// Параметры автоматического выбора `count` кадров (для кнопки Auto-pick в окне)
+    pub fn auto_params(&self, count: usize) -> AutoSelectParams {
+        AutoSelectParams {
+            count,
+            min_corners: self.min_corners,
+            min_sharpness: self.auto_min_sharpness,
+            min_pose_distance: self.auto_min_pose_distance,
+        }
+    }
+
+    /// Итоговая геометрия доски: файл --board-config, дополненный флагами командной строки.
+    /// Флаги, противоречащие файлу, считаются ошибкой
+    pub fn board_config(&self) -> Result<CharucoBoardConfig, String> {
+        let file = match &self.board_config {
+            Some(path) => Some(CharucoBoardConfig::load(path).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let file_dictionary = match &file {
+            Some(config) => Some(config.dictionary_type().map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let defaults = BoardConfig::default().into_config();
+        let default_dictionary = defaults.dictionary_type().map_err(|e| e.to_string())?;
+        let dictionary = merge_board_value(
+            "dictionary",
+            file_dictionary,
+            self.dictionary,
+            default_dictionary,
+        )?;
+        Ok(CharucoBoardConfig {
+            squares_x: merge_board_value(
+                "squares-x",
+                file.as_ref().map(|c| c.squares_x),
+                self.squares_x,
+                defaults.squares_x,
+            )?,
+            squares_y: merge_board_value(
+                "squares-y",
+                file.as_ref().map(|c| c.squares_y),
+                self.squares_y,
+                defaults.squares_y,
+            )?,
+            square_length: merge_board_value(
+                "square-length",
+                file.as_ref().map(|c| c.square_length),
+                self.square_length,
+                defaults.square_length,
+            )?,
+            marker_length: merge_board_value(
+                "marker-length",
+                file.as_ref().map(|c| c.marker_length),
+                self.marker_length,
+                defaults.marker_length,
+            )?,
+            dictionary: format!("{:?}", dictionary),
+            unit: merge_board_value(
+                "board-unit",
+                file.as_ref()
+                    .map(|c| c.unit)
+                    .filter(|unit| *unit != LengthUnit::Unspecified),
+                self.board_unit,
+                defaults.unit,
+            )?,
+        })
+    }
+
+    /// Краткая сводка параметров запуска
+    pub fn summary(&self, board: &CharucoBoardConfig) -> String {
+        format!(
+            "Источник: {}\nКадры: {}\nВыбранные изображения: {} (сессия {})\nРезультат: {}\n\
+             Раскладка камер: {} ({} камер)\n\
+             Доска: {}x{}, квадрат {} {}, маркер {}, {}",
+            if self.video.is_empty() {
+                format!("камеры {}", self.live.join(", "))
+            } else {
+                self.video
+                    .iter()
+                    .map(|video| video.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            },
+            self.parsed_dir.display(),
+            self.picked_dir.display(),
+            self.session(),
+            self.output_dir.display(),
+            self.layout,
+            self.layout.cells(),
+            board.squares_x,
+            board.squares_y,
+            board.square_length,
+            board.unit.label(),
+            board.marker_length,
+            board.dictionary
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use generate_calibration_pattern::GenCalibPatternApp;
+    use opencv::core::MatTraitConst;
+    use opencv::objdetect::{BoardTraitConst, CharucoBoardTraitConst, DictionaryTraitConst};
+
+    use super::*;
+
+    #[test]
+    fn layout_and_board_are_parsed_from_arguments() {
+        let args = Args::try_parse_from([
+            "calibration_app",
+            "--video",
+            "rig.mp4",
+            "--layout",
+            "2x3",
+            "--squares-x",
+            "7",
+            "--min-corners",
+            "8",
+        ])
+        .unwrap();
+        assert_eq!(args.video, vec![PathBuf::from("rig.mp4")]);
+        assert_eq!((args.layout.rows, args.layout.cols), (2, 3));
+        assert_eq!(args.min_corners, 8);
+        let board = args.board_config().unwrap();
+        assert_eq!((board.squares_x, board.squares_y), (7, 5));
+    }
+
+    /// Файл доски, который генератор паттерна сохраняет рядом с изображением
+    fn generated_board_file(test: &str) -> (GenCalibPatternApp, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("{}_{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("board.toml");
+        let generator = GenCalibPatternApp::default();
+        generator.board_config().save(&path).unwrap();
+        (generator, path)
+    }
+
+    fn parse_with_board(path: &Path, extra: &[&str]) -> Args {
+        let mut args = vec![
+            "calibration_app",
+            "--video",
+            "rig.mp4",
+            "--board-config",
+            path.to_str().unwrap(),
+        ];
+        args.extend_from_slice(extra);
+        Args::try_parse_from(args).unwrap()
+    }
+
+    #[test]
+    fn generated_board_file_gives_same_board() {
+        let (generator, path) = generated_board_file("args_board_round_trip");
+        let expected = generator.board_builder().build().unwrap();
+        let loaded = parse_with_board(&path, &[])
+            .board_config()
+            .unwrap()
+            .to_board()
+            .unwrap();
+        assert_eq!(
+            loaded.get_chessboard_size().unwrap(),
+            expected.get_chessboard_size().unwrap()
+        );
+        assert_eq!(
+            loaded.get_square_length().unwrap(),
+            expected.get_square_length().unwrap()
+        );
+        assert_eq!(
+            loaded.get_marker_length().unwrap(),
+            expected.get_marker_length().unwrap()
+        );
+        let (loaded, expected) = (
+            loaded.get_dictionary().unwrap(),
+            expected.get_dictionary().unwrap(),
+        );
+        assert_eq!(loaded.marker_size(), expected.marker_size());
+        assert_eq!(loaded.bytes_list().rows(), expected.bytes_list().rows());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn flag_contradicting_board_file_is_an_error() {
+        let (generator, path) = generated_board_file("args_board_conflict");
+        let squares_x = generator.board_config().squares_x;
+        let same = squares_x.to_string();
+        let other = (squares_x + 1).to_string();
+        assert!(
+            parse_with_board(&path, &["--squares-x", &same])
+                .board_config()
+                .is_ok()
+        );
+        assert!(
+            parse_with_board(&path, &["--squares-x", &other])
+                .board_config()
+                .is_err()
+        );
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn malformed_layout_is_rejected() {
+        let result =
+            Args::try_parse_from(["calibration_app", "--video", "rig.mp4", "--layout", "2by3"]);
+        assert!(result.is_err());
+    }
+}
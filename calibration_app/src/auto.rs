@@ -0,0 +1,160 @@
+use lib_cv::board::CharucoBoardConfig;
+use lib_cv::calibration::{ContrastEnhancement, perform_calibration};
+use lib_cv::frame_selection::{
+    AutoSelectParams, FrameCandidate, auto_select_frames_by_clip, score_frame,
+};
+use lib_cv::utils::{CLIP_FRAME_STRIDE, PickedManifest, split_image_into_grid};
+use log::{info, warn};
+use opencv::objdetect::CharucoBoard;
+
+use crate::args::Args;
+use crate::frames::FrameStore;
+use crate::picking;
+use crate::progress::ProgressReporter;
+use crate::results;
+
+/// Автоматический режим без окна: оценивает все кадры, выбирает лучшие,
+/// сохраняет их квадранты и сразу калибрует только по ним. `clips` - имена записей, если кадры
+/// взяты из нескольких видео
+pub fn run(
+    args: &Args,
+    charuco_board: &CharucoBoard,
+    board_config: &CharucoBoardConfig,
+    frames: &mut FrameStore,
+    clips: &[String],
+    params: &AutoSelectParams,
+) -> Result<(), String> {
+    // Манифест проверяется до долгой оценки кадров: кадры другой доски - сразу ошибка
+    let mut manifest = picking::load_manifest(&args.picked_dir, board_config, args.session())?;
+    manifest.clips = clips.to_vec();
+    let reporter = ProgressReporter::text();
+    let selected = pick_frames(
+        args,
+        charuco_board,
+        frames,
+        &mut manifest,
+        params,
+        args.contrast_enhancement().as_ref(),
+        &reporter,
+    );
+    reporter.finish();
+    let selected = selected?;
+    println!("Выбрано {} кадров: {:?}", selected.len(), selected);
+
+    picking::warn_weak_pairs(&manifest, args.layout.cells(), args.min_pair_frames);
+    // Калибруются только кадры этого запуска из папки его сессии: кадры прежних запусков
+    // остаются в --picked-dir, но в калибровку не попадают
+    let run_dir = manifest
+        .session
+        .as_ref()
+        .filter(|session| !session.is_empty())
+        .map_or_else(
+            || args.picked_dir.clone(),
+            |session| args.picked_dir.join(session),
+        );
+    let result = perform_calibration(
+        &run_dir,
+        &args.output_dir,
+        charuco_board,
+        args.layout.cells(),
+        &args.picked_calibration_params(board_config),
+        &mut reporter.calibration(args.layout.cells()),
+    );
+    reporter.finish();
+    let result = result.ok_or_else(|| "Калибровка не удалась, подробности в логе".to_string())?;
+    for line in result.summary() {
+        println!("{}", line);
+    }
+    if let Err(e) = results::save_report(&args.output_dir, &result) {
+        warn!("Не удалось сохранить отчёт о калибровке: {}", e);
+    }
+    if let Err(e) = results::save_json_report(&args.output_dir, &result) {
+        warn!("Не удалось сохранить отчёт о калибровке в JSON: {}", e);
+    }
+    Ok(())
+}
+
+/// Оценивает все кадры `frames`, выбирает лучшие по `params` и сохраняет их квадранты
+/// в --picked-dir, добавляя в `manifest`. Кадры нескольких записей выбираются из каждой
+/// пропорционально числу пригодных кадров в ней. С `contrast` доска ищется на кадрах
+/// с выровненным контрастом. Ход оценки сообщается в `reporter`; отмена
+/// прерывает оценку до сохранения. Возвращает номера выбранных кадров
+pub fn pick_frames(
+    args: &Args,
+    charuco_board: &CharucoBoard,
+    frames: &mut FrameStore,
+    manifest: &mut PickedManifest,
+    params: &AutoSelectParams,
+    contrast: Option<&ContrastEnhancement>,
+    reporter: &ProgressReporter,
+) -> Result<Vec<usize>, String> {
+    let total = frames.len();
+    let mut candidates: Vec<FrameCandidate> = Vec::with_capacity(total);
+    let mut by_clip: Vec<Vec<FrameCandidate>> = Vec::new();
+    for position in 0..total {
+        let index = frames.frame_number(position);
+        if !reporter.update(
+            "Scoring frames",
+            format!("frame {}", index),
+            position + 1,
+            total,
+        ) {
+            return Err("Оценка кадров отменена".to_string());
+        }
+        let frame = match frames.read(position) {
+            Ok(frame) => frame,
+            Err(e) => {
+                warn!("Кадр {} не читается и пропущен: {}", index, e);
+                continue;
+            }
+        };
+        let quadrants = split_image_into_grid(&frame, &args.layout)
+            .map_err(|e| format!("Не получилось разбить кадр {}: {}", index, e))?;
+        match score_frame(index, &quadrants, charuco_board, contrast) {
+            Ok(candidate) => {
+                let clip_i = index / CLIP_FRAME_STRIDE;
+                if by_clip.len() <= clip_i {
+                    by_clip.resize_with(clip_i + 1, Vec::new);
+                }
+                by_clip[clip_i].push(candidate.clone());
+                candidates.push(candidate);
+            }
+            Err(e) => warn!("Кадр {} не оценён: {}", index, e),
+        }
+    }
+
+    let (selected, rejected) = auto_select_frames_by_clip(&by_clip, params);
+    for (frame, reason) in &rejected {
+        info!("Кадр {}: отклонён, {}", frame, reason);
+    }
+    if selected.is_empty() {
+        return Err("Не выбрано ни одного кадра для калибровки".to_string());
+    }
+
+    for position in 0..total {
+        let index = frames.frame_number(position);
+        if !selected.contains(&index) {
+            continue;
+        }
+        let frame = frames
+            .read(position)
+            .map_err(|e| format!("Не удалось перечитать кадр {}: {}", index, e))?;
+        let quadrants = split_image_into_grid(&frame, &args.layout)
+            .map_err(|e| format!("Не получилось разбить кадр {}: {}", index, e))?;
+        let candidate = candidates.iter().find(|c| c.frame == index);
+        let corners = candidate.map(|c| c.corners.clone()).unwrap_or_default();
+        let shared = candidate
+            .map(|c| c.shared_with_first.clone())
+            .unwrap_or_default();
+        picking::save_picked(
+            &args.picked_dir,
+            manifest,
+            index,
+            &quadrants,
+            &corners,
+            &shared,
+            params.min_corners,
+        )?;
+    }
+    Ok(selected)
+}
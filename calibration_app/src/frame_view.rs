@@ -0,0 +1,409 @@
+use std::path::Path;
+use std::time::Instant;
+
+use lib_cv::board::CharucoBoardConfig;
+use lib_cv::calibration::{
+    CharucoDetection, ContrastEnhancement, find_common_points, get_charuco_enhanced,
+    probe_dictionary,
+};
+use lib_cv::utils::{GridLayout, annotate_bottom, combine_grid, split_image_into_grid};
+use log::{debug, warn};
+use opencv::core::{Rect, Scalar, Size};
+use opencv::imgcodecs;
+use opencv::imgproc;
+use opencv::objdetect::{CharucoBoard, draw_detected_corners_charuco, draw_detected_markers};
+use opencv::prelude::*;
+
+/// Пороги количества углов ChArUco для оценки квадранта
+#[derive(Debug, Clone, Copy)]
+pub struct CornerThresholds {
+    /// Меньше - квадрант непригоден (красная рамка) и не сохраняется
+    pub min_corners: usize,
+    /// Не меньше - квадрант хороший (зелёная рамка), между порогами - жёлтая
+    pub good_corners: usize,
+}
+
+/// Размер, в который вписывается мозаика при масштабе `fit` (экран 1080p)
+const FIT_SIZE: Size = Size {
+    width: 1920,
+    height: 1080,
+};
+/// Во сколько раз меняется масштаб показа по клавишам +/-
+const ZOOM_STEP: f64 = 1.25;
+const MIN_DISPLAY_SCALE: f64 = 0.05;
+
+/// Масштаб показа мозаики. Уменьшается только копия для окна: поиск доски, разметка
+/// и сохранённые изображения остаются в исходном разрешении
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayScale {
+    /// Вписать в экран 1080p, не увеличивая
+    Fit,
+    /// Доля исходного размера, (0, 1]
+    Fixed(f64),
+}
+
+impl DisplayScale {
+    /// Коэффициент масштаба для мозаики размера `size`
+    pub fn factor(&self, size: Size) -> f64 {
+        match *self {
+            Self::Fit if size.width > 0 && size.height > 0 => (FIT_SIZE.width as f64
+                / size.width as f64)
+                .min(FIT_SIZE.height as f64 / size.height as f64)
+                .min(1.0),
+            Self::Fit => 1.0,
+            Self::Fixed(scale) => scale,
+        }
+    }
+
+    /// Масштаб после `steps` нажатий + (отрицательные - нажатия -) от текущего
+    pub fn zoomed(&self, steps: i32, size: Size) -> Self {
+        let scale = self.factor(size) * ZOOM_STEP.powi(steps);
+        Self::Fixed(scale.clamp(MIN_DISPLAY_SCALE, 1.0))
+    }
+
+    /// Уменьшенная копия мозаики для показа. Разметка уже нарисована на исходной
+    /// мозаике, поэтому после уменьшения она остаётся на своих местах
+    pub fn apply(&self, mosaic: &Mat) -> opencv::Result<Mat> {
+        let factor = self.factor(mosaic.size()?);
+        if factor >= 1.0 {
+            return mosaic.try_clone();
+        }
+        let mut display = Mat::default();
+        imgproc::resize(
+            mosaic,
+            &mut display,
+            Size::default(),
+            factor,
+            factor,
+            imgproc::INTER_AREA,
+        )?;
+        Ok(display)
+    }
+
+    /// Подпись масштаба для строки состояния
+    pub fn label(&self, size: Size) -> String {
+        let percent = (self.factor(size) * 100.0).round();
+        match self {
+            Self::Fit => format!("Scale {}% (fit)", percent),
+            Self::Fixed(_) => format!("Scale {}%", percent),
+        }
+    }
+}
+
+/// Результат поиска доски в одном квадранте
+pub struct QuadrantDetection {
+    pub corners: usize,
+    /// Сколько маркеров ArUco найдено: маркеры без углов обычно значат, что доска видна
+    /// частично или её геометрия задана неверно
+    pub markers: usize,
+    /// Сколько найденных углов есть и в квадранте 1 (нужно для стереокалибровки)
+    pub shared_with_first: usize,
+}
+
+/// Прочитанный кадр: исходные квадранты, размеченная мозаика и оценка каждого квадранта
+pub struct FrameView {
+    pub quadrants: Vec<Mat>,
+    pub mosaic: Mat,
+    pub detections: Vec<QuadrantDetection>,
+    /// Доска, найденная в камере 1: по ней оценивается поза доски
+    pub reference: Option<CharucoDetection>,
+}
+
+impl FrameView {
+    /// Описания камер ниже порога, по одной строке на камеру
+    pub fn weak_cameras(&self, thresholds: &CornerThresholds) -> Vec<String> {
+        self.detections
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.corners < thresholds.min_corners)
+            .map(|(cam_i, d)| {
+                format!(
+                    "Cam {}: {} corners < {}",
+                    cam_i + 1,
+                    d.corners,
+                    thresholds.min_corners
+                )
+            })
+            .collect()
+    }
+}
+
+/// Читает кадр, ищет доску в каждом квадранте, считает углы и общие с квадрантом 1 углы,
+/// затем рисует разметку и цветную рамку качества. С `contrast` доска ищется на квадрантах
+/// с выровненным контрастом, а разметка рисуется на исходных
+pub fn render_frame(
+    charuco_board: &CharucoBoard,
+    path: &Path,
+    layout: &GridLayout,
+    thresholds: &CornerThresholds,
+    contrast: Option<&ContrastEnhancement>,
+) -> Result<FrameView, String> {
+    let frame = imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)
+        .map_err(|e| format!("не получилось считать кадр: {}", e))?;
+    if frame.empty() {
+        return Err(format!("не получилось считать кадр {}", path.display()));
+    }
+    render_image(charuco_board, &frame, layout, thresholds, contrast)
+}
+
+/// Как [`render_frame`], но для уже прочитанного общего кадра камер
+pub fn render_image(
+    charuco_board: &CharucoBoard,
+    frame: &Mat,
+    layout: &GridLayout,
+    thresholds: &CornerThresholds,
+    contrast: Option<&ContrastEnhancement>,
+) -> Result<FrameView, String> {
+    let quadrants = split_image_into_grid(frame, layout)
+        .map_err(|e| format!("не получилось разбить изображение: {}", e))?;
+    render_quadrants(charuco_board, quadrants, layout, thresholds, contrast)
+}
+
+/// Как [`render_frame`], но для уже разделённых по камерам изображений
+/// (например, кадров нескольких подключённых камер)
+pub fn render_quadrants(
+    charuco_board: &CharucoBoard,
+    quadrants: Vec<Mat>,
+    layout: &GridLayout,
+    thresholds: &CornerThresholds,
+    contrast: Option<&ContrastEnhancement>,
+) -> Result<FrameView, String> {
+    // Сначала ищем доску во всех квадрантах: для подсчёта общих углов нужны все результаты
+    let started = Instant::now();
+    let detected = detect_quadrants(charuco_board, &quadrants, contrast);
+    debug!(
+        "Поиск доски в {} квадрантах: {:?}",
+        quadrants.len(),
+        started.elapsed()
+    );
+
+    let first_ids = detected
+        .first()
+        .and_then(|d| d.as_ref())
+        .map(|d| d.charuco_ids.clone())
+        .unwrap_or_default();
+    let detections: Vec<QuadrantDetection> = detected
+        .iter()
+        .map(|d| match d {
+            Some(d) => QuadrantDetection {
+                corners: d.charuco_ids.len(),
+                markers: d.marker_ids.len(),
+                shared_with_first: find_common_points(&[first_ids.clone(), d.charuco_ids.clone()])
+                    .len(),
+            },
+            None => QuadrantDetection {
+                corners: 0,
+                markers: 0,
+                shared_with_first: 0,
+            },
+        })
+        .collect();
+
+    let mut edited = Vec::with_capacity(quadrants.len());
+    for (cam_i, quadrant) in quadrants.iter().enumerate() {
+        let mut edited_quadrant = quadrant.clone();
+        if let Some(detection) = &detected[cam_i] {
+            draw_detected_markers(
+                &mut edited_quadrant,
+                &detection.marker_corners,
+                &detection.marker_ids,
+                Scalar::new(255.0, 0.0, 0.0, 255.0),
+            )
+            .expect("Не получилось нарисовать маркеры");
+            draw_detected_corners_charuco(
+                &mut edited_quadrant,
+                &detection.charuco_corners,
+                &detection.charuco_ids,
+                Scalar::new(0.0, 255.0, 0.0, 255.0),
+            )
+            .expect("Не получилось нарисовать на изображении углы Charuco");
+        }
+        draw_quality(&mut edited_quadrant, cam_i, &detections[cam_i], thresholds)
+            .map_err(|e| format!("не получилось нарисовать оценку квадранта: {}", e))?;
+        edited.push(edited_quadrant);
+    }
+
+    let mosaic = combine_grid(&edited, layout.cols)
+        .map_err(|e| format!("ошибка в сшивании изображений камер: {}", e))?;
+    Ok(FrameView {
+        quadrants,
+        mosaic,
+        detections,
+        reference: detected.into_iter().next().flatten(),
+    })
+}
+
+/// Проверяет по кадру `frame`, что маркеры доски декодируются словарём из `board_config`.
+/// Если маркеры явно принадлежат другому словарю, предупреждает в лог и возвращает
+/// строку для показа в окне. Проверка перебирает несколько словарей, поэтому делается
+/// только по первому кадру
+pub fn check_dictionary(frame: &Mat, board_config: &CharucoBoardConfig) -> Option<String> {
+    let configured = board_config.dictionary_type().ok()?;
+    match probe_dictionary(frame, configured) {
+        Ok(Some((found, markers))) => {
+            warn!(
+                "Маркеры на первом кадре декодируются как {:?} ({} маркеров), а задан словарь {}",
+                found, markers, board_config.dictionary
+            );
+            Some(format!(
+                "Markers decode as {:?}, but {} is configured",
+                found, board_config.dictionary
+            ))
+        }
+        Ok(None) => None,
+        Err(e) => {
+            debug!("Словарь маркеров не проверен: {}", e);
+            None
+        }
+    }
+}
+
+/// Ищет доску во всех квадрантах одновременно, по потоку на квадрант: поиск на больших
+/// квадрантах занимает основное время показа кадра. У каждого потока своя копия доски,
+/// `get_charuco` создаёт для неё отдельный детектор. Результаты идут в порядке квадрантов
+fn detect_quadrants(
+    charuco_board: &CharucoBoard,
+    quadrants: &[Mat],
+    contrast: Option<&ContrastEnhancement>,
+) -> Vec<Option<CharucoDetection>> {
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = quadrants
+            .iter()
+            .map(|quadrant| {
+                let board = charuco_board.clone();
+                scope.spawn(move || get_charuco_enhanced(&board, quadrant, contrast))
+            })
+            .collect();
+        workers
+            .into_iter()
+            .enumerate()
+            .map(|(cam_i, worker)| match worker.join() {
+                Ok(Ok(detection)) => Some(detection),
+                // Ошибка поиска доски в одном квадранте не должна скрывать весь кадр:
+                // такой квадрант показывается без разметки
+                Ok(Err(e)) => {
+                    warn!(
+                        "Ошибка при извлечении Charuco углов в квадранте {}: {}",
+                        cam_i + 1,
+                        e
+                    );
+                    None
+                }
+                Err(_) => {
+                    warn!("Поиск доски в квадранте {} завершился аварийно", cam_i + 1);
+                    None
+                }
+            })
+            .collect()
+    })
+}
+
+/// Рамка цвета качества и подпись с количеством углов в нижнем углу квадранта
+fn draw_quality(
+    image: &mut Mat,
+    cam_i: usize,
+    detection: &QuadrantDetection,
+    thresholds: &CornerThresholds,
+) -> opencv::Result<()> {
+    // Цвета в BGR
+    let color = if detection.corners >= thresholds.good_corners {
+        Scalar::new(0.0, 200.0, 0.0, 255.0)
+    } else if detection.corners >= thresholds.min_corners {
+        Scalar::new(0.0, 220.0, 255.0, 255.0)
+    } else {
+        Scalar::new(0.0, 0.0, 255.0, 255.0)
+    };
+    let thickness = (image.rows() / 60).max(3);
+    imgproc::rectangle(
+        image,
+        Rect::new(0, 0, image.cols(), image.rows()),
+        color,
+        thickness,
+        imgproc::LINE_8,
+        0,
+    )?;
+
+    let mut lines = vec![format!(
+        "Cam {}: {} corners, {} markers",
+        cam_i + 1,
+        detection.corners,
+        detection.markers
+    )];
+    if cam_i > 0 {
+        lines.push(format!(
+            "Shared with cam 1: {}",
+            detection.shared_with_first
+        ));
+    }
+    annotate_bottom(image, &lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_cv::board::BoardConfig;
+
+    /// Квадрант 1280x720 с доской 10x5 (100 пикс. на квадрат); `covered` - ширина
+    /// закрытой белым левой части кадра
+    fn board_quadrant(board: &CharucoBoard, covered: i32) -> Mat {
+        let mut board_image = Mat::default();
+        board
+            .generate_image(Size::new(1000, 500), &mut board_image, 0, 1)
+            .unwrap();
+        let mut gray =
+            Mat::new_rows_cols_with_default(720, 1280, opencv::core::CV_8UC1, Scalar::all(255.0))
+                .unwrap();
+        board_image
+            .copy_to(&mut gray.roi_mut(Rect::new(140, 110, 1000, 500)).unwrap())
+            .unwrap();
+        if covered > 0 {
+            gray.roi_mut(Rect::new(0, 0, covered, 720))
+                .unwrap()
+                .set_to_def(&Scalar::all(255.0))
+                .unwrap();
+        }
+        let mut quadrant = Mat::default();
+        imgproc::cvt_color_def(&gray, &mut quadrant, imgproc::COLOR_GRAY2BGR).unwrap();
+        quadrant
+    }
+
+    #[test]
+    fn concurrent_detection_matches_sequential_order_and_reports_speedup() {
+        let board = BoardConfig::new().build().unwrap();
+        let quadrants = vec![
+            board_quadrant(&board, 0),
+            board_quadrant(&board, 640),
+            board_quadrant(&board, 0),
+            board_quadrant(&board, 1280),
+        ];
+
+        let started = Instant::now();
+        let sequential: Vec<CharucoDetection> = quadrants
+            .iter()
+            .map(|quadrant| get_charuco_enhanced(&board, quadrant, None).unwrap())
+            .collect();
+        let sequential_time = started.elapsed();
+        let started = Instant::now();
+        let concurrent = detect_quadrants(&board, &quadrants, None);
+        let concurrent_time = started.elapsed();
+        println!(
+            "Поиск доски в 4 квадрантах 1280x720: последовательно {:?}, параллельно {:?}, \
+             ускорение {:.2}",
+            sequential_time,
+            concurrent_time,
+            sequential_time.as_secs_f64() / concurrent_time.as_secs_f64()
+        );
+
+        let ids = |detection: &CharucoDetection| detection.charuco_ids.to_vec();
+        assert_eq!(concurrent.len(), 4);
+        for (expected, found) in sequential.iter().zip(&concurrent) {
+            assert_eq!(ids(expected), ids(found.as_ref().unwrap()));
+        }
+        // Порядок квадрантов сохранён: полная доска, половина доски, полная, пустой кадр
+        let corners: Vec<usize> = sequential.iter().map(|d| d.charuco_ids.len()).collect();
+        assert_eq!(corners[0], 36);
+        assert!(corners[1] > 0 && corners[1] < corners[0]);
+        assert_eq!(corners[2], 36);
+        assert_eq!(corners[3], 0);
+    }
+}
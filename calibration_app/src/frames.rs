@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use lib_cv::utils::{CLIP_FRAME_STRIDE, FrameListing, SeekableVideo};
+use opencv::core::Mat;
+use opencv::imgcodecs;
+use opencv::prelude::*;
+
+/// Сколько последних декодированных кадров видео держать в памяти
+const VIDEO_FRAME_CACHE: usize = 8;
+
+/// Кадры для ручного выбора: извлечённые заранее в --parsed-dir или декодируемые
+/// из видео по запросу. Кадры адресуются позицией в списке, а номер кадра видео
+/// (он же номер сцены в именах выбранных изображений) берётся из [`FrameStore::frame_number`]
+pub enum FrameStore {
+    Extracted(FrameListing),
+    Video(SeekableVideo),
+    /// Несколько записей подряд. Номера кадров записи `i` сдвинуты на
+    /// `i * CLIP_FRAME_STRIDE`, чтобы сцены разных записей не совпадали
+    Clips(Vec<FrameStore>),
+}
+
+impl FrameStore {
+    pub fn open_video(video: &Path) -> Result<Self, String> {
+        let video = SeekableVideo::open(video, VIDEO_FRAME_CACHE).map_err(|e| e.to_string())?;
+        if video.frame_count() == 0 {
+            return Err("В видео нет кадров".to_string());
+        }
+        Ok(Self::Video(video))
+    }
+
+    /// Объединяет кадры записей; одна запись остаётся как есть
+    pub fn from_clips(mut clips: Vec<FrameStore>) -> Self {
+        if clips.len() == 1 {
+            return clips.remove(0);
+        }
+        Self::Clips(clips)
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Extracted(listing) => listing.frames.len(),
+            Self::Video(video) => video.frame_count(),
+            Self::Clips(clips) => clips.iter().map(FrameStore::len).sum(),
+        }
+    }
+
+    /// Номер кадра видео в позиции `position`. Для позиции за концом кадров - сама
+    /// позиция, как у видео: номер нужен только для сообщений и имён файлов
+    pub fn frame_number(&self, position: usize) -> usize {
+        match self {
+            Self::Extracted(listing) => listing.frames.get(position).map_or(position, |f| f.index),
+            Self::Video(_) => position,
+            Self::Clips(clips) => match locate(clips, position) {
+                Ok((clip_i, position)) => {
+                    clip_i * CLIP_FRAME_STRIDE + clips[clip_i].frame_number(position)
+                }
+                Err(_) => position,
+            },
+        }
+    }
+
+    /// Позиция первого кадра с номером не меньше `number`
+    pub fn position_of(&self, number: usize) -> usize {
+        match self {
+            Self::Extracted(listing) => listing.frames.partition_point(|f| f.index < number),
+            Self::Video(video) => number.min(video.frame_count().saturating_sub(1)),
+            Self::Clips(clips) => {
+                if clips.is_empty() {
+                    return 0;
+                }
+                // Пустые записи не занимают позиций: номер из такой записи указывает
+                // на первый кадр следующей
+                let clip_i = (number / CLIP_FRAME_STRIDE).min(clips.len() - 1);
+                let start: usize = clips[..clip_i].iter().map(FrameStore::len).sum();
+                let position = start + clips[clip_i].position_of(number % CLIP_FRAME_STRIDE);
+                position.min(self.len().saturating_sub(1))
+            }
+        }
+    }
+
+    pub fn read(&mut self, position: usize) -> Result<Mat, String> {
+        match self {
+            Self::Extracted(listing) => {
+                let path = &listing
+                    .frames
+                    .get(position)
+                    .ok_or_else(|| out_of_range(position, listing.frames.len()))?
+                    .path;
+                let frame = imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)
+                    .map_err(|e| format!("не получилось считать кадр: {}", e))?;
+                if frame.empty() {
+                    return Err(format!("не получилось считать кадр {}", path.display()));
+                }
+                Ok(frame)
+            }
+            Self::Video(video) => video.frame(position).map_err(|e| e.to_string()),
+            Self::Clips(clips) => {
+                let (clip_i, position) = locate(clips, position)?;
+                clips[clip_i].read(position)
+            }
+        }
+    }
+}
+
+/// Запись и позиция в ней для общей позиции `position`. Пустые записи пропускаются
+fn locate(clips: &[FrameStore], position: usize) -> Result<(usize, usize), String> {
+    let mut rest = position;
+    for (clip_i, clip) in clips.iter().enumerate() {
+        if rest < clip.len() {
+            return Ok((clip_i, rest));
+        }
+        rest -= clip.len();
+    }
+    let total = clips.iter().map(FrameStore::len).sum();
+    Err(out_of_range(position, total))
+}
+
+fn out_of_range(position: usize, total: usize) -> String {
+    format!(
+        "позиция кадра {} за пределами записей ({} кадров)",
+        position, total
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use lib_cv::utils::FrameEntry;
+
+    use super::*;
+
+    fn extracted(indices: &[usize]) -> FrameStore {
+        FrameStore::Extracted(FrameListing {
+            frames: indices
+                .iter()
+                .map(|&index| FrameEntry {
+                    index,
+                    path: PathBuf::from(format!("missing_{}.png", index)),
+                })
+                .collect(),
+            ..FrameListing::default()
+        })
+    }
+
+    #[test]
+    fn zero_length_clips_are_skipped() {
+        let store =
+            FrameStore::from_clips(vec![extracted(&[]), extracted(&[3, 5]), extracted(&[])]);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.frame_number(0), CLIP_FRAME_STRIDE + 3);
+        assert_eq!(store.frame_number(1), CLIP_FRAME_STRIDE + 5);
+        assert_eq!(store.position_of(4), 0);
+        assert_eq!(store.position_of(CLIP_FRAME_STRIDE + 5), 1);
+        assert_eq!(store.position_of(2 * CLIP_FRAME_STRIDE), 1);
+    }
+
+    #[test]
+    fn empty_store_reports_errors_instead_of_panicking() {
+        let mut store = FrameStore::from_clips(vec![extracted(&[]), extracted(&[])]);
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.position_of(7), 0);
+        let error = store.read(0).unwrap_err();
+        assert!(error.contains("за пределами"), "{}", error);
+
+        let mut store = FrameStore::Clips(Vec::new());
+        assert_eq!(store.position_of(7), 0);
+        assert!(store.read(0).is_err());
+    }
+
+    #[test]
+    fn read_past_the_end_is_an_error() {
+        let mut store = FrameStore::from_clips(vec![extracted(&[1]), extracted(&[2])]);
+        assert!(store.read(2).unwrap_err().contains("за пределами"));
+        let mut single = extracted(&[1]);
+        assert!(single.read(1).unwrap_err().contains("за пределами"));
+    }
+}
@@ -0,0 +1,980 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use eframe::egui::{self, ColorImage, TextureHandle, TextureOptions};
+use lib_cv::board::{CharucoBoardConfig, LengthUnit};
+use lib_cv::calibration::{
+    CalibrationResult, ContrastEnhancement, PickedCalibrationParams, calibrate_picked_images,
+};
+use lib_cv::utils::{PickedFrame, PickedManifest, combine_grid};
+use log::{info, warn};
+use opencv::core::{CV_8UC3, Mat, Scalar, Size, StsError};
+use opencv::imgcodecs;
+use opencv::imgproc;
+use opencv::objdetect::CharucoBoard;
+use opencv::prelude::*;
+
+use crate::args::Args;
+use crate::auto;
+use crate::frame_view::{DisplayScale, FrameView};
+use crate::navigation::{Action, ReviewAction};
+use crate::picking;
+use crate::poses;
+use crate::progress::ProgressReporter;
+use crate::reprojection;
+use crate::results;
+use crate::session::PickerSession;
+
+/// Ширина изображения одной камеры в миниатюре выбранного кадра, пикс.
+const THUMBNAIL_CELL_WIDTH: i32 = 96;
+/// Как часто окно опрашивает фоновую операцию
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Сколько кадров выбирать кнопкой Auto-pick по умолчанию
+const DEFAULT_AUTO_PICK: usize = 20;
+
+/// Долгая операция в фоновом потоке, пока окно показывает её прогресс
+enum Job {
+    /// Автоматический выбор: сеанс выбора на это время переходит в рабочий поток
+    AutoPick {
+        reporter: ProgressReporter,
+        worker: JoinHandle<(PickerSession, Result<Vec<usize>, String>)>,
+    },
+    Calibrate {
+        reporter: ProgressReporter,
+        worker: JoinHandle<Option<CalibrationResult>>,
+    },
+    /// Просмотр перепроекции в окне highgui: манифест выбора на это время переходит
+    /// в рабочий поток и возвращается вместе с числом удалённых сцен
+    Reprojection {
+        worker: JoinHandle<(PickedManifest, usize)>,
+    },
+    /// Предпросмотр дисторсии в окне highgui, которое само возвращает решение
+    Distortion {
+        worker: JoinHandle<opencv::Result<ReviewAction>>,
+    },
+}
+
+impl Job {
+    /// `None` для окон просмотра: у них нет прогресса, только ожидание закрытия окна
+    fn reporter(&self) -> Option<&ProgressReporter> {
+        match self {
+            Job::AutoPick { reporter, .. } | Job::Calibrate { reporter, .. } => Some(reporter),
+            Job::Reprojection { .. } | Job::Distortion { .. } => None,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        match self {
+            Job::AutoPick { worker, .. } => worker.is_finished(),
+            Job::Calibrate { worker, .. } => worker.is_finished(),
+            Job::Reprojection { worker } => worker.is_finished(),
+            Job::Distortion { worker } => worker.is_finished(),
+        }
+    }
+}
+
+/// Окно ручного выбора кадров: текущая мозаика камер, галерея выбранных кадров и
+/// управление выбором и калибровкой. Клавиши - те же, что в окне highgui
+pub struct PickerApp {
+    args: Args,
+    charuco_board: CharucoBoard,
+    board_config: CharucoBoardConfig,
+    /// Доска в полях редактора, пока изменения не применены
+    board_draft: CharucoBoardConfig,
+    /// `None`, пока сеанс занят автоматическим выбором
+    session: Option<PickerSession>,
+    view: Option<FrameView>,
+    /// Позиция кадра, для которой построены `view` и `texture`
+    shown: Option<usize>,
+    texture: Option<TextureHandle>,
+    /// Масштаб, в котором мозаика загружается в `texture`
+    display_scale: DisplayScale,
+    /// Миниатюры выбранных кадров по номеру кадра
+    thumbnails: HashMap<usize, TextureHandle>,
+    go_to: String,
+    focus_go_to: bool,
+    auto_count: usize,
+    job: Option<Job>,
+    result: Option<CalibrationResult>,
+}
+
+/// Открывает окно выбора кадров и возвращается после его закрытия
+pub fn run(
+    args: Args,
+    charuco_board: CharucoBoard,
+    board_config: CharucoBoardConfig,
+    session: PickerSession,
+) -> eframe::Result<()> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([1400.0, 900.0])
+            .with_min_inner_size([900.0, 600.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "Charuco Доска",
+        options,
+        Box::new(move |_cc| {
+            Ok(Box::new(PickerApp::new(
+                args,
+                charuco_board,
+                board_config,
+                session,
+            )))
+        }),
+    )
+}
+
+impl PickerApp {
+    pub fn new(
+        args: Args,
+        charuco_board: CharucoBoard,
+        board_config: CharucoBoardConfig,
+        session: PickerSession,
+    ) -> Self {
+        Self {
+            auto_count: args.auto.unwrap_or(DEFAULT_AUTO_PICK),
+            display_scale: args.display_scale,
+            args,
+            charuco_board,
+            board_draft: board_config.clone(),
+            board_config,
+            session: Some(session),
+            view: None,
+            shown: None,
+            texture: None,
+            thumbnails: HashMap::new(),
+            go_to: String::new(),
+            focus_go_to: false,
+            job: None,
+            result: None,
+        }
+    }
+
+    /// Перерисовывает текущий кадр, если позиция изменилась
+    fn refresh_view(&mut self, ctx: &egui::Context) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        if self.shown == Some(session.position()) {
+            return;
+        }
+        self.view = session.render_current(&self.charuco_board);
+        self.shown = Some(session.position());
+        let Some(view) = &self.view else {
+            session
+                .notice
+                .push("No readable frames, q - quit".to_string());
+            return;
+        };
+        Self::upload_view(view, self.display_scale, &mut self.texture, ctx);
+    }
+
+    /// Загружает уменьшенную до `display_scale` мозаику кадра в текстуру окна
+    fn upload_view(
+        view: &FrameView,
+        display_scale: DisplayScale,
+        texture: &mut Option<TextureHandle>,
+        ctx: &egui::Context,
+    ) {
+        match display_scale
+            .apply(&view.mosaic)
+            .and_then(|display| to_color_image(&display))
+        {
+            Ok(image) => match texture {
+                Some(texture) => texture.set(image, TextureOptions::LINEAR),
+                None => *texture = Some(ctx.load_texture("mosaic", image, TextureOptions::LINEAR)),
+            },
+            Err(e) => warn!("Не удалось показать кадр: {}", e),
+        }
+    }
+
+    fn apply(&mut self, action: Action, ctx: &egui::Context) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        if action == Action::None {
+            return;
+        }
+        if let Action::Zoom(steps) = action {
+            if let Some(view) = &self.view {
+                let size = view.mosaic.size().unwrap_or_default();
+                self.display_scale = self.display_scale.zoomed(steps, size);
+                Self::upload_view(view, self.display_scale, &mut self.texture, ctx);
+            }
+            return;
+        }
+        // Сообщение показывается до следующего действия
+        session.notice.clear();
+        if action != Action::Finish {
+            session.cancel_confirmation();
+        }
+        match action {
+            Action::Move(delta) => session.step(delta),
+            Action::GoTo => self.focus_go_to = true,
+            Action::SavePicked => {
+                if let Some(view) = &self.view {
+                    session.save_current(view);
+                    // Повторный выбор кадра перезаписывает его изображения
+                    self.thumbnails.remove(&session.frame_number());
+                }
+            }
+            Action::UndoPicked => session.undo_last(),
+            Action::SaveMosaic => {
+                if let Some(view) = &self.view {
+                    session.save_mosaic(view);
+                }
+            }
+            Action::Finish => {
+                if session.ready_to_calibrate(&self.args.scene_limits()) {
+                    self.start_calibration();
+                }
+            }
+            Action::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            Action::Zoom(_) | Action::None => {}
+        }
+    }
+
+    fn go_to_entered(&mut self) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        session.notice.clear();
+        match self.go_to.trim().parse() {
+            Ok(number) => session.go_to(number),
+            Err(_) => session
+                .notice
+                .push(format!("Not a frame number: {}", self.go_to)),
+        }
+    }
+
+    fn start_auto_pick(&mut self) {
+        let Some(mut session) = self.session.take() else {
+            return;
+        };
+        session.notice.clear();
+        let params = self.args.auto_params(self.auto_count);
+        let reporter = ProgressReporter::background();
+        let worker_reporter = reporter.clone();
+        let args = self.args.clone();
+        // Доска не Sync, поэтому в рабочий поток переходит её копия
+        let board = self.charuco_board.clone();
+        let worker = std::thread::spawn(move || {
+            let contrast = session.contrast;
+            let result = auto::pick_frames(
+                &args,
+                &board,
+                &mut session.frames,
+                &mut session.manifest,
+                &params,
+                contrast.as_ref(),
+                &worker_reporter,
+            );
+            (session, result)
+        });
+        self.job = Some(Job::AutoPick { reporter, worker });
+    }
+
+    fn start_calibration(&mut self) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        let cameras = self.args.layout.cells();
+        picking::warn_weak_pairs(&session.manifest, cameras, self.args.min_pair_frames);
+        let reporter = ProgressReporter::background();
+        let worker_reporter = reporter.clone();
+        let args = self.args.clone();
+        let board = self.charuco_board.clone();
+        // Доска ищется с тем же выравниванием контраста, что и при выборе кадров
+        let params = PickedCalibrationParams {
+            contrast: session.contrast,
+            ..args.picked_calibration_params(&self.board_config)
+        };
+        let worker = std::thread::spawn(move || {
+            calibrate_picked_images(
+                &args.picked_dir,
+                &board,
+                cameras,
+                &params,
+                &mut worker_reporter.calibration(cameras),
+            )
+        });
+        self.job = Some(Job::Calibrate { reporter, worker });
+    }
+
+    /// Забирает результат закончившейся фоновой операции
+    fn poll_job(&mut self, ctx: &egui::Context) {
+        if !self.job.as_ref().is_some_and(Job::is_finished) {
+            return;
+        }
+        match self.job.take() {
+            Some(Job::AutoPick { worker, .. }) => {
+                let (mut session, result) = worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                match result {
+                    Ok(selected) => {
+                        info!("Автоматически выбрано {} кадров", selected.len());
+                        session.add_picked(selected.len());
+                        session
+                            .notice
+                            .push(format!("Auto-picked {} frames", selected.len()));
+                        for frame in &selected {
+                            self.thumbnails.remove(frame);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        session.notice.push("Auto-pick failed, see log".to_string());
+                    }
+                }
+                self.session = Some(session);
+            }
+            Some(Job::Calibrate { worker, .. }) => {
+                let result = worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                match result {
+                    Some(result) => self.result = Some(result),
+                    None => {
+                        if let Some(session) = &mut self.session {
+                            session
+                                .notice
+                                .push("Calibration failed or cancelled, see log".to_string());
+                        }
+                    }
+                }
+            }
+            Some(Job::Reprojection { worker }) => {
+                let (manifest, removed) = worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                if let Some(session) = &mut self.session {
+                    session.manifest = manifest;
+                    // Без удалённых сцен результат устарел: нужна повторная калибровка
+                    if removed > 0 {
+                        session
+                            .notice
+                            .push(format!("Removed {} scenes, Esc - recalibrate", removed));
+                        self.result = None;
+                        self.shown = None;
+                    }
+                }
+            }
+            Some(Job::Distortion { worker }) => {
+                let action = worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                match action {
+                    Ok(action) => self.review(action, ctx),
+                    Err(e) => {
+                        warn!("Окно дисторсии не показано: {}", e);
+                        if let Some(session) = &mut self.session {
+                            session
+                                .notice
+                                .push("Distortion preview failed, see log".to_string());
+                        }
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Решение по результату калибровки, как в окне результатов highgui
+    fn review(&mut self, action: ReviewAction, ctx: &egui::Context) {
+        let (Some(result), Some(session)) = (&self.result, &mut self.session) else {
+            return;
+        };
+        match action {
+            ReviewAction::Accept => {
+                crate::accept_calibration(&self.args.output_dir, result, &self.board_config);
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            ReviewAction::Discard => {
+                info!("Результат калибровки отброшен");
+                session
+                    .notice
+                    .push("Calibration discarded, pick more frames".to_string());
+                self.result = None;
+            }
+            ReviewAction::Reprojection => {
+                // Окно highgui крутит свой цикл событий, поэтому работает в отдельном
+                // потоке, а окно egui тем временем продолжает перерисовываться
+                let result = result.clone();
+                let board = self.charuco_board.clone();
+                let mut manifest = session.manifest.clone();
+                let args = self.args.clone();
+                let worker = std::thread::spawn(move || {
+                    let removed = reprojection::browse(
+                        &result,
+                        &board,
+                        &args.picked_dir,
+                        &mut manifest,
+                        &args.layout,
+                        args.max_view_error,
+                        &args.output_dir.join(reprojection::REVIEW_DIR),
+                    );
+                    (manifest, removed)
+                });
+                self.job = Some(Job::Reprojection { worker });
+            }
+            ReviewAction::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            ReviewAction::None => {}
+        }
+    }
+
+    /// Нажатые клавиши, если ввод не занят текстовым полем
+    fn pressed_keys(ctx: &egui::Context) -> Vec<egui::Key> {
+        if ctx.wants_keyboard_input() {
+            return Vec::new();
+        }
+        ctx.input(|input| {
+            input
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    egui::Event::Key {
+                        key, pressed: true, ..
+                    } => Some(*key),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    fn show_job(&self, ctx: &egui::Context) {
+        let Some(job) = &self.job else {
+            return;
+        };
+        let Some(reporter) = job.reporter() else {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.vertical_centered(|ui| {
+                    ui.add_space(ui.available_height() / 3.0);
+                    ui.label("Открыто окно просмотра: закройте его, чтобы продолжить");
+                });
+            });
+            ctx.request_repaint_after(JOB_POLL_INTERVAL);
+            return;
+        };
+        if Self::pressed_keys(ctx).contains(&egui::Key::Escape) {
+            reporter.cancel();
+        }
+        let (status, fraction) = reporter.status();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(ui.available_height() / 3.0);
+                ui.label(status);
+                ui.add(egui::ProgressBar::new(fraction).show_percentage());
+                if reporter.is_cancelled() {
+                    ui.label("Отмена...");
+                } else if ui.button("Отменить (Esc)").clicked() {
+                    reporter.cancel();
+                }
+            });
+        });
+        ctx.request_repaint_after(JOB_POLL_INTERVAL);
+    }
+
+    fn show_controls(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        egui::CollapsingHeader::new("Доска").show(ui, |ui| {
+            let draft = &mut self.board_draft;
+            ui.horizontal(|ui| {
+                ui.label("Квадратов");
+                ui.add(egui::DragValue::new(&mut draft.squares_x).range(2..=100));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut draft.squares_y).range(2..=100));
+            });
+            ui.add(
+                egui::DragValue::new(&mut draft.square_length)
+                    .range(0.0..=f32::MAX)
+                    .speed(0.1)
+                    .prefix("Квадрат: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut draft.marker_length)
+                    .range(0.0..=f32::MAX)
+                    .speed(0.1)
+                    .prefix("Маркер: "),
+            );
+            ui.horizontal(|ui| {
+                ui.label("Словарь");
+                ui.text_edit_singleline(&mut draft.dictionary);
+            });
+            egui::ComboBox::from_label("Единицы")
+                .selected_text(draft.unit.label())
+                .show_ui(ui, |ui| {
+                    for unit in [
+                        LengthUnit::Millimeters,
+                        LengthUnit::Centimeters,
+                        LengthUnit::Meters,
+                        LengthUnit::PatternPixels,
+                        LengthUnit::Unspecified,
+                    ] {
+                        ui.selectable_value(&mut draft.unit, unit, unit.label());
+                    }
+                });
+            let changed = *draft != self.board_config;
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(changed, egui::Button::new("Применить"))
+                    .clicked()
+                {
+                    let applied = apply_board(
+                        draft,
+                        &mut self.board_config,
+                        &mut self.charuco_board,
+                        &mut session.manifest,
+                    );
+                    if let Err(notice) = applied {
+                        session.notice.push(notice.to_string());
+                    }
+                    self.shown = None;
+                }
+                if ui
+                    .add_enabled(changed, egui::Button::new("Сбросить"))
+                    .clicked()
+                {
+                    *draft = self.board_config.clone();
+                }
+            });
+        });
+        egui::CollapsingHeader::new("Калибровка").show(ui, |ui| {
+            let args = &mut self.args;
+            ui.checkbox(&mut args.refine_intrinsics, "Уточнять внутренние параметры");
+            ui.add(
+                egui::DragValue::new(&mut args.min_scenes)
+                    .range(1..=1000)
+                    .prefix("Сцен на камеру, минимум: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut args.min_complete_scenes)
+                    .range(0..=1000)
+                    .prefix("Полных сцен, минимум: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut args.min_pair_frames)
+                    .range(0..=1000)
+                    .prefix("Кадров на пару камер: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut args.max_view_error)
+                    .range(0.0..=100.0)
+                    .speed(0.05)
+                    .prefix("Порог ошибки вида, пикс.: "),
+            );
+        });
+        egui::CollapsingHeader::new("Параметры запуска").show(ui, |ui| {
+            ui.label(self.args.summary(&self.board_config));
+        });
+        ui.separator();
+
+        ui.label("Пороги углов в камере");
+        let mut thresholds = session.thresholds;
+        ui.add(egui::Slider::new(&mut thresholds.min_corners, 1..=100).text("Минимум"));
+        ui.add(egui::Slider::new(&mut thresholds.good_corners, 1..=100).text("Хорошо"));
+        thresholds.good_corners = thresholds.good_corners.max(thresholds.min_corners);
+        if thresholds.min_corners != session.thresholds.min_corners
+            || thresholds.good_corners != session.thresholds.good_corners
+        {
+            session.thresholds = thresholds;
+            self.shown = None;
+        }
+        ui.separator();
+
+        let mut enabled = session.contrast.is_some();
+        if ui
+            .checkbox(&mut enabled, "Выравнивать контраст (CLAHE)")
+            .on_hover_text(
+                "Доска ищется на кадрах с выровненной яркостью; разметка и сохранённые кадры - исходные",
+            )
+            .changed()
+        {
+            session.contrast = enabled.then_some(ContrastEnhancement {
+                clip_limit: self.args.clahe_clip_limit,
+                tile_grid: self.args.clahe_tiles,
+            });
+            self.shown = None;
+        }
+        if let Some(contrast) = &mut session.contrast {
+            let before = *contrast;
+            ui.add(
+                egui::Slider::new(&mut contrast.clip_limit, 0.5..=10.0)
+                    .text("Ограничение усиления"),
+            );
+            ui.add(egui::Slider::new(&mut contrast.tile_grid, 1..=32).text("Плиток по стороне"));
+            if *contrast != before {
+                self.shown = None;
+            }
+        }
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            let field = ui.add(
+                egui::TextEdit::singleline(&mut self.go_to)
+                    .hint_text("№ кадра")
+                    .desired_width(80.0),
+            );
+            if std::mem::take(&mut self.focus_go_to) {
+                field.request_focus();
+            }
+            let entered = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Перейти (g)").clicked() || entered {
+                self.go_to_entered();
+            }
+        });
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.auto_count).range(1..=500));
+            if ui.button("Auto-pick").clicked() {
+                self.start_auto_pick();
+            }
+        });
+        if ui.button("Калибровать (Esc)").clicked() {
+            self.apply(Action::Finish, ctx);
+        }
+    }
+
+    fn show_gallery(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        ui.heading(format!("Выбрано: {}", session.manifest.frames.len()));
+        ui.label(picking::scene_status(
+            &session.manifest,
+            self.args.layout.cells(),
+            &self.args.scene_limits(),
+        ));
+        if let Some(status) = picking::pair_status(&session.manifest, self.args.layout.cells()) {
+            ui.label(status);
+        }
+        ui.separator();
+
+        let picked: Vec<PickedFrame> = session.manifest.frames.iter().rev().cloned().collect();
+        let current = session.frame_number();
+        let mut go_to = None;
+        let mut remove = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for frame in &picked {
+                let texture = self.thumbnail(ctx, frame);
+                ui.horizontal(|ui| {
+                    if let Some(texture) = texture {
+                        let image = egui::Image::from_texture(&texture).sense(egui::Sense::click());
+                        if ui.add(image).on_hover_text("Перейти к кадру").clicked() {
+                            go_to = Some(frame.frame);
+                        }
+                    }
+                    ui.vertical(|ui| {
+                        let title = format!("#{}", frame.frame);
+                        if frame.frame == current {
+                            ui.strong(title);
+                        } else {
+                            ui.label(title);
+                        }
+                        for (cam_i, corners) in frame.corners.iter().enumerate() {
+                            ui.small(format!("Cam {}: {} corners", cam_i + 1, corners));
+                        }
+                        if ui.small_button("Удалить").clicked() {
+                            remove = Some(frame.frame);
+                        }
+                    });
+                });
+                ui.separator();
+            }
+        });
+
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        if let Some(frame) = go_to {
+            session.notice.clear();
+            session.go_to(frame);
+        }
+        if let Some(frame) = remove {
+            session.notice.clear();
+            session.remove(frame);
+            self.thumbnails.remove(&frame);
+        }
+    }
+
+    /// Миниатюра выбранного кадра из сохранённых изображений камер (строится один раз)
+    fn thumbnail(&mut self, ctx: &egui::Context, frame: &PickedFrame) -> Option<TextureHandle> {
+        if let Some(texture) = self.thumbnails.get(&frame.frame) {
+            return Some(texture.clone());
+        }
+        let image = thumbnail_image(&self.args.picked_dir, frame, &self.args)
+            .and_then(|mosaic| to_color_image(&mosaic));
+        match image {
+            Ok(image) => {
+                let texture = ctx.load_texture(
+                    format!("picked_{}", frame.frame),
+                    image,
+                    TextureOptions::LINEAR,
+                );
+                self.thumbnails.insert(frame.frame, texture.clone());
+                Some(texture)
+            }
+            Err(e) => {
+                warn!("Миниатюра кадра {} не построена: {}", frame.frame, e);
+                None
+            }
+        }
+    }
+
+    fn show_status(&self, ui: &mut egui::Ui) {
+        let Some(session) = &self.session else {
+            return;
+        };
+        let frame_number = session.frame_number();
+        let mut line = format!(
+            "Кадр {}/{} (#{})",
+            session.position() + 1,
+            session.frames.len(),
+            frame_number
+        );
+        if session.manifest.contains(frame_number) {
+            line.push_str(" [выбран]");
+        }
+        if session.skipped_count() > 0 {
+            line.push_str(&format!(
+                ", пропущено нечитаемых: {}",
+                session.skipped_count()
+            ));
+        }
+        if let Some(view) = &self.view {
+            let size = view.mosaic.size().unwrap_or_default();
+            line.push_str(&format!(", {}", self.display_scale.label(size)));
+        }
+        ui.label(line);
+        ui.label(poses::pose_line(session.current_pose.as_ref()));
+        for line in session.poses.lines() {
+            ui.monospace(line);
+        }
+        for notice in &session.notice {
+            ui.colored_label(egui::Color32::YELLOW, notice);
+        }
+    }
+
+    fn show_result(&mut self, ctx: &egui::Context) {
+        let Some(result) = &self.result else {
+            return;
+        };
+        let mut action = ReviewAction::None;
+        let mut distortion = false;
+        egui::Window::new("Результат калибровки")
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                for line in result.summary() {
+                    ui.label(line);
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Сохранить (Enter)").clicked() {
+                        action = ReviewAction::Accept;
+                    }
+                    if ui.button("Отбросить (Backspace)").clicked() {
+                        action = ReviewAction::Discard;
+                    }
+                    if ui.button("Перепроекция (r)").clicked() {
+                        action = ReviewAction::Reprojection;
+                    }
+                    if ui.button("Дисторсия").clicked() {
+                        distortion = true;
+                    }
+                });
+            });
+        if distortion && let Some(session) = &self.session {
+            // Окно предпросмотра highgui само возвращает решение, но крутит свой цикл
+            // событий, поэтому работает в отдельном потоке
+            let samples = crate::sample_images(&self.args, &session.manifest);
+            let result = result.clone();
+            let worker = std::thread::spawn(move || results::review(&result, &samples));
+            self.job = Some(Job::Distortion { worker });
+            return;
+        }
+        for key in Self::pressed_keys(ctx) {
+            if action == ReviewAction::None {
+                action = ReviewAction::from_egui_key(key);
+            }
+        }
+        self.review(action, ctx);
+    }
+}
+
+impl eframe::App for PickerApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_job(ctx);
+        if self.job.is_some() {
+            self.show_job(ctx);
+            return;
+        }
+        if self.result.is_some() {
+            // Пока открыт результат калибровки, клавиши относятся к нему
+            self.show_result(ctx);
+        } else {
+            for key in Self::pressed_keys(ctx) {
+                self.apply(Action::from_egui_key(key), ctx);
+            }
+        }
+        self.refresh_view(ctx);
+
+        egui::SidePanel::left("controls").show(ctx, |ui| self.show_controls(ui, ctx));
+        egui::SidePanel::right("picked")
+            .default_width(260.0)
+            .show(ctx, |ui| self.show_gallery(ui, ctx));
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| self.show_status(ui));
+        egui::CentralPanel::default().show(ctx, |ui| match &self.texture {
+            Some(texture) => {
+                ui.centered_and_justified(|ui| {
+                    ui.add(egui::Image::from_texture(texture).shrink_to_fit())
+                });
+            }
+            None => {
+                ui.label("Нет кадра");
+            }
+        });
+    }
+}
+
+/// Применяет доску из редактора, при отказе возвращает сообщение для окна. Пока выбраны
+/// кадры, доску менять нельзя: уголки в сохранённых кадрах искались бы уже другой доской
+fn apply_board(
+    draft: &CharucoBoardConfig,
+    board_config: &mut CharucoBoardConfig,
+    charuco_board: &mut CharucoBoard,
+    manifest: &mut PickedManifest,
+) -> Result<(), &'static str> {
+    let differences = board_config.differences(draft);
+    if !manifest.frames.is_empty() && !differences.is_empty() {
+        return Err("Unpick all frames before changing the board");
+    }
+    match draft.to_board() {
+        Ok(board) => {
+            info!("Доска изменена: {}", differences.join(", "));
+            *charuco_board = board;
+            *board_config = draft.clone();
+            manifest.board = Some(draft.clone());
+            Ok(())
+        }
+        Err(e) => {
+            warn!("Доска не применена: {}", e);
+            Err("Invalid board, see log")
+        }
+    }
+}
+
+/// BGR изображение OpenCV как RGB изображение egui
+fn to_color_image(image: &Mat) -> opencv::Result<ColorImage> {
+    let mut rgb = Mat::default();
+    imgproc::cvt_color_def(image, &mut rgb, imgproc::COLOR_BGR2RGB)?;
+    Ok(ColorImage::from_rgb(
+        [rgb.cols() as usize, rgb.rows() as usize],
+        rgb.data_bytes()?,
+    ))
+}
+
+/// Уменьшенные изображения камер выбранного кадра, собранные по --layout. Камеры,
+/// не сохранённые для этого кадра, остаются чёрными
+fn thumbnail_image(picked_dir: &Path, frame: &PickedFrame, args: &Args) -> opencv::Result<Mat> {
+    let images: Vec<Option<Mat>> = (1..=args.layout.cells())
+        .map(|cam| {
+            let name = frame.files.get(&cam)?;
+            let path = picked_dir.join(name);
+            imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)
+                .ok()
+                .filter(|image| !image.empty())
+        })
+        .collect();
+    let Some(first) = images.iter().flatten().next() else {
+        return Err(opencv::Error::new(
+            StsError,
+            "не найдено ни одного изображения камеры".to_string(),
+        ));
+    };
+    let height = (THUMBNAIL_CELL_WIDTH * first.rows() / first.cols().max(1)).max(1);
+    let size = Size::new(THUMBNAIL_CELL_WIDTH, height);
+    let cells = images
+        .iter()
+        .map(|image| match image {
+            Some(image) => {
+                let mut small = Mat::default();
+                imgproc::resize(image, &mut small, size, 0.0, 0.0, imgproc::INTER_AREA)?;
+                Ok(small)
+            }
+            None => Mat::new_size_with_default(size, CV_8UC3, Scalar::all(0.0)),
+        })
+        .collect::<opencv::Result<Vec<Mat>>>()?;
+    combine_grid(&cells, args.layout.cols)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use lib_cv::board::BoardConfig;
+
+    use super::*;
+
+    fn current() -> (CharucoBoardConfig, CharucoBoard) {
+        let config = BoardConfig::default().into_config();
+        let board = config.to_board().unwrap();
+        (config, board)
+    }
+
+    #[test]
+    fn board_applies_without_picked_frames() {
+        let (mut config, mut board) = current();
+        let mut manifest = PickedManifest::default();
+        let draft = CharucoBoardConfig {
+            squares_x: 7,
+            ..config.clone()
+        };
+        apply_board(&draft, &mut config, &mut board, &mut manifest).unwrap();
+        assert_eq!(config, draft);
+        assert_eq!(manifest.board, Some(draft));
+        assert_eq!(board.get_chessboard_size().unwrap(), Size::new(7, 5));
+    }
+
+    #[test]
+    fn board_is_kept_while_frames_are_picked() {
+        let (mut config, mut board) = current();
+        let before = config.clone();
+        let mut manifest = PickedManifest::default();
+        manifest.frames.push(PickedFrame {
+            frame: 1,
+            corners: vec![10, 10],
+            files: BTreeMap::new(),
+            session: None,
+            clip: None,
+            shared_with_first: Vec::new(),
+        });
+        let draft = CharucoBoardConfig {
+            squares_x: 7,
+            ..config.clone()
+        };
+        assert!(apply_board(&draft, &mut config, &mut board, &mut manifest).is_err());
+        assert_eq!(config, before);
+        assert_eq!(board.get_chessboard_size().unwrap(), Size::new(10, 5));
+    }
+
+    #[test]
+    fn invalid_board_is_rejected() {
+        let (mut config, mut board) = current();
+        let before = config.clone();
+        let mut manifest = PickedManifest::default();
+        let draft = CharucoBoardConfig {
+            marker_length: config.square_length * 2.0,
+            ..config.clone()
+        };
+        assert!(apply_board(&draft, &mut config, &mut board, &mut manifest).is_err());
+        assert_eq!(config, before);
+        assert_eq!(manifest.board, None);
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use lib_cv::frame_selection::AutoSelectParams;
+use lib_cv::utils::GridLayout;
+use serde::Deserialize;
+
+use crate::args::Args;
+
+/// Задание пакетной калибровки для --headless. Относительные пути считаются от папки
+/// файла задания, чтобы задание можно было запускать из любой папки
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CalibrationJob {
+    /// Видео или папки с видео, как --video
+    pub videos: Vec<PathBuf>,
+    /// Файл доски board.toml
+    pub board_config: PathBuf,
+    /// Раскладка камер в кадре, например "2x2"
+    pub layout: String,
+    /// Папка результатов: calibration_params.yml и отчёты
+    pub output_dir: PathBuf,
+    /// Папка извлечённых кадров, по умолчанию `output_dir`/parsed
+    #[serde(default)]
+    pub parsed_dir: Option<PathBuf>,
+    /// Папка выбранных кадров, по умолчанию `output_dir`/picked
+    #[serde(default)]
+    pub picked_dir: Option<PathBuf>,
+    pub auto: AutoJob,
+}
+
+/// Автоматический выбор кадров задания; пропущенные пороги берутся по умолчанию
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoJob {
+    /// Сколько кадров выбрать
+    pub count: usize,
+    #[serde(default)]
+    pub min_corners: Option<usize>,
+    #[serde(default)]
+    pub min_sharpness: Option<f64>,
+    #[serde(default)]
+    pub min_pose_distance: Option<f64>,
+}
+
+impl CalibrationJob {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Не удалось прочитать задание {}: {}", path.display(), e))?;
+        let mut job: Self = toml::from_str(&text)
+            .map_err(|e| format!("Некорректное задание {}: {}", path.display(), e))?;
+        if job.videos.is_empty() {
+            return Err(format!("В задании {} не указаны видео", path.display()));
+        }
+        if job.auto.count == 0 {
+            return Err(format!(
+                "В задании {} auto.count должно быть больше 0",
+                path.display()
+            ));
+        }
+        let base = path.parent().unwrap_or(Path::new(""));
+        for video in &mut job.videos {
+            *video = base.join(&*video);
+        }
+        job.board_config = base.join(&job.board_config);
+        job.output_dir = base.join(&job.output_dir);
+        job.parsed_dir = job.parsed_dir.map(|dir| base.join(dir));
+        job.picked_dir = job.picked_dir.map(|dir| base.join(dir));
+        Ok(job)
+    }
+
+    /// Переносит задание в параметры запуска: дальше работает обычный режим --auto
+    pub fn apply(&self, args: &mut Args) -> Result<(), String> {
+        let defaults = AutoSelectParams::default();
+        args.layout = self
+            .layout
+            .parse::<GridLayout>()
+            .map_err(|e| format!("Задание: {}", e))?;
+        args.video = self.videos.clone();
+        args.board_config = Some(self.board_config.clone());
+        args.output_dir = self.output_dir.clone();
+        args.parsed_dir = self
+            .parsed_dir
+            .clone()
+            .unwrap_or_else(|| self.output_dir.join("parsed"));
+        args.picked_dir = self
+            .picked_dir
+            .clone()
+            .unwrap_or_else(|| self.output_dir.join("picked"));
+        args.auto = Some(self.auto.count);
+        args.min_corners = self.auto.min_corners.unwrap_or(defaults.min_corners);
+        args.auto_min_sharpness = self.auto.min_sharpness.unwrap_or(defaults.min_sharpness);
+        args.auto_min_pose_distance = self
+            .auto
+            .min_pose_distance
+            .unwrap_or(defaults.min_pose_distance);
+        Ok(())
+    }
+}
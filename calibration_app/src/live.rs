@@ -0,0 +1,392 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use lib_cv::board::CharucoBoardConfig;
+use lib_cv::utils::{PickedManifest, annotate, split_image_into_grid};
+use log::{error, info, warn};
+use opencv::core::{Mat, StsError, Vector};
+use opencv::highgui;
+use opencv::imgcodecs;
+use opencv::objdetect::CharucoBoard;
+use opencv::prelude::*;
+use opencv::videoio::{CAP_ANY, CAP_GSTREAMER, VideoCapture};
+
+use crate::args::Args;
+use crate::frame_view::{FrameView, check_dictionary, render_quadrants};
+use crate::navigation::Action;
+use crate::picking;
+use crate::poses;
+
+const WINDOW_NAME: &str = "Камеры";
+/// Пауза цикла предпросмотра в ожидании клавиши, мс
+const PREVIEW_WAIT_MS: i32 = 10;
+/// Пауза потока захвата после неудачного чтения
+const RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// Источник кадров режима --live: номер устройства или конвейер GStreamer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiveSource {
+    Device(i32),
+    Pipeline(String),
+}
+
+impl LiveSource {
+    /// Число считается номером устройства, всё остальное - конвейером GStreamer
+    pub fn parse(source: &str) -> Self {
+        match source.trim().parse() {
+            Ok(index) => Self::Device(index),
+            Err(_) => Self::Pipeline(source.to_string()),
+        }
+    }
+
+    fn open(&self) -> opencv::Result<VideoCapture> {
+        let capture = match self {
+            Self::Device(index) => VideoCapture::new(*index, CAP_ANY)?,
+            Self::Pipeline(pipeline) => VideoCapture::from_file(pipeline, CAP_GSTREAMER)?,
+        };
+        if !capture.is_opened()? {
+            return Err(opencv::Error::new(
+                StsError,
+                format!("Не удалось открыть {}", self),
+            ));
+        }
+        Ok(capture)
+    }
+}
+
+impl fmt::Display for LiveSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Device(index) => write!(f, "устройство {}", index),
+            Self::Pipeline(pipeline) => write!(f, "конвейер \"{}\"", pipeline),
+        }
+    }
+}
+
+/// Набор кадров всех устройств, снятых одновременно
+#[derive(Clone)]
+struct FrameSet {
+    frames: Vec<Mat>,
+    /// Порядковый номер набора: по нему предпросмотр узнаёт о новых кадрах
+    number: u64,
+}
+
+/// Захват кадров всех устройств в отдельном потоке, чтобы поиск доски не тормозил чтение
+/// камер. Кадры всех устройств сначала захватываются (`grab`) подряд и только потом
+/// декодируются (`retrieve`): так моменты съёмки разных камер максимально близки.
+/// Хранится только последний набор, старые наборы вытесняются
+struct LiveGrabber {
+    latest: Arc<Mutex<Option<FrameSet>>>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl LiveGrabber {
+    fn start(sources: &[LiveSource]) -> Result<Self, String> {
+        let captures = sources
+            .iter()
+            .map(|source| {
+                info!("Открываю {}", source);
+                source.open().map_err(|e| format!("{}: {}", source, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let latest = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let latest = Arc::clone(&latest);
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || grab_loop(captures, &latest, &stop))
+        };
+        Ok(Self {
+            latest,
+            stop,
+            worker: Some(worker),
+        })
+    }
+
+    /// Последний набор кадров, если он новее набора номер `after`
+    fn latest_after(&self, after: u64) -> Option<FrameSet> {
+        self.latest
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|set| set.number > after)
+            .cloned()
+    }
+
+    fn is_running(&self) -> bool {
+        self.worker.as_ref().is_some_and(|w| !w.is_finished())
+    }
+}
+
+impl Drop for LiveGrabber {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn grab_loop(mut captures: Vec<VideoCapture>, latest: &Mutex<Option<FrameSet>>, stop: &AtomicBool) {
+    let mut number = 0;
+    let mut failing = false;
+    while !stop.load(Ordering::Relaxed) {
+        // Сначала только захват на всех устройствах, декодирование - потом
+        let grabbed: Vec<bool> = captures
+            .iter_mut()
+            .map(|capture| capture.grab().unwrap_or(false))
+            .collect();
+        let mut frames = Vec::with_capacity(captures.len());
+        if grabbed.iter().all(|&ok| ok) {
+            for capture in &mut captures {
+                let mut frame = Mat::default();
+                if !capture.retrieve(&mut frame, 0).unwrap_or(false) || frame.empty() {
+                    break;
+                }
+                frames.push(frame);
+            }
+        }
+
+        if frames.len() != captures.len() {
+            if !failing {
+                warn!("Не удалось прочитать кадр одной из камер, повторяю");
+                failing = true;
+            }
+            std::thread::sleep(RETRY_DELAY);
+            continue;
+        }
+        if failing {
+            info!("Чтение камер восстановлено");
+            failing = false;
+        }
+        number += 1;
+        *latest.lock().unwrap() = Some(FrameSet { frames, number });
+    }
+}
+
+/// Делит набор кадров на изображения камер: кадр единственного устройства - по ячейкам
+/// --layout, иначе каждое устройство - отдельная камера
+fn split_cameras(frames: &[Mat], args: &Args) -> opencv::Result<Vec<Mat>> {
+    match frames {
+        [single] => split_image_into_grid(single, &args.layout),
+        _ => Ok(frames.to_vec()),
+    }
+}
+
+fn render_set(
+    set: &FrameSet,
+    args: &Args,
+    charuco_board: &CharucoBoard,
+) -> Result<FrameView, String> {
+    let cameras = split_cameras(&set.frames, args)
+        .map_err(|e| format!("не получилось разбить изображение: {}", e))?;
+    render_quadrants(
+        charuco_board,
+        cameras,
+        &args.layout,
+        &args.corner_thresholds(),
+        args.contrast_enhancement().as_ref(),
+    )
+}
+
+/// Выбор кадров с подключённых камер: непрерывный предпросмотр с разметкой доски,
+/// пробел сохраняет свежий набор кадров всех камер в --picked-dir под следующим
+/// свободным номером кадра. Esc калибрует так же, как при выборе из видео
+pub fn run(
+    args: &Args,
+    charuco_board: &CharucoBoard,
+    board_config: &CharucoBoardConfig,
+    sources: &[LiveSource],
+    mut manifest: PickedManifest,
+) -> Result<(), String> {
+    let grabber = LiveGrabber::start(sources)?;
+    highgui::named_window(WINDOW_NAME, highgui::WINDOW_KEEPRATIO).unwrap();
+
+    let thresholds = args.corner_thresholds();
+    // Номера кадров продолжают прежние, чтобы не перезаписать выбранные ранее
+    let mut next_frame = manifest
+        .frames
+        .iter()
+        .map(|f| f.frame + 1)
+        .max()
+        .unwrap_or(0);
+    let mut shown = 0;
+    let mut view: Option<FrameView> = None;
+    let mut display_scale = args.display_scale;
+    let mut poses = args.pose_tally();
+    let mut current_pose = None;
+    let mut notice: Vec<String> = Vec::new();
+    let mut picked_this_session = 0usize;
+    let mut confirm_existing = false;
+    loop {
+        if !grabber.is_running() {
+            return Err("Поток захвата кадров остановился".to_string());
+        }
+        if let Some(set) = grabber.latest_after(shown) {
+            if shown == 0
+                && let Some(first) = set.frames.first()
+            {
+                notice.extend(check_dictionary(first, board_config));
+            }
+            shown = set.number;
+            match render_set(&set, args, charuco_board) {
+                Ok(rendered) => {
+                    current_pose = poses.estimate(&rendered, charuco_board, thresholds.min_corners);
+                    view = Some(rendered);
+                }
+                Err(e) => warn!("Кадр камер не показан: {}", e),
+            }
+        }
+        let Some(current) = &view else {
+            // Первый набор кадров ещё не получен
+            highgui::wait_key(PREVIEW_WAIT_MS).unwrap();
+            continue;
+        };
+
+        // Подписи рисуются после уменьшения, чтобы оставаться читаемыми
+        let mosaic_size = current.mosaic.size().unwrap_or_default();
+        let mut display = match display_scale.apply(&current.mosaic) {
+            Ok(display) => display,
+            Err(e) => {
+                // Этот набор кадров пропускается, показ продолжится со следующего
+                warn!("Кадр камер не показан: {}", e);
+                view = None;
+                highgui::wait_key(PREVIEW_WAIT_MS).unwrap();
+                continue;
+            }
+        };
+        let mut overlay = vec![
+            format!(
+                "Live, next frame #{}, {}",
+                next_frame,
+                display_scale.label(mosaic_size)
+            ),
+            format!("Picked: {}", manifest.frames.len()),
+        ];
+        overlay.push(picking::scene_status(
+            &manifest,
+            args.layout.cells(),
+            &args.scene_limits(),
+        ));
+        overlay.extend(picking::pair_status(&manifest, args.layout.cells()));
+        overlay.push(poses::pose_line(current_pose.as_ref()));
+        overlay.extend(poses.lines());
+        overlay.extend(notice.iter().cloned());
+        if let Err(e) = annotate(&mut display, &overlay) {
+            warn!("Не удалось подписать кадр: {}", e);
+        }
+        highgui::imshow(WINDOW_NAME, &display).unwrap();
+
+        let key = highgui::wait_key_ex(PREVIEW_WAIT_MS).unwrap();
+        let action = Action::from_key(key);
+        if action == Action::None {
+            continue;
+        }
+        if let Action::Zoom(steps) = action {
+            display_scale = display_scale.zoomed(steps, mosaic_size);
+            continue;
+        }
+        // Сообщение показывается до следующего нажатия, подтверждение - только для него
+        notice.clear();
+        let confirmed = std::mem::take(&mut confirm_existing);
+        match action {
+            Action::SavePicked => {
+                // Сохраняется самый свежий набор, а не показанный: он ближе к моменту нажатия
+                let saved = match grabber.latest_after(0) {
+                    Some(set) => render_set(&set, args, charuco_board),
+                    None => Err("нет кадров камер".to_string()),
+                };
+                let saved = match saved {
+                    Ok(saved) => saved,
+                    Err(e) => {
+                        warn!("Кадр не сохранён: {}", e);
+                        notice.push("Nothing saved, see log".to_string());
+                        continue;
+                    }
+                };
+                let corners: Vec<usize> = saved.detections.iter().map(|d| d.corners).collect();
+                let shared: Vec<usize> = saved
+                    .detections
+                    .iter()
+                    .map(|d| d.shared_with_first)
+                    .collect();
+                match picking::save_picked(
+                    &args.picked_dir,
+                    &mut manifest,
+                    next_frame,
+                    &saved.quadrants,
+                    &corners,
+                    &shared,
+                    thresholds.min_corners,
+                ) {
+                    Ok(skipped_cameras) => {
+                        info!("Снимок камер сохранён как кадр {}", next_frame);
+                        poses.record(
+                            next_frame,
+                            poses.estimate(&saved, charuco_board, thresholds.min_corners),
+                        );
+                        notice.push(format!("Saved frame #{}", next_frame));
+                        if !skipped_cameras.is_empty() {
+                            notice.push(format!("Saved without cams {:?}", skipped_cameras));
+                            notice.extend(saved.weak_cameras(&thresholds));
+                        }
+                        next_frame += 1;
+                        picked_this_session += 1;
+                    }
+                    Err(e) => {
+                        warn!("{}", e);
+                        notice.push("Nothing saved, see log".to_string());
+                    }
+                }
+            }
+            Action::UndoPicked => match picking::undo_last_pick(&args.picked_dir, &mut manifest) {
+                Ok(Some(removed)) => {
+                    picked_this_session = picked_this_session.saturating_sub(1);
+                    poses.forget(removed.frame);
+                    notice.push(format!("Removed picked frame #{}", removed.frame))
+                }
+                Ok(None) => notice.push("No picked frames to remove".to_string()),
+                Err(e) => warn!("Не удалось удалить выбранный кадр: {}", e),
+            },
+            Action::SaveMosaic => {
+                let path = args.picked_dir.join(format!("combined_live_{}.png", shown));
+                match imgcodecs::imwrite(&path.to_string_lossy(), &current.mosaic, &Vector::new()) {
+                    Ok(true) => info!("Мозаика камер сохранена в {}", path.display()),
+                    Ok(false) => error!("Не удалось сохранить {}", path.display()),
+                    Err(e) => error!("Не удалось сохранить {}: {}", path.display(), e),
+                }
+            }
+            Action::Finish => {
+                if !crate::ready_to_calibrate(
+                    &manifest,
+                    args.layout.cells(),
+                    &args.scene_limits(),
+                    picked_this_session,
+                    confirmed,
+                    &mut confirm_existing,
+                    &mut notice,
+                ) {
+                    continue;
+                }
+                let _ = highgui::destroy_window(WINDOW_NAME);
+                if crate::calibrate_and_review(
+                    args,
+                    charuco_board,
+                    board_config,
+                    &mut manifest,
+                    &mut notice,
+                ) {
+                    return Ok(());
+                }
+                highgui::named_window(WINDOW_NAME, highgui::WINDOW_KEEPRATIO).unwrap();
+            }
+            Action::Quit => return Ok(()),
+            Action::Move(_) | Action::GoTo | Action::Zoom(_) | Action::None => {}
+        }
+    }
+}
@@ -1,240 +1,715 @@
-use std::path::Path;
+mod args;
+mod auto;
+mod frame_view;
+mod frames;
+mod gui;
+mod job;
+mod live;
+mod navigation;
+mod picking;
+mod poses;
+mod progress;
+mod reprojection;
+mod results;
+mod review_video;
+mod session;
 
-use lib_cv::calibration::{get_charuco, perform_calibration};
-use lib_cv::utils::{combine_quadrants, split_image_into_quadrants, video_to_frames};
-use log::info;
-use opencv::core::{Scalar, Vector};
-use opencv::highgui;
-use opencv::imgcodecs;
-use opencv::objdetect::{draw_detected_corners_charuco, draw_detected_markers};
+use std::path::{Path, PathBuf};
+
+use args::Args;
+use clap::Parser;
+use frame_view::check_dictionary;
+use frames::FrameStore;
+use job::CalibrationJob;
+use lib_cv::board::{BOARD_CONFIG_FILE, BoardConfig, CharucoBoardConfig};
+use lib_cv::calibration::{CalibrationResult, SceneCoverage, SceneLimits, calibrate_picked_images};
+use lib_cv::utils::{
+    FrameListing, PickedManifest, UtilsError, datetime_stamp, extract_frames_if_needed, list_frames,
+};
+use log::{info, warn};
+use navigation::ReviewAction;
+use opencv::objdetect::CharucoBoard;
+use progress::ProgressReporter;
+use session::PickerSession;
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    const PICKED_IMAGE_PATH: &str =
-        "/home/watermelon0guy/Изображения/Experiments/raspberry_pi_cardboard/calibration/picked";
-    const PARSED_IMAGE_PATH: &str =
-        "/home/watermelon0guy/Изображения/Experiments/raspberry_pi_cardboard/calibration/parsed";
-    const VIDEO_PATH: &str =
-        "/home/watermelon0guy/Видео/Experiments/raspberry_pi_cardboard/20250603_113751_hires.mp4";
-    const CAMERAS_PARAMS_PATH: &str =
-        "/home/watermelon0guy/Изображения/Experiments/raspberry_pi_cardboard/calibration";
-    highgui::named_window("Charuco Доска", highgui::WINDOW_KEEPRATIO).unwrap();
-
-    video_to_frames(Path::new(VIDEO_PATH), Path::new(PARSED_IMAGE_PATH)).unwrap();
-
-    let dictionary = opencv::objdetect::get_predefined_dictionary(
-        opencv::objdetect::PredefinedDictionaryType::DICT_4X4_50,
-    )
-    .unwrap();
-    let charuco_board = opencv::objdetect::CharucoBoard::new_def(
-        opencv::core::Size::new(10, 5),
-        13.0,
-        9.1,
-        &dictionary,
-    )
-    .unwrap();
-
-    let mut current_i = 0;
-    loop {
-        let current_frame = match imgcodecs::imread(
-            &format!("{}/{}.png", PARSED_IMAGE_PATH, current_i),
-            imgcodecs::IMREAD_COLOR,
-        ) {
-            Ok(frame) => frame,
-            Err(_) => {
-                eprintln!("Не получилось считать кадр");
-                continue;
+    let mut args = Args::parse();
+    if args.headless {
+        if let Err(e) = run_headless(args) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    args.session.get_or_insert_with(datetime_stamp);
+    if let Err(e) = args.prepare_dirs() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let board_config = match args.board_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("Параметры запуска:\n{}", args.summary(&board_config));
+    warn_if_board_changed(&args.output_dir, &board_config);
+
+    let charuco_board = match BoardConfig::from(board_config.clone()).build() {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("Не удалось построить доску: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let live_sources = match args.live_sources() {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if !live_sources.is_empty() {
+        let manifest = match picking::load_manifest(&args.picked_dir, &board_config, args.session())
+        {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         };
+        if let Err(e) = live::run(
+            &args,
+            &charuco_board,
+            &board_config,
+            &live_sources,
+            manifest,
+        ) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Без --live видео обязательно: это проверяет разбор аргументов
+    let videos = match args.videos() {
+        Ok(videos) if !videos.is_empty() => videos,
+        Ok(_) => {
+            eprintln!("Не задано видео --video");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if videos.len() > 1 && args.review_video.is_some() {
+        eprintln!(
+            "--review-video размечает одну запись, а задано {}",
+            videos.len()
+        );
+        std::process::exit(1);
+    }
 
-        let Ok(quadrants) = split_image_into_quadrants(&current_frame) else {
-            eprintln!("Не получилось разбить изображение");
-            continue;
+    let mut clips = Vec::with_capacity(videos.len());
+    for (clip_i, video) in videos.iter().enumerate() {
+        let frames = if args.seek_frames() {
+            info!(
+                "Кадры декодируются из {} по запросу, без извлечения",
+                video.display()
+            );
+            match FrameStore::open_video(video) {
+                Ok(frames) => frames,
+                Err(e) => {
+                    eprintln!("Не удалось открыть видео {}: {}", video.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            let parsed_dir = args.clip_parsed_dir(video, clip_i, videos.len());
+            let listing = match extracted_listing(&args, video, &parsed_dir) {
+                Ok(Some(listing)) => listing,
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Some(output) = &args.review_video {
+                if let Err(e) = review_video::run(&args, &charuco_board, &listing, video, output) {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            FrameStore::Extracted(listing)
         };
+        clips.push(frames);
+    }
+    let mut frames = FrameStore::from_clips(clips);
+    let clip_names = clip_names(&videos);
 
-        let img_1 = quadrants[0].clone();
-        let img_2 = quadrants[1].clone();
-        let img_3 = quadrants[2].clone();
-        let img_4 = quadrants[3].clone();
-
-        let Ok((
-            marker_corners_1,
-            marker_ids_1,
-            charuco_corners_1,
-            charuco_ids_1,
-            _obj_points_1,
-            _img_points_1,
-        )) = get_charuco(&charuco_board, &img_1)
-        else {
-            eprintln!("Ошибка при извлечении Charuco углов");
-            continue;
-        };
-        let mut edited_1 = img_1.clone();
-        draw_detected_markers(
-            &mut edited_1,
-            &marker_corners_1,
-            &marker_ids_1,
-            Scalar::new(255.0, 0.0, 0.0, 255.0),
-        )
-        .expect("Не получилось нарисовать маркеры");
-        draw_detected_corners_charuco(
-            &mut edited_1,
-            &charuco_corners_1,
-            &charuco_ids_1,
-            Scalar::new(0.0, 255.0, 0.0, 255.0),
+    if let Some(params) = args.auto_select_params() {
+        if let Err(e) = auto::run(
+            &args,
+            &charuco_board,
+            &board_config,
+            &mut frames,
+            &clip_names,
+            &params,
+        ) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        save_calibration_board(&args.output_dir, &board_config);
+        return;
+    }
+
+    let mut manifest = match picking::load_manifest(&args.picked_dir, &board_config, args.session())
+    {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    manifest.clips = clip_names;
+
+    let dictionary_warning = frames
+        .read(0)
+        .ok()
+        .and_then(|frame| check_dictionary(&frame, &board_config));
+    let mut session = PickerSession::new(
+        frames,
+        manifest,
+        args.picked_dir.clone(),
+        args.layout,
+        args.corner_thresholds(),
+        args.contrast_enhancement(),
+        args.pose_tally(),
+    );
+    session.notice.extend(dictionary_warning);
+    if let Err(e) = gui::run(args, charuco_board, board_config, session) {
+        eprintln!("Не удалось открыть окно: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Пакетный режим --headless: все параметры берутся из задания --config, окна
+/// не создаются. Извлекает кадры, выбирает лучшие, калибрует и сохраняет отчёты.
+/// Любая ошибка возвращается, чтобы программа завершилась с ненулевым кодом
+fn run_headless(mut args: Args) -> Result<(), String> {
+    let config = args
+        .config
+        .clone()
+        .ok_or_else(|| "Для --headless нужно задание --config".to_string())?;
+    CalibrationJob::load(&config)?.apply(&mut args)?;
+    args.session.get_or_insert_with(datetime_stamp);
+    args.prepare_dirs()?;
+    let board_config = args.board_config()?;
+    info!("Параметры запуска:\n{}", args.summary(&board_config));
+    warn_if_board_changed(&args.output_dir, &board_config);
+    let charuco_board = BoardConfig::from(board_config.clone())
+        .build()
+        .map_err(|e| format!("Не удалось построить доску: {}", e))?;
+
+    let videos = args.videos()?;
+    let mut clips = Vec::with_capacity(videos.len());
+    for (clip_i, video) in videos.iter().enumerate() {
+        let parsed_dir = args.clip_parsed_dir(video, clip_i, videos.len());
+        let listing = extracted_listing(&args, video, &parsed_dir)?
+            .ok_or_else(|| format!("Кадры {} не извлечены", video.display()))?;
+        clips.push(FrameStore::Extracted(listing));
+    }
+    if clips.is_empty() {
+        return Err("В задании нет видео".to_string());
+    }
+    let mut frames = FrameStore::from_clips(clips);
+    let params = args
+        .auto_select_params()
+        .ok_or_else(|| "В задании не заданы параметры автовыбора".to_string())?;
+    auto::run(
+        &args,
+        &charuco_board,
+        &board_config,
+        &mut frames,
+        &clip_names(&videos),
+        &params,
+    )?;
+    save_calibration_board(&args.output_dir, &board_config);
+    Ok(())
+}
+
+/// Имена записей для манифеста; у единственной записи имя не нужно
+fn clip_names(videos: &[PathBuf]) -> Vec<String> {
+    if videos.len() > 1 {
+        videos
+            .iter()
+            .map(|video| video.display().to_string())
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Извлекает кадры видео в `parsed_dir`, если их там ещё нет, и перечисляет их.
+/// `None` - извлечение отменено или кадров нет (причина уже выведена)
+fn extracted_listing(
+    args: &Args,
+    video: &Path,
+    parsed_dir: &Path,
+) -> Result<Option<FrameListing>, String> {
+    // Подпапки записей при нескольких видео создаются здесь, а не в prepare_dirs
+    std::fs::create_dir_all(parsed_dir)
+        .map_err(|e| format!("Не удалось создать папку {}: {}", parsed_dir.display(), e))?;
+    // Без окна (автоматический режим) прогресс печатается в терминал
+    let headless = args.auto_select_params().is_some() || args.review_video.is_some();
+    let extract = |reporter: &ProgressReporter| {
+        extract_frames_if_needed(
+            video,
+            parsed_dir,
+            args.force_reparse,
+            &mut reporter.frames(),
         )
-        .expect("Не получилось нарисовать на изображении углы Charuco");
-
-        let Ok((
-            marker_corners_2,
-            marker_ids_2,
-            charuco_corners_2,
-            charuco_ids_2,
-            _obj_points_2,
-            _img_points_2,
-        )) = get_charuco(&charuco_board, &img_2)
-        else {
-            eprintln!("Ошибка при извлечении Charuco углов");
-            continue;
+    };
+    let extracted = if headless {
+        let reporter = ProgressReporter::text();
+        let extracted = extract(&reporter);
+        reporter.finish();
+        extracted
+    } else {
+        progress::run_in_window(extract)
+    };
+    match extracted {
+        Ok(_) => {}
+        Err(UtilsError::Cancelled) => {
+            eprintln!("Извлечение кадров отменено, при следующем запуске оно начнётся заново");
+            return Ok(None);
+        }
+        Err(e) => return Err(e.to_string()),
+    }
+
+    let listing = list_frames(parsed_dir, "", "png").map_err(|e| e.to_string())?;
+    if listing.frames.is_empty() {
+        eprintln!("В {} нет извлечённых кадров", parsed_dir.display());
+        return Ok(None);
+    }
+    if !listing.gaps.is_empty() {
+        warn!(
+            "Пропущено {} кадров: {:?}",
+            listing.gaps.len(),
+            listing.gaps
+        );
+    }
+
+    Ok(Some(listing))
+}
+
+/// Проверяет, можно ли калибровать по выбранным кадрам. Если в этом запуске ничего
+/// не выбрано, калибровка по кадрам прошлых запусков начинается только после повторного
+/// Esc (`confirmed`); первое нажатие взводит `confirm_existing`. Калибровка не начинается,
+/// пока сцен меньше, чем требуют `limits`
+fn ready_to_calibrate(
+    manifest: &PickedManifest,
+    cameras: usize,
+    limits: &SceneLimits,
+    picked_this_session: usize,
+    confirmed: bool,
+    confirm_existing: &mut bool,
+    notice: &mut Vec<String>,
+) -> bool {
+    if manifest.frames.is_empty() {
+        notice.push("No picked frames, nothing to calibrate (q - quit)".to_string());
+        return false;
+    }
+    let coverage = SceneCoverage::from_manifest(manifest, cameras);
+    if !limits.satisfied_by(&coverage) {
+        let short: Vec<String> = limits
+            .short_cameras(&coverage)
+            .iter()
+            .map(|(cam, count)| format!("cam {}: {}", cam, count))
+            .collect();
+        if !short.is_empty() {
+            notice.push(format!(
+                "Too few scenes: {} (need {})",
+                short.join(", "),
+                limits.min_per_camera
+            ));
+        }
+        if coverage.complete < limits.min_complete {
+            notice.push(format!(
+                "Only {} scenes with all cameras (need {})",
+                coverage.complete, limits.min_complete
+            ));
+        }
+        return false;
+    }
+    if picked_this_session == 0 && !confirmed {
+        // В папке могут быть кадры прошлого запуска, снятые совсем в других условиях
+        *confirm_existing = true;
+        notice.push(format!(
+            "No frames picked this session. Esc again - calibrate with {} existing frames",
+            manifest.frames.len()
+        ));
+        return false;
+    }
+    true
+}
+
+/// Калибрует по выбранным кадрам и показывает результат. Возвращает `true`, если работа
+/// закончена (результат принят или пользователь вышел), и `false`, если нужно вернуться
+/// к выбору кадров; причина возврата добавляется в `notice`
+fn calibrate_and_review(
+    args: &Args,
+    charuco_board: &CharucoBoard,
+    board_config: &CharucoBoardConfig,
+    manifest: &mut PickedManifest,
+    notice: &mut Vec<String>,
+) -> bool {
+    picking::warn_weak_pairs(manifest, args.layout.cells(), args.min_pair_frames);
+    let Some(result) = calibrate(args, charuco_board, board_config) else {
+        notice.push("Calibration failed or cancelled, see log".to_string());
+        return false;
+    };
+    let decision = loop {
+        let samples = sample_images(args, manifest);
+        let action = match results::review(&result, &samples) {
+            Ok(action) => action,
+            Err(e) => {
+                warn!("Окно результатов калибровки не показано: {}", e);
+                notice.push("Results window failed, see log".to_string());
+                break ReviewAction::None;
+            }
         };
-        let mut edited_2 = img_2.clone();
-        draw_detected_markers(
-            &mut edited_2,
-            &marker_corners_2,
-            &marker_ids_2,
-            Scalar::new(255.0, 0.0, 0.0, 255.0),
-        )
-        .expect("Не получилось нарисовать маркеры");
-        draw_detected_corners_charuco(
-            &mut edited_2,
-            &charuco_corners_2,
-            &charuco_ids_2,
-            Scalar::new(0.0, 255.0, 0.0, 255.0),
+        match action {
+            ReviewAction::Reprojection => {
+                let removed = reprojection::browse(
+                    &result,
+                    charuco_board,
+                    &args.picked_dir,
+                    manifest,
+                    &args.layout,
+                    args.max_view_error,
+                    &args.output_dir.join(reprojection::REVIEW_DIR),
+                );
+                // Без удалённых сцен результат устарел: нужна повторная калибровка
+                if removed > 0 {
+                    notice.push(format!("Removed {} scenes, Esc - recalibrate", removed));
+                    break ReviewAction::None;
+                }
+            }
+            action => break action,
+        }
+    };
+    match decision {
+        ReviewAction::Accept => {
+            accept_calibration(&args.output_dir, &result, board_config);
+            true
+        }
+        ReviewAction::Discard => {
+            info!("Результат калибровки отброшен");
+            notice.push("Calibration discarded, pick more frames".to_string());
+            false
+        }
+        ReviewAction::None => false,
+        ReviewAction::Quit | ReviewAction::Reprojection => true,
+    }
+}
+
+/// Калибрует по выбранным кадрам в рабочем потоке, показывая прогресс. Ничего не сохраняет
+fn calibrate(
+    args: &Args,
+    charuco_board: &CharucoBoard,
+    board_config: &CharucoBoardConfig,
+) -> Option<CalibrationResult> {
+    // Доска не Sync, поэтому в рабочий поток переходит её копия
+    let board = charuco_board.clone();
+    let params = args.picked_calibration_params(board_config);
+    progress::run_in_window(move |reporter| {
+        calibrate_picked_images(
+            &args.picked_dir,
+            &board,
+            args.layout.cells(),
+            &params,
+            &mut reporter.calibration(args.layout.cells()),
         )
-        .expect("Не получилось нарисовать на изображении углы Charuco");
-
-        let Ok((
-            marker_corners_3,
-            marker_ids_3,
-            charuco_corners_3,
-            charuco_ids_3,
-            _obj_points_3,
-            _img_points_3,
-        )) = get_charuco(&charuco_board, &img_3)
-        else {
-            eprintln!("Ошибка при извлечении Charuco углов");
-            continue;
-        };
-        let mut edited_3 = img_3.clone();
-        draw_detected_markers(
-            &mut edited_3,
-            &marker_corners_3,
-            &marker_ids_3,
-            Scalar::new(255.0, 0.0, 0.0, 255.0),
+    })
+}
+
+/// Последний выбранный кадр каждой камеры для предпросмотра устранения дисторсии
+fn sample_images(args: &Args, manifest: &PickedManifest) -> Vec<Option<PathBuf>> {
+    (1..=args.layout.cells())
+        .map(|cam| {
+            manifest
+                .frames
+                .iter()
+                .rev()
+                .find_map(|frame| frame.files.get(&cam))
+                .map(|name| args.picked_dir.join(name))
+        })
+        .collect()
+}
+
+/// Сохраняет принятую калибровку: параметры камер, отчёт и доску
+fn accept_calibration(
+    output_dir: &Path,
+    result: &CalibrationResult,
+    board_config: &CharucoBoardConfig,
+) {
+    if let Err(e) = result.save(output_dir) {
+        eprintln!("Ошибка при сохранении параметров: {}", e);
+        return;
+    }
+    if let Err(e) = results::save_report(output_dir, result) {
+        warn!("Не удалось сохранить отчёт о калибровке: {}", e);
+    }
+    if let Err(e) = results::save_json_report(output_dir, result) {
+        warn!("Не удалось сохранить отчёт о калибровке в JSON: {}", e);
+    }
+    save_calibration_board(output_dir, board_config);
+    info!("Калибровка сохранена в {}", output_dir.display());
+}
+
+/// Предупреждает, если прежняя калибровка в `output_dir` сделана с другой доской:
+/// её параметры будут перезаписаны, а реконструкция с ними получит неверный масштаб
+fn warn_if_board_changed(output_dir: &Path, board_config: &CharucoBoardConfig) {
+    let path = output_dir.join(BOARD_CONFIG_FILE);
+    match CharucoBoardConfig::load(&path) {
+        Ok(previous) => {
+            let differences = previous.differences(board_config);
+            if !differences.is_empty() {
+                warn!(
+                    "ВНИМАНИЕ: прежняя калибровка в {} сделана с другой доской: {}",
+                    output_dir.display(),
+                    differences.join(", ")
+                );
+            }
+        }
+        Err(e) if path.exists() => warn!("Не удалось прочитать {}: {}", path.display(), e),
+        Err(_) => {}
+    }
+}
+
+/// Сохраняет доску рядом с calibration_params.yml, чтобы реконструкция могла её проверить
+fn save_calibration_board(output_dir: &Path, board_config: &CharucoBoardConfig) {
+    if let Err(e) = board_config.save(output_dir.join(BOARD_CONFIG_FILE)) {
+        warn!("Не удалось сохранить параметры доски: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_cv::calibration::CALIBRATION_PARAMS_FILE;
+    use lib_cv::utils::combine_grid;
+    use opencv::core::{BORDER_CONSTANT, Mat, Scalar, Size};
+    use opencv::prelude::*;
+    use opencv::videoio::VideoWriter;
+
+    /// Размер кадра одной камеры, пикс.
+    const CELL: (i32, i32) = (320, 240);
+    /// Пикселей на квадрат изображения доски
+    const SQUARE_PX: i32 = 100;
+
+    fn rotation(x_degrees: f64, y_degrees: f64) -> [[f64; 3]; 3] {
+        let (sx, cx) = x_degrees.to_radians().sin_cos();
+        let (sy, cy) = y_degrees.to_radians().sin_cos();
+        // R = Ry · Rx
+        [
+            [cy, sy * sx, sy * cx],
+            [0.0, cx, -sx],
+            [-sy, cy * sx, cy * cx],
+        ]
+    }
+
+    fn mul(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+        let mut c = [[0.0; 3]; 3];
+        for (row, a_row) in c.iter_mut().zip(a) {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| a_row[k] * b[k][j]).sum();
+            }
+        }
+        c
+    }
+
+    fn apply(r: &[[f64; 3]; 3], t: [f64; 3]) -> [f64; 3] {
+        let mut out = [0.0; 3];
+        for (value, row) in out.iter_mut().zip(r) {
+            *value = row.iter().zip(t).map(|(a, b)| a * b).sum();
+        }
+        out
+    }
+
+    /// Кадр камеры `cam` (четыре камеры в ряд с шагом 20 мм, слегка повёрнутые к центру),
+    /// на котором доска стоит в позе `board_r`, `board_t` относительно камеры 0
+    fn render_camera(
+        board_image: &Mat,
+        square_length: f64,
+        cam: usize,
+        board_r: &[[f64; 3]; 3],
+        board_t: [f64; 3],
+    ) -> Mat {
+        let cam_r = rotation(0.0, -3.0 * cam as f64);
+        let r = mul(&cam_r, board_r);
+        let rotated = apply(&cam_r, board_t);
+        let t = [rotated[0] - 20.0 * cam as f64, rotated[1], rotated[2]];
+
+        // H = K · [r1 r2 t] · S: пиксель изображения доски -> пиксель кадра камеры
+        let k = [[400.0, 0.0, 160.0], [0.0, 400.0, 120.0], [0.0, 0.0, 1.0]];
+        let scale = square_length / SQUARE_PX as f64;
+        let pose = [
+            [r[0][0] * scale, r[0][1] * scale, t[0]],
+            [r[1][0] * scale, r[1][1] * scale, t[1]],
+            [r[2][0] * scale, r[2][1] * scale, t[2]],
+        ];
+        let h = Mat::from_slice_2d(&mul(&k, &pose)).unwrap();
+        let mut cell = Mat::default();
+        opencv::imgproc::warp_perspective(
+            board_image,
+            &mut cell,
+            &h,
+            Size::new(CELL.0, CELL.1),
+            opencv::imgproc::INTER_LINEAR,
+            BORDER_CONSTANT,
+            Scalar::all(255.0),
         )
-        .expect("Не получилось нарисовать маркеры");
-        draw_detected_corners_charuco(
-            &mut edited_3,
-            &charuco_corners_3,
-            &charuco_ids_3,
-            Scalar::new(0.0, 255.0, 0.0, 255.0),
+        .unwrap();
+        let mut color = Mat::default();
+        opencv::imgproc::cvt_color_def(&cell, &mut color, opencv::imgproc::COLOR_GRAY2BGR).unwrap();
+        color
+    }
+
+    /// Видео 2x2 с доской, которая поворачивается и смещается перед четырьмя камерами
+    fn write_board_video(path: &Path, board_config: &CharucoBoardConfig, frames: usize) {
+        let board = BoardConfig::from(board_config.clone()).build().unwrap();
+        let mut board_image = Mat::default();
+        board
+            .generate_image(
+                Size::new(
+                    board_config.squares_x * SQUARE_PX,
+                    board_config.squares_y * SQUARE_PX,
+                ),
+                &mut board_image,
+                0,
+                1,
+            )
+            .unwrap();
+        let width = (board_config.squares_x as f64) * board_config.square_length as f64;
+        let height = (board_config.squares_y as f64) * board_config.square_length as f64;
+
+        let fourcc = VideoWriter::fourcc('m', 'p', '4', 'v').unwrap();
+        let mut writer = VideoWriter::new(
+            &path.to_string_lossy(),
+            fourcc,
+            10.0,
+            Size::new(2 * CELL.0, 2 * CELL.1),
+            true,
         )
-        .expect("Не получилось нарисовать на изображении углы Charuco");
-
-        let Ok((
-            marker_corners_4,
-            marker_ids_4,
-            charuco_corners_4,
-            charuco_ids_4,
-            _obj_points_4,
-            _img_points_4,
-        )) = get_charuco(&charuco_board, &img_4)
-        else {
-            eprintln!("Ошибка при извлечении Charuco углов");
-            continue;
-        };
-        let mut edited_4 = img_4.clone();
-        draw_detected_markers(
-            &mut edited_4,
-            &marker_corners_4,
-            &marker_ids_4,
-            Scalar::new(255.0, 0.0, 0.0, 255.0),
+        .unwrap();
+        assert!(writer.is_opened().unwrap());
+        for i in 0..frames {
+            let phase = i as f64 / frames as f64 * std::f64::consts::TAU;
+            let board_r = rotation(25.0 * phase.sin(), 25.0 * (2.0 * phase).cos());
+            // Центр доски смещается по кругу перед камерами на расстоянии ~300 мм
+            let centre = [12.0 * phase.cos() + 30.0, 10.0 * phase.sin(), 300.0];
+            let offset = apply(&board_r, [width / 2.0, height / 2.0, 0.0]);
+            let board_t = [
+                centre[0] - offset[0],
+                centre[1] - offset[1],
+                centre[2] - offset[2],
+            ];
+            let cells: Vec<Mat> = (0..4)
+                .map(|cam| {
+                    render_camera(
+                        &board_image,
+                        board_config.square_length as f64,
+                        cam,
+                        &board_r,
+                        board_t,
+                    )
+                })
+                .collect();
+            writer.write(&combine_grid(&cells, 2).unwrap()).unwrap();
+        }
+        writer.release().unwrap();
+    }
+
+    #[test]
+    fn headless_job_calibrates_synthetic_video() {
+        let dir = std::env::temp_dir().join(format!("headless_job_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let board_config = BoardConfig::new().into_config();
+        board_config.save(dir.join("board.toml")).unwrap();
+        write_board_video(&dir.join("board.mp4"), &board_config, 40);
+        let job = dir.join("calib_job.toml");
+        std::fs::write(
+            &job,
+            "videos = [\"board.mp4\"]\n\
+             board_config = \"board.toml\"\n\
+             layout = \"2x2\"\n\
+             output_dir = \"out\"\n\
+             [auto]\n\
+             count = 12\n\
+             min_sharpness = 5.0\n\
+             min_pose_distance = 0.01\n",
         )
-        .expect("Не получилось нарисовать маркеры");
-        draw_detected_corners_charuco(
-            &mut edited_4,
-            &charuco_corners_4,
-            &charuco_ids_4,
-            Scalar::new(0.0, 255.0, 0.0, 255.0),
+        .unwrap();
+
+        let args = Args::try_parse_from(
+            ["calibration_app", "--headless", "--config"]
+                .map(PathBuf::from)
+                .into_iter()
+                .chain([job]),
         )
-        .expect("Не получилось нарисовать на изображении углы Charuco");
+        .unwrap();
+        run_headless(args).unwrap();
 
-        let Ok(edited_combined) = combine_quadrants(&edited_1, &edited_2, &edited_3, &edited_4)
-        else {
-            eprintln!("Ошибка в сшивании 4 изображений");
-            continue;
-        };
+        let output = dir.join("out");
+        for file in [
+            CALIBRATION_PARAMS_FILE,
+            results::REPORT_FILE,
+            results::JSON_REPORT_FILE,
+            BOARD_CONFIG_FILE,
+        ] {
+            assert!(output.join(file).is_file(), "нет {}", file);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-        highgui::imshow("Charuco Доска", &edited_combined).unwrap();
+    #[test]
+    fn headless_job_with_missing_video_fails() {
+        let dir = std::env::temp_dir().join(format!("headless_missing_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        BoardConfig::new()
+            .into_config()
+            .save(dir.join("board.toml"))
+            .unwrap();
+        let job = dir.join("calib_job.toml");
+        std::fs::write(
+            &job,
+            "videos = [\"missing.mp4\"]\n\
+             board_config = \"board.toml\"\n\
+             layout = \"2x2\"\n\
+             output_dir = \"out\"\n\
+             [auto]\n\
+             count = 12\n",
+        )
+        .unwrap();
 
-        let key = highgui::wait_key(0).unwrap();
-        match key {
-            83 => {
-                current_i += 1;
-            }
-            81 => {
-                current_i -= 1;
-            }
-            32 => {
-                let timestamp = current_i.to_string();
-                imgcodecs::imwrite(
-                    &format!("{}/img_1_{}.png", PICKED_IMAGE_PATH, timestamp),
-                    &img_1,
-                    &opencv::core::Vector::new(),
-                )
-                .unwrap();
-                imgcodecs::imwrite(
-                    &format!("{}/img_2_{}.png", PICKED_IMAGE_PATH, timestamp),
-                    &img_2,
-                    &Vector::new(),
-                )
-                .unwrap();
-                imgcodecs::imwrite(
-                    &format!("{}/img_3_{}.png", PICKED_IMAGE_PATH, timestamp),
-                    &img_3,
-                    &Vector::new(),
-                )
-                .unwrap();
-                imgcodecs::imwrite(
-                    &format!("{}/img_4_{}.png", PICKED_IMAGE_PATH, timestamp),
-                    &img_4,
-                    &Vector::new(),
-                )
-                .unwrap();
-                info!("Изображения сохранены с timestamp: {}", timestamp);
-            }
-            101 => {
-                let timestamp = current_i.to_string();
-                imgcodecs::imwrite(
-                    &format!("{}/combined_{}.png", PICKED_IMAGE_PATH, timestamp),
-                    &edited_combined,
-                    &Vector::new(),
-                )
-                .unwrap();
-                info!(
-                    "Комбинированное изображение сохранено с timestamp: {}",
-                    timestamp
-                );
-            }
-            27 => break, // Escape key
-            _ => {}
-        }
+        let args = Args::try_parse_from(
+            ["calibration_app", "--headless", "--config"]
+                .map(PathBuf::from)
+                .into_iter()
+                .chain([job]),
+        )
+        .unwrap();
+        assert!(run_headless(args).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-    perform_calibration(
-        &PICKED_IMAGE_PATH,
-        &Path::new(CAMERAS_PARAMS_PATH),
-        &charuco_board,
-        4,
-    );
 }
@@ -0,0 +1,276 @@
+use std::collections::BTreeSet;
+
+use eframe::egui;
+
+// Коды клавиш из highgui::wait_key_ex. Стрелки отличаются между бэкендами:
+// GTK/X11 отдаёт keysym (0xFF51..), Windows - виртуальный код в старших битах.
+// Младший байт стрелок (81..86) совпадает с заглавными буквами, поэтому он не принимается
+pub const KEY_ESC: i32 = 27;
+const KEY_SPACE: i32 = 32;
+const KEY_LEFT: [i32; 2] = [0xFF51, 0x250000];
+const KEY_UP: [i32; 2] = [0xFF52, 0x260000];
+const KEY_RIGHT: [i32; 2] = [0xFF53, 0x270000];
+const KEY_DOWN: [i32; 2] = [0xFF54, 0x280000];
+const KEY_PAGE_UP: [i32; 2] = [0xFF55, 0x210000];
+const KEY_PAGE_DOWN: [i32; 2] = [0xFF56, 0x220000];
+const KEY_DELETE: [i32; 2] = [0xFFFF, 0x2E0000];
+const KEY_ENTER: [i32; 3] = [10, 13, 0xFF0D];
+const KEY_BACKSPACE: [i32; 2] = [8, 0xFF08];
+
+/// Сдвиг по клавишам вверх/вниз
+const SHORT_JUMP: isize = 10;
+/// Сдвиг по клавишам PageUp/PageDown
+const LONG_JUMP: isize = 100;
+
+/// Действие пользователя в окне выбора кадров
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Сдвиг на указанное число кадров
+    Move(isize),
+    /// Перейти к кадру с номером, введённым в терминале
+    GoTo,
+    /// Сохранить квадранты текущего кадра для калибровки
+    SavePicked,
+    /// Удалить последний выбранный кадр
+    UndoPicked,
+    /// Сохранить размеченную мозаику
+    SaveMosaic,
+    /// Изменить масштаб показа на указанное число шагов
+    Zoom(i32),
+    /// Закончить выбор и откалибровать
+    Finish,
+    /// Выйти без калибровки
+    Quit,
+    None,
+}
+
+impl Action {
+    pub fn from_key(key: i32) -> Self {
+        // Буквы сравниваются без учёта регистра и модификаторов в старших битах
+        let letter = u8::try_from(key & 0xFF)
+            .ok()
+            .map(|c| c.to_ascii_lowercase() as char);
+        match key {
+            k if KEY_LEFT.contains(&k) => return Action::Move(-1),
+            k if KEY_RIGHT.contains(&k) => return Action::Move(1),
+            k if KEY_DOWN.contains(&k) => return Action::Move(-SHORT_JUMP),
+            k if KEY_UP.contains(&k) => return Action::Move(SHORT_JUMP),
+            k if KEY_PAGE_DOWN.contains(&k) => return Action::Move(-LONG_JUMP),
+            k if KEY_PAGE_UP.contains(&k) => return Action::Move(LONG_JUMP),
+            k if KEY_DELETE.contains(&k) => return Action::UndoPicked,
+            KEY_ESC => return Action::Finish,
+            KEY_SPACE => return Action::SavePicked,
+            _ => {}
+        }
+        match letter {
+            Some('a') => Action::Move(-1),
+            Some('d') => Action::Move(1),
+            Some('s') => Action::Move(-SHORT_JUMP),
+            Some('w') => Action::Move(SHORT_JUMP),
+            Some('g') => Action::GoTo,
+            Some('e') => Action::SaveMosaic,
+            Some('x') => Action::UndoPicked,
+            Some('q') => Action::Quit,
+            Some('+' | '=') => Action::Zoom(1),
+            Some('-') => Action::Zoom(-1),
+            _ => Action::None,
+        }
+    }
+}
+
+impl Action {
+    /// Действие по клавише окна egui; те же клавиши, что и в окне highgui.
+    /// Ввод номера кадра (g) окно обрабатывает само
+    pub fn from_egui_key(key: egui::Key) -> Self {
+        use egui::Key;
+        match key {
+            Key::ArrowLeft | Key::A => Action::Move(-1),
+            Key::ArrowRight | Key::D => Action::Move(1),
+            Key::ArrowDown | Key::S => Action::Move(-SHORT_JUMP),
+            Key::ArrowUp | Key::W => Action::Move(SHORT_JUMP),
+            Key::PageDown => Action::Move(-LONG_JUMP),
+            Key::PageUp => Action::Move(LONG_JUMP),
+            Key::G => Action::GoTo,
+            Key::Space => Action::SavePicked,
+            Key::Delete | Key::X => Action::UndoPicked,
+            Key::E => Action::SaveMosaic,
+            Key::Escape => Action::Finish,
+            Key::Q => Action::Quit,
+            Key::Plus | Key::Equals => Action::Zoom(1),
+            Key::Minus => Action::Zoom(-1),
+            _ => Action::None,
+        }
+    }
+}
+
+/// Решение пользователя в окне результатов калибровки
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewAction {
+    /// Принять: сохранить параметры и отчёт
+    Accept,
+    /// Отбросить результат и вернуться к выбору кадров
+    Discard,
+    /// Показать перепроекцию углов доски на выбранных сценах
+    Reprojection,
+    /// Выйти, ничего не сохраняя
+    Quit,
+    None,
+}
+
+impl ReviewAction {
+    pub fn from_key(key: i32) -> Self {
+        let letter = u8::try_from(key & 0xFF)
+            .ok()
+            .map(|c| c.to_ascii_lowercase() as char);
+        match key {
+            k if KEY_ENTER.contains(&k) => return ReviewAction::Accept,
+            k if KEY_BACKSPACE.contains(&k) => return ReviewAction::Discard,
+            KEY_ESC => return ReviewAction::Quit,
+            _ => {}
+        }
+        match letter {
+            Some('y') => ReviewAction::Accept,
+            Some('n') => ReviewAction::Discard,
+            Some('r') => ReviewAction::Reprojection,
+            _ => ReviewAction::None,
+        }
+    }
+}
+
+impl ReviewAction {
+    /// Решение по клавише окна egui; те же клавиши, что и в окне highgui
+    pub fn from_egui_key(key: egui::Key) -> Self {
+        use egui::Key;
+        match key {
+            Key::Enter | Key::Y => ReviewAction::Accept,
+            Key::Backspace | Key::N => ReviewAction::Discard,
+            Key::R => ReviewAction::Reprojection,
+            Key::Escape => ReviewAction::Quit,
+            _ => ReviewAction::None,
+        }
+    }
+}
+
+/// Позиция в списке кадров, всегда в пределах [0, len - 1]
+pub struct FrameCursor {
+    position: usize,
+    len: usize,
+}
+
+impl FrameCursor {
+    pub fn new(len: usize) -> Self {
+        Self { position: 0, len }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn step(&mut self, delta: isize) {
+        self.set(self.position.saturating_add_signed(delta));
+    }
+
+    pub fn set(&mut self, position: usize) {
+        self.position = position.min(self.len.saturating_sub(1));
+    }
+
+    /// Сдвигается на `delta`, перешагивая позиции из `skipped`.
+    /// Возвращает `false`, если пропущены все кадры
+    pub fn step_skipping(&mut self, delta: isize, skipped: &BTreeSet<usize>) -> bool {
+        self.step(delta);
+        self.settle(delta >= 0, skipped)
+    }
+
+    /// Переходит на позицию, а если она пропущена - на ближайшую следующую читаемую
+    pub fn set_skipping(&mut self, position: usize, skipped: &BTreeSet<usize>) -> bool {
+        self.set(position);
+        self.settle(true, skipped)
+    }
+
+    /// Ищет читаемую позицию сначала в направлении движения, затем в обратном
+    /// (например, если последний кадр не читается, остаёмся на предпоследнем)
+    fn settle(&mut self, forward: bool, skipped: &BTreeSet<usize>) -> bool {
+        if !skipped.contains(&self.position) {
+            return true;
+        }
+        let is_readable = |p: &usize| !skipped.contains(p);
+        let mut ahead = self.position + 1..self.len;
+        let mut behind = (0..self.position).rev();
+        let found = if forward {
+            ahead.find(is_readable).or_else(|| behind.find(is_readable))
+        } else {
+            behind.find(is_readable).or_else(|| ahead.find(is_readable))
+        };
+        match found {
+            Some(position) => {
+                self.position = position;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skipped(positions: &[usize]) -> BTreeSet<usize> {
+        positions.iter().copied().collect()
+    }
+
+    #[test]
+    fn uppercase_letters_are_not_arrows() {
+        // 'Q', 'R', 'S', 'T', 'U', 'V' - это 81..86
+        assert_eq!(Action::from_key('S' as i32), Action::Move(-SHORT_JUMP));
+        assert_eq!(Action::from_key('Q' as i32), Action::Quit);
+        for key in ['R', 'T', 'U', 'V'] {
+            assert_eq!(Action::from_key(key as i32), Action::None, "{}", key);
+        }
+        assert_eq!(
+            ReviewAction::from_key('R' as i32),
+            ReviewAction::Reprojection
+        );
+    }
+
+    #[test]
+    fn arrow_codes_of_both_backends_move() {
+        assert_eq!(Action::from_key(0xFF53), Action::Move(1));
+        assert_eq!(Action::from_key(0x270000), Action::Move(1));
+        assert_eq!(Action::from_key(0xFF51), Action::Move(-1));
+        assert_eq!(Action::from_key(0x220000), Action::Move(-LONG_JUMP));
+    }
+
+    #[test]
+    fn step_skips_unreadable_frames() {
+        let mut cursor = FrameCursor::new(10);
+        let skipped = skipped(&[1, 2, 5]);
+        assert!(cursor.step_skipping(1, &skipped));
+        assert_eq!(cursor.position(), 3);
+        assert!(cursor.step_skipping(2, &skipped));
+        assert_eq!(cursor.position(), 6);
+        assert!(cursor.step_skipping(-1, &skipped));
+        assert_eq!(cursor.position(), 4);
+        assert!(cursor.step_skipping(-1, &skipped));
+        assert_eq!(cursor.position(), 3);
+        assert!(cursor.step_skipping(-1, &skipped));
+        assert_eq!(cursor.position(), 0);
+    }
+
+    #[test]
+    fn unreadable_last_frame_stays_on_previous() {
+        let mut cursor = FrameCursor::new(5);
+        let skipped = skipped(&[4]);
+        assert!(cursor.step_skipping(100, &skipped));
+        assert_eq!(cursor.position(), 3);
+        assert!(cursor.set_skipping(4, &skipped));
+        assert_eq!(cursor.position(), 3);
+    }
+
+    #[test]
+    fn all_frames_unreadable_is_reported() {
+        let mut cursor = FrameCursor::new(3);
+        assert!(!cursor.step_skipping(1, &skipped(&[0, 1, 2])));
+        assert!(!cursor.set_skipping(0, &skipped(&[0, 1, 2])));
+    }
+}
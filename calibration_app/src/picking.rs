@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use lib_cv::board::CharucoBoardConfig;
+use lib_cv::calibration::{MIN_STEREO_SHARED_CORNERS, SceneCoverage, SceneLimits};
+use lib_cv::utils::{
+    CLIP_FRAME_STRIDE, PickedFrame, PickedManifest, list_picked_calibration_images,
+};
+use log::{info, warn};
+use opencv::core::{Mat, Vector};
+use opencv::imgcodecs;
+
+/// Загружает манифест выбранных кадров. Если его ещё нет, а изображения уже сохранены
+/// прежними версиями, манифест собирается по именам файлов, чтобы они не потерялись.
+/// Кадры, выбранные с другой доской, смешивать с новыми нельзя, поэтому это ошибка.
+/// Манифест запоминает доску `board_config`, с которой будут выбираться новые кадры,
+/// и сессию `session`: новые кадры сохраняются в её папку
+pub fn load_manifest(
+    picked_dir: &Path,
+    board_config: &CharucoBoardConfig,
+    session: &str,
+) -> Result<PickedManifest, String> {
+    let mut manifest = match PickedManifest::load(picked_dir).map_err(|e| e.to_string())? {
+        Some(manifest) => manifest,
+        None => list_picked_calibration_images(picked_dir)
+            .map(|picked| PickedManifest::from_picked_images(picked_dir, &picked))
+            .unwrap_or_default(),
+    };
+    if !manifest.frames.is_empty() {
+        match &manifest.board {
+            Some(previous) => {
+                let differences = previous.differences(board_config);
+                if !differences.is_empty() {
+                    return Err(format!(
+                        "Кадры в {} выбраны с другой доской ({}). Удалите их или укажите \
+                         другую папку --picked-dir",
+                        picked_dir.display(),
+                        differences.join(", ")
+                    ));
+                }
+            }
+            None => warn!(
+                "Неизвестно, с какой доской выбраны {} кадров в {}: убедитесь, что с этой же",
+                manifest.frames.len(),
+                picked_dir.display()
+            ),
+        }
+    }
+    manifest.board = Some(board_config.clone());
+    manifest.session = Some(session.to_string());
+    Ok(manifest)
+}
+
+/// Сохраняет квадранты камер, где найдено не меньше `min_corners` углов, в папку сессии
+/// манифеста и добавляет кадр в манифест (повторный выбор кадра заменяет прежнюю запись).
+/// Файлы кадра, выбранного в другой сессии, остаются на диске. При выборе из нескольких
+/// записей кадр помечается записью, к которой относится его номер.
+/// Возвращает номера (с 1) пропущенных камер
+pub fn save_picked(
+    picked_dir: &Path,
+    manifest: &mut PickedManifest,
+    frame: usize,
+    quadrants: &[Mat],
+    corners: &[usize],
+    shared_with_first: &[usize],
+    min_corners: usize,
+) -> Result<Vec<usize>, String> {
+    let session = manifest.session.clone();
+    if let Some(session) = &session {
+        let dir = picked_dir.join(session);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Не удалось создать папку {}: {}", dir.display(), e))?;
+    }
+    let mut files = BTreeMap::new();
+    let mut skipped = Vec::new();
+    for (cam_i, (quadrant, &count)) in quadrants.iter().zip(corners).enumerate() {
+        if count < min_corners {
+            skipped.push(cam_i + 1);
+            continue;
+        }
+        let file_name = format!("img_{}_{}.png", cam_i + 1, frame);
+        let name = match &session {
+            Some(session) => format!("{}/{}", session, file_name),
+            None => file_name,
+        };
+        let path = picked_dir.join(&name);
+        match imgcodecs::imwrite(&path.to_string_lossy(), quadrant, &Vector::new()) {
+            Ok(true) => {}
+            Ok(false) => return Err(format!("Не удалось сохранить {}", path.display())),
+            Err(e) => return Err(format!("Не удалось сохранить {}: {}", path.display(), e)),
+        }
+        files.insert(cam_i + 1, name);
+    }
+    if files.is_empty() {
+        return Err(format!(
+            "Кадр {}: доска не найдена ни в одной камере",
+            frame
+        ));
+    }
+
+    // Файлы прежнего выбора этого кадра в той же сессии, не перезаписанные сейчас,
+    // больше не нужны
+    if let Some(position) = manifest.frames.iter().position(|f| f.frame == frame) {
+        let previous = manifest.frames.remove(position);
+        if previous.session == session {
+            remove_files(
+                picked_dir,
+                previous
+                    .files
+                    .iter()
+                    .filter(|(cam, _)| !files.contains_key(cam))
+                    .map(|(_, name)| name),
+            );
+        } else {
+            info!(
+                "Кадр {} выбран заново, файлы прежней сессии {} оставлены на диске",
+                frame,
+                previous.session.as_deref().unwrap_or("без сессии")
+            );
+        }
+    }
+    manifest.frames.push(PickedFrame {
+        frame,
+        corners: corners.to_vec(),
+        files,
+        shared_with_first: shared_with_first.to_vec(),
+        session,
+        clip: manifest.clips.get(frame / CLIP_FRAME_STRIDE).cloned(),
+    });
+    manifest.save(picked_dir).map_err(|e| e.to_string())?;
+    Ok(skipped)
+}
+
+/// Удаляет файлы и запись последнего выбранного кадра
+pub fn undo_last_pick(
+    picked_dir: &Path,
+    manifest: &mut PickedManifest,
+) -> Result<Option<PickedFrame>, String> {
+    let Some(last) = manifest.frames.pop() else {
+        return Ok(None);
+    };
+    remove_files(picked_dir, last.files.values());
+    manifest.save(picked_dir).map_err(|e| e.to_string())?;
+    info!("Кадр {} удалён из выбранных", last.frame);
+    Ok(Some(last))
+}
+
+/// Удаляет файлы и запись выбранного кадра `frame`
+pub fn remove_pick(
+    picked_dir: &Path,
+    manifest: &mut PickedManifest,
+    frame: usize,
+) -> Result<Option<PickedFrame>, String> {
+    let Some(position) = manifest.frames.iter().position(|f| f.frame == frame) else {
+        return Ok(None);
+    };
+    let removed = manifest.frames.remove(position);
+    remove_files(picked_dir, removed.files.values());
+    manifest.save(picked_dir).map_err(|e| e.to_string())?;
+    info!("Кадр {} удалён из выбранных", removed.frame);
+    Ok(Some(removed))
+}
+
+/// Строка состояния с числом выбранных сцен каждой камеры и сцен со всеми камерами
+/// рядом с минимумами `limits`
+pub fn scene_status(manifest: &PickedManifest, cameras: usize, limits: &SceneLimits) -> String {
+    let coverage = SceneCoverage::from_manifest(manifest, cameras);
+    let counts: Vec<String> = coverage
+        .per_camera
+        .iter()
+        .enumerate()
+        .map(|(cam_i, count)| format!("{}: {}", cam_i + 1, count))
+        .collect();
+    format!(
+        "Scenes {} (min {}), all cams: {} (min {})",
+        counts.join(", "),
+        limits.min_per_camera,
+        coverage.complete,
+        limits.min_complete
+    )
+}
+
+/// Строка состояния с числом кадров, полезных для стереокалибровки каждой пары камер,
+/// или ничего для одной камеры
+pub fn pair_status(manifest: &PickedManifest, cameras: usize) -> Option<String> {
+    let pairs = manifest.stereo_pair_frames(cameras, MIN_STEREO_SHARED_CORNERS);
+    if pairs.is_empty() {
+        return None;
+    }
+    let counts: Vec<String> = pairs
+        .iter()
+        .map(|(cam, frames)| format!("1-{}: {}", cam, frames))
+        .collect();
+    Some(format!(
+        "Pairs {} frames with >={} shared corners",
+        counts.join(", "),
+        MIN_STEREO_SHARED_CORNERS
+    ))
+}
+
+/// Предупреждает о парах камер, у которых меньше `min_frames` кадров с достаточным
+/// числом общих углов: внешние параметры такой пары будут неточными
+pub fn warn_weak_pairs(manifest: &PickedManifest, cameras: usize, min_frames: usize) {
+    for (cam, frames) in manifest.stereo_pair_frames(cameras, MIN_STEREO_SHARED_CORNERS) {
+        if frames < min_frames {
+            warn!(
+                "Пара камер 1-{}: только {} кадров с не менее чем {} общими углами (нужно {}), \
+                 положение камеры {} будет неточным",
+                cam, frames, MIN_STEREO_SHARED_CORNERS, min_frames, cam
+            );
+        }
+    }
+}
+
+fn remove_files<'a>(picked_dir: &Path, names: impl Iterator<Item = &'a String>) {
+    for name in names {
+        let path = picked_dir.join(name);
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Не удалось удалить {}: {}", path.display(), e);
+        }
+    }
+}
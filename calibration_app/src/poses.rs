@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use lib_cv::calibration::{
+    BoardPose, CameraParameters, PoseBins, estimate_board_pose, provisional_camera,
+};
+use log::debug;
+use opencv::objdetect::CharucoBoard;
+use opencv::prelude::*;
+
+use crate::frame_view::FrameView;
+
+/// Счётчик поз доски в выбранных кадрах по ячейкам наклона и расстояния: пустые ячейки
+/// подсказывают, каких поз не хватает для хорошей калибровки. Поза оценивается по камере 1
+pub struct PoseTally {
+    bins: PoseBins,
+    /// Камера 1 прежней калибровки; без неё поза считается по приблизительной камере
+    camera: Option<CameraParameters>,
+    /// Ячейка позы каждого выбранного кадра по номеру кадра
+    picked: BTreeMap<usize, (usize, usize)>,
+}
+
+impl PoseTally {
+    pub fn new(bins: PoseBins, camera: Option<CameraParameters>) -> Self {
+        Self {
+            bins,
+            camera,
+            picked: BTreeMap::new(),
+        }
+    }
+
+    /// Поза доски в камере 1 кадра `view`, если там найдено не меньше `min_corners` углов
+    pub fn estimate(
+        &self,
+        view: &FrameView,
+        charuco_board: &CharucoBoard,
+        min_corners: usize,
+    ) -> Option<BoardPose> {
+        let detection = view.reference.as_ref()?;
+        if detection.charuco_ids.len() < min_corners {
+            return None;
+        }
+        let provisional;
+        let camera = match &self.camera {
+            Some(camera) => camera,
+            None => {
+                provisional = provisional_camera(view.quadrants.first()?.size().ok()?).ok()?;
+                &provisional
+            }
+        };
+        estimate_board_pose(detection, charuco_board, camera)
+            .and_then(|(rvec, tvec)| BoardPose::from_rvec_tvec(&rvec, &tvec))
+            .inspect_err(|e| debug!("Поза доски не оценена: {}", e))
+            .ok()
+    }
+
+    /// Запоминает позу выбранного кадра `frame`; кадр без позы в счётчик не входит
+    pub fn record(&mut self, frame: usize, pose: Option<BoardPose>) {
+        match pose {
+            Some(pose) => {
+                self.picked.insert(frame, self.bins.bin(&pose));
+            }
+            None => {
+                self.picked.remove(&frame);
+            }
+        }
+    }
+
+    pub fn forget(&mut self, frame: usize) {
+        self.picked.remove(&frame);
+    }
+
+    /// Таблица счётчика: строка на ячейку наклона, столбец на ячейку расстояния
+    pub fn lines(&self) -> Vec<String> {
+        let tilts = self.bins.tilt_labels();
+        let distances = self.bins.distance_labels();
+        let mut counts = vec![vec![0usize; distances.len()]; tilts.len()];
+        for &(tilt, distance) in self.picked.values() {
+            counts[tilt][distance] += 1;
+        }
+        let mut lines = vec![format!(
+            "Poses {}: tilt deg x dist {}",
+            self.picked.len(),
+            distances.join(" | ")
+        )];
+        lines.extend(tilts.iter().zip(&counts).map(|(label, row)| {
+            let row: Vec<String> = row.iter().map(|count| count.to_string()).collect();
+            format!("  {}: {}", label, row.join(" | "))
+        }));
+        lines
+    }
+}
+
+/// Строка с наклонами и расстоянием доски текущего кадра
+pub fn pose_line(pose: Option<&BoardPose>) -> String {
+    match pose {
+        Some(pose) => format!(
+            "Board tilt {:.0} deg (x {:.0}, y {:.0}), distance {:.0}",
+            pose.tilt(),
+            pose.tilt_x,
+            pose.tilt_y,
+            pose.distance
+        ),
+        None => "Board pose: n/a".to_string(),
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lib_cv::calibration::CalibrationStage;
+use lib_cv::utils::annotate;
+use log::warn;
+use opencv::core::{CV_8UC3, Mat, Point, Rect, Scalar};
+use opencv::highgui;
+use opencv::imgproc;
+
+use crate::navigation::KEY_ESC;
+
+const WINDOW_NAME: &str = "Прогресс";
+/// Как часто обновляется окно или текстовая полоса прогресса
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 30;
+
+#[derive(Debug, Clone)]
+struct ProgressState {
+    phase: String,
+    detail: String,
+    done: usize,
+    total: usize,
+    /// Начало текущей фазы: от него считается оставшееся время
+    phase_started: Instant,
+    last_printed: Option<Instant>,
+}
+
+impl ProgressState {
+    fn fraction(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            (self.done as f64 / self.total as f64).min(1.0)
+        }
+    }
+
+    /// Оставшееся время по средней скорости текущей фазы
+    fn eta(&self) -> Option<Duration> {
+        if self.done == 0 || self.total <= self.done {
+            return None;
+        }
+        let elapsed = self.phase_started.elapsed().as_secs_f64();
+        let remaining = elapsed * (self.total - self.done) as f64 / self.done as f64;
+        Some(Duration::from_secs_f64(remaining))
+    }
+
+    fn status_line(&self) -> String {
+        let eta = self
+            .eta()
+            .map(|eta| format!(", ETA {}", format_duration(eta)))
+            .unwrap_or_default();
+        format!("{:3.0}%{}", self.fraction() * 100.0, eta)
+    }
+}
+
+/// Прогресс долгой операции, общий для рабочего потока и отображающего его потока.
+/// Рабочий поток сообщает о ходе работы через [`ProgressReporter::update`] и узнаёт из
+/// его результата, не отменил ли пользователь операцию
+#[derive(Clone)]
+pub struct ProgressReporter {
+    state: Arc<Mutex<ProgressState>>,
+    cancelled: Arc<AtomicBool>,
+    /// Печатать текстовую полосу прогресса в stderr (режим без окна)
+    text: bool,
+}
+
+impl ProgressReporter {
+    fn new(text: bool) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ProgressState {
+                phase: String::new(),
+                detail: String::new(),
+                done: 0,
+                total: 0,
+                phase_started: Instant::now(),
+                last_printed: None,
+            })),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            text,
+        }
+    }
+
+    /// Прогресс для режима без окна: текстовая полоса с оценкой времени в stderr
+    pub fn text() -> Self {
+        Self::new(true)
+    }
+
+    /// Прогресс операции в фоновом потоке окна egui: окно само опрашивает [`Self::status`]
+    pub fn background() -> Self {
+        Self::new(false)
+    }
+
+    /// Строка состояния и доля выполненного для показа в окне
+    pub fn status(&self) -> (String, f32) {
+        let state = self.snapshot();
+        (
+            format!("{}: {} {}", state.phase, state.detail, state.status_line()),
+            state.fraction() as f32,
+        )
+    }
+
+    /// Обновляет прогресс. Возвращает `false`, если операцию нужно прервать
+    pub fn update(&self, phase: &str, detail: String, done: usize, total: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.phase != phase {
+            if self.text && state.last_printed.is_some() {
+                eprintln!();
+            }
+            state.phase = phase.to_string();
+            state.phase_started = Instant::now();
+            state.last_printed = None;
+        }
+        state.detail = detail;
+        state.done = done;
+        state.total = total;
+
+        if self.text
+            && state
+                .last_printed
+                .is_none_or(|t| t.elapsed() >= REFRESH_INTERVAL || done >= total)
+        {
+            print_bar(&state);
+            state.last_printed = Some(Instant::now());
+        }
+        !self.is_cancelled()
+    }
+
+    /// Колбэк для извлечения кадров видео
+    pub fn frames(&self) -> impl FnMut(usize, usize) -> bool + '_ {
+        |done, total| {
+            self.update(
+                "Extracting frames",
+                format!("{}/{}", done, total),
+                done,
+                total,
+            )
+        }
+    }
+
+    /// Колбэк для этапов калибровки `cameras` камер
+    pub fn calibration(&self, cameras: usize) -> impl FnMut(CalibrationStage) -> bool + '_ {
+        move |stage| {
+            let (done, total) = stage.position(cameras);
+            self.update("Calibration", stage.to_string(), done, total)
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Завершает текстовую полосу переводом строки
+    pub fn finish(&self) {
+        if self.text && self.state.lock().unwrap().last_printed.is_some() {
+            eprintln!();
+        }
+    }
+
+    fn snapshot(&self) -> ProgressState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// Выполняет `job` в рабочем потоке и, пока он работает, показывает окно прогресса,
+/// чтобы интерфейс не замирал. Esc в окне отменяет операцию: `job` увидит это по
+/// результату [`ProgressReporter::update`] и должен завершиться сам
+pub fn run_in_window<T, F>(job: F) -> T
+where
+    T: Send,
+    F: FnOnce(&ProgressReporter) -> T + Send,
+{
+    let reporter = ProgressReporter::new(false);
+    let result = std::thread::scope(|scope| {
+        let worker_reporter = reporter.clone();
+        let worker = scope.spawn(move || job(&worker_reporter));
+
+        if let Err(e) = highgui::named_window(WINDOW_NAME, highgui::WINDOW_AUTOSIZE) {
+            warn!("Не удалось открыть окно прогресса: {}", e);
+        }
+        while !worker.is_finished() {
+            match render_progress(&reporter.snapshot(), reporter.is_cancelled()) {
+                Ok(image) => {
+                    let _ = highgui::imshow(WINDOW_NAME, &image);
+                }
+                Err(e) => warn!("Не удалось нарисовать прогресс: {}", e),
+            }
+            let key = highgui::wait_key(REFRESH_INTERVAL.as_millis() as i32).unwrap_or(-1);
+            if key == KEY_ESC && !reporter.is_cancelled() {
+                reporter.cancel();
+            }
+        }
+        worker
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    });
+    let _ = highgui::destroy_window(WINDOW_NAME);
+    result
+}
+
+fn render_progress(state: &ProgressState, cancelled: bool) -> opencv::Result<Mat> {
+    let (width, height) = (640, 160);
+    let mut image = Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(32.0))?;
+
+    let margin = 20;
+    let bar = Rect::new(margin, height - 50, width - 2 * margin, 24);
+    imgproc::rectangle(
+        &mut image,
+        bar,
+        Scalar::all(80.0),
+        imgproc::FILLED,
+        imgproc::LINE_8,
+        0,
+    )?;
+    let filled = (bar.width as f64 * state.fraction()) as i32;
+    if filled > 0 {
+        imgproc::rectangle(
+            &mut image,
+            Rect::new(bar.x, bar.y, filled, bar.height),
+            Scalar::new(80.0, 180.0, 80.0, 0.0),
+            imgproc::FILLED,
+            imgproc::LINE_8,
+            0,
+        )?;
+    }
+    imgproc::rectangle_points(
+        &mut image,
+        Point::new(bar.x, bar.y),
+        Point::new(bar.x + bar.width, bar.y + bar.height),
+        Scalar::all(200.0),
+        1,
+        imgproc::LINE_8,
+        0,
+    )?;
+
+    let lines = vec![
+        format!("{}: {}", state.phase, state.detail),
+        state.status_line(),
+        if cancelled {
+            "Cancelling...".to_string()
+        } else {
+            "Esc - cancel".to_string()
+        },
+    ];
+    annotate(&mut image, &lines)?;
+    Ok(image)
+}
+
+fn print_bar(state: &ProgressState) {
+    let filled = (BAR_WIDTH as f64 * state.fraction()).round() as usize;
+    eprint!(
+        "\r[{}{}] {} {}: {}   ",
+        "#".repeat(filled),
+        ".".repeat(BAR_WIDTH - filled),
+        state.status_line(),
+        state.phase,
+        state.detail
+    );
+    let _ = std::io::stderr().flush();
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
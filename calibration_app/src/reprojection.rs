@@ -0,0 +1,215 @@
+use std::path::Path;
+
+use lib_cv::calibration::{
+    CalibrationResult, draw_board_reprojection, get_charuco, reproject_board,
+};
+use lib_cv::utils::{GridLayout, PickedManifest, annotate, combine_grid};
+use log::{info, warn};
+use opencv::core::{CV_8UC3, Mat, Scalar, Vector};
+use opencv::highgui;
+use opencv::imgcodecs;
+use opencv::objdetect::CharucoBoard;
+use opencv::prelude::*;
+
+use crate::navigation::Action;
+use crate::picking;
+
+const WINDOW_NAME: &str = "Перепроекция";
+/// Подпапка --output-dir для сохранённых изображений перепроекции
+pub const REVIEW_DIR: &str = "review";
+
+/// Сцена с перепроекцией по всем камерам
+struct SceneView {
+    mosaic: Mat,
+    /// Наибольшая по камерам средняя ошибка перепроекции, пикс.
+    worst_error: Option<f64>,
+}
+
+/// Листает выбранные сцены с найденными и перепроецированными углами доски.
+/// Сцены можно удалять из выбранных. Возвращает число удалённых сцен:
+/// если оно не ноль, результат калибровки устарел
+pub fn browse(
+    result: &CalibrationResult,
+    charuco_board: &CharucoBoard,
+    picked_dir: &Path,
+    manifest: &mut PickedManifest,
+    layout: &GridLayout,
+    max_view_error: f64,
+    review_dir: &Path,
+) -> usize {
+    let mut removed = 0;
+    let mut position = 0;
+    highgui::named_window(WINDOW_NAME, highgui::WINDOW_KEEPRATIO).unwrap();
+    while !manifest.frames.is_empty() {
+        position = position.min(manifest.frames.len() - 1);
+        let frame = manifest.frames[position].frame;
+        let scene = match render_scene(
+            result,
+            charuco_board,
+            picked_dir,
+            manifest,
+            position,
+            layout,
+        ) {
+            Ok(scene) => scene,
+            Err(e) => {
+                warn!("Сцена {} не отрисована: {}", frame, e);
+                SceneView {
+                    mosaic: Mat::new_rows_cols_with_default(480, 640, CV_8UC3, Scalar::all(0.0))
+                        .unwrap_or_default(),
+                    worst_error: None,
+                }
+            }
+        };
+
+        let flag = match scene.worst_error {
+            Some(error) if error > max_view_error => format!(
+                ", ошибка {:.2} пикс. - ВЫШЕ ПОРОГА {:.2}",
+                error, max_view_error
+            ),
+            Some(error) => format!(", ошибка {:.2} пикс.", error),
+            None => String::new(),
+        };
+        let title = format!(
+            "Сцена {}/{} (кадр {}){}",
+            position + 1,
+            manifest.frames.len(),
+            frame,
+            flag
+        );
+        let _ = highgui::set_window_title(WINDOW_NAME, &title);
+        highgui::imshow(WINDOW_NAME, &scene.mosaic).unwrap();
+
+        match Action::from_key(highgui::wait_key_ex(0).unwrap()) {
+            Action::Move(delta) => {
+                position = position
+                    .saturating_add_signed(delta)
+                    .min(manifest.frames.len() - 1)
+            }
+            Action::UndoPicked => match picking::remove_pick(picked_dir, manifest, frame) {
+                Ok(Some(_)) => removed += 1,
+                Ok(None) => {}
+                Err(e) => warn!("Не удалось удалить сцену {}: {}", frame, e),
+            },
+            Action::SaveMosaic => save_all(
+                result,
+                charuco_board,
+                picked_dir,
+                manifest,
+                layout,
+                review_dir,
+            ),
+            Action::Finish | Action::Quit => break,
+            _ => {}
+        }
+    }
+    let _ = highgui::destroy_window(WINDOW_NAME);
+    if removed > 0 {
+        info!("Из выбранных удалено сцен: {}", removed);
+    }
+    removed
+}
+
+/// Сохраняет изображения перепроекции всех сцен в `review_dir`
+fn save_all(
+    result: &CalibrationResult,
+    charuco_board: &CharucoBoard,
+    picked_dir: &Path,
+    manifest: &PickedManifest,
+    layout: &GridLayout,
+    review_dir: &Path,
+) {
+    if let Err(e) = std::fs::create_dir_all(review_dir) {
+        warn!("Не удалось создать {}: {}", review_dir.display(), e);
+        return;
+    }
+    let mut saved = 0;
+    for (position, entry) in manifest.frames.iter().enumerate() {
+        let scene = match render_scene(
+            result,
+            charuco_board,
+            picked_dir,
+            manifest,
+            position,
+            layout,
+        ) {
+            Ok(scene) => scene,
+            Err(e) => {
+                warn!("Сцена {} не отрисована: {}", entry.frame, e);
+                continue;
+            }
+        };
+        let path = review_dir.join(format!("reprojection_{}.png", entry.frame));
+        match imgcodecs::imwrite(&path.to_string_lossy(), &scene.mosaic, &Vector::new()) {
+            Ok(_) => saved += 1,
+            Err(e) => warn!("Не удалось сохранить {}: {}", path.display(), e),
+        }
+    }
+    info!(
+        "Сохранено {} изображений перепроекции в {}",
+        saved,
+        review_dir.display()
+    );
+}
+
+fn render_scene(
+    result: &CalibrationResult,
+    charuco_board: &CharucoBoard,
+    picked_dir: &Path,
+    manifest: &PickedManifest,
+    position: usize,
+    layout: &GridLayout,
+) -> opencv::Result<SceneView> {
+    let entry = &manifest.frames[position];
+    let mut tiles = Vec::with_capacity(result.cameras.len());
+    let mut worst_error: Option<f64> = None;
+    let mut tile_size = None;
+
+    for (cam_i, camera) in result.cameras.iter().enumerate() {
+        let Some(name) = entry.files.get(&(cam_i + 1)) else {
+            tiles.push(None);
+            continue;
+        };
+        let path = picked_dir.join(name);
+        let mut image = imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)?;
+        if image.empty() {
+            tiles.push(None);
+            continue;
+        }
+        tile_size.get_or_insert(image.size()?);
+
+        let detection = get_charuco(charuco_board, &image)?;
+        let label = match reproject_board(&detection, charuco_board, camera) {
+            Ok(reprojection) => {
+                draw_board_reprojection(&mut image, &reprojection)?;
+                let error = reprojection.mean_error();
+                worst_error = Some(worst_error.map_or(error, |w| w.max(error)));
+                format!("Cam {}: mean error {:.2} px", cam_i + 1, error)
+            }
+            Err(_) => format!("Cam {}: board pose not found", cam_i + 1),
+        };
+        annotate(
+            &mut image,
+            &[label, "green - detected, red - reprojected".to_string()],
+        )?;
+        tiles.push(Some(image));
+    }
+
+    let Some(tile_size) = tile_size else {
+        return Err(opencv::Error::new(
+            opencv::core::StsError,
+            format!("Нет изображений сцены {}", entry.frame),
+        ));
+    };
+    let tiles = tiles
+        .into_iter()
+        .map(|tile| match tile {
+            Some(tile) => Ok(tile),
+            None => Mat::zeros_size(tile_size, CV_8UC3)?.to_mat(),
+        })
+        .collect::<opencv::Result<Vec<Mat>>>()?;
+    Ok(SceneView {
+        mosaic: combine_grid(&tiles, layout.cols)?,
+        worst_error,
+    })
+}
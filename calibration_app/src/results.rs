@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use lib_cv::calibration::{CalibrationResult, camera_distance_matrix};
+use lib_cv::reconstruction::Undistorter;
+use lib_cv::utils::{annotate, annotate_bottom, combine_grid, write_atomically};
+use log::warn;
+use opencv::core::{CV_8UC3, Mat, Scalar, StsError};
+use opencv::highgui;
+use opencv::imgcodecs;
+use opencv::prelude::*;
+
+use crate::navigation::ReviewAction;
+
+const WINDOW_NAME: &str = "Результаты калибровки";
+/// Текстовый отчёт о калибровке рядом с calibration_params.yml
+pub const REPORT_FILE: &str = "calibration_report.txt";
+/// Тот же отчёт в JSON для автоматической обработки
+pub const JSON_REPORT_FILE: &str = "calibration_report.json";
+
+/// Показывает результат калибровки и ждёт решения пользователя. `samples` - выбранный
+/// кадр каждой камеры: он показывается до и после устранения дисторсии, чтобы сразу было
+/// видно, остались ли края доски прямыми. Ошибка, если окно не удалось показать
+pub fn review(
+    result: &CalibrationResult,
+    samples: &[Option<PathBuf>],
+) -> opencv::Result<ReviewAction> {
+    let view = match render_results(result, samples) {
+        Ok(view) => view,
+        Err(e) => {
+            warn!("Не удалось построить предпросмотр калибровки: {}", e);
+            summary_only(result)?
+        }
+    };
+    if view.empty() {
+        return Err(opencv::Error::new(
+            StsError,
+            "пустое изображение результатов калибровки".to_string(),
+        ));
+    }
+
+    highgui::named_window(WINDOW_NAME, highgui::WINDOW_KEEPRATIO)?;
+    highgui::imshow(WINDOW_NAME, &view)?;
+    let action = loop {
+        let key = highgui::wait_key_ex(0)?;
+        match ReviewAction::from_key(key) {
+            ReviewAction::None => {}
+            action => break action,
+        }
+    };
+    let _ = highgui::destroy_window(WINDOW_NAME);
+    Ok(action)
+}
+
+/// Сохраняет сводку калибровки и таблицу расстояний между камерами
+/// в `output_dir`/calibration_report.txt
+pub fn save_report(output_dir: &Path, result: &CalibrationResult) -> std::io::Result<()> {
+    let mut lines = result.summary();
+    lines.extend(result.distance_table());
+    let mut text = lines.join("\n");
+    text.push('\n');
+    write_atomically(&output_dir.join(REPORT_FILE), |tmp| {
+        std::fs::write(tmp, text)
+    })
+}
+
+/// Сохраняет RMS камер и стереопар, расстояния между камерами с их единицей и число сцен
+/// в `output_dir`/calibration_report.json
+pub fn save_json_report(output_dir: &Path, result: &CalibrationResult) -> std::io::Result<()> {
+    let cameras: Vec<serde_json::Value> = result
+        .cameras
+        .iter()
+        .enumerate()
+        .map(|(i, camera)| {
+            serde_json::json!({
+                "camera": i + 1,
+                "rms": camera.rms_error,
+                "stereo_rms": camera.stereo_rms_error,
+            })
+        })
+        .collect();
+    let distances = camera_distance_matrix(&result.cameras)
+        .inspect_err(|e| warn!("Расстояния между камерами не посчитаны: {}", e))
+        .ok();
+    let report = serde_json::json!({
+        "scenes": result.scenes,
+        "unit": result.unit().name(),
+        "cameras": cameras,
+        "distances": distances,
+    });
+    let text = serde_json::to_string_pretty(&report).map_err(std::io::Error::other)?;
+    write_atomically(&output_dir.join(JSON_REPORT_FILE), |tmp| {
+        std::fs::write(tmp, text)
+    })
+}
+
+fn render_results(result: &CalibrationResult, samples: &[Option<PathBuf>]) -> opencv::Result<Mat> {
+    let mut tiles = Vec::with_capacity(2 * result.cameras.len());
+    for (cam_i, camera) in result.cameras.iter().enumerate() {
+        let Some(path) = samples.get(cam_i).and_then(|p| p.as_ref()) else {
+            continue;
+        };
+        let mut original = imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)?;
+        if original.empty() {
+            warn!("Не удалось прочитать {}", path.display());
+            continue;
+        }
+        let mut undistorted = Undistorter::new(camera, original.size()?)?.undistort(&original)?;
+        annotate(&mut original, &[format!("Cam {}: original", cam_i + 1)])?;
+        annotate(
+            &mut undistorted,
+            &[format!("Cam {}: undistorted", cam_i + 1)],
+        )?;
+        tiles.push(original);
+        tiles.push(undistorted);
+    }
+    if tiles.is_empty() {
+        return summary_only(result);
+    }
+    let mut view = combine_grid(&tiles, 2)?;
+    annotate_bottom(&mut view, &overlay_lines(result))?;
+    Ok(view)
+}
+
+fn summary_only(result: &CalibrationResult) -> opencv::Result<Mat> {
+    let mut view = Mat::new_rows_cols_with_default(480, 960, CV_8UC3, Scalar::all(0.0))?;
+    annotate(&mut view, &overlay_lines(result))?;
+    Ok(view)
+}
+
+fn overlay_lines(result: &CalibrationResult) -> Vec<String> {
+    let mut lines = result.summary();
+    lines.push("Enter/y - accept and save, n/Backspace - discard and pick more frames".to_string());
+    lines.push("r - review corner reprojection per scene".to_string());
+    lines.push("Esc - quit without saving".to_string());
+    lines
+}
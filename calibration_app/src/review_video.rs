@@ -0,0 +1,94 @@
+use std::path::Path;
+
+use lib_cv::utils::{FrameListing, UtilsError, annotate, frames_to_video, get_video_fps};
+use log::{info, warn};
+use opencv::objdetect::CharucoBoard;
+use opencv::videoio::VideoWriter;
+
+use crate::args::Args;
+use crate::frame_view::render_frame;
+use crate::progress::ProgressReporter;
+
+/// Частота кадров обзорного видео, если частоту исходного видео узнать не удалось
+const FALLBACK_FPS: f64 = 25.0;
+
+/// Режим --review-video без окна: размечает каждый --review-step-й кадр так же, как
+/// при выборе кадров (углы, маркеры и число углов в каждой камере), подписывает номер
+/// кадра и записывает мозаики в видео. По нему видно, на каких участках записи доска
+/// находится хорошо. Возвращает число записанных кадров
+pub fn run(
+    args: &Args,
+    charuco_board: &CharucoBoard,
+    listing: &FrameListing,
+    source_video: &Path,
+    output: &Path,
+) -> Result<usize, String> {
+    let step = args.review_step as usize;
+    // Обзор идёт с той же скоростью, что и запись: пропущенные кадры не растягивают время
+    let fps = match get_video_fps(source_video) {
+        Ok(fps) if fps > 0.0 => (fps / step as f64).max(1.0),
+        _ => {
+            warn!(
+                "Частота кадров {} неизвестна, обзор пишется с {} кадр/с",
+                source_video.display(),
+                FALLBACK_FPS
+            );
+            FALLBACK_FPS
+        }
+    };
+    let [c1, c2, c3, c4] = args.review_codec;
+    let fourcc = VideoWriter::fourcc(c1, c2, c3, c4).map_err(|e| {
+        format!(
+            "Кодек {}: {}",
+            args.review_codec.iter().collect::<String>(),
+            e
+        )
+    })?;
+
+    let thresholds = args.corner_thresholds();
+    let contrast = args.contrast_enhancement();
+    let total = listing.frames.len().div_ceil(step);
+    let reporter = ProgressReporter::text();
+    let mosaics = listing
+        .frames
+        .iter()
+        .step_by(step)
+        .enumerate()
+        .filter_map(|(i, entry)| {
+            if !reporter.update(
+                "Review video",
+                format!("frame {}", entry.index),
+                i + 1,
+                total,
+            ) {
+                return Some(Err(UtilsError::Cancelled));
+            }
+            let mut view = match render_frame(
+                charuco_board,
+                &entry.path,
+                &args.layout,
+                &thresholds,
+                contrast.as_ref(),
+            ) {
+                Ok(view) => view,
+                Err(e) => {
+                    warn!("Кадр {} пропущен: {}", entry.index, e);
+                    return None;
+                }
+            };
+            if let Err(e) = annotate(&mut view.mosaic, &[format!("Frame #{}", entry.index)]) {
+                warn!("Не удалось подписать кадр {}: {}", entry.index, e);
+            }
+            Some(Ok(view.mosaic))
+        });
+    let written = frames_to_video(mosaics, output, fps, fourcc);
+    reporter.finish();
+    let written = written.map_err(|e| format!("Обзорное видео не записано: {}", e))?;
+    info!(
+        "Обзорное видео ({} кадров, каждый {}-й) сохранено в {}",
+        written,
+        step,
+        output.display()
+    );
+    Ok(written)
+}
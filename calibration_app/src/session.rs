@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use lib_cv::calibration::{BoardPose, ContrastEnhancement, SceneLimits};
+use lib_cv::utils::{GridLayout, PickedFrame, PickedManifest};
+use log::{info, warn};
+use opencv::core::Vector;
+use opencv::imgcodecs;
+use opencv::objdetect::CharucoBoard;
+
+use crate::frame_view::{CornerThresholds, FrameView, render_image};
+use crate::frames::FrameStore;
+use crate::navigation::FrameCursor;
+use crate::picking;
+use crate::poses::PoseTally;
+
+/// Состояние ручного выбора кадров без привязки к окну: текущий кадр, выбранные кадры
+/// и их сохранение. Сообщения о результате действий копятся в `notice`, пока окно их
+/// не покажет
+pub struct PickerSession {
+    pub frames: FrameStore,
+    pub manifest: PickedManifest,
+    pub picked_dir: PathBuf,
+    pub layout: GridLayout,
+    pub thresholds: CornerThresholds,
+    /// Выравнивание контраста перед поиском доски, `None` - поиск по исходным кадрам
+    pub contrast: Option<ContrastEnhancement>,
+    pub notice: Vec<String>,
+    /// Позы доски выбранных в этом запуске кадров
+    pub poses: PoseTally,
+    /// Поза доски на показанном кадре
+    pub current_pose: Option<BoardPose>,
+    cursor: FrameCursor,
+    /// Позиции кадров, которые не удалось прочитать: навигация их перешагивает
+    skipped: BTreeSet<usize>,
+    direction: isize,
+    /// Кадры, выбранные в этом запуске: без них калибровка по старым кадрам требует подтверждения
+    picked_this_session: usize,
+    confirm_existing: bool,
+}
+
+impl PickerSession {
+    pub fn new(
+        frames: FrameStore,
+        manifest: PickedManifest,
+        picked_dir: PathBuf,
+        layout: GridLayout,
+        thresholds: CornerThresholds,
+        contrast: Option<ContrastEnhancement>,
+        poses: PoseTally,
+    ) -> Self {
+        let cursor = FrameCursor::new(frames.len());
+        Self {
+            frames,
+            manifest,
+            picked_dir,
+            layout,
+            thresholds,
+            contrast,
+            notice: Vec::new(),
+            poses,
+            current_pose: None,
+            cursor,
+            skipped: BTreeSet::new(),
+            direction: 1,
+            picked_this_session: 0,
+            confirm_existing: false,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.cursor.position()
+    }
+
+    /// Номер кадра видео в текущей позиции
+    pub fn frame_number(&self) -> usize {
+        self.frames.frame_number(self.cursor.position())
+    }
+
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.len()
+    }
+
+    /// Читает и размечает текущий кадр; нечитаемые кадры запоминаются и перешагиваются
+    /// в направлении последнего движения. `None`, если не читается ни один кадр
+    pub fn render_current(&mut self, charuco_board: &CharucoBoard) -> Option<FrameView> {
+        loop {
+            let position = self.cursor.position();
+            match self.frames.read(position).and_then(|frame| {
+                render_image(
+                    charuco_board,
+                    &frame,
+                    &self.layout,
+                    &self.thresholds,
+                    self.contrast.as_ref(),
+                )
+            }) {
+                Ok(view) => {
+                    self.current_pose =
+                        self.poses
+                            .estimate(&view, charuco_board, self.thresholds.min_corners);
+                    return Some(view);
+                }
+                Err(e) => {
+                    warn!(
+                        "Кадр {} пропущен: {}",
+                        self.frames.frame_number(position),
+                        e
+                    );
+                    self.skipped.insert(position);
+                    if !self.cursor.step_skipping(self.direction, &self.skipped) {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn step(&mut self, delta: isize) {
+        self.direction = delta.signum();
+        self.cursor.step_skipping(delta, &self.skipped);
+    }
+
+    /// Переходит к кадру видео с номером `number` (или к ближайшему следующему)
+    pub fn go_to(&mut self, number: usize) {
+        self.direction = 1;
+        self.cursor
+            .set_skipping(self.frames.position_of(number), &self.skipped);
+    }
+
+    /// Сохраняет квадранты показанного кадра `view` как выбранный кадр
+    pub fn save_current(&mut self, view: &FrameView) {
+        let frame_number = self.frame_number();
+        let corners: Vec<usize> = view.detections.iter().map(|d| d.corners).collect();
+        let shared: Vec<usize> = view
+            .detections
+            .iter()
+            .map(|d| d.shared_with_first)
+            .collect();
+        match picking::save_picked(
+            &self.picked_dir,
+            &mut self.manifest,
+            frame_number,
+            &view.quadrants,
+            &corners,
+            &shared,
+            self.thresholds.min_corners,
+        ) {
+            Ok(skipped_cameras) => {
+                self.picked_this_session += 1;
+                self.poses.record(frame_number, self.current_pose);
+                info!("Изображения сохранены с timestamp: {}", frame_number);
+                if !skipped_cameras.is_empty() {
+                    warn!(
+                        "Кадр {}: не сохранены камеры {:?}",
+                        frame_number, skipped_cameras
+                    );
+                    self.notice
+                        .push(format!("Saved without cams {:?}", skipped_cameras));
+                    self.notice.extend(view.weak_cameras(&self.thresholds));
+                }
+            }
+            Err(e) => {
+                warn!("{}", e);
+                self.notice.push("Nothing saved, see log".to_string());
+            }
+        }
+    }
+
+    pub fn undo_last(&mut self) {
+        match picking::undo_last_pick(&self.picked_dir, &mut self.manifest) {
+            Ok(Some(removed)) => self.forget(&removed),
+            Ok(None) => self.notice.push("No picked frames to remove".to_string()),
+            Err(e) => warn!("Не удалось удалить выбранный кадр: {}", e),
+        }
+    }
+
+    /// Удаляет из выбранных кадр видео с номером `frame`
+    pub fn remove(&mut self, frame: usize) {
+        match picking::remove_pick(&self.picked_dir, &mut self.manifest, frame) {
+            Ok(Some(removed)) => self.forget(&removed),
+            Ok(None) => {}
+            Err(e) => warn!("Не удалось удалить выбранный кадр: {}", e),
+        }
+    }
+
+    fn forget(&mut self, removed: &PickedFrame) {
+        self.picked_this_session = self.picked_this_session.saturating_sub(1);
+        self.poses.forget(removed.frame);
+        self.notice
+            .push(format!("Removed picked frame #{}", removed.frame));
+    }
+
+    /// Сохраняет размеченную мозаику кадра в --picked-dir как combined_{frame}.png
+    pub fn save_mosaic(&mut self, view: &FrameView) {
+        let timestamp = self.frame_number().to_string();
+        let path = self.picked_dir.join(format!("combined_{}.png", timestamp));
+        match imgcodecs::imwrite(&path.to_string_lossy(), &view.mosaic, &Vector::new()) {
+            Ok(true) => info!(
+                "Комбинированное изображение сохранено с timestamp: {}",
+                timestamp
+            ),
+            Ok(false) => warn!("Не удалось сохранить {}", path.display()),
+            Err(e) => warn!("Не удалось сохранить {}: {}", path.display(), e),
+        }
+    }
+
+    /// Кадры, выбранные автоматически, тоже считаются выбранными в этом запуске
+    pub fn add_picked(&mut self, count: usize) {
+        self.picked_this_session += count;
+    }
+
+    /// Можно ли калибровать, см. [`crate::ready_to_calibrate`]. Подтверждение действует
+    /// только для следующего вызова
+    pub fn ready_to_calibrate(&mut self, limits: &SceneLimits) -> bool {
+        let confirmed = std::mem::take(&mut self.confirm_existing);
+        crate::ready_to_calibrate(
+            &self.manifest,
+            self.layout.cells(),
+            limits,
+            self.picked_this_session,
+            confirmed,
+            &mut self.confirm_existing,
+            &mut self.notice,
+        )
+    }
+
+    /// Сбрасывает ожидание подтверждения: оно относится только к следующему действию
+    pub fn cancel_confirmation(&mut self) {
+        self.confirm_existing = false;
+    }
+}
@@ -1,6 +1,7 @@
 use std::ops::RangeInclusive;
 
 use eframe::egui::{self, ColorImage, SliderClamping};
+use lib_cv::board::{BoardConfig, CharucoBoardConfig, LengthUnit};
 use opencv::{Error, core::Size, imgproc, objdetect::PredefinedDictionaryType, prelude::*};
 
 pub struct GenCalibPatternApp {
@@ -10,6 +11,8 @@ pub struct GenCalibPatternApp {
     marker_length: i32,      // marker side length (same unit than squareLength)
     dictionary: ChArUcoDict, // dictionary of markers indicating the type of markers
     dictionaries: Vec<ChArUcoDict>,
+    /// Почему паттерн с текущими параметрами не построен
+    error: Option<String>,
 }
 
 #[derive(Clone)]
@@ -68,6 +71,7 @@ impl Default for GenCalibPatternApp {
             marker_length: 42,
             dictionary: ChArUcoDict::default(),
             dictionaries,
+            error: None,
         }
     }
 }
@@ -84,13 +88,7 @@ impl GenCalibPatternApp {
     }
 
     pub fn generate_pattern_mat_rgb(&mut self) -> Result<Mat, Error> {
-        let dictionary = opencv::objdetect::get_predefined_dictionary(self.dictionary.type_opencv)?;
-        let charuco_board = opencv::objdetect::CharucoBoard::new_def(
-            self.size,
-            self.square_length as f32,
-            self.marker_length as f32,
-            &dictionary,
-        )?;
+        let charuco_board = self.board_builder().build()?;
         let mut mat_image = Mat::default();
         charuco_board.generate_image(
             opencv::core::Size::new(
@@ -114,14 +112,21 @@ impl GenCalibPatternApp {
         Ok(color_image)
     }
 
+    /// Перестраивает текстуру паттерна. Если с текущими параметрами паттерн не строится,
+    /// прежняя текстура убирается, а ошибка запоминается для показа вместо неё
     pub fn set_texture_handler(
         &mut self,
         ctx: &eframe::egui::Context,
     ) -> Result<(), opencv::Error> {
         let color_image = match self.generate_pattern() {
             Ok(image) => image,
-            Err(e) => return Err(e),
+            Err(e) => {
+                self.texture_handle = None;
+                self.error = Some(e.message.clone());
+                return Err(e);
+            }
         };
+        self.error = None;
 
         if let Some(handle) = &mut self.texture_handle {
             handle.set(color_image, eframe::egui::TextureOptions::NEAREST);
@@ -157,9 +162,28 @@ impl GenCalibPatternApp {
             &self.generate_pattern_mat_rgb()?,
             &opencv::core::Vector::new(),
         )?;
+
+        // Рядом с изображением сохраняем геометрию доски для calibration_app --board-config
+        self.board_config()
+            .save(path.with_extension("toml"))
+            .map_err(|e| Error::new(opencv::core::StsError, e.to_string()))?;
         Ok(())
     }
 
+    /// Параметры доски в единицах генератора (пикселях)
+    pub fn board_builder(&self) -> BoardConfig {
+        BoardConfig::new()
+            .size(self.size.width, self.size.height)
+            .square_len(self.square_length as f32)
+            .marker_len(self.marker_length as f32)
+            .dict(self.dictionary.type_opencv)
+            .unit(LengthUnit::PatternPixels)
+    }
+
+    pub fn board_config(&self) -> CharucoBoardConfig {
+        self.board_builder().into_config()
+    }
+
     fn generate_filename(&self) -> String {
         format!(
             "charuco_pattern_{}x{}_{}.png",
@@ -215,7 +239,14 @@ impl eframe::App for GenCalibPatternApp {
 
         eframe::egui::CentralPanel::default().show(ctx, |ui| {
             let _ = self.set_texture_handler(&ctx);
-            if let Some(texture) = &self.texture_handle {
+            if let Some(error) = &self.error {
+                ui.centered_and_justified(|ui| {
+                    ui.colored_label(
+                        eframe::egui::Color32::RED,
+                        format!("Паттерн не построен: {}", error),
+                    )
+                });
+            } else if let Some(texture) = &self.texture_handle {
                 ui.centered_and_justified(|ui| {
                     ui.add(eframe::egui::Image::from_texture(texture).shrink_to_fit())
                 });
@@ -228,3 +259,30 @@ impl eframe::App for GenCalibPatternApp {
         //     .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_pattern_covers_whole_board() {
+        let mut app = GenCalibPatternApp::default();
+        let image = app.generate_pattern().unwrap();
+        assert_eq!(image.size, [10 * 60, 7 * 60]);
+    }
+
+    #[test]
+    fn invalid_board_is_an_error() {
+        let mut app = GenCalibPatternApp {
+            marker_length: 61,
+            ..GenCalibPatternApp::default()
+        };
+        assert!(app.generate_pattern().is_err());
+
+        let mut app = GenCalibPatternApp {
+            size: Size::new(0, 0),
+            ..GenCalibPatternApp::default()
+        };
+        assert!(app.generate_pattern().is_err());
+    }
+}
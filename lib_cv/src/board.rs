@@ -0,0 +1,347 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use opencv::core::Size;
+use opencv::objdetect::{CharucoBoard, PredefinedDictionaryType, get_predefined_dictionary};
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::predefined_dictionary_from_name;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BoardConfigError {
+    #[error("Не удалось прочитать или записать файл доски {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Некорректный файл доски {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Не удалось сериализовать параметры доски: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Неизвестный словарь маркеров {0}")]
+    UnknownDictionary(String),
+    #[error("Некорректная геометрия доски: {0}")]
+    InvalidGeometry(String),
+    #[error(transparent)]
+    OpenCv(#[from] opencv::Error),
+}
+
+impl From<BoardConfigError> for opencv::Error {
+    fn from(e: BoardConfigError) -> Self {
+        match e {
+            BoardConfigError::OpenCv(e) => e,
+            other => opencv::Error::new(opencv::core::StsError, other.to_string()),
+        }
+    }
+}
+
+/// Имя файла доски, который сохраняется рядом с файлом калибровки
+pub const BOARD_CONFIG_FILE: &str = "board.toml";
+
+/// Единица длин доски. Калибровка не знает единиц: расстояния между камерами и координаты
+/// облаков получаются в тех же единицах, в которых заданы квадрат и маркер доски
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LengthUnit {
+    #[serde(rename = "mm")]
+    Millimeters,
+    #[serde(rename = "cm")]
+    Centimeters,
+    #[serde(rename = "m")]
+    Meters,
+    /// Пиксели изображения паттерна из generate_calibration_pattern: настоящий размер
+    /// зависит от печати, поэтому такие единицы нужно пересчитать по измеренной доске
+    #[serde(rename = "px")]
+    PatternPixels,
+    /// Единица не указана: файлы доски и калибровки прежних версий
+    #[default]
+    #[serde(rename = "unspecified")]
+    Unspecified,
+}
+
+impl LengthUnit {
+    /// Обозначение латиницей, как в файлах: `mm`, `cm`, `m`, `px`, `unspecified`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Millimeters => "mm",
+            Self::Centimeters => "cm",
+            Self::Meters => "m",
+            Self::PatternPixels => "px",
+            Self::Unspecified => "unspecified",
+        }
+    }
+
+    /// Обозначение для лога
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Millimeters => "мм",
+            Self::Centimeters => "см",
+            Self::Meters => "м",
+            Self::PatternPixels => "пикс. паттерна",
+            Self::Unspecified => "ед. доски",
+        }
+    }
+}
+
+impl std::fmt::Display for LengthUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for LengthUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Self::Millimeters,
+            Self::Centimeters,
+            Self::Meters,
+            Self::PatternPixels,
+            Self::Unspecified,
+        ]
+        .into_iter()
+        .find(|unit| unit.name() == s)
+        .ok_or_else(|| {
+            format!(
+                "Неизвестная единица длины {}: ожидается mm, cm, m, px или unspecified",
+                s
+            )
+        })
+    }
+}
+
+/// Геометрия доски ChArUco, которую генератор паттерна сохраняет рядом с изображением,
+/// а calibration_app читает, чтобы не вводить параметры вручную
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CharucoBoardConfig {
+    /// Количество квадратов по горизонтали
+    pub squares_x: i32,
+    /// Количество квадратов по вертикали
+    pub squares_y: i32,
+    /// Длина стороны квадрата
+    pub square_length: f32,
+    /// Длина стороны маркера (в тех же единицах, что и квадрат)
+    pub marker_length: f32,
+    /// Имя словаря маркеров, например `DICT_4X4_50`
+    pub dictionary: String,
+    /// Единица длин квадрата и маркера (`unspecified` в файлах прежних версий)
+    #[serde(default)]
+    pub unit: LengthUnit,
+}
+
+impl CharucoBoardConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BoardConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|source| BoardConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let config: Self = toml::from_str(&text).map_err(|source| BoardConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        // Проверяем параметры сразу, чтобы ошибка указывала на файл, а не на построение доски
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), BoardConfigError> {
+        let path = path.as_ref();
+        let text = toml::to_string_pretty(self)?;
+        fs::write(path, text).map_err(|source| BoardConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        info!("Параметры доски сохранены в {}", path.display());
+        Ok(())
+    }
+
+    /// Файл доски, сохранённый рядом с файлом калибровки `calibration_file`
+    pub fn path_for_calibration(calibration_file: &Path) -> PathBuf {
+        calibration_file.with_file_name(BOARD_CONFIG_FILE)
+    }
+
+    /// Читает доску, с которой была сделана калибровка. `None`, если файла доски нет
+    pub fn load_for_calibration(calibration_file: &Path) -> Result<Option<Self>, BoardConfigError> {
+        let path = Self::path_for_calibration(calibration_file);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::load(path).map(Some)
+    }
+
+    /// Описания расхождений с другой доской, пусто - доски совпадают.
+    /// Длины сравниваются с относительным допуском, чтобы не реагировать на округление
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        if (self.squares_x, self.squares_y) != (other.squares_x, other.squares_y) {
+            differences.push(format!(
+                "размер {}x{} вместо {}x{}",
+                other.squares_x, other.squares_y, self.squares_x, self.squares_y
+            ));
+        }
+        let lengths = [
+            ("квадрат", self.square_length, other.square_length),
+            ("маркер", self.marker_length, other.marker_length),
+        ];
+        for (name, expected, actual) in lengths {
+            if (expected - actual).abs() > 1e-3 * expected.abs().max(1.0) {
+                differences.push(format!("{} {} вместо {}", name, actual, expected));
+            }
+        }
+        if self.dictionary != other.dictionary {
+            differences.push(format!(
+                "словарь {} вместо {}",
+                other.dictionary, self.dictionary
+            ));
+        }
+        // Неуказанная единица не противоречит никакой: файлы прежних версий её не хранят
+        let units_known = ![self.unit, other.unit].contains(&LengthUnit::Unspecified);
+        if units_known && self.unit != other.unit {
+            differences.push(format!("единицы {} вместо {}", other.unit, self.unit));
+        }
+        differences
+    }
+
+    /// Проверяет словарь и геометрию: размеры положительны, маркер меньше квадрата
+    pub fn validate(&self) -> Result<(), BoardConfigError> {
+        self.dictionary_type()?;
+        if self.squares_x < 2 || self.squares_y < 2 {
+            return Err(BoardConfigError::InvalidGeometry(format!(
+                "нужно минимум 2x2 квадрата, задано {}x{}",
+                self.squares_x, self.squares_y
+            )));
+        }
+        if !(self.marker_length > 0.0 && self.marker_length < self.square_length) {
+            return Err(BoardConfigError::InvalidGeometry(format!(
+                "маркер ({}) должен быть больше нуля и меньше квадрата ({})",
+                self.marker_length, self.square_length
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn dictionary_type(&self) -> Result<PredefinedDictionaryType, BoardConfigError> {
+        predefined_dictionary_from_name(&self.dictionary)
+            .ok_or_else(|| BoardConfigError::UnknownDictionary(self.dictionary.clone()))
+    }
+
+    pub fn to_board(&self) -> Result<CharucoBoard, BoardConfigError> {
+        self.validate()?;
+        let dictionary = get_predefined_dictionary(self.dictionary_type()?)?;
+        Ok(CharucoBoard::new_def(
+            Size::new(self.squares_x, self.squares_y),
+            self.square_length,
+            self.marker_length,
+            &dictionary,
+        )?)
+    }
+}
+
+/// Построитель доски ChArUco - единый источник параметров доски для всех приложений.
+/// Не заданные явно параметры берутся по умолчанию: 10x5 квадратов, квадрат 13 мм,
+/// маркер 9.1 мм, словарь `DICT_4X4_50`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardConfig {
+    config: CharucoBoardConfig,
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        Self {
+            config: CharucoBoardConfig {
+                squares_x: 10,
+                squares_y: 5,
+                square_length: 13.0,
+                marker_length: 9.1,
+                dictionary: format!("{:?}", PredefinedDictionaryType::DICT_4X4_50),
+                unit: LengthUnit::Millimeters,
+            },
+        }
+    }
+}
+
+impl From<CharucoBoardConfig> for BoardConfig {
+    fn from(config: CharucoBoardConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl BoardConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Количество квадратов по горизонтали и вертикали
+    pub fn size(mut self, squares_x: i32, squares_y: i32) -> Self {
+        self.config.squares_x = squares_x;
+        self.config.squares_y = squares_y;
+        self
+    }
+
+    pub fn square_len(mut self, square_length: f32) -> Self {
+        self.config.square_length = square_length;
+        self
+    }
+
+    pub fn marker_len(mut self, marker_length: f32) -> Self {
+        self.config.marker_length = marker_length;
+        self
+    }
+
+    pub fn dict(mut self, dictionary: PredefinedDictionaryType) -> Self {
+        self.config.dictionary = format!("{:?}", dictionary);
+        self
+    }
+
+    /// Единица длин квадрата и маркера
+    pub fn unit(mut self, unit: LengthUnit) -> Self {
+        self.config.unit = unit;
+        self
+    }
+
+    pub fn config(&self) -> &CharucoBoardConfig {
+        &self.config
+    }
+
+    pub fn into_config(self) -> CharucoBoardConfig {
+        self.config
+    }
+
+    pub fn build(&self) -> Result<CharucoBoard, opencv::Error> {
+        Ok(self.config.to_board()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opencv::core::MatTraitConst;
+    use opencv::objdetect::{BoardTraitConst, CharucoBoardTraitConst, DictionaryTraitConst};
+
+    use super::*;
+
+    #[test]
+    fn built_board_matches_config() {
+        let builder = BoardConfig::new()
+            .size(7, 4)
+            .square_len(20.0)
+            .marker_len(15.0)
+            .dict(PredefinedDictionaryType::DICT_5X5_100);
+        let board = builder.build().unwrap();
+        assert_eq!(board.get_chessboard_size().unwrap(), Size::new(7, 4));
+        assert_eq!(board.get_square_length().unwrap(), 20.0);
+        assert_eq!(board.get_marker_length().unwrap(), 15.0);
+        let dictionary = board.get_dictionary().unwrap();
+        assert_eq!(dictionary.marker_size(), 5);
+        assert_eq!(dictionary.bytes_list().rows(), 100);
+        assert_eq!(builder.config().dictionary, "DICT_5X5_100");
+    }
+
+    #[test]
+    fn marker_larger_than_square_is_rejected() {
+        let builder = BoardConfig::new().square_len(10.0).marker_len(12.0);
+        assert!(builder.build().is_err());
+    }
+}
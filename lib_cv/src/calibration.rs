@@ -1,31 +1,202 @@
-use std::collections::HashSet;
-use std::fs;
-use std::path::Path;
-
-use log::{debug, error, info};
-use opencv::calib3d::{calibrate_camera, stereo_calibrate};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, error, info, warn};
+use opencv::calib3d::{
+    SOLVEPNP_ITERATIVE, calibrate_camera, project_points, solve_pnp, stereo_calibrate,
+    undistort_points,
+};
 use opencv::core::{
-    FileStorage, FileStorage_Mode, NORM_L2, Point2f, TermCriteria, TermCriteria_Type, Vector, norm,
+    CV_8U, CV_32F, CV_64F, DMatch, FileStorage, FileStorage_Mode, KeyPoint, NORM_L2, NORM_MINMAX,
+    Point, Point2f, Scalar, Size, StsError, TermCriteria, TermCriteria_Type, Vector, gemm, merge,
+    no_array, norm, norm2, normalize, split, sv_decomp_def,
+};
+use opencv::imgcodecs::{IMREAD_COLOR, imread, imwrite};
+use opencv::imgproc::{
+    COLOR_BGR2Lab, COLOR_Lab2BGR, COLORMAP_JET, LINE_AA, MARKER_CROSS, apply_color_map, circle,
+    contour_area_def, convex_hull_def, create_clahe, cvt_color_def, draw_marker, gaussian_blur_def,
+    line,
+};
+use opencv::objdetect::{
+    ArucoDetector, CharucoBoard, CharucoDetector, PredefinedDictionaryType,
+    get_predefined_dictionary,
 };
-use opencv::imgcodecs::{IMREAD_COLOR, imread};
-use opencv::objdetect::{CharucoBoard, CharucoDetector};
 use opencv::prelude::*;
 use opencv::{self, Error};
 
-pub fn get_charuco(
-    charuco_board: &CharucoBoard,
+use crate::board::LengthUnit;
+use crate::correspondence::refine_subpixel;
+use crate::utils::{
+    CLIP_FRAME_STRIDE, CombinedVideoSource, FrameSource, GridLayout, PICKED_MANIFEST_FILE,
+    PickedManifest, UtilsError, datetime_stamp, list_picked_calibration_images, path_to_str,
+    write_atomically,
+};
+
+/// Ищет предопределённый словарь ArUco по имени вида `DICT_4X4_50`
+pub fn predefined_dictionary_from_name(name: &str) -> Option<PredefinedDictionaryType> {
+    (0..=21)
+        .filter_map(|i| PredefinedDictionaryType::try_from(i).ok())
+        .find(|dict| format!("{:?}", dict) == name)
+}
+
+/// Семейства словарей ArUco, перебираемые при проверке словаря: размер маркера в битах
+/// и размеры словарей семейства по возрастанию
+const PROBED_DICTIONARY_FAMILIES: [(u32, [usize; 4]); 4] = [
+    (4, [50, 100, 250, 1000]),
+    (5, [50, 100, 250, 1000]),
+    (6, [50, 100, 250, 1000]),
+    (7, [50, 100, 250, 1000]),
+];
+
+/// Сколько маркеров нужно найти, чтобы словарь считался определённым
+const MIN_PROBE_MARKERS: usize = 4;
+
+/// Проверяет, в каком словаре декодируются маркеры на изображении. Возвращает словарь
+/// и число найденных в нём маркеров, если он явно лучше `configured`: маркеров найдено
+/// не меньше `MIN_PROBE_MARKERS` и хотя бы вдвое больше, чем с `configured`.
+/// Из семейства выбирается наименьший словарь, вмещающий найденные номера маркеров.
+/// Поиск идёт по каждому семейству, поэтому проверку стоит делать один раз, а не на каждом кадре
+pub fn probe_dictionary(
     img: &Mat,
-) -> Result<
-    (
-        Vector<Vector<Point2f>>,
-        Vector<i32>,
-        Vector<Point2f>,
-        Vector<i32>,
-        Mat,
-        Mat,
-    ),
-    Error,
-> {
+    configured: PredefinedDictionaryType,
+) -> Result<Option<(PredefinedDictionaryType, usize)>, Error> {
+    let mut detector = ArucoDetector::new_def()?;
+    let mut count_markers = |dictionary: PredefinedDictionaryType| -> Result<Vector<i32>, Error> {
+        detector.set_dictionary(&get_predefined_dictionary(dictionary)?)?;
+        let mut corners = Vector::<Vector<Point2f>>::new();
+        let mut ids = Vector::<i32>::new();
+        detector.detect_markers_def(img, &mut corners, &mut ids)?;
+        Ok(ids)
+    };
+
+    let configured_count = count_markers(configured)?.len();
+    let mut best: Option<(PredefinedDictionaryType, usize)> = None;
+    for (bits, sizes) in PROBED_DICTIONARY_FAMILIES {
+        let Some(largest) =
+            predefined_dictionary_from_name(&format!("DICT_{}X{}_{}", bits, bits, sizes[3]))
+        else {
+            continue;
+        };
+        let ids = count_markers(largest)?;
+        let max_id = ids.iter().max().unwrap_or(0).max(0) as usize;
+        let smallest = sizes
+            .iter()
+            .find(|&&size| size > max_id)
+            .and_then(|size| {
+                predefined_dictionary_from_name(&format!("DICT_{}X{}_{}", bits, bits, size))
+            })
+            .unwrap_or(largest);
+        debug!("Словарь {:?}: найдено {} маркеров", smallest, ids.len());
+        if best.is_none_or(|(_, count)| ids.len() > count) {
+            best = Some((smallest, ids.len()));
+        }
+    }
+
+    Ok(best.filter(|&(dictionary, count)| {
+        dictionary != configured && count >= MIN_PROBE_MARKERS && count >= 2 * configured_count
+    }))
+}
+
+/// Результат поиска доски ChArUco на изображении
+#[derive(Debug, Clone)]
+pub struct CharucoDetection {
+    /// Углы найденных маркеров ArUco
+    pub marker_corners: Vector<Vector<Point2f>>,
+    pub marker_ids: Vector<i32>,
+    /// Найденные углы шахматной доски
+    pub charuco_corners: Vector<Point2f>,
+    pub charuco_ids: Vector<i32>,
+    /// 3D координаты найденных углов в системе доски
+    pub object_points: Mat,
+    /// Соответствующие им 2D точки изображения
+    pub image_points: Mat,
+    /// Насколько кадр пригоден для калибровки
+    pub quality: DetectionQuality,
+}
+
+/// Сторона маркера на изображении, пикс., начиная с которой маркер считается крупным:
+/// углы мелких маркеров находятся менее точно
+const GOOD_MARKER_SIDE_PX: f64 = 40.0;
+
+/// Оценка найденной на кадре доски: чем больше углов найдено, чем большую часть кадра они
+/// покрывают и чем крупнее маркеры, тем точнее кадр для калибровки
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DetectionQuality {
+    /// Доля найденных углов доски, 0..1
+    pub corner_ratio: f64,
+    /// Площадь выпуклой оболочки найденных углов в долях площади кадра, 0..1
+    pub coverage: f64,
+    /// Средняя длина стороны найденных маркеров, пикс.
+    pub mean_marker_side: f64,
+}
+
+impl DetectionQuality {
+    fn measure(
+        charuco_board: &CharucoBoard,
+        image_size: Size,
+        charuco_corners: &Vector<Point2f>,
+        marker_corners: &Vector<Vector<Point2f>>,
+    ) -> Result<Self, Error> {
+        let board_corners = charuco_board.get_chessboard_corners()?.len();
+        let corner_ratio = if board_corners > 0 {
+            charuco_corners.len() as f64 / board_corners as f64
+        } else {
+            0.0
+        };
+
+        let image_area = image_size.area() as f64;
+        let coverage = if charuco_corners.len() >= 3 && image_area > 0.0 {
+            let mut hull = Vector::<Point2f>::new();
+            convex_hull_def(charuco_corners, &mut hull)?;
+            (contour_area_def(&hull)? / image_area).min(1.0)
+        } else {
+            0.0
+        };
+
+        let sides: Vec<f64> = marker_corners
+            .iter()
+            .flat_map(|marker| {
+                let n = marker.len();
+                (0..n)
+                    .filter_map(|i| Some((marker.get(i).ok()?, marker.get((i + 1) % n).ok()?)))
+                    .map(|(a, b)| ((a.x - b.x) as f64).hypot((a.y - b.y) as f64))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mean_marker_side = if sides.is_empty() {
+            0.0
+        } else {
+            sides.iter().sum::<f64>() / sides.len() as f64
+        };
+
+        Ok(Self {
+            corner_ratio,
+            coverage,
+            mean_marker_side,
+        })
+    }
+
+    /// Итоговая оценка 0..1: среднее доли углов, покрытия и размера маркеров (маркеры
+    /// со стороной от [`GOOD_MARKER_SIDE_PX`] считаются одинаково хорошими)
+    pub fn score(&self) -> f64 {
+        let marker_size = (self.mean_marker_side / GOOD_MARKER_SIDE_PX).min(1.0);
+        (self.corner_ratio + self.coverage + marker_size) / 3.0
+    }
+}
+
+/// Номера `k` кадров с наибольшей оценкой, по возрастанию номера. `scored_frames` - пары
+/// (номер кадра, оценка); при равных оценках предпочтение отдаётся более раннему кадру
+pub fn select_best_frames(scored_frames: &[(usize, f64)], k: usize) -> Vec<usize> {
+    let mut ranked = scored_frames.to_vec();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    let mut best: Vec<usize> = ranked.into_iter().take(k).map(|(frame, _)| frame).collect();
+    best.sort_unstable();
+    best
+}
+
+pub fn get_charuco(charuco_board: &CharucoBoard, img: &Mat) -> Result<CharucoDetection, Error> {
     let charuco_detector = CharucoDetector::new_def(charuco_board)?;
     let mut charuco_corners: Vector<Point2f> = Vector::new();
     let mut charuco_ids: Vector<i32> = Vector::new();
@@ -39,76 +210,150 @@ pub fn get_charuco(
         &mut marker_ids,
     )?;
 
-    let mut obj_points: Mat = Mat::default();
-    let mut img_points: Mat = Mat::default();
+    let mut object_points: Mat = Mat::default();
+    let mut image_points: Mat = Mat::default();
     let _ = charuco_board.match_image_points(
         &charuco_corners,
         &charuco_ids,
-        &mut obj_points,
-        &mut img_points,
+        &mut object_points,
+        &mut image_points,
     );
+    let quality = DetectionQuality::measure(
+        charuco_board,
+        img.size()?,
+        &charuco_corners,
+        &marker_corners,
+    )?;
 
-    Ok((
+    Ok(CharucoDetection {
         marker_corners,
         marker_ids,
         charuco_corners,
         charuco_ids,
-        obj_points,
-        img_points,
-    ))
+        object_points,
+        image_points,
+        quality,
+    })
+}
+
+/// Параметры выравнивания контраста (CLAHE) перед поиском доски на тёмных кадрах
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContrastEnhancement {
+    /// Ограничение усиления контраста: больше - сильнее выравнивание и шум
+    pub clip_limit: f64,
+    /// Число плиток по каждой стороне изображения, в которых выравнивается гистограмма
+    pub tile_grid: i32,
+}
+
+impl Default for ContrastEnhancement {
+    fn default() -> Self {
+        Self {
+            clip_limit: 2.0,
+            tile_grid: 8,
+        }
+    }
+}
+
+impl ContrastEnhancement {
+    /// Применяет CLAHE к яркости изображения (канал L пространства Lab), цвета не меняются
+    pub fn apply(&self, img: &Mat) -> Result<Mat, Error> {
+        let mut clahe = create_clahe(self.clip_limit, Size::new(self.tile_grid, self.tile_grid))?;
+        let mut enhanced = Mat::default();
+        if img.channels() == 1 {
+            clahe.apply(img, &mut enhanced)?;
+            return Ok(enhanced);
+        }
+        let mut lab = Mat::default();
+        cvt_color_def(img, &mut lab, COLOR_BGR2Lab)?;
+        let mut channels = Vector::<Mat>::new();
+        split(&lab, &mut channels)?;
+        let mut lightness = Mat::default();
+        clahe.apply(&channels.get(0)?, &mut lightness)?;
+        channels.set(0, lightness)?;
+        merge(&channels, &mut lab)?;
+        cvt_color_def(&lab, &mut enhanced, COLOR_Lab2BGR)?;
+        Ok(enhanced)
+    }
+}
+
+/// Как [`get_charuco`], но с `enhancement` доска ищется на изображении с выровненным
+/// контрастом, а углы затем уточняются по исходному изображению: выравнивание искажает
+/// градиенты, по которым уточняются углы
+pub fn get_charuco_enhanced(
+    charuco_board: &CharucoBoard,
+    img: &Mat,
+    enhancement: Option<&ContrastEnhancement>,
+) -> Result<CharucoDetection, Error> {
+    let Some(enhancement) = enhancement else {
+        return get_charuco(charuco_board, img);
+    };
+    let mut detection = get_charuco(charuco_board, &enhancement.apply(img)?)?;
+    if !detection.charuco_corners.is_empty() {
+        detection.charuco_corners = refine_subpixel(img, &detection.charuco_corners)?;
+        detection.object_points = Mat::default();
+        detection.image_points = Mat::default();
+        let _ = charuco_board.match_image_points(
+            &detection.charuco_corners,
+            &detection.charuco_ids,
+            &mut detection.object_points,
+            &mut detection.image_points,
+        );
+    }
+    Ok(detection)
 }
 
+/// Результат калибровки внутренних параметров одной камеры: RMS, матрица камеры,
+/// дисторсия, позы доски (rvecs, tvecs), точки доски и изображения, id и углы ChArUco
+/// по кадрам с найденной доской
+pub type IntrinsicCalibration = (
+    f64,
+    Mat,
+    Mat,
+    Vector<Mat>,
+    Vector<Mat>,
+    Vector<Mat>,
+    Vector<Mat>,
+    Vector<Vector<i32>>,
+    Vector<Vector<Point2f>>,
+);
+
+/// Калибрует внутренние параметры камеры по её кадрам. С `enhancement` доска ищется
+/// на кадрах с выровненным контрастом (см. [`get_charuco_enhanced`])
 pub fn calibrate_with_charuco(
     imgs: &Vector<Mat>,
     charuco_board: &CharucoBoard,
-) -> Result<
-    (
-        f64,
-        Mat,
-        Mat,
-        Vector<Mat>,
-        Vector<Mat>,
-        Vector<Mat>,
-        Vector<Mat>,
-        Vector<Vector<i32>>,
-        Vector<Vector<Point2f>>,
-    ),
-    Error,
-> {
-    let charuco_detector = CharucoDetector::new_def(charuco_board)?;
+    enhancement: Option<&ContrastEnhancement>,
+) -> Result<IntrinsicCalibration, Error> {
+    let img_size = imgs.get(0)?.size()?;
+    let detections = imgs
+        .iter()
+        .map(|img| get_charuco_enhanced(charuco_board, &img, enhancement))
+        .collect::<Result<Vec<_>, Error>>()?;
+    calibrate_with_detections(&detections, img_size)
+}
 
+/// Калибрует внутренние параметры камеры по уже найденным на её кадрах доскам, как
+/// [`calibrate_with_charuco`]. Кадры без найденных углов пропускаются
+pub fn calibrate_with_detections(
+    detections: &[CharucoDetection],
+    img_size: Size,
+) -> Result<IntrinsicCalibration, Error> {
     let mut all_charuco_corners = Vector::<Vector<Point2f>>::new();
     let mut all_charuco_ids = Vector::<Vector<i32>>::new();
     let mut all_object_points = Vector::<Mat>::new();
     let mut all_image_points = Vector::<Mat>::new();
 
-    let img_size = imgs.get(0)?.size()?;
-
-    for img in imgs {
-        let mut charuco_corners: Vector<Point2f> = Vector::new();
-        let mut charuco_ids: Vector<i32> = Vector::new();
-        charuco_detector.detect_board_def(&img, &mut charuco_corners, &mut charuco_ids)?;
-        if charuco_corners.is_empty() {
-            continue;
-        }
-
-        let mut obj_points = Mat::default();
-        let mut img_points = Mat::default();
-
-        charuco_board.match_image_points(
-            &charuco_corners,
-            &charuco_ids,
-            &mut obj_points,
-            &mut img_points,
-        )?;
-
-        if obj_points.empty() || img_points.empty() {
+    for detection in detections {
+        if detection.charuco_corners.is_empty()
+            || detection.object_points.empty()
+            || detection.image_points.empty()
+        {
             continue;
         }
-        all_charuco_corners.push(charuco_corners);
-        all_charuco_ids.push(charuco_ids);
-        all_object_points.push(obj_points);
-        all_image_points.push(img_points);
+        all_charuco_corners.push(detection.charuco_corners.clone());
+        all_charuco_ids.push(detection.charuco_ids.clone());
+        all_object_points.push(detection.object_points.clone());
+        all_image_points.push(detection.image_points.clone());
     }
 
     let mut camera_matrix = Mat::default();
@@ -147,61 +392,206 @@ pub fn calibrate_with_charuco(
     ))
 }
 
+/// Этап калибровки, о котором сообщает колбэк прогресса
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationStage {
+    /// Чтение выбранных изображений
+    LoadingImages,
+    /// Калибровка внутренних параметров камеры `camera` (с 0) из `total`
+    Intrinsics { camera: usize, total: usize },
+    /// Стереокалибровка пары основной камеры `primary` и камеры `camera` (с 0)
+    StereoPair {
+        primary: usize,
+        camera: usize,
+        total: usize,
+    },
+    /// Сохранение результатов
+    Saving,
+}
+
+impl std::fmt::Display for CalibrationStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LoadingImages => write!(f, "loading images"),
+            Self::Intrinsics { camera, total } => {
+                write!(
+                    f,
+                    "calibrating intrinsics (camera {}/{})",
+                    camera + 1,
+                    total
+                )
+            }
+            Self::StereoPair {
+                primary, camera, ..
+            } => write!(f, "stereo pair {}-{}", primary, camera),
+            Self::Saving => write!(f, "saving"),
+        }
+    }
+}
+
+impl CalibrationStage {
+    /// Номер этапа и общее число этапов калибровки `cameras` камер: для индикатора прогресса
+    pub fn position(&self, cameras: usize) -> (usize, usize) {
+        let total = 2 * cameras + 1;
+        let done = match *self {
+            Self::LoadingImages => 0,
+            Self::Intrinsics { camera, .. } => 1 + camera,
+            // Пары нумеруются по порядку камер без основной
+            Self::StereoPair {
+                primary, camera, ..
+            } => cameras + 1 + camera - usize::from(camera > primary),
+            Self::Saving => total - 1,
+        };
+        (done, total)
+    }
+}
+
+fn cancelled_error() -> Error {
+    Error::new(StsError, "Калибровка отменена")
+}
+
+/// Минимум общих с основной камерой углов, при котором сцена участвует в стереокалибровке пары
+pub const MIN_STEREO_SHARED_CORNERS: usize = 10;
+
+/// Флаги `stereo_calibrate` для пар основной камеры с остальными (`CALIB_*` из calib3d).
+///
+/// По умолчанию `CALIB_FIX_INTRINSIC`: внутренние параметры берутся из калибровки каждой
+/// камеры и не меняются, оцениваются только R и t пары. Это надёжно, когда внутренние
+/// параметры точные, и устойчиво при малом числе общих сцен.
+/// [`StereoFlags::REFINE_INTRINSICS`] (`CALIB_USE_INTRINSIC_GUESS`) уточняет их совместно
+/// с позой пары: полезно, когда калибровка отдельных камер лишь приблизительная, но требует
+/// много разнообразных общих сцен, иначе параметры могут уйти дальше, чем были.
+/// Уточнённые параметры сохраняются для второй камеры пары. Основная камера уточняется в
+/// каждой паре по-своему; для неё сохраняются параметры из пары с наибольшим числом общих
+/// сцен (при равенстве - с меньшей ошибкой стереокалибровки)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StereoFlags(pub i32);
+
+impl StereoFlags {
+    pub const FIX_INTRINSIC: Self = Self(opencv::calib3d::CALIB_FIX_INTRINSIC);
+    pub const REFINE_INTRINSICS: Self = Self(opencv::calib3d::CALIB_USE_INTRINSIC_GUESS);
+
+    /// Меняет ли `stereo_calibrate` внутренние параметры камер
+    pub fn refines_intrinsics(&self) -> bool {
+        self.0 & opencv::calib3d::CALIB_FIX_INTRINSIC == 0
+    }
+}
+
+impl Default for StereoFlags {
+    fn default() -> Self {
+        Self::FIX_INTRINSIC
+    }
+}
+
+/// Калибрует камеры по наборам изображений. Камера `primary` задаёт систему координат
+/// (единичный поворот, нулевой сдвиг), положение остальных находится стереокалибровкой
+/// пары с ней, поэтому основной лучше выбирать камеру, видящую доску вместе с наибольшим
+/// числом других. Стереокалибровка пар выполняется с флагами `stereo_flags`.
+/// С `enhancement` доска ищется на кадрах с выровненным контрастом.
+/// Перед каждым этапом вызывается `progress`; если он вернул `false`,
+/// калибровка прерывается с ошибкой
 pub fn calibrate_multiple_with_charuco(
-    imgs: &Vec<Vector<Mat>>,
+    imgs: &[Vector<Mat>],
     charuco_board: &CharucoBoard,
+    stereo_flags: StereoFlags,
+    primary: usize,
+    enhancement: Option<&ContrastEnhancement>,
+    progress: &mut dyn FnMut(CalibrationStage) -> bool,
 ) -> Result<Vec<CameraParameters>, opencv::Error> {
-    debug!("Начало калибровки камер");
     debug!("Параметры доски ChArUco: {:?}", charuco_board);
+    let mut detections = Vec::with_capacity(imgs.len());
+    let mut image_sizes = Vec::with_capacity(imgs.len());
+    for img_set in imgs {
+        image_sizes.push(match img_set.iter().next() {
+            Some(img) => img.size()?,
+            None => Size::default(),
+        });
+        detections.push(
+            img_set
+                .iter()
+                .map(|img| get_charuco_enhanced(charuco_board, &img, enhancement))
+                .collect::<Result<Vec<_>, Error>>()?,
+        );
+    }
+    calibrate_multiple_with_detections(&detections, &image_sizes, stereo_flags, primary, progress)
+}
+
+/// Как [`calibrate_multiple_with_charuco`], но по уже найденным доскам: `detections[cam][i]` -
+/// доска на кадре камеры `cam` сцены `i`, `image_sizes[cam]` - размер кадров камеры.
+/// Самих кадров не требуется, поэтому сцены можно собирать, не держа изображения в памяти
+pub fn calibrate_multiple_with_detections(
+    detections: &[Vec<CharucoDetection>],
+    image_sizes: &[Size],
+    stereo_flags: StereoFlags,
+    primary: usize,
+    progress: &mut dyn FnMut(CalibrationStage) -> bool,
+) -> Result<Vec<CameraParameters>, opencv::Error> {
+    debug!("Начало калибровки камер");
+    debug!("Флаги стереокалибровки: {:?}", stereo_flags);
     let mut ret: Vec<f64> = Vec::default();
     let mut camera_matrix: Vec<Mat> = Vec::default();
     let mut dist_coeffs: Vec<Mat> = Vec::default();
-    let mut r_vecs: Vec<Vector<Mat>> = Vec::default();
-    let mut t_vecs: Vec<Vector<Mat>> = Vec::default();
-    let mut object_points: Vec<Vector<Mat>> = Vec::default();
-    let mut image_points: Vec<Vector<Mat>> = Vec::default();
-    let mut charuco_ids: Vec<Vector<Vector<i32>>> = Vec::default();
-    let mut charuco_corners: Vec<Vector<Vector<Point2f>>> = Vec::default();
-
-    if imgs.len() < 2 {
+
+    if detections.len() < 2 {
         error!("Ошибка: для калибровки требуется как минимум 2 набора изображений");
         return Ok(vec![]);
     }
+    if primary >= detections.len() {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Основная камера {} вне диапазона: наборов изображений {}",
+                primary,
+                detections.len()
+            ),
+        ));
+    }
+    if image_sizes.len() != detections.len() {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Размеры кадров заданы для {} камер из {}",
+                image_sizes.len(),
+                detections.len()
+            ),
+        ));
+    }
 
     debug!(
         "Количество наборов изображений для калибровки: {}",
-        imgs.len()
+        detections.len()
     );
 
-    for img_set in imgs {
-        match calibrate_with_charuco(img_set, charuco_board) {
-            Ok((
-                curr_cam_ret_val,
-                curr_cam_camera_matrix_val,
-                curr_cam_dist_coeffs_val,
-                curr_cam_r_vecs_val,
-                curr_cam_t_vecs_val,
-                curr_cam_all_object_points_val,
-                curr_cam_all_image_points_val,
-                curr_cam_all_charuco_ids,
-                curr_cam_charuco_corners,
-            )) => {
+    for (camera, camera_detections) in detections.iter().enumerate() {
+        if !progress(CalibrationStage::Intrinsics {
+            camera,
+            total: detections.len(),
+        }) {
+            return Err(cancelled_error());
+        }
+        match calibrate_with_detections(camera_detections, image_sizes[camera]) {
+            Ok((curr_cam_ret_val, curr_cam_camera_matrix_val, curr_cam_dist_coeffs_val, ..)) => {
                 debug!("Ошибка обычной калибровки {}", curr_cam_ret_val);
                 ret.push(curr_cam_ret_val);
                 camera_matrix.push(curr_cam_camera_matrix_val);
                 dist_coeffs.push(curr_cam_dist_coeffs_val);
-                r_vecs.push(curr_cam_r_vecs_val);
-                t_vecs.push(curr_cam_t_vecs_val);
-                object_points.push(curr_cam_all_object_points_val);
-                image_points.push(curr_cam_all_image_points_val);
-                charuco_ids.push(curr_cam_all_charuco_ids);
-                charuco_corners.push(curr_cam_charuco_corners);
             }
-            Err(e) => error!("Ошибка калибровки calibrate_with_charuco: {:?}", e),
+            Err(e) => error!("Ошибка калибровки calibrate_with_detections: {:?}", e),
         }
     }
 
     let camera_count = camera_matrix.len();
+    if camera_count != detections.len() {
+        // Номера камер сместились бы относительно наборов изображений
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Внутренние параметры найдены только для {} камер из {}",
+                camera_count,
+                detections.len()
+            ),
+        ));
+    }
 
     let criteria = TermCriteria::new(
         TermCriteria_Type::COUNT as i32 | TermCriteria_Type::EPS as i32,
@@ -210,37 +600,53 @@ pub fn calibrate_multiple_with_charuco(
     )
     .unwrap();
 
-    let mut cameras = Vec::with_capacity(camera_count);
+    let mut cameras: Vec<Option<CameraParameters>> = vec![None; camera_count];
 
-    // Параметры для первой камеры (основной). Вообще можно сделать выбор основной камеры кастомизируемый.
-    cameras.push(CameraParameters {
-        intrinsic: camera_matrix[0].clone(),
-        distortion: dist_coeffs[0].clone(),
-        ..CameraParameters::new().unwrap()
+    // Основная камера задаёт систему координат
+    cameras[primary] = Some(CameraParameters {
+        intrinsic: camera_matrix[primary].clone(),
+        distortion: dist_coeffs[primary].clone(),
+        rms_error: Some(ret[primary]),
+        ..CameraParameters::new()?
     });
 
-    for i in 1..camera_count {
+    // Уточнённые параметры основной камеры из самой обеспеченной сценами пары:
+    // число общих сцен, ошибка стереокалибровки, матрица и дисторсия
+    let mut primary_refined: Option<(usize, f64, Mat, Mat)> = None;
+
+    for i in (0..camera_count).filter(|&i| i != primary) {
+        if !progress(CalibrationStage::StereoPair {
+            primary,
+            camera: i,
+            total: camera_count,
+        }) {
+            return Err(cancelled_error());
+        }
         let mut common_object_points = Vector::<Mat>::new();
         let mut common_image_points1 = Vector::<Mat>::new();
         let mut common_image_points2 = Vector::<Mat>::new();
 
-        for frame_idx in 0..charuco_ids[0].len() {
-            let ids_cam1 = &charuco_ids[0].get(frame_idx)?;
-            let ids_cam2 = &charuco_ids[i].get(frame_idx)?;
+        // Сцены сопоставляются по индексу: кадры без доски не сдвигают нумерацию
+        for (frame_idx, (detection1, detection2)) in
+            detections[primary].iter().zip(&detections[i]).enumerate()
+        {
+            let ids_cam1 = &detection1.charuco_ids;
+            let ids_cam2 = &detection2.charuco_ids;
             debug!("Содержимое ids_cam1: {:?}", ids_cam1);
             debug!("Содержимое ids_cam2: {:?}", ids_cam2);
 
             let common: HashSet<i32> = find_common_points(&[ids_cam1.clone(), ids_cam2.clone()]);
             debug!("Содержимое common: {:?}", common);
             debug!(
-                "Камера 0 и камера {}: найдено {} общих точек",
+                "Камера {} и камера {}: найдено {} общих точек",
+                primary,
                 i,
                 common.len()
             );
-            if common.len() < 10 {
+            if common.len() < MIN_STEREO_SHARED_CORNERS {
                 debug!(
-                    "ВНИМАНИЕ: недостаточно общих точек между камерой 0 и камерой {}",
-                    i
+                    "ВНИМАНИЕ: недостаточно общих точек между камерой {} и камерой {}",
+                    primary, i
                 );
                 continue;
             }
@@ -262,13 +668,14 @@ pub fn calibrate_multiple_with_charuco(
             debug!("Содержимое idx_cam1: {:?}", idx_cam1);
             debug!("Содержимое idx_cam2: {:?}", idx_cam2);
 
-            let obj_points = select_rows(&object_points[0].get(frame_idx)?, &idx_cam1)?;
-            let img_points1 = select_rows(&image_points[0].get(frame_idx)?, &idx_cam1)?;
-            let img_points2 = select_rows(&image_points[i].get(frame_idx)?, &idx_cam2)?;
+            let obj_points = select_rows(&detection1.object_points, &idx_cam1)?;
+            let img_points1 = select_rows(&detection1.image_points, &idx_cam1)?;
+            let img_points2 = select_rows(&detection2.image_points, &idx_cam2)?;
 
             debug!(
-                "Кадр {}, Камера 0 и {}: выбрано {} 3D точек, {} точек на изображении 1, {} точек на изображении 2",
+                "Кадр {}, Камера {} и {}: выбрано {} 3D точек, {} точек на изображении 1, {} точек на изображении 2",
                 frame_idx,
+                primary,
                 i,
                 obj_points.rows(),
                 img_points1.rows(),
@@ -280,22 +687,28 @@ pub fn calibrate_multiple_with_charuco(
             common_image_points2.push(img_points2);
         }
 
-        let img_size = imgs[0].get(0)?.size()?;
+        let img_size = image_sizes[primary];
 
-        debug!("Подготовка 1 камеры к стереокалибровке");
+        debug!("Подготовка основной камеры к стереокалибровке");
         debug!(
             "Количество кадров с общими точками: {}",
             common_object_points.len()
         );
 
         // Надо временно поделить на несколько частей, так как иначе получим множественное заимствование.
-        let mut cam_1_matrix = camera_matrix[0].clone();
-        let mut cam_1_dist = dist_coeffs[0].clone();
+        let mut cam_1_matrix = camera_matrix[primary].clone();
+        let mut cam_1_dist = dist_coeffs[primary].clone();
         let mut cam_2_matrix = camera_matrix[i].clone();
         let mut cam_2_dist = dist_coeffs[i].clone();
 
-        debug!("Матрица камеры 0 до стерео калибровки:\n{:?}", cam_1_matrix);
-        debug!("Дисторсия камеры 0 до стерео калибровки:\n{:?}", cam_1_dist);
+        debug!(
+            "Матрица камеры {} до стерео калибровки:\n{:?}",
+            primary, cam_1_matrix
+        );
+        debug!(
+            "Дисторсия камеры {} до стерео калибровки:\n{:?}",
+            primary, cam_1_dist
+        );
         debug!(
             "Матрица камеры {} до стерео калибровки:\n{:?}",
             i, cam_2_matrix
@@ -324,7 +737,7 @@ pub fn calibrate_multiple_with_charuco(
             &mut t,
             &mut e,
             &mut f,
-            opencv::calib3d::CALIB_FIX_INTRINSIC,
+            stereo_flags.0,
             criteria,
         )?;
 
@@ -333,12 +746,12 @@ pub fn calibrate_multiple_with_charuco(
             i, stereo_error
         );
         debug!(
-            "Матрица камеры 0 после стерео калибровки:\n{:?}",
-            cam_1_matrix
+            "Матрица камеры {} после стерео калибровки:\n{:?}",
+            primary, cam_1_matrix
         );
         debug!(
-            "Дисторсия камеры 0 после стерео калибровки:\n{:?}",
-            cam_1_dist
+            "Дисторсия камеры {} после стерео калибровки:\n{:?}",
+            primary, cam_1_dist
         );
         debug!(
             "Матрица камеры {} после стерео калибровки:\n{:?}",
@@ -353,29 +766,53 @@ pub fn calibrate_multiple_with_charuco(
 
         // Вычисляем норму вектора трансляции для получения расстояния
         let t_norm = norm(&t, opencv::core::NORM_L2, &Mat::default())?;
-        debug!("Расстояние между камерой 0 и камерой {}: {} мм", i, t_norm);
+        debug!(
+            "Расстояние между камерой {} и камерой {}: {} (в единицах доски)",
+            primary, i, t_norm
+        );
 
-        // Удаляем обновление матриц камеры
-        // camera_matrix[0] = cam_1_matrix;
-        // dist_coeffs[0] = cam_1_dist;
-        // camera_matrix[i] = cam_2_matrix;
-        // dist_coeffs[i] = cam_2_dist;
+        if stereo_flags.refines_intrinsics() {
+            camera_matrix[i] = cam_2_matrix;
+            dist_coeffs[i] = cam_2_dist;
+            let scenes = common_object_points.len();
+            let better = primary_refined
+                .as_ref()
+                .is_none_or(|(best_scenes, best_error, ..)| {
+                    (scenes, -stereo_error) > (*best_scenes, -best_error)
+                });
+            if better {
+                primary_refined = Some((scenes, stereo_error, cam_1_matrix, cam_1_dist));
+            }
+        }
 
-        cameras.push(CameraParameters {
+        cameras[i] = Some(CameraParameters {
             intrinsic: camera_matrix[i].clone(),
             distortion: dist_coeffs[i].clone(),
             rotation: r,
             translation: t,
             essential_matrix: e,
             fundamental_matrix: f,
+            rms_error: Some(ret[i]),
+            stereo_rms_error: Some(stereo_error),
+            unit: LengthUnit::Unspecified,
         });
 
         debug!("=== Калибровка камеры {} завершена ===", i);
     }
     debug!("=== Калибровка множества камер завершена ===");
 
-    // Анализируем расстояния между камерами
-    let _ = calculate_adjacent_camera_distances(&cameras);
+    if let (Some((scenes, _, matrix, dist)), Some(camera)) =
+        (primary_refined, cameras[primary].as_mut())
+    {
+        debug!(
+            "Внутренние параметры камеры {} взяты из пары с {} общими сценами",
+            primary, scenes
+        );
+        camera.intrinsic = matrix;
+        camera.distortion = dist;
+    }
+
+    let cameras: Vec<CameraParameters> = cameras.into_iter().flatten().collect();
     debug!("Проверка {:#?}", cameras[1]);
     Ok(cameras)
 }
@@ -396,7 +833,10 @@ fn select_rows(src: &Mat, indices: &Vector<i32>) -> opencv::Result<Mat> {
     Ok(dst)
 }
 
-/// Вычисляет расстояния между соседними камерами и возвращает их в виде вектора
+/// Вычисляет расстояния от основной камеры до камер 1, 2, ... и возвращает их в виде вектора.
+/// Дополнительно в лог пишутся расстояния между соседними по номеру камерами: это имеет смысл,
+/// только если камеры стоят в ряд по порядку номеров. Для произвольной расстановки
+/// см. [`camera_distance_matrix`]
 pub fn calculate_adjacent_camera_distances(
     cameras: &[CameraParameters],
 ) -> Result<Vec<f64>, opencv::Error> {
@@ -408,6 +848,7 @@ pub fn calculate_adjacent_camera_distances(
     }
 
     let mut distances = Vec::with_capacity(cameras.len() - 1);
+    let unit = cameras[0].unit.label();
 
     for i in 1..cameras.len() {
         let t = &cameras[i].translation;
@@ -419,10 +860,10 @@ pub fn calculate_adjacent_camera_distances(
         let tz = t.at_2d::<f64>(2, 0)?;
 
         debug!("Камера {} → Камера 0:", i);
-        debug!("  Полное расстояние: {:.2} мм", t_norm);
+        debug!("  Полное расстояние: {:.2} {}", t_norm, unit);
         debug!(
-            "  Компоненты вектора: X={:.2} мм, Y={:.2} мм, Z={:.2} мм",
-            tx, ty, tz
+            "  Компоненты вектора: X={:.2}, Y={:.2}, Z={:.2} {}",
+            tx, ty, tz, unit
         );
 
         // Если это не первая камера (т.е. i > 1), также вычисляем относительное расстояние
@@ -439,10 +880,10 @@ pub fn calculate_adjacent_camera_distances(
             let rel_t_norm = (rel_tx * rel_tx + rel_ty * rel_ty + rel_tz * rel_tz).sqrt();
 
             debug!("  Относительно камеры {}:", i - 1);
-            debug!("    Относительное расстояние: {:.2} мм", rel_t_norm);
+            debug!("    Относительное расстояние: {:.2} {}", rel_t_norm, unit);
             debug!(
-                "    Относительные компоненты: X={:.2} мм, Y={:.2} мм, Z={:.2} мм",
-                rel_tx, rel_ty, rel_tz
+                "    Относительные компоненты: X={:.2}, Y={:.2}, Z={:.2} {}",
+                rel_tx, rel_ty, rel_tz, unit
             );
         }
 
@@ -453,180 +894,1683 @@ pub fn calculate_adjacent_camera_distances(
     Ok(distances)
 }
 
-#[derive(Debug)]
-pub struct CameraParameters {
-    pub intrinsic: Mat,
-    pub distortion: Mat,
-    pub rotation: Mat,
-    pub translation: Mat,
-    pub essential_matrix: Mat,
-    pub fundamental_matrix: Mat,
+/// Центр камеры в системе основной камеры: C = -R^T * t
+fn camera_center(camera: &CameraParameters) -> Result<[f64; 3], Error> {
+    let mut center = [0.0; 3];
+    for (col, c) in center.iter_mut().enumerate() {
+        for row in 0..3 {
+            *c -= camera.rotation.at_2d::<f64>(row, col as i32)?
+                * camera.translation.at_2d::<f64>(row, 0)?;
+        }
+    }
+    Ok(center)
 }
 
-impl CameraParameters {
-    pub fn new() -> opencv::Result<Self> {
-        Ok(Self {
-            intrinsic: Mat::default(),
-            distortion: Mat::default(),
-            rotation: Mat::eye(3, 3, opencv::core::CV_64F)?.to_mat()?,
-            translation: Mat::zeros(3, 1, opencv::core::CV_64F)?.to_mat()?,
-            essential_matrix: Mat::default(),
-            fundamental_matrix: Mat::default(),
+/// Расстояния (базы) между всеми парами камер в единицах доски: элемент `[i][j]` -
+/// расстояние между центрами камер `i` и `j`. Центры переводятся в систему основной камеры,
+/// поэтому расстановка камер может быть любой
+pub fn camera_distance_matrix(cameras: &[CameraParameters]) -> Result<Vec<Vec<f64>>, Error> {
+    let centers = cameras
+        .iter()
+        .map(camera_center)
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(centers
+        .iter()
+        .map(|a| {
+            centers
+                .iter()
+                .map(|b| {
+                    a.iter()
+                        .zip(b)
+                        .map(|(x, y)| (x - y) * (x - y))
+                        .sum::<f64>()
+                        .sqrt()
+                })
+                .collect()
         })
-    }
+        .collect())
 }
 
-#[derive(Debug)]
-pub struct CalibrationFrame {
-    pub object_points: Mat,       // CV_32FC3 (3D точки)
-    pub image_points: Mat,        // CV_32FC2 (2D точки изображения)
-    pub charuco_ids: Vector<i32>, // ID точек
+/// Цвета каркасов камер в PLY (RGB), назначаются по номеру камеры по кругу
+const FRUSTUM_COLORS: [(u8, u8, u8); 6] = [
+    (230, 25, 75),
+    (60, 180, 75),
+    (0, 130, 200),
+    (245, 130, 48),
+    (145, 30, 180),
+    (70, 240, 240),
+];
+
+/// Рёбра каркаса камеры по номерам вершин из [`frustum_vertices`]: от центра к углам
+/// основания, контур основания и треугольник-метка над верхней стороной кадра
+const FRUSTUM_EDGES: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (2, 3),
+    (3, 4),
+    (4, 1),
+    (1, 5),
+    (2, 5),
+];
+
+/// Вершины каркаса камеры в системе основной камеры: центр, углы кадра на глубине `scale`
+/// (левый верхний, правый верхний, правый нижний, левый нижний) и вершина метки "верх".
+/// Размер кадра оценивается по главной точке матрицы камеры
+fn frustum_vertices(camera: &CameraParameters, scale: f64) -> Result<[[f64; 3]; 6], Error> {
+    let k = &camera.intrinsic;
+    let (fx, fy) = (*k.at_2d::<f64>(0, 0)?, *k.at_2d::<f64>(1, 1)?);
+    let (cx, cy) = (*k.at_2d::<f64>(0, 2)?, *k.at_2d::<f64>(1, 2)?);
+    let (x, y) = (cx / fx * scale, cy / fy * scale);
+    let local = [
+        [0.0, 0.0, 0.0],
+        [-x, -y, scale],
+        [x, -y, scale],
+        [x, y, scale],
+        [-x, y, scale],
+        [0.0, -1.5 * y, scale],
+    ];
+    // X_w = R^T * (X_c - t)
+    let mut vertices = [[0.0; 3]; 6];
+    for (vertex, point) in vertices.iter_mut().zip(&local) {
+        for (col, v) in vertex.iter_mut().enumerate() {
+            for (row, p) in point.iter().enumerate() {
+                *v += camera.rotation.at_2d::<f64>(row as i32, col as i32)?
+                    * (p - camera.translation.at_2d::<f64>(row as i32, 0)?);
+            }
+        }
+    }
+    Ok(vertices)
 }
 
-// Функция для нахождения общих точек
-pub fn find_common_points(frames: &[Vector<i32>]) -> HashSet<i32> {
-    if frames.is_empty() {
-        return HashSet::new();
+/// Сохраняет положения камер как каркасы-пирамиды в PLY (вершины и рёбра, у каждой камеры
+/// свой цвет) в системе основной камеры. Файл открывается вместе с облаком точек и
+/// показывает геометрию установки. `scale` - глубина пирамиды в единицах доски
+pub fn save_camera_frustums_ply<P: AsRef<Path>>(
+    cameras: &[CameraParameters],
+    scale: f64,
+    path: P,
+) -> opencv::Result<()> {
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Размер каркаса камеры должен быть положительным, задан {}",
+                scale
+            ),
+        ));
     }
+    let frustums = cameras
+        .iter()
+        .map(|camera| frustum_vertices(camera, scale))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    write_atomically(path.as_ref(), |tmp_path| -> Result<(), UtilsError> {
+        let mut file = BufWriter::new(File::create(tmp_path)?);
+        writeln!(file, "ply")?;
+        writeln!(file, "format ascii 1.0")?;
+        writeln!(file, "element vertex {}", frustums.len() * 6)?;
+        writeln!(file, "property float x")?;
+        writeln!(file, "property float y")?;
+        writeln!(file, "property float z")?;
+        writeln!(file, "property uchar red")?;
+        writeln!(file, "property uchar green")?;
+        writeln!(file, "property uchar blue")?;
+        writeln!(
+            file,
+            "element edge {}",
+            frustums.len() * FRUSTUM_EDGES.len()
+        )?;
+        writeln!(file, "property int vertex1")?;
+        writeln!(file, "property int vertex2")?;
+        writeln!(file, "property uchar red")?;
+        writeln!(file, "property uchar green")?;
+        writeln!(file, "property uchar blue")?;
+        writeln!(file, "end_header")?;
+
+        for (i, vertices) in frustums.iter().enumerate() {
+            let (r, g, b) = FRUSTUM_COLORS[i % FRUSTUM_COLORS.len()];
+            for [x, y, z] in vertices {
+                writeln!(file, "{} {} {} {} {} {}", x, y, z, r, g, b)?;
+            }
+        }
+        for i in 0..frustums.len() {
+            let (r, g, b) = FRUSTUM_COLORS[i % FRUSTUM_COLORS.len()];
+            for (from, to) in FRUSTUM_EDGES {
+                writeln!(file, "{} {} {} {} {}", i * 6 + from, i * 6 + to, r, g, b)?;
+            }
+        }
 
-    // Первый набор - копируем значения
-    let mut common_ids: HashSet<i32> = frames.get(0).unwrap().iter().collect();
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        Ok(())
+    })?;
+    debug!("Каркасы камер сохранены в {}", path.as_ref().display());
+    Ok(())
+}
 
-    for frame in frames.iter().skip(1) {
-        // Временный HashSet для сравнения
-        let current_ids: HashSet<_> = frame.iter().collect();
-        common_ids = common_ids.intersection(&current_ids).cloned().collect();
-    }
+/// Минимум найденных углов доски на изображении для оценки её позы
+const MIN_PNP_CORNERS: i32 = 4;
 
-    common_ids
+/// Оценивает позу доски относительно камеры по найденным углам (`solve_pnp`).
+/// Если в `detection` нет сопоставленных 3D точек, они заново берутся из `charuco_board`.
+/// Возвращает `(rvec, tvec)`: вектор Родрига и смещение доски в системе камеры
+/// в единицах доски. Меньше 4 углов - ошибка
+pub fn estimate_board_pose(
+    detection: &CharucoDetection,
+    charuco_board: &CharucoBoard,
+    camera: &CameraParameters,
+) -> Result<(Mat, Mat), Error> {
+    let (object_points, image_points) = matched_board_points(detection, charuco_board)?;
+    solve_board_pose(&object_points, &image_points, camera)
 }
 
-pub fn perform_calibration(
-    image_path: &str,
-    cameras_params_path: &Path,
+fn matched_board_points(
+    detection: &CharucoDetection,
     charuco_board: &CharucoBoard,
-    num_cameras: usize,
-) {
-    debug!("Поиск калибровочных изображений в: {}", image_path);
+) -> Result<(Mat, Mat), Error> {
+    if detection.object_points.rows() > 0 {
+        return Ok((
+            detection.object_points.clone(),
+            detection.image_points.clone(),
+        ));
+    }
+    let mut object_points = Mat::default();
+    let mut image_points = Mat::default();
+    charuco_board.match_image_points(
+        &detection.charuco_corners,
+        &detection.charuco_ids,
+        &mut object_points,
+        &mut image_points,
+    )?;
+    Ok((object_points, image_points))
+}
 
-    // Собираем все файлы в директории
-    let dir_entries = match fs::read_dir(image_path) {
-        Ok(entries) => entries,
-        Err(e) => {
-            error!("Ошибка чтения директории: {}", e);
-            return;
-        }
-    };
+fn solve_board_pose(
+    object_points: &Mat,
+    image_points: &Mat,
+    camera: &CameraParameters,
+) -> Result<(Mat, Mat), Error> {
+    if object_points.rows() < MIN_PNP_CORNERS {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Для оценки позы доски нужно минимум {} углов, найдено {}",
+                MIN_PNP_CORNERS,
+                object_points.rows()
+            ),
+        ));
+    }
 
-    // Группируем изображения по камерам и кадрам
-    let mut frame_numbers = Vec::new();
-    let mut camera_images: Vec<Vector<Mat>> = vec![Vector::<Mat>::new(); num_cameras];
+    let mut rvec = Mat::default();
+    let mut tvec = Mat::default();
+    if !solve_pnp(
+        object_points,
+        image_points,
+        &camera.intrinsic,
+        &camera.distortion,
+        &mut rvec,
+        &mut tvec,
+        false,
+        SOLVEPNP_ITERATIVE,
+    )? {
+        return Err(Error::new(StsError, "solve_pnp не нашёл позу доски"));
+    }
+    Ok((rvec, tvec))
+}
 
-    for entry in dir_entries {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
+/// Приблизительная камера для оценки позы доски до калибровки: фокусное расстояние
+/// равно большей стороне кадра `size` (угол обзора около 53°), главная точка - в центре,
+/// дисторсии нет. Углы наклона по ней получаются грубыми, но для выбора разнообразных
+/// поз этого хватает
+pub fn provisional_camera(size: Size) -> Result<CameraParameters, Error> {
+    let focal = size.width.max(size.height) as f64;
+    let mut camera = CameraParameters::new()?;
+    camera.intrinsic = Mat::from_slice_2d(&[
+        [focal, 0.0, size.width as f64 / 2.0],
+        [0.0, focal, size.height as f64 / 2.0],
+        [0.0, 0.0, 1.0],
+    ])?;
+    camera.distortion = Mat::zeros(1, 5, opencv::core::CV_64F)?.to_mat()?;
+    Ok(camera)
+}
 
-        let file_name = entry.file_name();
-        let file_name = file_name.to_string_lossy();
-        debug!("Загружаю {}", file_name);
-
-        if file_name.starts_with("img_") && file_name.ends_with(".png") {
-            let parts: Vec<&str> = file_name.split('_').collect();
-            if parts.len() == 3 {
-                if let Ok(cam_num) = parts[1].parse::<usize>() {
-                    if let Ok(frame_num) = parts[2].trim_end_matches(".png").parse::<usize>() {
-                        if let Ok(img) = imread(&entry.path().to_string_lossy(), IMREAD_COLOR) {
-                            camera_images[cam_num - 1].push(img);
-                            frame_numbers.push(frame_num);
-                        }
-                    }
-                }
-            }
+/// Наклон и удалённость доски относительно камеры
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardPose {
+    /// Наклон доски вокруг горизонтальной оси камеры (вверх-вниз), градусы
+    pub tilt_x: f64,
+    /// Наклон доски вокруг вертикальной оси камеры (влево-вправо), градусы
+    pub tilt_y: f64,
+    /// Расстояние от камеры до начала координат доски, в единицах доски
+    pub distance: f64,
+}
+
+impl BoardPose {
+    /// Поза по результату [`estimate_board_pose`]: наклоны - углы нормали доски
+    /// (третий столбец матрицы поворота) к оптической оси камеры
+    pub fn from_rvec_tvec(rvec: &Mat, tvec: &Mat) -> Result<Self, Error> {
+        let mut rotation = Mat::default();
+        opencv::calib3d::rodrigues_def(rvec, &mut rotation)?;
+        let mut normal = [0.0; 3];
+        for (i, value) in normal.iter_mut().enumerate() {
+            *value = *rotation.at_2d::<f64>(i as i32, 2)?;
         }
+        let mut translation = Mat::default();
+        tvec.convert_to(&mut translation, opencv::core::CV_64F, 1.0, 0.0)?;
+        // Нормаль доски смотрит на камеру или от неё в зависимости от стороны доски
+        let depth = normal[2].abs();
+        Ok(Self {
+            tilt_x: normal[1].atan2(depth).to_degrees(),
+            tilt_y: normal[0].atan2(depth).to_degrees(),
+            distance: norm(&translation, opencv::core::NORM_L2, &no_array())?,
+        })
     }
 
-    // Удаляем дубликаты frame_numbers и сортируем
-    frame_numbers.sort();
-    frame_numbers.dedup();
+    /// Полный угол между нормалью доски и оптической осью камеры, градусы
+    pub fn tilt(&self) -> f64 {
+        let (x, y) = (
+            self.tilt_x.to_radians().tan(),
+            self.tilt_y.to_radians().tan(),
+        );
+        (x * x + y * y).sqrt().atan().to_degrees()
+    }
+}
 
-    info!("Найдено {} наборов(сцен) изображений", frame_numbers.len());
+/// Границы ячеек покрытия поз доски: по полному наклону (градусы) и по расстоянию
+/// (единицы доски), по возрастанию. Границы делят значения на `len() + 1` ячеек
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoseBins {
+    pub tilt: Vec<f64>,
+    pub distance: Vec<f64>,
+}
 
-    // Выполняем калибровку
-    match calibrate_multiple_with_charuco(&camera_images, charuco_board) {
-        Ok(cameras) => {
-            info!(
-                "Калибровка успешно завершена. Получено {} камер:",
-                cameras.len()
-            );
-            for (i, cam) in cameras.iter().enumerate() {
-                if i > 0 {
-                    debug!(
-                        "Дистанция от основной камеры: {:.2} мм",
-                        norm(&cam.translation, NORM_L2, &Mat::default()).unwrap()
-                    );
-                }
-            }
+impl PoseBins {
+    /// Ячейка позы: (индекс по наклону, индекс по расстоянию)
+    pub fn bin(&self, pose: &BoardPose) -> (usize, usize) {
+        (
+            self.tilt.partition_point(|&edge| edge <= pose.tilt()),
+            self.distance.partition_point(|&edge| edge <= pose.distance),
+        )
+    }
 
-            // Сохранение параметров в файл (опционально)
-            if let Err(e) = save_camera_parameters(
-                &cameras,
-                &format!(
-                    "{}/calibration_params.yml",
-                    cameras_params_path.to_str().unwrap()
-                ),
-            ) {
-                error!("Ошибка при сохранении параметров: {}", e);
-            }
-        }
-        Err(e) => error!("Ошибка при калибровке: {:?}", e),
+    /// Подписи ячеек наклона вида `<15`, `15-30`, `>=45`
+    pub fn tilt_labels(&self) -> Vec<String> {
+        bin_labels(&self.tilt)
+    }
+
+    /// Подписи ячеек расстояния
+    pub fn distance_labels(&self) -> Vec<String> {
+        bin_labels(&self.distance)
     }
 }
 
-fn save_camera_parameters(cameras: &[CameraParameters], path: &str) -> opencv::Result<()> {
-    let mut fs = FileStorage::new(path, FileStorage_Mode::WRITE as i32, "")?;
+fn bin_labels(edges: &[f64]) -> Vec<String> {
+    let Some((first, last)) = edges.first().zip(edges.last()) else {
+        return vec!["all".to_string()];
+    };
+    let mut labels = vec![format!("<{}", first)];
+    labels.extend(
+        edges
+            .windows(2)
+            .map(|pair| format!("{}-{}", pair[0], pair[1])),
+    );
+    labels.push(format!(">={}", last));
+    labels
+}
 
-    for (i, cam) in cameras.iter().enumerate() {
-        // Для матриц используем специальные методы записи
-        fs.write_mat(&format!("camera_{}_intrinsic", i), &cam.intrinsic)?;
-        fs.write_mat(&format!("camera_{}_distortion", i), &cam.distortion)?;
+/// Найденные углы доски и те же углы, перепроецированные через калибровку камеры
+/// по позе доски из [`estimate_board_pose`]
+#[derive(Debug, Clone)]
+pub struct BoardReprojection {
+    pub detected: Vec<Point2f>,
+    pub projected: Vec<Point2f>,
+    pub rvec: Mat,
+    pub tvec: Mat,
+}
 
-        if i > 0 {
-            fs.write_mat(&format!("camera_{}_rotation", i), &cam.rotation)?;
-            fs.write_mat(&format!("camera_{}_translation", i), &cam.translation)?;
+impl BoardReprojection {
+    /// Расстояния между найденными и перепроецированными углами, пикс.
+    pub fn errors(&self) -> Vec<f64> {
+        self.detected
+            .iter()
+            .zip(&self.projected)
+            .map(|(d, p)| ((d.x - p.x) as f64).hypot((d.y - p.y) as f64))
+            .collect()
+    }
+
+    /// Средняя ошибка перепроекции по углам, пикс.
+    pub fn mean_error(&self) -> f64 {
+        let errors = self.errors();
+        if errors.is_empty() {
+            return 0.0;
         }
+        errors.iter().sum::<f64>() / errors.len() as f64
     }
 
-    fs.release()?;
-    Ok(())
+    fn squared_error_sum(&self) -> f64 {
+        self.errors().iter().map(|e| e * e).sum()
+    }
 }
 
-pub fn load_camera_parameters(path: &str) -> opencv::Result<Vec<CameraParameters>> {
-    let mut fs = FileStorage::new(path, FileStorage_Mode::READ as i32, "")?;
+/// Перепроецирует найденные углы доски через внутренние параметры и дисторсию `camera`
+pub fn reproject_board(
+    detection: &CharucoDetection,
+    charuco_board: &CharucoBoard,
+    camera: &CameraParameters,
+) -> Result<BoardReprojection, Error> {
+    let (object_points, image_points) = matched_board_points(detection, charuco_board)?;
+    let (rvec, tvec) = solve_board_pose(&object_points, &image_points, camera)?;
+
+    let mut projected = Vector::<Point2f>::new();
+    project_points(
+        &object_points,
+        &rvec,
+        &tvec,
+        &camera.intrinsic,
+        &camera.distortion,
+        &mut projected,
+        &mut Mat::default(),
+        0.0,
+    )?;
+    let detected = (0..image_points.rows())
+        .map(|j| image_points.at::<Point2f>(j).copied())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(BoardReprojection {
+        detected,
+        projected: projected.to_vec(),
+        rvec,
+        tvec,
+    })
+}
 
-    let mut cameras = Vec::new();
-    let mut i = 0;
+/// Цвет найденных углов в [`draw_reprojection`] (BGR)
+const DETECTED_CORNER_COLOR: (f64, f64, f64) = (0.0, 255.0, 0.0);
+/// Цвет перепроецированных углов и линий к ним (BGR)
+const PROJECTED_CORNER_COLOR: (f64, f64, f64) = (0.0, 0.0, 255.0);
 
-    loop {
-        let intrinsic_name = format!("camera_{}_intrinsic", i);
-        debug!("Попытка считать данные для камеры {}", i);
-        if fs.get_node(&intrinsic_name)?.empty()? {
-            break;
-        }
+/// Копия `img` с найденными углами доски (зелёные кружки) и теми же углами,
+/// перепроецированными по калибровке `camera` и позе доски на этом кадре (красные крестики),
+/// соединёнными линиями: длинные линии сразу выдают плохие кадры
+pub fn draw_reprojection(
+    img: &Mat,
+    detection: &CharucoDetection,
+    camera: &CameraParameters,
+    charuco_board: &CharucoBoard,
+) -> Result<Mat, Error> {
+    let reprojection = reproject_board(detection, charuco_board, camera)?;
+    let mut image = img.try_clone()?;
+    draw_board_reprojection(&mut image, &reprojection)?;
+    Ok(image)
+}
 
-        let mut cam_params = CameraParameters::new()?;
+/// Рисует на `image` уже посчитанную перепроекцию, см. [`draw_reprojection`]
+pub fn draw_board_reprojection(
+    image: &mut Mat,
+    reprojection: &BoardReprojection,
+) -> Result<(), Error> {
+    let to_point = |p: &Point2f| Point::new(p.x.round() as i32, p.y.round() as i32);
+    let detected_color = Scalar::new(
+        DETECTED_CORNER_COLOR.0,
+        DETECTED_CORNER_COLOR.1,
+        DETECTED_CORNER_COLOR.2,
+        0.0,
+    );
+    let projected_color = Scalar::new(
+        PROJECTED_CORNER_COLOR.0,
+        PROJECTED_CORNER_COLOR.1,
+        PROJECTED_CORNER_COLOR.2,
+        0.0,
+    );
+    for (detected, projected) in reprojection.detected.iter().zip(&reprojection.projected) {
+        let (d, p) = (to_point(detected), to_point(projected));
+        line(image, d, p, projected_color, 1, LINE_AA, 0)?;
+        circle(image, d, 4, detected_color, 1, LINE_AA, 0)?;
+        draw_marker(image, p, projected_color, MARKER_CROSS, 8, 1, LINE_AA)?;
+    }
+    Ok(())
+}
 
-        cam_params.intrinsic = fs.get_node(&intrinsic_name)?.mat()?;
-        cam_params.distortion = fs.get_node(&format!("camera_{}_distortion", i))?.mat()?;
+/// Средняя невязка [`essential_matrix_residual`], выше которой сохранённая существенная
+/// матрица считается не соответствующей камерам: порядка 1% в нормированных координатах
+pub const MAX_ESSENTIAL_RESIDUAL: f64 = 0.01;
+
+/// Проверяет сохранённую существенную матрицу пары камер на сопоставленных точках:
+/// для верной калибровки нормированные координаты точек удовлетворяют `x2ᵀ E x1 = 0`.
+/// `reference` - основная камера калибровки (камера 1), `camera` - камера пары, чьи
+/// `essential_matrix` и точки `keypoints_camera` проверяются. В файл параметров E не
+/// сохраняется, поэтому у загруженных камер она строится по относительной позе пары: `E = [t]x R`.
+/// В `matches` `query_idx` указывает в `keypoints_reference`, `train_idx` - в `keypoints_camera`.
+///
+/// Точки приводятся к нормированным координатам с учётом дисторсии, E делится на свою
+/// норму, чтобы невязка не зависела от единиц базы. Возвращает среднее `|x2ᵀ E x1|`:
+/// ошибка сопоставления в пиксель даёт невязку порядка `1 / f`, заметно большее
+/// значение (см. [`MAX_ESSENTIAL_RESIDUAL`]) говорит о плохой или сбитой калибровке
+pub fn essential_matrix_residual(
+    reference: &CameraParameters,
+    camera: &CameraParameters,
+    keypoints_reference: &Vector<KeyPoint>,
+    keypoints_camera: &Vector<KeyPoint>,
+    matches: &Vector<DMatch>,
+) -> Result<f64, Error> {
+    if matches.is_empty() {
+        return Err(Error::new(
+            StsError,
+            "Нет сопоставлений для проверки существенной матрицы".to_string(),
+        ));
+    }
 
-        if i > 0 {
-            cam_params.rotation = fs.get_node(&format!("camera_{}_rotation", i))?.mat()?;
-            cam_params.translation = fs.get_node(&format!("camera_{}_translation", i))?.mat()?;
+    let mut points_reference = Vector::<Point2f>::with_capacity(matches.len());
+    let mut points_camera = Vector::<Point2f>::with_capacity(matches.len());
+    for m in matches.iter() {
+        points_reference.push(keypoints_reference.get(m.query_idx as usize)?.pt());
+        points_camera.push(keypoints_camera.get(m.train_idx as usize)?.pt());
+    }
+    let normalized_reference = normalized_points(&points_reference, reference)?;
+    let normalized_camera = normalized_points(&points_camera, camera)?;
+
+    let essential = if camera.essential_matrix.empty() {
+        essential_from_pose(reference, camera)?
+    } else {
+        let mut essential = Mat::default();
+        camera
+            .essential_matrix
+            .convert_to(&mut essential, CV_64F, 1.0, 0.0)?;
+        essential
+    };
+    let scale = norm(&essential, opencv::core::NORM_L2, &no_array())?;
+    if scale <= f64::EPSILON {
+        return Err(Error::new(
+            StsError,
+            "Существенная матрица камеры нулевая".to_string(),
+        ));
+    }
+    let mut e = [[0.0; 3]; 3];
+    for (i, row) in e.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = *essential.at_2d::<f64>(i as i32, j as i32)? / scale;
         }
-
-        cameras.push(cam_params);
-        i += 1;
+    }
+
+    let mut total = 0.0;
+    for (p1, p2) in normalized_reference.iter().zip(normalized_camera.iter()) {
+        let x1 = [p1.x as f64, p1.y as f64, 1.0];
+        let x2 = [p2.x as f64, p2.y as f64, 1.0];
+        let residual: f64 = (0..3)
+            .map(|i| x2[i] * (0..3).map(|j| e[i][j] * x1[j]).sum::<f64>())
+            .sum();
+        total += residual.abs();
+    }
+    let mean = total / matches.len() as f64;
+    if mean > MAX_ESSENTIAL_RESIDUAL {
+        warn!(
+            "Невязка существенной матрицы {:.5} по {} сопоставлениям больше {}: \
+             калибровка пары камер, вероятно, неверна",
+            mean,
+            matches.len(),
+            MAX_ESSENTIAL_RESIDUAL
+        );
+    } else {
+        info!(
+            "Невязка существенной матрицы {:.5} по {} сопоставлениям",
+            mean,
+            matches.len()
+        );
+    }
+    Ok(mean)
+}
+
+/// Существенная матрица пары камер по их позам: по относительной позе камеры `camera`
+/// относительно `reference` (`R = R_c R_refᵀ`, `t = t_c - R t_ref`) строится `E = [t]x R`
+fn essential_from_pose(
+    reference: &CameraParameters,
+    camera: &CameraParameters,
+) -> Result<Mat, Error> {
+    let rotation_reference = mat3(&reference.rotation)?;
+    let rotation_camera = mat3(&camera.rotation)?;
+    let translation_reference = vec3(&reference.translation)?;
+    let translation_camera = vec3(&camera.translation)?;
+
+    let mut r = [[0.0; 3]; 3];
+    for (i, row) in r.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3)
+                .map(|k| rotation_camera[i][k] * rotation_reference[j][k])
+                .sum();
+        }
+    }
+    let mut t = [0.0; 3];
+    for (i, value) in t.iter_mut().enumerate() {
+        *value = translation_camera[i]
+            - (0..3)
+                .map(|k| r[i][k] * translation_reference[k])
+                .sum::<f64>();
+    }
+
+    let skew = [[0.0, -t[2], t[1]], [t[2], 0.0, -t[0]], [-t[1], t[0], 0.0]];
+    let mut essential = [[0.0; 3]; 3];
+    for (essential_row, skew_row) in essential.iter_mut().zip(&skew) {
+        for (j, value) in essential_row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| skew_row[k] * r[k][j]).sum();
+        }
+    }
+    Mat::from_slice_2d(&essential)
+}
+
+fn mat3(m: &Mat) -> Result<[[f64; 3]; 3], Error> {
+    let mut m64 = Mat::default();
+    m.convert_to(&mut m64, CV_64F, 1.0, 0.0)?;
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = *m64.at_2d::<f64>(i as i32, j as i32)?;
+        }
+    }
+    Ok(out)
+}
+
+fn vec3(m: &Mat) -> Result<[f64; 3], Error> {
+    let mut m64 = Mat::default();
+    m.convert_to(&mut m64, CV_64F, 1.0, 0.0)?;
+    let mut out = [0.0; 3];
+    for (i, value) in out.iter_mut().enumerate() {
+        *value = *m64.at::<f64>(i as i32)?;
+    }
+    Ok(out)
+}
+
+/// Точки кадра в нормированных координатах камеры без дисторсии
+fn normalized_points(
+    points: &Vector<Point2f>,
+    camera: &CameraParameters,
+) -> Result<Vector<Point2f>, Error> {
+    let mut normalized = Vector::<Point2f>::new();
+    undistort_points(
+        points,
+        &mut normalized,
+        &camera.intrinsic,
+        &camera.distortion,
+        &no_array(),
+        &no_array(),
+    )?;
+    Ok(normalized)
+}
+
+/// Проверяет загруженную калибровку камеры на новых снимках доски без перекалибровки.
+/// На каждом изображении ищется доска, её поза оценивается `solve_pnp` по сохранённым
+/// внутренним параметрам и дисторсии, после чего углы доски проецируются обратно.
+/// Возвращает RMS ошибки перепроекции в пикселях по всем найденным углам: большое
+/// значение означает, что калибровка не соответствует текущему объективу или зуму
+pub fn validate_calibration(
+    camera: &CameraParameters,
+    charuco_board: &CharucoBoard,
+    images: &Vector<Mat>,
+) -> Result<f64, Error> {
+    let mut squared_sum = 0.0;
+    let mut point_count = 0usize;
+    let mut used_images = 0usize;
+
+    for (i, image) in images.iter().enumerate() {
+        let detection = get_charuco(charuco_board, &image)?;
+        let reprojection = match reproject_board(&detection, charuco_board, camera) {
+            Ok(reprojection) => reprojection,
+            Err(e) => {
+                debug!("Изображение {} пропущено: {}", i, e.message);
+                continue;
+            }
+        };
+
+        let image_sum = reprojection.squared_error_sum();
+        let corners = reprojection.detected.len();
+        debug!(
+            "Изображение {}: RMS {:.3} пикс. по {} углам",
+            i,
+            (image_sum / corners as f64).sqrt(),
+            corners
+        );
+        squared_sum += image_sum;
+        point_count += corners;
+        used_images += 1;
+    }
+
+    if point_count == 0 {
+        return Err(Error::new(
+            StsError,
+            "Доска не найдена ни на одном изображении: проверить калибровку невозможно",
+        ));
+    }
+    let rms = (squared_sum / point_count as f64).sqrt();
+    info!(
+        "Проверка калибровки: RMS {:.3} пикс. по {} изображениям из {}",
+        rms,
+        used_images,
+        images.len()
+    );
+    Ok(rms)
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraParameters {
+    pub intrinsic: Mat,
+    pub distortion: Mat,
+    pub rotation: Mat,
+    pub translation: Mat,
+    pub essential_matrix: Mat,
+    pub fundamental_matrix: Mat,
+    /// Среднеквадратичная ошибка перепроекции калибровки внутренних параметров, пикс.
+    /// (`None`, если параметры загружены из файла)
+    pub rms_error: Option<f64>,
+    /// Среднеквадратичная ошибка стереокалибровки пары с основной камерой, пикс.
+    /// (`None` для основной камеры и параметров из файла)
+    pub stereo_rms_error: Option<f64>,
+    /// Единица сдвигов `translation`: единица длин доски калибровки
+    pub unit: LengthUnit,
+}
+
+impl CameraParameters {
+    pub fn new() -> opencv::Result<Self> {
+        Ok(Self {
+            intrinsic: Mat::default(),
+            distortion: Mat::default(),
+            rotation: Mat::eye(3, 3, opencv::core::CV_64F)?.to_mat()?,
+            translation: Mat::zeros(3, 1, opencv::core::CV_64F)?.to_mat()?,
+            essential_matrix: Mat::default(),
+            fundamental_matrix: Mat::default(),
+            rms_error: None,
+            stereo_rms_error: None,
+            unit: LengthUnit::Unspecified,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct CalibrationFrame {
+    pub object_points: Mat,       // CV_32FC3 (3D точки)
+    pub image_points: Mat,        // CV_32FC2 (2D точки изображения)
+    pub charuco_ids: Vector<i32>, // ID точек
+}
+
+// Функция для нахождения общих точек
+pub fn find_common_points(frames: &[Vector<i32>]) -> HashSet<i32> {
+    if frames.is_empty() {
+        return HashSet::new();
+    }
+
+    // Первый набор - копируем значения
+    let mut common_ids: HashSet<i32> = frames.get(0).unwrap().iter().collect();
+
+    for frame in frames.iter().skip(1) {
+        // Временный HashSet для сравнения
+        let current_ids: HashSet<_> = frame.iter().collect();
+        common_ids = common_ids.intersection(&current_ids).cloned().collect();
+    }
+
+    common_ids
+}
+
+/// В скольких кадрах встречается каждый ID (повтор ID в одном кадре считается один раз).
+/// В отличие от [`find_common_points`] не требует присутствия ID во всех кадрах: по счётчикам
+/// можно оставить ID, найденные хотя бы в K кадрах
+pub fn find_common_points_weighted(frames: &[Vector<i32>]) -> HashMap<i32, usize> {
+    let mut counts = HashMap::new();
+    for frame in frames {
+        let ids: HashSet<i32> = frame.iter().collect();
+        for id in ids {
+            *counts.entry(id).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// Файл параметров камер в папке результатов калибровки
+pub const CALIBRATION_PARAMS_FILE: &str = "calibration_params.yml";
+
+/// Результат калибровки нескольких камер
+#[derive(Debug, Clone)]
+pub struct CalibrationResult {
+    pub cameras: Vec<CameraParameters>,
+    /// Расстояния от основной камеры до камер 1, 2, ... в единицах доски, см. [`Self::unit`]
+    pub distances: Vec<f64>,
+    /// Число сцен, по которым выполнена калибровка
+    pub scenes: usize,
+    /// Карты покрытия кадра углами доски по камерам (см. [`calibration_coverage_heatmap`]),
+    /// пустая матрица - карту построить не удалось
+    pub coverage: Vec<Mat>,
+}
+
+impl CalibrationResult {
+    /// Единица расстояний между камерами: единица доски, с которой выбраны кадры
+    pub fn unit(&self) -> LengthUnit {
+        self.cameras
+            .first()
+            .map_or(LengthUnit::Unspecified, |c| c.unit)
+    }
+
+    /// Сводка по камерам, по строке на камеру. Латиницей, так как выводится и на изображение
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Calibrated {} cameras from {} scenes",
+            self.cameras.len(),
+            self.scenes
+        )];
+        for (i, camera) in self.cameras.iter().enumerate() {
+            let mut line = format!("Cam {}: RMS {}", i + 1, format_rms(camera.rms_error));
+            if i > 0 {
+                line.push_str(&format!(
+                    ", stereo 1-{} RMS {}",
+                    i + 1,
+                    format_rms(camera.stereo_rms_error)
+                ));
+                if let Some(distance) = self.distances.get(i - 1) {
+                    line.push_str(&format!(", distance {:.1} {}", distance, self.unit()));
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Таблица расстояний между всеми парами камер (см. [`camera_distance_matrix`]),
+    /// строка на камеру. Пусто, если расстояния посчитать не удалось
+    pub fn distance_table(&self) -> Vec<String> {
+        let matrix = match camera_distance_matrix(&self.cameras) {
+            Ok(matrix) => matrix,
+            Err(e) => {
+                warn!("Расстояния между камерами не посчитаны: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut lines = vec![format!(
+            "Baselines, {}:{}",
+            self.unit(),
+            (1..=matrix.len())
+                .map(|cam| format!("{:>9}", format!("Cam {}", cam)))
+                .collect::<String>()
+        )];
+        for (i, row) in matrix.iter().enumerate() {
+            lines.push(format!(
+                "{:<10}{}",
+                format!("Cam {}", i + 1),
+                row.iter()
+                    .map(|d| format!("{:>9.1}", d))
+                    .collect::<String>()
+            ));
+        }
+        lines
+    }
+
+    /// Сохраняет параметры камер в `cameras_params_path`/calibration_params_{дата_время}.yml
+    /// и копирует их в calibration_params.yml: так прежние результаты не перезаписываются,
+    /// а последний всегда лежит по одному и тому же пути.
+    /// Рядом сохраняются карты покрытия `coverage_camera_{cam}.png` (камеры с 1)
+    pub fn save(&self, cameras_params_path: &Path) -> opencv::Result<()> {
+        let path = cameras_params_path.join(CALIBRATION_PARAMS_FILE);
+        let dated = path.with_file_name(format!("calibration_params_{}.yml", datetime_stamp()));
+        save_camera_parameters(&self.cameras, &dated)?;
+        write_atomically(&path, |tmp| std::fs::copy(&dated, tmp))
+            .map_err(|e| Error::new(StsError, format!("{}: {}", path.display(), e)))?;
+        info!(
+            "Параметры камер сохранены в {} и {}",
+            dated.display(),
+            path.display()
+        );
+        for (cam_i, heatmap) in self.coverage.iter().enumerate() {
+            if heatmap.empty() {
+                continue;
+            }
+            let path = cameras_params_path.join(format!("coverage_camera_{}.png", cam_i + 1));
+            write_atomically(&path, |tmp_path| -> Result<(), UtilsError> {
+                imwrite(path_to_str(tmp_path)?, heatmap, &Vector::new())?;
+                Ok(())
+            })?;
+            debug!("Карта покрытия сохранена в {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Карта покрытия кадра углами ChArUco: попадания углов всех кадров `all_corners`
+/// накапливаются в изображении размера `image_size`, размываются, нормируются к 0-255
+/// и раскрашиваются палитрой JET. Мало углов у краёв (синие края) означает, что
+/// дисторсия там оценена плохо и нужны кадры с доской у границ изображения
+pub fn calibration_coverage_heatmap(
+    all_corners: &[Vector<Point2f>],
+    image_size: Size,
+) -> Result<Mat, Error> {
+    let mut hits = Mat::new_rows_cols_with_default(
+        image_size.height,
+        image_size.width,
+        CV_32F,
+        Scalar::all(0.0),
+    )?;
+    for corners in all_corners {
+        for corner in corners {
+            let (x, y) = (corner.x as i32, corner.y as i32);
+            if x >= 0 && y >= 0 && x < image_size.width && y < image_size.height {
+                *hits.at_2d_mut::<f32>(y, x)? += 1.0;
+            }
+        }
+    }
+
+    // Одно попадание должно покрывать окрестность порядка клетки доски
+    let sigma = image_size.width.max(image_size.height) as f64 / 40.0;
+    let mut blurred = Mat::default();
+    gaussian_blur_def(&hits, &mut blurred, Size::new(0, 0), sigma)?;
+    let mut normalized = Mat::default();
+    normalize(
+        &blurred,
+        &mut normalized,
+        0.0,
+        255.0,
+        NORM_MINMAX,
+        CV_8U,
+        &no_array(),
+    )?;
+    let mut heatmap = Mat::default();
+    apply_color_map(&normalized, &mut heatmap, COLORMAP_JET)?;
+    Ok(heatmap)
+}
+
+/// Карта покрытия одной камеры по её калибровочным изображениям
+fn camera_coverage(detections: &[CharucoDetection], image_size: Size) -> Result<Mat, Error> {
+    if detections.is_empty() {
+        return Ok(Mat::default());
+    }
+    let all_corners: Vec<Vector<Point2f>> = detections
+        .iter()
+        .map(|detection| detection.charuco_corners.clone())
+        .collect();
+    calibration_coverage_heatmap(&all_corners, image_size)
+}
+
+pub(crate) fn format_rms(rms: Option<f64>) -> String {
+    rms.map(|rms| format!("{:.3} px", rms))
+        .unwrap_or_else(|| "n/a".to_string())
+}
+
+/// Наименьшее число сцен по умолчанию: и для каждой камеры, и сцен со всеми камерами сразу
+pub const DEFAULT_MIN_SCENES: usize = 10;
+
+/// Сколько сцен выбрано для каждой камеры и сколько из них есть у всех камер сразу
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SceneCoverage {
+    /// Число сцен камеры `i + 1`
+    pub per_camera: Vec<usize>,
+    /// Число сцен, в которых есть все камеры: только они идут в калибровку
+    pub complete: usize,
+}
+
+impl SceneCoverage {
+    /// По изображениям, сгруппированным как в [`list_picked_calibration_images`]
+    pub fn from_picked(
+        picked: &BTreeMap<usize, BTreeMap<usize, PathBuf>>,
+        num_cameras: usize,
+    ) -> Self {
+        let per_camera = (1..=num_cameras)
+            .map(|cam| picked.get(&cam).map_or(0, |frames| frames.len()))
+            .collect();
+        let complete = picked.get(&1).map_or(0, |frames| {
+            frames
+                .keys()
+                .filter(|frame| {
+                    (2..=num_cameras)
+                        .all(|cam| picked.get(&cam).is_some_and(|f| f.contains_key(frame)))
+                })
+                .count()
+        });
+        Self {
+            per_camera,
+            complete,
+        }
+    }
+
+    /// По манифесту выбранных кадров, без проверки файлов на диске
+    pub fn from_manifest(manifest: &PickedManifest, num_cameras: usize) -> Self {
+        let per_camera = (1..=num_cameras)
+            .map(|cam| {
+                manifest
+                    .frames
+                    .iter()
+                    .filter(|f| f.files.contains_key(&cam))
+                    .count()
+            })
+            .collect();
+        let complete = manifest
+            .frames
+            .iter()
+            .filter(|f| (1..=num_cameras).all(|cam| f.files.contains_key(&cam)))
+            .count();
+        Self {
+            per_camera,
+            complete,
+        }
+    }
+}
+
+/// Ограничения на число сцен калибровки. Калибровка по слишком малому числу сцен даёт
+/// неверные параметры или падает внутри OpenCV, поэтому она не начинается, пока
+/// ограничения снизу не выполнены
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SceneLimits {
+    /// Наименьшее число сцен у каждой камеры
+    pub min_per_camera: usize,
+    /// Наименьшее число сцен, в которых есть все камеры
+    pub min_complete: usize,
+    /// Если сцен больше, в калибровку идут лучшие по [`DetectionQuality`]; `None` - все сцены
+    pub max_scenes: Option<usize>,
+}
+
+impl Default for SceneLimits {
+    fn default() -> Self {
+        Self {
+            min_per_camera: DEFAULT_MIN_SCENES,
+            min_complete: DEFAULT_MIN_SCENES,
+            max_scenes: None,
+        }
+    }
+}
+
+impl SceneLimits {
+    /// Камеры (с 1), у которых меньше `min_per_camera` сцен, с их числом сцен
+    pub fn short_cameras(&self, coverage: &SceneCoverage) -> Vec<(usize, usize)> {
+        coverage
+            .per_camera
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count < self.min_per_camera)
+            .map(|(cam_i, &count)| (cam_i + 1, count))
+            .collect()
+    }
+
+    /// Достаточно ли сцен для калибровки
+    pub fn satisfied_by(&self, coverage: &SceneCoverage) -> bool {
+        coverage.complete >= self.min_complete && self.short_cameras(coverage).is_empty()
+    }
+}
+
+/// Параметры калибровки по выбранным кадрам для [`calibrate_picked_images`]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PickedCalibrationParams {
+    pub stereo_flags: StereoFlags,
+    /// Ограничения числа сцен: если сцен мало, калибровка не начинается
+    pub limits: SceneLimits,
+    /// Выравнивание контраста перед поиском доски: то же, с которым кадры выбирались,
+    /// иначе на тёмных кадрах доска при калибровке не найдётся
+    pub contrast: Option<ContrastEnhancement>,
+    /// Единица длин доски, в которой получатся сдвиги камер. Если не указана,
+    /// берётся из доски манифеста выбранных кадров
+    pub unit: LengthUnit,
+}
+
+/// Калибрует камеры по изображениям `img_{cam}_{frame}.png` и сохраняет calibration_params.yml.
+/// Число сцен ограничивается `params.limits`: если сцен мало, калибровка не начинается.
+/// `progress` сообщает о текущем этапе; если он вернул `false`, калибровка прерывается
+/// до сохранения, и прежний calibration_params.yml остаётся нетронутым.
+/// Возвращает результат или `None`, если калибровка не удалась или отменена (причина пишется в лог)
+pub fn perform_calibration(
+    image_path: &Path,
+    cameras_params_path: &Path,
+    charuco_board: &CharucoBoard,
+    num_cameras: usize,
+    params: &PickedCalibrationParams,
+    progress: &mut dyn FnMut(CalibrationStage) -> bool,
+) -> Option<CalibrationResult> {
+    let result = calibrate_picked_images(image_path, charuco_board, num_cameras, params, progress)?;
+    if !progress(CalibrationStage::Saving) {
+        info!("Калибровка отменена, параметры не сохранены");
+        return None;
+    }
+    if let Err(e) = result.save(cameras_params_path) {
+        error!("Ошибка при сохранении параметров: {}", e);
+    }
+    Some(result)
+}
+
+/// Как [`perform_calibration`], но ничего не сохраняет: результат можно сначала
+/// показать пользователю и сохранить через [`CalibrationResult::save`]
+pub fn calibrate_picked_images(
+    image_path: &Path,
+    charuco_board: &CharucoBoard,
+    num_cameras: usize,
+    params: &PickedCalibrationParams,
+    progress: &mut dyn FnMut(CalibrationStage) -> bool,
+) -> Option<CalibrationResult> {
+    let limits = &params.limits;
+    let mut cancelled = false;
+    let mut progress = |stage: CalibrationStage| {
+        cancelled = cancelled || !progress(stage);
+        !cancelled
+    };
+    if !progress(CalibrationStage::LoadingImages) {
+        info!("Калибровка отменена");
+        return None;
+    }
+    debug!(
+        "Поиск калибровочных изображений в: {}",
+        image_path.display()
+    );
+
+    // Манифест точнее группирует кадры по сценам, поэтому он предпочтительнее списка файлов.
+    // Доска манифеста задаёт единицу длин результата, если она не указана в параметрах
+    let mut unit = params.unit;
+    let picked = match PickedManifest::load(image_path) {
+        Ok(Some(manifest)) => {
+            info!("Кадры сгруппированы по манифесту {}", PICKED_MANIFEST_FILE);
+            if let (LengthUnit::Unspecified, Some(board)) = (unit, &manifest.board) {
+                unit = board.unit;
+            }
+            manifest.picked_images(image_path)
+        }
+        result => {
+            if let Err(e) = result {
+                warn!("Манифест выбранных кадров не прочитан: {}", e);
+            }
+            match list_picked_calibration_images(image_path) {
+                Ok(picked) => picked,
+                Err(e) => {
+                    error!("Ошибка чтения директории: {}", e);
+                    return None;
+                }
+            }
+        }
+    };
+
+    let coverage = SceneCoverage::from_picked(&picked, num_cameras);
+    if !limits.satisfied_by(&coverage) {
+        let short: Vec<String> = limits
+            .short_cameras(&coverage)
+            .iter()
+            .map(|(cam, count)| format!("камера {} - {}", cam, count))
+            .collect();
+        if !short.is_empty() {
+            error!(
+                "Мало сцен для калибровки: {} (нужно не меньше {})",
+                short.join(", "),
+                limits.min_per_camera
+            );
+        }
+        if coverage.complete < limits.min_complete {
+            error!(
+                "Сцен, в которых есть все камеры: {} (нужно не меньше {})",
+                coverage.complete, limits.min_complete
+            );
+        }
+        return None;
+    }
+
+    // Сцена используется, только если она есть у всех камер, иначе кадры разных камер
+    // перестанут соответствовать друг другу
+    let mut frame_numbers: Vec<usize> = picked
+        .get(&1)
+        .map(|frames| frames.keys().copied().collect())
+        .unwrap_or_default();
+    frame_numbers.retain(|frame| {
+        let complete =
+            (1..=num_cameras).all(|cam| picked.get(&cam).is_some_and(|f| f.contains_key(frame)));
+        if !complete {
+            warn!("Сцена {} есть не у всех камер и будет пропущена", frame);
+        }
+        complete
+    });
+    for cam in picked.keys().filter(|&&cam| cam == 0 || cam > num_cameras) {
+        warn!(
+            "Изображения камеры {} не соответствуют конфигурации и пропущены",
+            cam
+        );
+    }
+
+    // Группируем найденные доски по камерам в порядке номеров кадров; сами изображения
+    // после поиска доски не нужны. Если изображение одной из камер не прочитано,
+    // пропускается вся сцена, чтобы доски разных камер оставались одной и той же сцены
+    let mut camera_detections: Vec<Vec<CharucoDetection>> = vec![Vec::new(); num_cameras];
+    let mut image_sizes = vec![Size::default(); num_cameras];
+    let mut read_frames = Vec::with_capacity(frame_numbers.len());
+    'scenes: for frame in &frame_numbers {
+        let mut scene = Vec::with_capacity(num_cameras);
+        for (cam_i, size) in image_sizes.iter_mut().enumerate() {
+            let path = &picked[&(cam_i + 1)][frame];
+            debug!("Загружаю {}", path.display());
+            let img = match imread(&path.to_string_lossy(), IMREAD_COLOR) {
+                Ok(img) if !img.empty() => img,
+                Ok(_) => {
+                    error!(
+                        "Не удалось прочитать {}, сцена {} пропущена",
+                        path.display(),
+                        frame
+                    );
+                    continue 'scenes;
+                }
+                Err(e) => {
+                    error!(
+                        "Не удалось прочитать {}: {}, сцена {} пропущена",
+                        path.display(),
+                        e,
+                        frame
+                    );
+                    continue 'scenes;
+                }
+            };
+            match img.size().and_then(|img_size| {
+                *size = img_size;
+                get_charuco_enhanced(charuco_board, &img, params.contrast.as_ref())
+            }) {
+                Ok(detection) => scene.push(detection),
+                Err(e) => {
+                    error!(
+                        "Ошибка поиска доски на {}: {}, сцена {} пропущена",
+                        path.display(),
+                        e,
+                        frame
+                    );
+                    continue 'scenes;
+                }
+            }
+        }
+        for (detections, detection) in camera_detections.iter_mut().zip(scene) {
+            detections.push(detection);
+        }
+        read_frames.push(*frame);
+    }
+    let frame_numbers = read_frames;
+
+    info!("Найдено {} наборов(сцен) изображений", frame_numbers.len());
+
+    match calibrate_scenes(
+        camera_detections,
+        image_sizes,
+        frame_numbers,
+        params.stereo_flags,
+        limits.max_scenes,
+        unit,
+        &mut progress,
+    ) {
+        Ok(result) => Some(result),
+        Err(_) if cancelled => {
+            info!("Калибровка отменена");
+            None
+        }
+        Err(e) => {
+            error!("Ошибка при калибровке: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Отбор сцен из видео для [`perform_calibration_from_videos`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VideoCalibrationParams {
+    /// Проверяется каждый `frame_step`-й кадр записи: соседние кадры почти одинаковы
+    pub frame_step: usize,
+    /// Минимум углов ChArUco в каждой камере, чтобы кадр стал сценой калибровки
+    pub min_corners: usize,
+    /// Ограничения числа сцен. Лучшие сцены отбираются по уже найденным доскам,
+    /// кадры записей в памяти не держатся
+    pub limits: SceneLimits,
+    pub stereo_flags: StereoFlags,
+    /// Выравнивание контраста перед поиском доски на тёмных записях
+    pub contrast: Option<ContrastEnhancement>,
+}
+
+impl Default for VideoCalibrationParams {
+    fn default() -> Self {
+        Self {
+            frame_step: 15,
+            min_corners: 6,
+            limits: SceneLimits {
+                max_scenes: Some(50),
+                ..SceneLimits::default()
+            },
+            stereo_flags: StereoFlags::default(),
+            contrast: None,
+        }
+    }
+}
+
+/// Калибрует камеры по нескольким записям без извлечения и ручного выбора кадров: в каждой
+/// записи `video_paths` проверяется каждый `params.frame_step`-й кадр, камеры вырезаются
+/// из него по сетке `layout`, и кадр становится сценой, если доска найдена во всех камерах.
+/// Сцены всех записей объединяются по камерам и калибруются вместе, так что запись не нужно
+/// склеивать заранее. Сцены нумеруются как при выборе из нескольких записей
+/// (см. [`CLIP_FRAME_STRIDE`]). Результат сохраняется в `cameras_params_path`, как в
+/// [`perform_calibration`]; `unit` - единица длин доски. `progress` вызывается с
+/// [`CalibrationStage::LoadingImages`] на каждом проверяемом кадре, `false` отменяет калибровку.
+/// Возвращает `None`, если калибровка не удалась или отменена (причина пишется в лог)
+pub fn perform_calibration_from_videos(
+    video_paths: &[PathBuf],
+    layout: GridLayout,
+    charuco_board: &CharucoBoard,
+    unit: LengthUnit,
+    params: &VideoCalibrationParams,
+    cameras_params_path: &Path,
+    progress: &mut dyn FnMut(CalibrationStage) -> bool,
+) -> Option<CalibrationResult> {
+    let mut cancelled = false;
+    let mut progress = |stage: CalibrationStage| {
+        cancelled = cancelled || !progress(stage);
+        !cancelled
+    };
+    let num_cameras = layout.cells();
+    let mut camera_detections: Vec<Vec<CharucoDetection>> = vec![Vec::new(); num_cameras];
+    let mut image_sizes = vec![Size::default(); num_cameras];
+    let mut frame_numbers = Vec::new();
+
+    for (clip_i, video_path) in video_paths.iter().enumerate() {
+        let scenes_before = frame_numbers.len();
+        let mut source = match CombinedVideoSource::open(video_path, layout) {
+            Ok(source) => source,
+            Err(e) => {
+                error!("Не удалось открыть {}: {}", video_path.display(), e);
+                return None;
+            }
+        };
+        let mut frames = Vec::new();
+        for frame in 0.. {
+            if let Err(e) = source.read_frames(&mut frames) {
+                error!("Ошибка чтения {}: {}", video_path.display(), e);
+                return None;
+            }
+            if frames.first().is_none_or(|f| f.empty()) {
+                break;
+            }
+            if frame % params.frame_step.max(1) != 0 {
+                continue;
+            }
+            if !progress(CalibrationStage::LoadingImages) {
+                info!("Калибровка отменена");
+                return None;
+            }
+            // В памяти остаются только найденные доски: кадры всех записей не поместились бы
+            let mut scene = Vec::with_capacity(num_cameras);
+            for image in &frames {
+                match get_charuco_enhanced(charuco_board, image, params.contrast.as_ref()) {
+                    Ok(d) if d.charuco_ids.len() >= params.min_corners => scene.push(d),
+                    _ => break,
+                }
+            }
+            if scene.len() != num_cameras {
+                continue;
+            }
+            for ((detections, size), (detection, image)) in camera_detections
+                .iter_mut()
+                .zip(image_sizes.iter_mut())
+                .zip(scene.into_iter().zip(&frames))
+            {
+                detections.push(detection);
+                *size = image.size().unwrap_or(*size);
+            }
+            frame_numbers.push(clip_i * CLIP_FRAME_STRIDE + frame);
+        }
+        info!(
+            "{}: найдено {} сцен с доской во всех камерах",
+            video_path.display(),
+            frame_numbers.len() - scenes_before
+        );
+    }
+
+    let coverage = SceneCoverage {
+        per_camera: vec![frame_numbers.len(); num_cameras],
+        complete: frame_numbers.len(),
+    };
+    if !params.limits.satisfied_by(&coverage) {
+        error!(
+            "Сцен, в которых есть все камеры: {} по {} записям (нужно не меньше {})",
+            frame_numbers.len(),
+            video_paths.len(),
+            params.limits.min_complete.max(params.limits.min_per_camera)
+        );
+        return None;
+    }
+    info!(
+        "Найдено {} сцен в {} записях",
+        frame_numbers.len(),
+        video_paths.len()
+    );
+
+    let result = match calibrate_scenes(
+        camera_detections,
+        image_sizes,
+        frame_numbers,
+        params.stereo_flags,
+        params.limits.max_scenes,
+        unit,
+        &mut progress,
+    ) {
+        Ok(result) => result,
+        Err(_) if cancelled => {
+            info!("Калибровка отменена");
+            return None;
+        }
+        Err(e) => {
+            error!("Ошибка при калибровке: {:?}", e);
+            return None;
+        }
+    };
+    if !progress(CalibrationStage::Saving) {
+        info!("Калибровка отменена, параметры не сохранены");
+        return None;
+    }
+    if let Err(e) = result.save(cameras_params_path) {
+        error!("Ошибка при сохранении параметров: {}", e);
+    }
+    Some(result)
+}
+
+/// Калибрует по доскам, найденным на кадрах сцен и сгруппированным по камерам:
+/// `camera_detections[cam][i]` - доска на кадре камеры `cam` сцены `frame_numbers[i]`,
+/// `image_sizes[cam]` - размер кадров камеры. Общая часть калибровки по выбранным
+/// изображениям и по видео
+fn calibrate_scenes(
+    mut camera_detections: Vec<Vec<CharucoDetection>>,
+    image_sizes: Vec<Size>,
+    mut frame_numbers: Vec<usize>,
+    stereo_flags: StereoFlags,
+    max_scenes: Option<usize>,
+    unit: LengthUnit,
+    progress: &mut dyn FnMut(CalibrationStage) -> bool,
+) -> Result<CalibrationResult, Error> {
+    if let Some(k) = max_scenes
+        && frame_numbers.len() > k
+    {
+        match select_best_scenes(&camera_detections, &frame_numbers, k) {
+            Ok((detections, frames)) => {
+                info!(
+                    "Для калибровки выбраны {} лучших сцен из {}: {:?}",
+                    frames.len(),
+                    frame_numbers.len(),
+                    frames
+                );
+                camera_detections = detections;
+                frame_numbers = frames;
+            }
+            Err(e) => warn!("Сцены не отобраны, калибрую по всем: {}", e),
+        }
+    }
+
+    let mut cameras = calibrate_multiple_with_detections(
+        &camera_detections,
+        &image_sizes,
+        stereo_flags,
+        0,
+        progress,
+    )?;
+    info!(
+        "Калибровка успешно завершена. Получено {} камер:",
+        cameras.len()
+    );
+    for camera in &mut cameras {
+        camera.unit = unit;
+    }
+    if unit == LengthUnit::Unspecified {
+        warn!("Единица длин доски неизвестна: расстояния будут в единицах доски");
+    }
+    let distances = calculate_adjacent_camera_distances(&cameras).unwrap_or_default();
+    for (i, distance) in distances.iter().enumerate() {
+        debug!(
+            "Дистанция от основной камеры до камеры {}: {:.2} {}",
+            i + 1,
+            distance,
+            unit.label()
+        );
+    }
+    let coverage = camera_detections
+        .iter()
+        .zip(&image_sizes)
+        .enumerate()
+        .map(|(cam_i, (detections, &image_size))| {
+            camera_coverage(detections, image_size).unwrap_or_else(|e| {
+                warn!("Карта покрытия камеры {} не построена: {}", cam_i + 1, e);
+                Mat::default()
+            })
+        })
+        .collect();
+    Ok(CalibrationResult {
+        cameras,
+        distances,
+        scenes: frame_numbers.len(),
+        coverage,
+    })
+}
+
+/// Оставляет `k` сцен с наибольшей оценкой. Оценка сцены - оценка самой слабой камеры:
+/// плохой кадр одной камеры портит и стереокалибровку её пары
+fn select_best_scenes(
+    camera_detections: &[Vec<CharucoDetection>],
+    frame_numbers: &[usize],
+    k: usize,
+) -> Result<(Vec<Vec<CharucoDetection>>, Vec<usize>), Error> {
+    if camera_detections
+        .iter()
+        .any(|detections| detections.len() != frame_numbers.len())
+    {
+        return Err(Error::new(
+            StsError,
+            "не все изображения сцен прочитаны".to_string(),
+        ));
+    }
+    let scored: Vec<(usize, f64)> = frame_numbers
+        .iter()
+        .enumerate()
+        .map(|(scene_i, &frame)| {
+            let score = camera_detections
+                .iter()
+                .map(|detections| detections[scene_i].quality.score())
+                .fold(f64::INFINITY, f64::min);
+            debug!("Оценка сцены {}: {:.3}", frame, score);
+            (frame, score)
+        })
+        .collect();
+
+    let best = select_best_frames(&scored, k);
+    let keep: Vec<usize> = frame_numbers
+        .iter()
+        .enumerate()
+        .filter(|(_, frame)| best.contains(frame))
+        .map(|(scene_i, _)| scene_i)
+        .collect();
+    let detections = camera_detections
+        .iter()
+        .map(|detections| keep.iter().map(|&i| detections[i].clone()).collect())
+        .collect();
+    Ok((detections, best))
+}
+
+/// Формат файла параметров камер FileStorage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationFormat {
+    Yaml,
+    Xml,
+    Json,
+}
+
+impl CalibrationFormat {
+    /// Формат по расширению файла: .yml/.yaml, .xml или .json
+    pub fn from_path(path: &Path) -> opencv::Result<Self> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("yml" | "yaml") => Ok(Self::Yaml),
+            Some("xml") => Ok(Self::Xml),
+            Some("json") => Ok(Self::Json),
+            _ => Err(Error::new(
+                StsError,
+                format!(
+                    "Неизвестный формат файла параметров камер {}: ожидается .yml, .yaml, .xml или .json",
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    fn file_storage_flag(self) -> i32 {
+        let format = match self {
+            Self::Yaml => FileStorage_Mode::FORMAT_YAML,
+            Self::Xml => FileStorage_Mode::FORMAT_XML,
+            Self::Json => FileStorage_Mode::FORMAT_JSON,
+        };
+        format as i32
+    }
+}
+
+/// Ключ файла параметров с единицей сдвигов камер (см. [`LengthUnit::name`])
+const LENGTH_UNIT_KEY: &str = "length_unit";
+
+/// Сохраняет параметры камер в формате, заданном расширением `path` (см. [`CalibrationFormat`]).
+/// Единица сдвигов берётся у основной камеры и пишется одна на весь файл
+pub fn save_camera_parameters(cameras: &[CameraParameters], path: &Path) -> opencv::Result<()> {
+    let format = CalibrationFormat::from_path(path)?;
+    write_atomically(path, |tmp_path| -> Result<(), UtilsError> {
+        let mut fs = FileStorage::new(
+            path_to_str(tmp_path)?,
+            FileStorage_Mode::WRITE as i32 | format.file_storage_flag(),
+            "",
+        )?;
+
+        if let Some(primary) = cameras.first() {
+            fs.write_str(LENGTH_UNIT_KEY, primary.unit.name())?;
+        }
+        for (i, cam) in cameras.iter().enumerate() {
+            // Для матриц используем специальные методы записи
+            fs.write_mat(&format!("camera_{}_intrinsic", i), &cam.intrinsic)?;
+            fs.write_mat(&format!("camera_{}_distortion", i), &cam.distortion)?;
+
+            if i > 0 {
+                fs.write_mat(&format!("camera_{}_rotation", i), &cam.rotation)?;
+                fs.write_mat(&format!("camera_{}_translation", i), &cam.translation)?;
+            }
+        }
+
+        fs.release()?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Сохраняет позы всех камер, включая основную (`camera_{i}_rotation`, `camera_{i}_translation`).
+/// Нужно, когда мировая система не совпадает с системой камеры 0, например система доски
+pub fn save_camera_poses<P: AsRef<Path>>(
+    cameras: &[CameraParameters],
+    path: P,
+) -> opencv::Result<()> {
+    write_atomically(path.as_ref(), |tmp_path| -> Result<(), UtilsError> {
+        let mut fs = FileStorage::new(path_to_str(tmp_path)?, FileStorage_Mode::WRITE as i32, "")?;
+        for (i, cam) in cameras.iter().enumerate() {
+            fs.write_mat(&format!("camera_{}_rotation", i), &cam.rotation)?;
+            fs.write_mat(&format!("camera_{}_translation", i), &cam.translation)?;
+        }
+        fs.release()?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Поправка матрицы поворота (норма Фробениуса разности), начиная с которой о ней
+/// предупреждается: меньшие поправки - обычное округление при записи в файл
+pub const ROTATION_REPAIR_WARN: f64 = 1e-6;
+
+/// Ближайшая к `r` ортонормированная матрица поворота 3x3 (`R = U * V^T` по SVD `r`).
+/// Матрица поворота, записанная в файл с округлением или полученная цепочкой умножений,
+/// перестаёт быть строго ортонормированной, и триангуляция незаметно искажается.
+/// Ошибка, если `r` не 3x3 или ближайшая ортонормированная матрица - отражение
+pub fn orthonormalize_rotation(r: &Mat) -> Result<Mat, Error> {
+    if r.rows() != 3 || r.cols() != 3 {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Матрица поворота должна быть 3x3, а не {}x{}",
+                r.rows(),
+                r.cols()
+            ),
+        ));
+    }
+    let mut rotation = Mat::default();
+    r.convert_to(&mut rotation, CV_64F, 1.0, 0.0)?;
+    let mut w = Mat::default();
+    let mut u = Mat::default();
+    let mut vt = Mat::default();
+    sv_decomp_def(&rotation, &mut w, &mut u, &mut vt)?;
+    let mut repaired = Mat::default();
+    gemm(&u, &vt, 1.0, &Mat::default(), 0.0, &mut repaired, 0)?;
+    if opencv::core::determinant(&repaired)? < 0.0 {
+        return Err(Error::new(
+            StsError,
+            "Матрица не является поворотом: её ближайшая ортонормированная матрица - отражение"
+                .to_string(),
+        ));
+    }
+    Ok(repaired)
+}
+
+/// Загружает параметры камер из YAML, XML или JSON FileStorage; формат определяется
+/// по расширению `path` (см. [`CalibrationFormat`])
+pub fn load_camera_parameters<P: AsRef<Path>>(path: P) -> opencv::Result<Vec<CameraParameters>> {
+    let format = CalibrationFormat::from_path(path.as_ref())?;
+    let mut fs = FileStorage::new(
+        path_to_str(path.as_ref())?,
+        FileStorage_Mode::READ as i32 | format.file_storage_flag(),
+        "",
+    )?;
+    if !fs.is_opened()? {
+        return Err(opencv::Error::new(
+            StsError,
+            format!("Не удалось открыть {}", path.as_ref().display()),
+        ));
+    }
+
+    // В файлах прежних версий единицы нет
+    let unit_node = fs.get_node(LENGTH_UNIT_KEY)?;
+    let unit = if unit_node.empty()? {
+        LengthUnit::Unspecified
+    } else {
+        unit_node
+            .string()?
+            .parse()
+            .map_err(|e: String| Error::new(StsError, e))?
+    };
+
+    let mut cameras = Vec::new();
+    let mut i = 0;
+
+    loop {
+        let intrinsic_name = format!("camera_{}_intrinsic", i);
+        debug!("Попытка считать данные для камеры {}", i);
+        if fs.get_node(&intrinsic_name)?.empty()? {
+            break;
+        }
+
+        let mut cam_params = CameraParameters::new()?;
+        cam_params.unit = unit;
+
+        cam_params.intrinsic = fs.get_node(&intrinsic_name)?.mat()?;
+        cam_params.distortion = fs.get_node(&format!("camera_{}_distortion", i))?.mat()?;
+
+        if i > 0 {
+            let mut rotation = Mat::default();
+            fs.get_node(&format!("camera_{}_rotation", i))?
+                .mat()?
+                .convert_to(&mut rotation, CV_64F, 1.0, 0.0)?;
+            cam_params.rotation = orthonormalize_rotation(&rotation)?;
+            let correction = norm2(&rotation, &cam_params.rotation, NORM_L2, &no_array())?;
+            if correction > ROTATION_REPAIR_WARN {
+                warn!(
+                    "Матрица поворота камеры {} не ортонормирована, исправлена (поправка {:.2e})",
+                    i + 1,
+                    correction
+                );
+            } else {
+                debug!(
+                    "Поправка матрицы поворота камеры {}: {:.2e}",
+                    i + 1,
+                    correction
+                );
+            }
+            cam_params.translation = fs.get_node(&format!("camera_{}_translation", i))?.mat()?;
+        }
+
+        cameras.push(cam_params);
+        i += 1;
     }
 
     fs.release()?;
@@ -640,3 +2584,452 @@ pub fn load_camera_parameters(path: &str) -> opencv::Result<Vec<CameraParameters
 
     Ok(cameras)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Углы сетки 9x4 (все углы доски 10x5), занимающие долю `extent` кадра 640x480
+    /// от его левого верхнего угла
+    fn corner_grid(extent: f32) -> Vector<Point2f> {
+        (0..36)
+            .map(|i| {
+                let (col, row) = ((i % 9) as f32, (i / 9) as f32);
+                Point2f::new(col / 8.0 * 640.0 * extent, row / 3.0 * 480.0 * extent)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_coverage_frame_scores_above_clustered_frame() {
+        let board = crate::board::BoardConfig::new().build().unwrap();
+        let markers: Vector<Vector<Point2f>> = Vector::new();
+        let size = Size::new(640, 480);
+        let full = DetectionQuality::measure(&board, size, &corner_grid(0.9), &markers).unwrap();
+        let clustered =
+            DetectionQuality::measure(&board, size, &corner_grid(0.2), &markers).unwrap();
+        assert_eq!(full.corner_ratio, 1.0);
+        assert_eq!(clustered.corner_ratio, 1.0);
+        assert!(full.coverage > 0.7 && clustered.coverage < 0.05);
+        assert!(full.score() > clustered.score());
+        let best = select_best_frames(&[(3, clustered.score()), (8, full.score())], 1);
+        assert_eq!(best, vec![8]);
+    }
+
+    #[test]
+    fn heatmap_is_hot_where_corners_accumulate() {
+        // Углы всех кадров собраны в левом верхнем углу кадра
+        let frames: Vec<Vector<Point2f>> = (0..10)
+            .map(|frame| {
+                (0..16)
+                    .map(|i| {
+                        Point2f::new(
+                            30.0 + (i % 4) as f32 * 5.0,
+                            20.0 + (i / 4 + frame % 2) as f32 * 5.0,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        let heatmap = calibration_coverage_heatmap(&frames, Size::new(320, 240)).unwrap();
+        assert_eq!(heatmap.size().unwrap(), Size::new(320, 240));
+        assert_eq!(heatmap.typ(), opencv::core::CV_8UC3);
+        // Палитра JET: горячие области красные, холодные синие (порядок каналов BGR)
+        let hot = *heatmap.at_2d::<opencv::core::Vec3b>(30, 37).unwrap();
+        assert!(hot[2] >= 100 && hot[0] < 50, "{hot:?}");
+        let cold = *heatmap.at_2d::<opencv::core::Vec3b>(200, 280).unwrap();
+        assert!(cold[0] >= 100 && cold[2] < 50, "{cold:?}");
+    }
+
+    fn camera(rotation_y_degrees: f64, translation: [f64; 3]) -> CameraParameters {
+        let mut camera = CameraParameters::new().unwrap();
+        camera.intrinsic =
+            Mat::from_slice_2d(&[[800.0, 0.0, 320.0], [0.0, 800.0, 240.0], [0.0, 0.0, 1.0]])
+                .unwrap();
+        camera.distortion = Mat::zeros(1, 5, CV_64F).unwrap().to_mat().unwrap();
+        let rvec = Mat::from_slice(&[0.0, rotation_y_degrees.to_radians(), 0.0])
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        opencv::calib3d::rodrigues_def(&rvec, &mut camera.rotation).unwrap();
+        camera.translation = Mat::from_slice(&translation)
+            .unwrap()
+            .reshape(1, 3)
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        camera
+    }
+
+    fn project(
+        camera: &CameraParameters,
+        points: &Vector<opencv::core::Point3f>,
+    ) -> Vector<KeyPoint> {
+        let mut rvec = Mat::default();
+        opencv::calib3d::rodrigues_def(&camera.rotation, &mut rvec).unwrap();
+        let mut projected = Vector::<Point2f>::new();
+        opencv::calib3d::project_points_def(
+            points,
+            &rvec,
+            &camera.translation,
+            &camera.intrinsic,
+            &camera.distortion,
+            &mut projected,
+        )
+        .unwrap();
+        projected
+            .iter()
+            .map(|p| KeyPoint::new_point_def(p, 1.0).unwrap())
+            .collect()
+    }
+
+    fn scene() -> Vector<opencv::core::Point3f> {
+        let mut points = Vector::new();
+        for i in 0..5 {
+            for j in 0..5 {
+                let depth = 900.0 + 40.0 * ((i * 5 + j) % 7) as f32;
+                points.push(opencv::core::Point3f::new(
+                    -200.0 + 100.0 * i as f32,
+                    -150.0 + 75.0 * j as f32,
+                    depth,
+                ));
+            }
+        }
+        points
+    }
+
+    fn residual(reference: &CameraParameters, camera: &CameraParameters) -> f64 {
+        let points = scene();
+        let keypoints_reference = project(reference, &points);
+        let keypoints_camera = project(camera, &points);
+        let matches: Vector<DMatch> = (0..points.len() as i32)
+            .map(|i| DMatch::new(i, i, 0.0).unwrap())
+            .collect();
+        essential_matrix_residual(
+            reference,
+            camera,
+            &keypoints_reference,
+            &keypoints_camera,
+            &matches,
+        )
+        .unwrap()
+    }
+
+    /// Доска по умолчанию и её изображение с яркостью, сжатой до 2..17
+    fn dark_board_image() -> (CharucoBoard, Mat) {
+        let board = crate::board::BoardConfig::new().build().unwrap();
+        let mut image = Mat::default();
+        board
+            .generate_image(Size::new(1000, 500), &mut image, 40, 1)
+            .unwrap();
+        let mut dark = Mat::default();
+        image.convert_to(&mut dark, -1, 0.06, 2.0).unwrap();
+        (board, dark)
+    }
+
+    #[test]
+    fn enhanced_detection_finds_board_on_dark_frame() {
+        let (board, dark) = dark_board_image();
+        let total = board.get_chessboard_corners().unwrap().len();
+        let plain = get_charuco(&board, &dark).unwrap();
+        let enhanced =
+            get_charuco_enhanced(&board, &dark, Some(&ContrastEnhancement::default())).unwrap();
+        assert!(enhanced.charuco_ids.len() * 2 >= total);
+        assert!(enhanced.charuco_ids.len() >= plain.charuco_ids.len());
+        assert_eq!(
+            enhanced.object_points.rows() as usize,
+            enhanced.charuco_ids.len()
+        );
+    }
+
+    fn detection_with_score(corner_ratio: f64) -> CharucoDetection {
+        CharucoDetection {
+            marker_corners: Vector::new(),
+            marker_ids: Vector::new(),
+            charuco_corners: Vector::new(),
+            charuco_ids: Vector::new(),
+            object_points: Mat::default(),
+            image_points: Mat::default(),
+            quality: DetectionQuality {
+                corner_ratio,
+                coverage: corner_ratio,
+                mean_marker_side: GOOD_MARKER_SIDE_PX,
+            },
+        }
+    }
+
+    /// Доска 6x4 углов с шагом 30 в сцене `scene`: немного повёрнута и сдвинута
+    /// относительно камеры 0, как при ручном выборе кадров
+    fn board_in_scene(scene: usize) -> Vec<opencv::core::Point3f> {
+        let phase = scene as f32 * 0.7;
+        let (sx, cx) = (0.35 * phase.sin()).sin_cos();
+        let (sy, cy) = (0.35 * phase.cos()).sin_cos();
+        let t = [
+            -60.0 + 8.0 * scene as f32,
+            -40.0 + 5.0 * (scene % 3) as f32,
+            550.0 + 15.0 * scene as f32,
+        ];
+        let mut points = Vec::new();
+        for row in 0..4 {
+            for col in 0..6 {
+                let (x, y) = (30.0 * col as f32, 30.0 * row as f32);
+                // R = Ry · Rx, точка доски лежит в плоскости z = 0
+                let (y1, z1) = (cx * y, sx * y);
+                points.push(opencv::core::Point3f::new(
+                    cy * x + sy * z1 + t[0],
+                    y1 + t[1],
+                    -sy * x + cy * z1 + t[2],
+                ));
+            }
+        }
+        points
+    }
+
+    /// Доски сцен, какими их видит `camera`: точки проекций с детерминированным шумом
+    /// в десятые доли пикселя, чтобы калибровки по отдельности и совместно различались
+    fn synthetic_detections(camera: &CameraParameters, scenes: usize) -> Vec<CharucoDetection> {
+        (0..scenes)
+            .map(|scene| {
+                let world: Vector<opencv::core::Point3f> = board_in_scene(scene).into();
+                let mut rvec = Mat::default();
+                opencv::calib3d::rodrigues_def(&camera.rotation, &mut rvec).unwrap();
+                let mut projected = Vector::<Point2f>::new();
+                opencv::calib3d::project_points_def(
+                    &world,
+                    &rvec,
+                    &camera.translation,
+                    &camera.intrinsic,
+                    &camera.distortion,
+                    &mut projected,
+                )
+                .unwrap();
+                let corners: Vector<Point2f> = projected
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        let noise = ((i * 7 + scene * 13) % 11) as f32 / 10.0 - 0.5;
+                        Point2f::new(p.x + 0.4 * noise, p.y - 0.3 * noise)
+                    })
+                    .collect();
+                let object: Vec<opencv::core::Point3f> = (0..corners.len())
+                    .map(|i| {
+                        opencv::core::Point3f::new(
+                            30.0 * (i % 6) as f32,
+                            30.0 * (i / 6) as f32,
+                            0.0,
+                        )
+                    })
+                    .collect();
+                let count = corners.len() as i32;
+                let column = |mat: Mat| mat.reshape(0, count).unwrap().try_clone().unwrap();
+                CharucoDetection {
+                    image_points: column(Mat::from_exact_iter(corners.iter()).unwrap()),
+                    object_points: column(Mat::from_exact_iter(object.into_iter()).unwrap()),
+                    charuco_ids: (0..count).collect(),
+                    charuco_corners: corners,
+                    ..detection_with_score(1.0)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn refined_primary_intrinsics_are_kept() {
+        let cameras = [camera(0.0, [0.0; 3]), camera(-6.0, [-80.0, 0.0, 5.0])];
+        let detections: Vec<Vec<CharucoDetection>> = cameras
+            .iter()
+            .map(|camera| synthetic_detections(camera, 12))
+            .collect();
+        let sizes = [Size::new(640, 480); 2];
+        let calibrate = |flags| {
+            calibrate_multiple_with_detections(&detections, &sizes, flags, 0, &mut |_| true)
+                .unwrap()
+        };
+        let fixed = calibrate(StereoFlags::FIX_INTRINSIC);
+        let refined = calibrate(StereoFlags::REFINE_INTRINSICS);
+        assert_eq!(refined.len(), 2);
+
+        // Без уточнения у основной камеры остаются параметры её собственной калибровки
+        let (_, mono, ..) = calibrate_with_detections(&detections[0], sizes[0]).unwrap();
+        assert!(norm2(&fixed[0].intrinsic, &mono, NORM_L2, &no_array()).unwrap() < 1e-12);
+        // С уточнением параметры основной камеры берутся из стереокалибровки пары
+        let change = norm2(
+            &refined[0].intrinsic,
+            &fixed[0].intrinsic,
+            NORM_L2,
+            &no_array(),
+        )
+        .unwrap();
+        assert!(change > 0.0);
+        let fx = *refined[0].intrinsic.at_2d::<f64>(0, 0).unwrap();
+        assert!((fx - 800.0).abs() < 40.0, "{fx}");
+    }
+
+    #[test]
+    fn ids_are_counted_per_frame() {
+        let frames: Vec<Vector<i32>> = vec![
+            Vector::from_slice(&[1, 2, 3]),
+            Vector::from_slice(&[2, 3, 4]),
+            // Повтор id в одном кадре считается один раз
+            Vector::from_slice(&[3, 4, 4, 5]),
+        ];
+        let counts = find_common_points_weighted(&frames);
+        let expected = HashMap::from([(1, 1), (2, 2), (3, 3), (4, 2), (5, 1)]);
+        assert_eq!(counts, expected);
+        assert_eq!(find_common_points(&frames), HashSet::from([3]));
+    }
+
+    #[test]
+    fn frustum_ply_has_six_vertices_and_ten_edges_per_camera() {
+        let cameras = [
+            camera(0.0, [0.0; 3]),
+            camera(-6.0, [-80.0, 0.0, 5.0]),
+            camera(6.0, [80.0, 0.0, 5.0]),
+        ];
+        let path = std::env::temp_dir().join(format!("frustums_{}.ply", std::process::id()));
+        save_camera_frustums_ply(&cameras, 20.0, &path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(text.contains("element vertex 18\n"));
+        assert!(text.contains("element edge 30\n"));
+        let (_, body) = text.split_once("end_header\n").unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 18 + 30);
+        for edge in &lines[18..] {
+            let indices: Vec<usize> = edge
+                .split_whitespace()
+                .take(2)
+                .map(|v| v.parse().unwrap())
+                .collect();
+            assert!(indices.iter().all(|&i| i < 18), "{edge}");
+        }
+        assert!(save_camera_frustums_ply(&cameras, 0.0, &path).is_err());
+    }
+
+    #[test]
+    fn primary_camera_defines_the_frame() {
+        let cameras = [camera(0.0, [0.0; 3]), camera(-6.0, [-80.0, 0.0, 5.0])];
+        let detections: Vec<Vec<CharucoDetection>> = cameras
+            .iter()
+            .map(|camera| synthetic_detections(camera, 12))
+            .collect();
+        let sizes = [Size::new(640, 480); 2];
+        let calibrated = calibrate_multiple_with_detections(
+            &detections,
+            &sizes,
+            StereoFlags::FIX_INTRINSIC,
+            1,
+            &mut |_| true,
+        )
+        .unwrap();
+        assert_eq!(calibrated.len(), 2);
+
+        let identity = Mat::eye(3, 3, CV_64F).unwrap().to_mat().unwrap();
+        let primary = &calibrated[1];
+        assert!(norm2(&primary.rotation, &identity, NORM_L2, &no_array()).unwrap() < 1e-12);
+        assert_eq!(
+            norm(&primary.translation, NORM_L2, &no_array()).unwrap(),
+            0.0
+        );
+        // Камера 0 теперь смещена относительно основной на ту же базу
+        let baseline = norm(&calibrated[0].translation, NORM_L2, &no_array()).unwrap();
+        assert!((baseline - 80.16).abs() < 3.0, "{baseline}");
+
+        let out_of_range = calibrate_multiple_with_detections(
+            &detections,
+            &sizes,
+            StereoFlags::FIX_INTRINSIC,
+            2,
+            &mut |_| true,
+        );
+        assert!(out_of_range.is_err());
+    }
+
+    #[test]
+    fn best_scenes_are_ranked_by_weakest_camera() {
+        let camera_detections = vec![
+            [0.9, 0.2, 0.8, 0.7].map(detection_with_score).to_vec(),
+            [0.9, 0.9, 0.3, 0.6].map(detection_with_score).to_vec(),
+        ];
+        let (detections, frames) =
+            select_best_scenes(&camera_detections, &[10, 20, 30, 40], 2).unwrap();
+        assert_eq!(frames, vec![10, 40]);
+        assert_eq!(detections.len(), 2);
+        assert!(detections.iter().all(|d| d.len() == 2));
+        assert_eq!(detections[1][1].quality.corner_ratio, 0.6);
+    }
+
+    #[test]
+    fn best_scenes_need_every_camera() {
+        let camera_detections = vec![
+            [0.9, 0.2].map(detection_with_score).to_vec(),
+            [0.9].map(detection_with_score).to_vec(),
+        ];
+        assert!(select_best_scenes(&camera_detections, &[1, 2], 1).is_err());
+    }
+
+    #[test]
+    fn pose_essential_satisfies_epipolar_constraint() {
+        // Основная камера не в начале координат: E должна строиться по относительной позе
+        let reference = camera(8.0, [40.0, -10.0, 25.0]);
+        let camera = camera(-12.0, [-150.0, 5.0, 30.0]);
+        assert!(camera.essential_matrix.empty());
+        let mean = residual(&reference, &camera);
+        assert!(mean < 1e-5, "{mean}");
+    }
+
+    #[test]
+    fn wrong_pose_is_detected() {
+        let reference = camera(8.0, [40.0, -10.0, 25.0]);
+        let mut camera = camera(-12.0, [-150.0, 5.0, 30.0]);
+        camera.translation = Mat::from_slice(&[0.0, 150.0, 0.0])
+            .unwrap()
+            .reshape(1, 3)
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        assert!(residual(&reference, &camera) > MAX_ESSENTIAL_RESIDUAL);
+    }
+
+    #[test]
+    fn orthonormalized_rotation_is_proper() {
+        let exact = camera(25.0, [0.0; 3]).rotation;
+        let mut noisy = Mat::default();
+        let noise = Mat::from_slice_2d(&[
+            [1e-3, -2e-3, 5e-4],
+            [3e-3, 1e-3, -1e-3],
+            [-5e-4, 2e-3, 2e-3],
+        ])
+        .unwrap();
+        opencv::core::add_def(&exact, &noise, &mut noisy).unwrap();
+
+        let repaired = orthonormalize_rotation(&noisy).unwrap();
+        assert!((opencv::core::determinant(&repaired).unwrap() - 1.0).abs() < 1e-12);
+        let mut rtr = Mat::default();
+        gemm(
+            &repaired,
+            &repaired,
+            1.0,
+            &Mat::default(),
+            0.0,
+            &mut rtr,
+            opencv::core::GEMM_1_T,
+        )
+        .unwrap();
+        let identity = Mat::eye(3, 3, CV_64F).unwrap().to_mat().unwrap();
+        assert!(norm2(&rtr, &identity, NORM_L2, &no_array()).unwrap() < 1e-12);
+        // Поправка порядка шума, а не произвольный поворот
+        assert!(norm2(&repaired, &exact, NORM_L2, &no_array()).unwrap() < 1e-2);
+    }
+
+    #[test]
+    fn reflection_is_rejected() {
+        let reflection =
+            Mat::from_slice_2d(&[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]).unwrap();
+        assert!(orthonormalize_rotation(&reflection).is_err());
+        assert!(
+            orthonormalize_rotation(&Mat::eye(2, 2, CV_64F).unwrap().to_mat().unwrap()).is_err()
+        );
+    }
+}
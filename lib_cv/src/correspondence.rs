@@ -1,8 +1,203 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
 use log::debug;
-use opencv::core::{DMatch, KeyPoint, NORM_L2, Vector};
+use opencv::core::{
+    CV_8U, DMatch, KeyPoint, NORM_HAMMING, NORM_L2, Point2f, Size, StsError, TermCriteria,
+    TermCriteria_Type, Vector, type_to_string,
+};
 use opencv::features2d::{BFMatcher, SIFT};
 use opencv::prelude::*;
 use opencv::{self, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::error::LibCvError;
+
+/// Параметры детектора SIFT и фильтрации найденных ключевых точек
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SiftParams {
+    pub nfeatures: i32,
+    pub n_octave_layers: i32,
+    pub contrast_threshold: f64,
+    pub edge_threshold: f64,
+    pub sigma: f64,
+    /// Минимальный отклик ключевой точки, точки со слабым откликом отбрасываются
+    pub min_response: Option<f32>,
+    /// Минимальный размер (диаметр окрестности) ключевой точки в пикселях
+    pub min_size: Option<f32>,
+    /// Веса каналов (R, G, B) при переводе цветного изображения в оттенки серого.
+    /// Для ярко окрашенных объектов можно усилить канал, который несёт текстуру
+    pub gray_weights: [f64; 3],
+}
+
+/// Порог теста отношения Лоу при сопоставлении признаков SIFT соседних камер: лучший сосед
+/// принимается, если он ближе второго хотя бы в `1 / ratio` раз
+pub const DEFAULT_MATCH_RATIO: f32 = 0.7;
+
+/// Стандартные веса яркости BT.601 в порядке (R, G, B)
+pub const BT601_LUMA_WEIGHTS: [f64; 3] = [0.299, 0.587, 0.114];
+
+impl Default for SiftParams {
+    fn default() -> Self {
+        Self {
+            nfeatures: 0,
+            n_octave_layers: 4,
+            contrast_threshold: 0.04,
+            edge_threshold: 10.0,
+            sigma: 1.6,
+            min_response: None,
+            min_size: None,
+            gray_weights: BT601_LUMA_WEIGHTS,
+        }
+    }
+}
+
+/// SIFT по взвешенному полутоновому изображению с последующей фильтрацией
+/// ключевых точек по отклику и размеру
+pub fn sift_with_params(
+    image: &Mat,
+    params: &SiftParams,
+) -> Result<(Vector<KeyPoint>, Mat), Error> {
+    sift_with_mask(image, params, &Mat::default())
+}
+
+/// Как [`sift_with_params`], но ключевые точки ищутся только там, где `mask` (CV_8U
+/// размера `image`) не равна нулю. Пустая `mask` - поиск по всему изображению
+pub fn sift_with_mask(
+    image: &Mat,
+    params: &SiftParams,
+    mask: &Mat,
+) -> Result<(Vector<KeyPoint>, Mat), Error> {
+    let gray = to_grayscale_weighted(image, params.gray_weights)?;
+    let mut detector = SIFT::create(
+        params.nfeatures,
+        params.n_octave_layers,
+        params.contrast_threshold,
+        params.edge_threshold,
+        params.sigma,
+        false,
+    )?;
+    let mut keypoints = Vector::<KeyPoint>::default();
+    let mut descriptors = Mat::default();
+    detector.detect_and_compute_def(&gray, mask, &mut keypoints, &mut descriptors)?;
+    filter_keypoints(
+        &keypoints,
+        &descriptors,
+        params.min_response,
+        params.min_size,
+    )
+}
+
+/// Половина стороны окна уточнения `refine_subpixel`, пикс.
+const SUBPIXEL_HALF_WINDOW: i32 = 5;
+
+/// Уточняет положения точек до долей пикселя по градиентам изображения (`corner_sub_pix`).
+/// Точки ближе `SUBPIXEL_HALF_WINDOW` к краю, где окно уточнения не помещается
+/// в изображение, и точки, сместившиеся дальше окна, остаются на исходном месте.
+/// Порядок и количество точек сохраняются
+pub fn refine_subpixel(img: &Mat, points: &Vector<Point2f>) -> Result<Vector<Point2f>, Error> {
+    let margin = (SUBPIXEL_HALF_WINDOW + 1) as f32;
+    let inside = |p: &Point2f| {
+        p.x >= margin
+            && p.y >= margin
+            && p.x < img.cols() as f32 - margin
+            && p.y < img.rows() as f32 - margin
+    };
+    let mut refined: Vector<Point2f> = points.iter().filter(inside).collect();
+    if refined.is_empty() {
+        return Ok(points.clone());
+    }
+
+    let gray = to_grayscale_weighted(img, BT601_LUMA_WEIGHTS)?;
+    let mut gray_8u = Mat::default();
+    gray.convert_to_def(&mut gray_8u, CV_8U)?;
+    let criteria = TermCriteria::new(
+        TermCriteria_Type::COUNT as i32 | TermCriteria_Type::EPS as i32,
+        30,
+        0.01,
+    )?;
+    opencv::imgproc::corner_sub_pix(
+        &gray_8u,
+        &mut refined,
+        Size::new(SUBPIXEL_HALF_WINDOW, SUBPIXEL_HALF_WINDOW),
+        Size::new(-1, -1),
+        criteria,
+    )?;
+
+    let mut refined = refined.iter();
+    let mut moved = 0;
+    let result = points
+        .iter()
+        .map(|p| {
+            if !inside(&p) {
+                return p;
+            }
+            let r = refined.next().unwrap_or(p);
+            // Уход за пределы окна значит, что рядом нет выраженного угла
+            if (r.x - p.x).abs() > SUBPIXEL_HALF_WINDOW as f32
+                || (r.y - p.y).abs() > SUBPIXEL_HALF_WINDOW as f32
+            {
+                return p;
+            }
+            moved += 1;
+            r
+        })
+        .collect();
+    debug!(
+        "Субпиксельное уточнение: {} из {} точек",
+        moved,
+        points.len()
+    );
+    Ok(result)
+}
+
+/// Переводит BGR изображение в оттенки серого с весами каналов `weights_rgb` (R, G, B).
+/// Одноканальное изображение возвращается без изменений
+pub fn to_grayscale_weighted(image: &Mat, weights_rgb: [f64; 3]) -> Result<Mat, Error> {
+    if image.channels() == 1 {
+        return Ok(image.clone());
+    }
+    // OpenCV хранит каналы в порядке BGR
+    let [r, g, b] = weights_rgb;
+    let weights = Mat::from_slice_2d(&[[b, g, r]])?;
+    let mut gray = Mat::default();
+    opencv::core::transform(image, &mut gray, &weights)?;
+    Ok(gray)
+}
+
+/// Удаляет ключевые точки с откликом меньше `min_response` или размером меньше `min_size`
+/// вместе с соответствующими строками дескрипторов
+pub fn filter_keypoints(
+    keypoints: &Vector<KeyPoint>,
+    descriptors: &Mat,
+    min_response: Option<f32>,
+    min_size: Option<f32>,
+) -> Result<(Vector<KeyPoint>, Mat), Error> {
+    if min_response.is_none() && min_size.is_none() {
+        return Ok((keypoints.clone(), descriptors.clone()));
+    }
+
+    let mut filtered_keypoints = Vector::<KeyPoint>::default();
+    let mut filtered_descriptors = Mat::default();
+
+    for (i, kp) in keypoints.iter().enumerate() {
+        if min_response.is_some_and(|r| kp.response() < r)
+            || min_size.is_some_and(|s| kp.size() < s)
+        {
+            continue;
+        }
+        filtered_descriptors.push_back(&descriptors.row(i as i32)?)?;
+        filtered_keypoints.push(kp);
+    }
+
+    debug!(
+        "Фильтрация ключевых точек: оставлено {} из {}",
+        filtered_keypoints.len(),
+        keypoints.len()
+    );
+    Ok((filtered_keypoints, filtered_descriptors))
+}
 
 pub fn sift(
     image_1: &Mat,
@@ -31,15 +226,96 @@ pub fn sift(
     Ok((keypoints_1, descriptors_1))
 }
 
+/// Тип дескрипторов, определяющий норму сопоставления
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DescriptorKind {
+    /// Вещественные дескрипторы (SIFT, SURF): евклидова норма
+    #[default]
+    Float,
+    /// Бинарные дескрипторы (ORB, BRISK, AKAZE): расстояние Хэмминга
+    Binary,
+}
+
+impl DescriptorKind {
+    /// Норма OpenCV для `BFMatcher`
+    pub fn norm(&self) -> i32 {
+        match self {
+            DescriptorKind::Float => NORM_L2,
+            DescriptorKind::Binary => NORM_HAMMING,
+        }
+    }
+
+    /// Определяет тип по матрице дескрипторов: бинарные детекторы отдают CV_8U
+    pub fn of(descriptors: &Mat) -> Self {
+        if descriptors.depth() == CV_8U {
+            DescriptorKind::Binary
+        } else {
+            DescriptorKind::Float
+        }
+    }
+}
+
+/// Проверяет, что дескрипторы можно сопоставлять друг с другом: одинаковая длина и тип.
+/// Иначе `BFMatcher` падает внутри OpenCV с непонятной ошибкой. Пустые матрицы не проверяются
+fn check_descriptors_compatible(
+    descriptors_1: &Mat,
+    descriptors_2: &Mat,
+) -> Result<(), LibCvError> {
+    if descriptors_1.empty() || descriptors_2.empty() {
+        return Ok(());
+    }
+    if descriptors_1.cols() != descriptors_2.cols() || descriptors_1.typ() != descriptors_2.typ() {
+        let describe = |descriptors: &Mat| {
+            let typ =
+                type_to_string(descriptors.typ()).unwrap_or_else(|_| descriptors.typ().to_string());
+            format!("{} значений {}", descriptors.cols(), typ)
+        };
+        return Err(LibCvError::DescriptorMismatch {
+            first: describe(descriptors_1),
+            second: describe(descriptors_2),
+        });
+    }
+    Ok(())
+}
+
+/// Сопоставление вещественных дескрипторов (SIFT) по евклидовой норме.
+/// Для бинарных дескрипторов нужен [`bf_match_with_kind`]
 pub fn bf_match(
     descriptors_1: &Mat,
     descriptors_2: &Mat,
     threshold: f32,
-) -> Result<Vector<DMatch>, Error> {
-    let mut bf_matcher = BFMatcher::create(NORM_L2, false)?;
-    let mut matched_descriptors = Vector::<DMatch>::default();
-    bf_matcher.add(&descriptors_1)?;
-    bf_matcher.match__def(&descriptors_2, &mut matched_descriptors)?;
+) -> Result<Vector<DMatch>, LibCvError> {
+    bf_match_with_kind(
+        descriptors_1,
+        descriptors_2,
+        threshold,
+        DescriptorKind::default(),
+    )
+}
+
+/// Как [`bf_match`], но с нормой по типу дескрипторов `kind`.
+/// С фичей `cuda` сопоставление идёт на GPU, если есть устройство CUDA, иначе на CPU
+pub fn bf_match_with_kind(
+    descriptors_1: &Mat,
+    descriptors_2: &Mat,
+    threshold: f32,
+    kind: DescriptorKind,
+) -> Result<Vector<DMatch>, LibCvError> {
+    check_descriptors_compatible(descriptors_1, descriptors_2)?;
+    #[cfg(feature = "cuda")]
+    let gpu_matches = crate::cuda::match_(descriptors_2, descriptors_1, kind.norm());
+    #[cfg(not(feature = "cuda"))]
+    let gpu_matches = None;
+    let matched_descriptors = match gpu_matches {
+        Some(matches) => matches,
+        None => {
+            let mut bf_matcher = BFMatcher::create(kind.norm(), false)?;
+            let mut matches = Vector::<DMatch>::default();
+            bf_matcher.add(&descriptors_1)?;
+            bf_matcher.match__def(&descriptors_2, &mut matches)?;
+            matches
+        }
+    };
 
     let filtered_matches: Vector<DMatch> = matched_descriptors
         .into_iter()
@@ -48,20 +324,52 @@ pub fn bf_match(
     Ok(filtered_matches)
 }
 
+/// Сопоставление вещественных дескрипторов (SIFT) по евклидовой норме с проверкой
+/// отношения расстояний. Для бинарных дескрипторов нужен [`bf_match_knn_with_kind`]
 pub fn bf_match_knn(
     descriptors_1: &Mat,
     descriptors_2: &Mat,
     neighbours_amount: i32,
     ratio: f32,
-) -> Result<Vector<Vector<DMatch>>, Error> {
-    let bf_matcher = BFMatcher::create(NORM_L2, false)?;
-    let mut matched_descriptors = Vector::<Vector<DMatch>>::default();
-    bf_matcher.knn_train_match_def(
-        &descriptors_1,
-        &descriptors_2,
-        &mut matched_descriptors,
+) -> Result<Vector<Vector<DMatch>>, LibCvError> {
+    bf_match_knn_with_kind(
+        descriptors_1,
+        descriptors_2,
         neighbours_amount,
-    )?;
+        ratio,
+        DescriptorKind::default(),
+    )
+}
+
+/// Как [`bf_match_knn`], но с нормой по типу дескрипторов `kind`.
+/// С фичей `cuda` сопоставление идёт на GPU, если есть устройство CUDA, иначе на CPU
+pub fn bf_match_knn_with_kind(
+    descriptors_1: &Mat,
+    descriptors_2: &Mat,
+    neighbours_amount: i32,
+    ratio: f32,
+    kind: DescriptorKind,
+) -> Result<Vector<Vector<DMatch>>, LibCvError> {
+    check_descriptors_compatible(descriptors_1, descriptors_2)?;
+    #[cfg(feature = "cuda")]
+    let gpu_matches =
+        crate::cuda::knn_match(descriptors_1, descriptors_2, neighbours_amount, kind.norm());
+    #[cfg(not(feature = "cuda"))]
+    let gpu_matches = None;
+    let matched_descriptors = match gpu_matches {
+        Some(matches) => matches,
+        None => {
+            let bf_matcher = BFMatcher::create(kind.norm(), false)?;
+            let mut matches = Vector::<Vector<DMatch>>::default();
+            bf_matcher.knn_train_match_def(
+                &descriptors_1,
+                &descriptors_2,
+                &mut matches,
+                neighbours_amount,
+            )?;
+            matches
+        }
+    };
 
     let filtered_matches: Vector<Vector<DMatch>> = matched_descriptors
         .into_iter()
@@ -80,37 +388,413 @@ pub fn bf_match_knn(
     Ok(filtered_matches)
 }
 
+/// Камера, с точками которой сопоставлены все остальные (звезда сопоставлений)
+const REFERENCE_CAMERA: usize = 0;
+
+/// Координаты сопоставленных точек: по матрице Nx2 (CV_64F) на камеру, строка `j` -
+/// `j`-е сопоставление. `all_matches[k]` - сопоставления референсной камеры с камерой
+/// `k + 1`, `all_keypoints` - ключевые точки всех камер, начиная с референсной.
+/// Сопоставление с отрицательным `train_idx` означает, что точки в камере нет: в строку
+/// пишутся координаты точки референсной камеры, а саму строку триангуляция должна
+/// пропустить по флагам видимости
 pub fn gather_points_2d_from_matches(
-    all_matches: &Vec<Vector<Vector<DMatch>>>,
-    all_keypoints: &Vec<Vector<KeyPoint>>,
+    all_matches: &[Vector<Vector<DMatch>>],
+    all_keypoints: &[Vector<KeyPoint>],
 ) -> Result<Vector<Mat>, Error> {
-    // Создаем матрицы с 2D точками для всех камер
+    let Some(reference_matches) = all_matches.first() else {
+        return Err(Error::new(
+            StsError,
+            "Нет сопоставлений ни с одной камерой".to_string(),
+        ));
+    };
+    if all_keypoints.len() != all_matches.len() + 1 {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Ключевые точки даны для {} камер, а сопоставления - для {} камер \
+                 и референсной",
+                all_keypoints.len(),
+                all_matches.len()
+            ),
+        ));
+    }
+    let num_matches = reference_matches.len();
+    debug!("Общее количество сопоставленных точек: {}", num_matches);
+    for (k, matches) in all_matches.iter().enumerate() {
+        if matches.len() != num_matches {
+            return Err(Error::new(
+                StsError,
+                format!(
+                    "Сопоставлений с камерой {}: {}, а с камерой {}: {}; они должны быть \
+                     выровнены по точкам референсной камеры",
+                    k + 2,
+                    matches.len(),
+                    REFERENCE_CAMERA + 2,
+                    num_matches
+                ),
+            ));
+        }
+    }
+
+    let reference_keypoints = &all_keypoints[REFERENCE_CAMERA];
+    let reference_points = reference_matches
+        .iter()
+        .map(|neighbours| {
+            Ok(reference_keypoints
+                .get(neighbours.get(0)?.query_idx as usize)?
+                .pt())
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
     let mut points_2d = Vector::<Mat>::default();
+    points_2d.push(points_to_mat(&reference_points)?);
+    for (target_camera, matches) in (REFERENCE_CAMERA + 1..).zip(all_matches) {
+        let target_keypoints = &all_keypoints[target_camera];
+        let target_points =
+            matches
+                .iter()
+                .zip(&reference_points)
+                .map(|(neighbours, &reference)| {
+                    let m = neighbours.get(0)?;
+                    if m.train_idx < 0 {
+                        return Ok(reference);
+                    }
+                    target_keypoints.get(m.train_idx as usize).map(|kp| kp.pt()).map_err(|_| {
+                    Error::new(
+                        StsError,
+                        format!(
+                            "Сопоставление ссылается на точку {} камеры {}, а у неё {} точек",
+                            m.train_idx,
+                            target_camera + 1,
+                            target_keypoints.len()
+                        ),
+                    )
+                })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+        points_2d.push(points_to_mat(&target_points)?);
+    }
 
-    // Для первой (референсной) камеры
-    let num_matches = all_matches[0].len();
-    debug!("Общее количество сопоставленных точек: {}", num_matches);
-    let mut points_cam_1 = Mat::zeros(num_matches as i32, 2, opencv::core::CV_64F)?.to_mat()?;
+    Ok(points_2d)
+}
 
-    for (j, matches) in all_matches[0].iter().enumerate() {
-        let match_ref = matches.get(0)?;
-        let kp = all_keypoints[0].get(match_ref.query_idx as usize)?;
-        *points_cam_1.at_2d_mut::<f64>(j as i32, 0)? = kp.pt().x as f64;
-        *points_cam_1.at_2d_mut::<f64>(j as i32, 1)? = kp.pt().y as f64;
+/// Точки в матрице Nx2 CV_64F
+fn points_to_mat(points: &[Point2f]) -> Result<Mat, Error> {
+    let mut mat = Mat::zeros(points.len() as i32, 2, opencv::core::CV_64F)?.to_mat()?;
+    for (j, point) in points.iter().enumerate() {
+        *mat.at_2d_mut::<f64>(j as i32, 0)? = point.x as f64;
+        *mat.at_2d_mut::<f64>(j as i32, 1)? = point.y as f64;
     }
-    points_2d.push(points_cam_1);
+    Ok(mat)
+}
 
-    for i in 1..all_matches.len() + 1 {
-        let mut points_cam = Mat::zeros(num_matches as i32, 2, opencv::core::CV_64F)?.to_mat()?;
+/// Наблюдения треков по сопоставлениям, выровненным по точкам референсной камеры (как после
+/// [`crate::reconstruction::min_visible_match_set`]): для строки `j` - трек `j` в камере 0
+/// и в каждой камере, где у него есть сопоставление. Возвращает тройки
+/// (номер трека, камера с 0, координаты в пикселях исходного кадра)
+pub fn correspondences_from_matches(
+    all_matches: &[Vector<Vector<DMatch>>],
+    all_keypoints: &[Vector<KeyPoint>],
+) -> Result<Vec<(usize, usize, Point2f)>, Error> {
+    let Some(reference_matches) = all_matches.first() else {
+        return Ok(Vec::new());
+    };
+    let mut observations = Vec::new();
+    for track_id in 0..reference_matches.len() {
+        let reference = reference_matches.get(track_id)?.get(0)?;
+        observations.push((
+            track_id,
+            0,
+            all_keypoints[0].get(reference.query_idx as usize)?.pt(),
+        ));
+        for (camera_i, camera_matches) in all_matches.iter().enumerate() {
+            let m = camera_matches.get(track_id)?.get(0)?;
+            if m.train_idx < 0 {
+                continue;
+            }
+            observations.push((
+                track_id,
+                camera_i + 1,
+                all_keypoints[camera_i + 1].get(m.train_idx as usize)?.pt(),
+            ));
+        }
+    }
+    Ok(observations)
+}
+
+/// Сохраняет наблюдения треков в CSV `track_id,cam_id,x,y` для внешних решателей
+/// (например, собственного bundle adjustment): строка на наблюдение трека одной камерой
+pub fn save_correspondences_csv<P: AsRef<Path>>(
+    observations: &[(usize, usize, Point2f)],
+    path: P,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "track_id,cam_id,x,y")?;
+    for (track_id, camera, point) in observations {
+        writeln!(file, "{},{},{},{}", track_id, camera, point.x, point.y)?;
+    }
+    file.flush()
+}
 
-        for (j, matches) in all_matches[i - 1].iter().enumerate() {
-            let match_ref = matches.get(0)?;
-            let kp = all_keypoints[i].get(match_ref.train_idx as usize)?;
-            *points_cam.at_2d_mut::<f64>(j as i32, 0)? = kp.pt().x as f64;
-            *points_cam.at_2d_mut::<f64>(j as i32, 1)? = kp.pt().y as f64;
+/// Пары координат (референсная точка, целевая точка) для двух изображений.
+/// Берётся ближайший сосед из каждого списка `matches` (как после `bf_match_knn`),
+/// `query_idx` указывает в `keypoints_1`, `train_idx` - в `keypoints_2`.
+/// Удобно для `find_homography`/`find_fundamental_mat` без промежуточных `Mat`
+pub fn matched_point_pairs(
+    matches: &Vector<Vector<DMatch>>,
+    keypoints_1: &Vector<KeyPoint>,
+    keypoints_2: &Vector<KeyPoint>,
+) -> Result<Vec<(Point2f, Point2f)>, Error> {
+    let mut pairs = Vec::with_capacity(matches.len());
+    for neighbours in matches.iter() {
+        if neighbours.is_empty() {
+            continue;
         }
-        points_2d.push(points_cam);
+        let m = neighbours.get(0)?;
+        let kp_1 = keypoints_1.get(m.query_idx as usize)?;
+        let kp_2 = keypoints_2.get(m.train_idx as usize)?;
+        pairs.push((kp_1.pt(), kp_2.pt()));
     }
+    Ok(pairs)
+}
 
-    Ok(points_2d)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{CV_8UC1, KeyPoint, Rect, Scalar};
+
+    /// Ключевые точки камеры `camera`: точка `i` лежит в (100 * camera + i, camera)
+    fn camera_keypoints(camera: usize, count: usize) -> Vector<KeyPoint> {
+        (0..count)
+            .map(|i| {
+                KeyPoint::new_coords_def((100 * camera + i) as f32, camera as f32, 1.0).unwrap()
+            })
+            .collect()
+    }
+
+    fn knn(pairs: &[(i32, i32)]) -> Vector<Vector<DMatch>> {
+        pairs
+            .iter()
+            .map(|&(query, train)| Vector::from_iter([DMatch::new(query, train, 0.0).unwrap()]))
+            .collect()
+    }
+
+    #[test]
+    fn three_camera_points_land_in_their_cameras() {
+        let keypoints: Vec<Vector<KeyPoint>> =
+            (0..3).map(|camera| camera_keypoints(camera, 4)).collect();
+        // С камерой 1 точки сопоставлены в обратном порядке, в камере 2 у точки 2 пары нет
+        let matches = [
+            knn(&[(0, 3), (1, 2), (2, 1)]),
+            knn(&[(0, 0), (1, 1), (2, -1)]),
+        ];
+        let points = gather_points_2d_from_matches(&matches, &keypoints).unwrap();
+        assert_eq!(points.len(), 3);
+        let rows = |camera: usize| -> Vec<(f64, f64)> {
+            let mat = points.get(camera).unwrap();
+            (0..mat.rows())
+                .map(|j| {
+                    (
+                        *mat.at_2d::<f64>(j, 0).unwrap(),
+                        *mat.at_2d::<f64>(j, 1).unwrap(),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(rows(0), vec![(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)]);
+        assert_eq!(rows(1), vec![(103.0, 1.0), (102.0, 1.0), (101.0, 1.0)]);
+        // Точки без пары получают координаты референсной камеры
+        assert_eq!(rows(2), vec![(200.0, 2.0), (201.0, 2.0), (2.0, 0.0)]);
+    }
+
+    #[test]
+    fn out_of_range_match_is_an_error() {
+        let keypoints: Vec<Vector<KeyPoint>> =
+            (0..3).map(|camera| camera_keypoints(camera, 2)).collect();
+        let matches = [knn(&[(0, 1)]), knn(&[(0, 5)])];
+        assert!(gather_points_2d_from_matches(&matches, &keypoints).is_err());
+        // Сопоставления даны не для всех камер
+        assert!(gather_points_2d_from_matches(&matches[..1], &keypoints).is_err());
+    }
+
+    #[test]
+    fn matched_pairs_take_query_and_train_points() {
+        let keypoints_1 = camera_keypoints(0, 3);
+        let keypoints_2 = camera_keypoints(1, 3);
+        let mut matches = knn(&[(0, 2), (2, 0)]);
+        // Признак без соседей пропускается
+        matches.insert(1, Vector::new()).unwrap();
+        // Берётся только ближайший сосед списка
+        matches.push(Vector::from_iter([
+            DMatch::new(1, 1, 0.0).unwrap(),
+            DMatch::new(1, 2, 1.0).unwrap(),
+        ]));
+
+        let pairs = matched_point_pairs(&matches, &keypoints_1, &keypoints_2).unwrap();
+        let expected: Vec<(Point2f, Point2f)> = [(0, 2), (2, 0), (1, 1)]
+            .iter()
+            .map(|&(query, train)| {
+                (
+                    keypoints_1.get(query).unwrap().pt(),
+                    keypoints_2.get(train).unwrap().pt(),
+                )
+            })
+            .collect();
+        assert_eq!(pairs, expected);
+
+        assert!(matched_point_pairs(&knn(&[(3, 0)]), &keypoints_1, &keypoints_2).is_err());
+        assert!(matched_point_pairs(&knn(&[(0, 3)]), &keypoints_1, &keypoints_2).is_err());
+    }
+
+    #[test]
+    fn gray_weights_are_given_in_rgb_order() {
+        let pixel = Mat::new_rows_cols_with_default(
+            1,
+            1,
+            opencv::core::CV_8UC3,
+            Scalar::new(10.0, 20.0, 30.0, 0.0),
+        )
+        .unwrap();
+        let gray_value = |weights| {
+            let gray = to_grayscale_weighted(&pixel, weights).unwrap();
+            *gray.at_2d::<u8>(0, 0).unwrap()
+        };
+        assert_eq!(gray_value([1.0, 0.0, 0.0]), 30);
+        assert_eq!(gray_value([0.0, 1.0, 0.0]), 20);
+        assert_eq!(gray_value([0.0, 0.0, 1.0]), 10);
+    }
+
+    #[test]
+    fn red_weights_find_red_on_green_texture() {
+        // Красные пятна на зелёном фоне той же яркости по BT.601 (0.299 * 255 ~ 0.587 * 130)
+        let mut image = Mat::new_rows_cols_with_default(
+            240,
+            320,
+            opencv::core::CV_8UC3,
+            Scalar::new(0.0, 130.0, 0.0, 0.0),
+        )
+        .unwrap();
+        for i in 0..6 {
+            for j in 0..4 {
+                opencv::imgproc::circle(
+                    &mut image,
+                    opencv::core::Point::new(30 + 50 * i, 35 + 55 * j),
+                    6 + (i + j) % 4 * 3,
+                    Scalar::new(0.0, 0.0, 255.0, 0.0),
+                    opencv::imgproc::FILLED,
+                    opencv::imgproc::LINE_8,
+                    0,
+                )
+                .unwrap();
+            }
+        }
+        let mut blurred = Mat::default();
+        opencv::imgproc::gaussian_blur_def(&image, &mut blurred, Size::new(3, 3), 0.8).unwrap();
+
+        let count = |gray_weights| {
+            let params = SiftParams {
+                gray_weights,
+                ..SiftParams::default()
+            };
+            sift_with_params(&blurred, &params).unwrap().0.len()
+        };
+        let luma = count(BT601_LUMA_WEIGHTS);
+        let red = count([1.0, 0.0, 0.0]);
+        assert!(red > luma, "красный канал {}, яркость {}", red, luma);
+    }
+
+    /// Сдвиг второго изображения относительно первого по горизонтали, пикс.
+    const SHIFT: i32 = 7;
+
+    /// Размытый шум и его копия, сдвинутая на `SHIFT` пикселей, с небольшим шумом сверху
+    fn textured_pair() -> (Mat, Mat) {
+        opencv::core::set_rng_seed(7).unwrap();
+        let mut noise =
+            Mat::new_rows_cols_with_default(320, 320, CV_8UC1, Scalar::all(0.0)).unwrap();
+        opencv::core::randu(&mut noise, &Scalar::all(0.0), &Scalar::all(255.0)).unwrap();
+        let mut texture = Mat::default();
+        opencv::imgproc::gaussian_blur_def(&noise, &mut texture, Size::new(5, 5), 1.5).unwrap();
+
+        let first = texture
+            .roi(Rect::new(0, 0, 300, 300))
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        let shifted = texture.roi(Rect::new(SHIFT, 0, 300, 300)).unwrap();
+        let mut jitter =
+            Mat::new_rows_cols_with_default(300, 300, CV_8UC1, Scalar::all(0.0)).unwrap();
+        opencv::core::randu(&mut jitter, &Scalar::all(0.0), &Scalar::all(12.0)).unwrap();
+        let mut second = Mat::default();
+        opencv::core::add_def(&shifted, &jitter, &mut second).unwrap();
+        (first, second)
+    }
+
+    fn orb(image: &Mat) -> (Vector<KeyPoint>, Mat) {
+        let mut detector = opencv::features2d::ORB::create_def().unwrap();
+        let mut keypoints = Vector::new();
+        let mut descriptors = Mat::default();
+        detector
+            .detect_and_compute_def(image, &Mat::default(), &mut keypoints, &mut descriptors)
+            .unwrap();
+        (keypoints, descriptors)
+    }
+
+    /// Доля сопоставлений, согласных со сдвигом изображений
+    fn correct_fraction(
+        matches: &Vector<DMatch>,
+        keypoints_1: &Vector<KeyPoint>,
+        keypoints_2: &Vector<KeyPoint>,
+    ) -> f64 {
+        let correct = matches
+            .iter()
+            .filter(|m| {
+                let p1 = keypoints_1.get(m.train_idx as usize).unwrap().pt();
+                let p2 = keypoints_2.get(m.query_idx as usize).unwrap().pt();
+                (p1.x - p2.x - SHIFT as f32).abs() < 2.0 && (p1.y - p2.y).abs() < 2.0
+            })
+            .count();
+        correct as f64 / matches.len().max(1) as f64
+    }
+
+    #[test]
+    fn orb_descriptors_match_with_hamming_norm() {
+        let (first, second) = textured_pair();
+        let (keypoints_1, descriptors_1) = orb(&first);
+        let (keypoints_2, descriptors_2) = orb(&second);
+        assert_eq!(DescriptorKind::of(&descriptors_1), DescriptorKind::Binary);
+
+        let hamming = bf_match_with_kind(
+            &descriptors_1,
+            &descriptors_2,
+            f32::MAX,
+            DescriptorKind::Binary,
+        )
+        .unwrap();
+        let l2 = bf_match(&descriptors_1, &descriptors_2, f32::MAX).unwrap();
+        let hamming_correct = correct_fraction(&hamming, &keypoints_1, &keypoints_2);
+        let l2_correct = correct_fraction(&l2, &keypoints_1, &keypoints_2);
+        assert!(hamming.len() > 50, "сопоставлений {}", hamming.len());
+        assert!(
+            hamming_correct > 0.7,
+            "верных по Хэммингу {}",
+            hamming_correct
+        );
+        assert!(
+            hamming_correct >= l2_correct,
+            "Хэмминг {}, L2 {}",
+            hamming_correct,
+            l2_correct
+        );
+    }
+
+    #[test]
+    fn mismatched_descriptors_are_rejected() {
+        let float = Mat::new_rows_cols_with_default(4, 128, opencv::core::CV_32F, Scalar::all(0.0))
+            .unwrap();
+        let binary = Mat::new_rows_cols_with_default(4, 32, CV_8UC1, Scalar::all(0.0)).unwrap();
+        assert!(matches!(
+            bf_match_knn(&float, &binary, 2, 0.8),
+            Err(LibCvError::DescriptorMismatch { .. })
+        ));
+    }
 }
@@ -0,0 +1,86 @@
+//! Сопоставление дескрипторов на GPU (фича `cuda`). Если устройства CUDA нет или OpenCV
+//! собран без CUDA, функции возвращают `None`, и вызывающий код сопоставляет на CPU.
+//! Поиск ключевых точек SIFT всегда идёт на CPU: в OpenCV нет CUDA-реализации SIFT,
+//! а `cuda::ORB` дал бы другие (бинарные) дескрипторы
+
+use std::sync::OnceLock;
+
+use log::{info, warn};
+use opencv::core::{DMatch, GpuMat, Mat, Vector, get_cuda_enabled_device_count, no_array};
+use opencv::cudafeatures2d::CUDA_DescriptorMatcher;
+use opencv::prelude::*;
+use opencv::{self, Error};
+
+/// Есть ли устройство CUDA; проверяется один раз за запуск
+fn cuda_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| match get_cuda_enabled_device_count() {
+        Ok(count) if count > 0 => {
+            info!(
+                "Дескрипторы сопоставляются на GPU (устройств CUDA: {})",
+                count
+            );
+            true
+        }
+        Ok(_) => {
+            info!("Устройств CUDA нет, дескрипторы сопоставляются на CPU");
+            false
+        }
+        Err(e) => {
+            info!("CUDA недоступна ({}), дескрипторы сопоставляются на CPU", e);
+            false
+        }
+    })
+}
+
+fn upload(descriptors: &Mat) -> Result<GpuMat, Error> {
+    let mut gpu = GpuMat::new_def()?;
+    gpu.upload(descriptors)?;
+    Ok(gpu)
+}
+
+/// `k` ближайших соседей в `train` для каждого дескриптора `query` на GPU.
+/// `None` - GPU недоступен или сопоставление на нём не удалось
+pub(crate) fn knn_match(
+    query: &Mat,
+    train: &Mat,
+    k: i32,
+    norm: i32,
+) -> Option<Vector<Vector<DMatch>>> {
+    if !cuda_available() {
+        return None;
+    }
+    let result = (|| -> Result<_, Error> {
+        let mut matcher = CUDA_DescriptorMatcher::create_bf_matcher(norm)?;
+        let mut matches = Vector::<Vector<DMatch>>::default();
+        matcher.knn_match(
+            &upload(query)?,
+            &upload(train)?,
+            &mut matches,
+            k,
+            &no_array(),
+            false,
+        )?;
+        Ok(matches)
+    })();
+    result
+        .inspect_err(|e| warn!("Сопоставление на GPU не удалось, повтор на CPU: {}", e))
+        .ok()
+}
+
+/// Ближайший сосед в `train` для каждого дескриптора `query` на GPU.
+/// `None` - GPU недоступен или сопоставление на нём не удалось
+pub(crate) fn match_(query: &Mat, train: &Mat, norm: i32) -> Option<Vector<DMatch>> {
+    if !cuda_available() {
+        return None;
+    }
+    let result = (|| -> Result<_, Error> {
+        let mut matcher = CUDA_DescriptorMatcher::create_bf_matcher(norm)?;
+        let mut matches = Vector::<DMatch>::default();
+        matcher.match__def(&upload(query)?, &upload(train)?, &mut matches)?;
+        Ok(matches)
+    })();
+    result
+        .inspect_err(|e| warn!("Сопоставление на GPU не удалось, повтор на CPU: {}", e))
+        .ok()
+}
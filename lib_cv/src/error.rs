@@ -0,0 +1,20 @@
+/// Ошибки, которые OpenCV сообщает невнятно, поэтому они проверяются заранее
+#[derive(Debug, thiserror::Error)]
+pub enum LibCvError {
+    #[error(
+        "Дескрипторы нельзя сопоставить: {first} и {second}. \
+         Ключевые точки всех камер должны находиться одним детектором"
+    )]
+    DescriptorMismatch { first: String, second: String },
+    #[error(transparent)]
+    OpenCv(#[from] opencv::Error),
+}
+
+impl From<LibCvError> for opencv::Error {
+    fn from(e: LibCvError) -> Self {
+        match e {
+            LibCvError::OpenCv(e) => e,
+            other => opencv::Error::new(opencv::core::StsError, other.to_string()),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt;
+
+use log::debug;
+use opencv::core::{CV_64F, Mat, Point2f, Vector};
+use opencv::imgproc;
+use opencv::objdetect::CharucoBoard;
+use opencv::prelude::*;
+use opencv::{self, Error};
+
+use crate::calibration::{ContrastEnhancement, find_common_points, get_charuco_enhanced};
+
+/// Оценка одного многокамерного кадра для автоматического выбора калибровочных кадров
+#[derive(Debug, Clone)]
+pub struct FrameCandidate {
+    /// Номер кадра видео
+    pub frame: usize,
+    /// Количество найденных углов ChArUco в каждой камере
+    pub corners: Vec<usize>,
+    /// Сколько углов каждой камеры найдено и в референсной камере
+    pub shared_with_first: Vec<usize>,
+    /// Резкость каждой камеры (дисперсия лапласиана)
+    pub sharpness: Vec<f64>,
+    /// Грубое описание положения доски в референсной камере: центр и размер
+    /// описывающего прямоугольника углов в долях кадра. Без калибровки это
+    /// заменяет настоящую позу при оценке новизны ракурса
+    pub pose_signature: Option<[f64; 4]>,
+}
+
+impl FrameCandidate {
+    /// Чем больше углов во всех камерах и чем резче самая размытая камера, тем лучше
+    pub fn score(&self) -> f64 {
+        let min_corners = self.corners.iter().copied().min().unwrap_or(0) as f64;
+        let min_sharpness = self.sharpness.iter().copied().fold(f64::INFINITY, f64::min);
+        min_corners * (1.0 + min_sharpness.max(0.0).ln_1p())
+    }
+}
+
+/// Пороги автоматического выбора кадров
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoSelectParams {
+    /// Сколько кадров выбрать
+    pub count: usize,
+    /// Минимум углов доски в каждой камере
+    pub min_corners: usize,
+    /// Минимальная резкость каждой камеры
+    pub min_sharpness: f64,
+    /// Минимальное расстояние между описаниями положения доски у выбранных кадров
+    pub min_pose_distance: f64,
+}
+
+impl Default for AutoSelectParams {
+    fn default() -> Self {
+        Self {
+            count: 20,
+            min_corners: 6,
+            min_sharpness: 50.0,
+            min_pose_distance: 0.08,
+        }
+    }
+}
+
+/// Причина, по которой кадр не попал в выборку
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// Доска не найдена или найдено слишком мало углов в камере (нумерация с 1)
+    BoardMissing { camera: usize, corners: usize },
+    /// Камера (нумерация с 1) слишком размыта
+    Blurry { camera: usize, sharpness: f64 },
+    /// Положение доски почти совпадает с уже выбранным кадром
+    SimilarPose { to_frame: usize },
+    /// Кадр годный, но набралось достаточно кадров с большей оценкой
+    LowerScore,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::BoardMissing { camera, corners } => {
+                write!(
+                    f,
+                    "доска не найдена в камере {} (углов: {})",
+                    camera, corners
+                )
+            }
+            Rejection::Blurry { camera, sharpness } => {
+                write!(f, "камера {} размыта (резкость {:.1})", camera, sharpness)
+            }
+            Rejection::SimilarPose { to_frame } => {
+                write!(f, "положение доски похоже на кадр {}", to_frame)
+            }
+            Rejection::LowerScore => write!(f, "набрано достаточно кадров с лучшей оценкой"),
+        }
+    }
+}
+
+/// Резкость изображения как дисперсия лапласиана яркости
+pub fn sharpness(image: &Mat) -> Result<f64, Error> {
+    let mut gray = Mat::default();
+    if image.channels() == 1 {
+        gray = image.clone();
+    } else {
+        imgproc::cvt_color_def(image, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    }
+    let mut laplacian = Mat::default();
+    imgproc::laplacian_def(&gray, &mut laplacian, CV_64F)?;
+    let mut mean = Mat::default();
+    let mut std_dev = Mat::default();
+    opencv::core::mean_std_dev_def(&laplacian, &mut mean, &mut std_dev)?;
+    let std_dev = *std_dev.at_2d::<f64>(0, 0)?;
+    Ok(std_dev * std_dev)
+}
+
+/// Оценивает кадр: ищет доску в каждой камере и измеряет резкость.
+/// С `contrast` доска ищется на изображении с выровненным контрастом.
+/// Описание положения доски берётся по первой камере
+pub fn score_frame(
+    frame: usize,
+    camera_images: &[Mat],
+    charuco_board: &CharucoBoard,
+    contrast: Option<&ContrastEnhancement>,
+) -> Result<FrameCandidate, Error> {
+    let mut corners = Vec::with_capacity(camera_images.len());
+    let mut shared_with_first = Vec::with_capacity(camera_images.len());
+    let mut first_ids = Vector::<i32>::new();
+    let mut sharpness_values = Vec::with_capacity(camera_images.len());
+    let mut pose_signature = None;
+
+    for (camera_i, image) in camera_images.iter().enumerate() {
+        let detection = get_charuco_enhanced(charuco_board, image, contrast)?;
+        corners.push(detection.charuco_ids.len());
+        sharpness_values.push(sharpness(image)?);
+        if camera_i == 0 {
+            pose_signature = board_signature(&detection.charuco_corners, image);
+            first_ids = detection.charuco_ids.clone();
+        }
+        shared_with_first
+            .push(find_common_points(&[first_ids.clone(), detection.charuco_ids]).len());
+    }
+
+    Ok(FrameCandidate {
+        frame,
+        corners,
+        shared_with_first,
+        sharpness: sharpness_values,
+        pose_signature,
+    })
+}
+
+fn board_signature(corners: &Vector<Point2f>, image: &Mat) -> Option<[f64; 4]> {
+    if corners.is_empty() {
+        return None;
+    }
+    let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+    let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+    for p in corners.iter() {
+        min_x = min_x.min(p.x);
+        min_y = min_y.min(p.y);
+        max_x = max_x.max(p.x);
+        max_y = max_y.max(p.y);
+    }
+    let width = image.cols() as f64;
+    let height = image.rows() as f64;
+    Some([
+        (min_x + max_x) as f64 / 2.0 / width,
+        (min_y + max_y) as f64 / 2.0 / height,
+        (max_x - min_x) as f64 / width,
+        (max_y - min_y) as f64 / height,
+    ])
+}
+
+fn signature_distance(a: &[f64; 4], b: &[f64; 4]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Причина, по которой кадр непригоден для выбора: доска найдена не во всех камерах
+/// или кадр размыт. `None` - кадр пригоден
+fn unusable_reason(candidate: &FrameCandidate, params: &AutoSelectParams) -> Option<Rejection> {
+    if let Some((camera_i, &corners)) = candidate
+        .corners
+        .iter()
+        .enumerate()
+        .find(|(_, c)| **c < params.min_corners)
+    {
+        return Some(Rejection::BoardMissing {
+            camera: camera_i + 1,
+            corners,
+        });
+    }
+    candidate
+        .sharpness
+        .iter()
+        .enumerate()
+        .find(|(_, s)| **s < params.min_sharpness)
+        .map(|(camera_i, &sharpness)| Rejection::Blurry {
+            camera: camera_i + 1,
+            sharpness,
+        })
+}
+
+/// Выбирает до `params.count` кадров: отбрасывает кадры без доски или размытые,
+/// затем жадно берёт кадры по убыванию оценки, пропуская ракурсы, похожие на уже выбранные.
+/// Возвращает номера выбранных кадров (по возрастанию) и причины отказа остальным
+pub fn auto_select_frames(
+    candidates: &[FrameCandidate],
+    params: &AutoSelectParams,
+) -> (Vec<usize>, Vec<(usize, Rejection)>) {
+    let mut rejected = Vec::new();
+    let mut usable: Vec<&FrameCandidate> = Vec::new();
+
+    for candidate in candidates {
+        match unusable_reason(candidate, params) {
+            Some(reason) => rejected.push((candidate.frame, reason)),
+            None => usable.push(candidate),
+        }
+    }
+
+    usable.sort_by(|a, b| b.score().total_cmp(&a.score()));
+
+    let mut selected: Vec<&FrameCandidate> = Vec::new();
+    for candidate in usable {
+        if selected.len() >= params.count {
+            rejected.push((candidate.frame, Rejection::LowerScore));
+            continue;
+        }
+        let similar = candidate.pose_signature.and_then(|signature| {
+            selected.iter().find(|s| {
+                s.pose_signature.is_some_and(|other| {
+                    signature_distance(&signature, &other) < params.min_pose_distance
+                })
+            })
+        });
+        match similar {
+            Some(s) => rejected.push((
+                candidate.frame,
+                Rejection::SimilarPose { to_frame: s.frame },
+            )),
+            None => selected.push(candidate),
+        }
+    }
+
+    let mut frames: Vec<usize> = selected.iter().map(|c| c.frame).collect();
+    frames.sort_unstable();
+    rejected.sort_by_key(|(frame, _)| *frame);
+    debug!(
+        "Автовыбор: выбрано {} кадров, отклонено {}",
+        frames.len(),
+        rejected.len()
+    );
+    (frames, rejected)
+}
+
+/// Делит `count` кадров между записями пропорционально числу пригодных кадров `usable`
+/// в каждой (остаток отдаётся записям с наибольшей дробной частью доли)
+pub fn distribute_budget(count: usize, usable: &[usize]) -> Vec<usize> {
+    let total: usize = usable.iter().sum();
+    if total == 0 {
+        return vec![0; usable.len()];
+    }
+    let count = count.min(total);
+    let mut budget: Vec<usize> = usable.iter().map(|&n| count * n / total).collect();
+    let mut by_remainder: Vec<usize> = (0..usable.len()).collect();
+    by_remainder.sort_by_key(|&i| std::cmp::Reverse(count * usable[i] % total));
+    let missing = count - budget.iter().sum::<usize>();
+    for &i in by_remainder.iter().take(missing) {
+        budget[i] += 1;
+    }
+    budget
+}
+
+/// Как [`auto_select_frames`] для кадров нескольких записей (`clips[i]` - кадры записи `i`):
+/// `params.count` делится между записями пропорционально числу пригодных кадров в них,
+/// чтобы короткая запись с особым ракурсом доски не терялась среди длинных
+pub fn auto_select_frames_by_clip(
+    clips: &[Vec<FrameCandidate>],
+    params: &AutoSelectParams,
+) -> (Vec<usize>, Vec<(usize, Rejection)>) {
+    let usable: Vec<usize> = clips
+        .iter()
+        .map(|candidates| {
+            candidates
+                .iter()
+                .filter(|c| unusable_reason(c, params).is_none())
+                .count()
+        })
+        .collect();
+    let budget = distribute_budget(params.count, &usable);
+    debug!(
+        "Автовыбор по записям: пригодно {:?}, бюджет {:?}",
+        usable, budget
+    );
+
+    let mut selected = Vec::new();
+    let mut rejected = Vec::new();
+    for (candidates, count) in clips.iter().zip(budget) {
+        let params = AutoSelectParams { count, ..*params };
+        let (clip_selected, clip_rejected) = auto_select_frames(candidates, &params);
+        selected.extend(clip_selected);
+        rejected.extend(clip_rejected);
+    }
+    selected.sort_unstable();
+    rejected.sort_by_key(|(frame, _)| *frame);
+    (selected, rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::BoardConfig;
+
+    #[test]
+    fn dark_frame_is_scored_with_contrast_enhancement() {
+        let board = BoardConfig::new().build().unwrap();
+        let mut image = Mat::default();
+        board
+            .generate_image(opencv::core::Size::new(1000, 500), &mut image, 40, 1)
+            .unwrap();
+        let mut dark = Mat::default();
+        image.convert_to(&mut dark, -1, 0.06, 2.0).unwrap();
+        let cameras = [dark.clone(), dark];
+
+        let contrast = ContrastEnhancement::default();
+        let candidate = score_frame(7, &cameras, &board, Some(&contrast)).unwrap();
+        let total = board.get_chessboard_corners().unwrap().len();
+        assert_eq!(candidate.frame, 7);
+        assert!(candidate.corners.iter().all(|&c| c * 2 >= total));
+        assert_eq!(candidate.shared_with_first[1], candidate.corners[1]);
+        assert!(candidate.pose_signature.is_some());
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::reconstruction::{Point3D, PointCloud};
+
+/// Параметры слияния покадровых облаков в общую карту
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FusionParams {
+    /// Радиус поиска соседей (в единицах калибровки, обычно мм). Точка без известного
+    /// `track_id` сливается с ближайшей точкой карты в этом радиусе. 0 - только по `track_id`
+    pub radius: f64,
+    /// Предельный вес накопленного среднего точки карты: после стольких наблюдений
+    /// новые наблюдения входят в позицию с постоянным весом `1 / max_observations`,
+    /// и точка продолжает следовать за медленным дрейфом сцены
+    pub max_observations: usize,
+}
+
+impl Default for FusionParams {
+    fn default() -> Self {
+        Self {
+            radius: 2.0,
+            max_observations: 30,
+        }
+    }
+}
+
+/// Точка общей карты: среднее всех слитых в неё наблюдений
+#[derive(Debug, Clone)]
+struct FusedPoint {
+    point: Point3D,
+    observations: usize,
+    /// Номер последнего слитого в точку кадра (по порядку вызовов `add_cloud`)
+    frame: usize,
+}
+
+type VoxelKey = (i64, i64, i64);
+
+/// Общая карта, в которую последовательно сливаются облака отдельных кадров.
+/// Точки связываются по `track_id`, а если трек новый (например, переинициализирован) -
+/// по расстоянию до уже накопленных точек
+pub struct FusedMap {
+    params: FusionParams,
+    points: Vec<FusedPoint>,
+    by_track: HashMap<usize, usize>,
+    /// Индексы точек по вокселям со стороной `radius` для поиска соседей
+    grid: HashMap<VoxelKey, Vec<usize>>,
+    /// Количество слитых облаков
+    frames: usize,
+}
+
+impl FusedMap {
+    pub fn new(params: FusionParams) -> Self {
+        Self {
+            params,
+            points: Vec::new(),
+            by_track: HashMap::new(),
+            grid: HashMap::new(),
+            frames: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Сливает облако кадра с картой. По расстоянию точка сливается только с точками,
+    /// накопленными предыдущими кадрами: соседние точки одного облака остаются раздельными
+    pub fn add_cloud(&mut self, cloud: &PointCloud) {
+        self.frames += 1;
+        let (mut by_id, mut by_radius) = (0, 0);
+        for point in &cloud.points {
+            let known_track = point
+                .track_id
+                .and_then(|id| self.by_track.get(&id).copied());
+            let target = match known_track {
+                Some(i) => {
+                    by_id += 1;
+                    Some(i)
+                }
+                None => {
+                    let nearest = self.nearest(point);
+                    by_radius += usize::from(nearest.is_some());
+                    nearest
+                }
+            };
+
+            let index = match target {
+                Some(i) => {
+                    self.merge(i, point);
+                    i
+                }
+                None => self.insert(point.clone()),
+            };
+            if let Some(id) = point.track_id {
+                self.by_track.insert(id, index);
+            }
+        }
+        debug!(
+            "Кадр {}: слито по треку {}, по расстоянию {}, точек в карте {}",
+            cloud.timestamp,
+            by_id,
+            by_radius,
+            self.points.len()
+        );
+    }
+
+    /// Облако общей карты; уверенность и ошибка перепроекции усреднены по наблюдениям
+    pub fn to_point_cloud(&self, timestamp: usize) -> PointCloud {
+        PointCloud {
+            points: self.points.iter().map(|p| p.point.clone()).collect(),
+            timestamp,
+        }
+    }
+
+    fn voxel(&self, point: &Point3D) -> Option<VoxelKey> {
+        if self.params.radius <= 0.0 {
+            return None;
+        }
+        let r = self.params.radius;
+        Some((
+            (point.x / r).floor() as i64,
+            (point.y / r).floor() as i64,
+            (point.z / r).floor() as i64,
+        ))
+    }
+
+    /// Ближайшая точка карты в радиусе среди точек, не затронутых текущим кадром
+    fn nearest(&self, point: &Point3D) -> Option<usize> {
+        let (vx, vy, vz) = self.voxel(point)?;
+        let radius_sq = self.params.radius * self.params.radius;
+
+        let mut best: Option<(f64, usize)> = None;
+        let neighbours = (-1..=1).flat_map(|dx| {
+            (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (vx + dx, vy + dy, vz + dz)))
+        });
+        for key in neighbours {
+            let Some(indices) = self.grid.get(&key) else {
+                continue;
+            };
+            for &i in indices {
+                if self.points[i].frame == self.frames {
+                    continue;
+                }
+                let d = distance_sq(&self.points[i].point, point);
+                if d <= radius_sq && best.is_none_or(|(best_d, _)| d < best_d) {
+                    best = Some((d, i));
+                }
+            }
+        }
+        best.map(|(_, i)| i)
+    }
+
+    fn insert(&mut self, point: Point3D) -> usize {
+        let index = self.points.len();
+        if let Some(key) = self.voxel(&point) {
+            self.grid.entry(key).or_default().push(index);
+        }
+        self.points.push(FusedPoint {
+            point,
+            observations: 1,
+            frame: self.frames,
+        });
+        index
+    }
+
+    fn merge(&mut self, index: usize, point: &Point3D) {
+        let old_key = self.voxel(&self.points[index].point);
+
+        let max_observations = self.params.max_observations.max(1);
+        let fused = &mut self.points[index];
+        fused.observations += 1;
+        fused.frame = self.frames;
+        let n = fused.observations.min(max_observations) as f64;
+        let p = &mut fused.point;
+        p.x += (point.x - p.x) / n;
+        p.y += (point.y - p.y) / n;
+        p.z += (point.z - p.z) / n;
+        p.confidence += (point.confidence - p.confidence) / n as f32;
+        p.reproj_error = match (p.reproj_error, point.reproj_error) {
+            (Some(a), Some(b)) => Some(a + (b - a) / n),
+            (a, b) => a.or(b),
+        };
+        if p.color.is_none() {
+            p.color = point.color;
+        }
+
+        // Среднее сместилось - при смене вокселя переносим индекс
+        let new_key = self.voxel(&self.points[index].point);
+        if old_key != new_key {
+            if let Some(indices) = old_key.and_then(|k| self.grid.get_mut(&k)) {
+                indices.retain(|&i| i != index);
+            }
+            if let Some(key) = new_key {
+                self.grid.entry(key).or_default().push(index);
+            }
+        }
+    }
+}
+
+fn distance_sq(a: &Point3D, b: &Point3D) -> f64 {
+    (a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cloud(timestamp: usize, points: &[(f64, Option<usize>)]) -> PointCloud {
+        PointCloud {
+            points: points
+                .iter()
+                .map(|&(x, track_id)| Point3D {
+                    track_id,
+                    ..Point3D::new(x, 0.0, 0.0, 1.0)
+                })
+                .collect(),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn close_points_of_one_cloud_stay_separate() {
+        let mut map = FusedMap::new(FusionParams::default());
+        map.add_cloud(&cloud(0, &[(0.0, None), (0.5, None), (1.0, None)]));
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn points_of_next_cloud_merge_by_radius() {
+        let mut map = FusedMap::new(FusionParams::default());
+        map.add_cloud(&cloud(0, &[(0.0, None), (10.0, None)]));
+        map.add_cloud(&cloud(1, &[(0.5, None), (10.5, None), (20.0, None)]));
+        assert_eq!(map.len(), 3);
+        let fused = map.to_point_cloud(1);
+        assert!((fused.points[0].x - 0.25).abs() < 1e-12);
+        assert!((fused.points[1].x - 10.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn observation_weight_is_capped() {
+        let params = FusionParams {
+            radius: 0.0,
+            max_observations: 2,
+        };
+        let mut map = FusedMap::new(params);
+        for (frame, x) in [0.0, 0.0, 0.0, 0.0, 8.0].into_iter().enumerate() {
+            map.add_cloud(&cloud(frame, &[(x, Some(1))]));
+        }
+        assert_eq!(map.len(), 1);
+        // Без ограничения среднее было бы 1.6
+        assert!((map.to_point_cloud(0).points[0].x - 4.0).abs() < 1e-12);
+    }
+}
@@ -1,4 +1,15 @@
+pub mod board;
 pub mod calibration;
 pub mod correspondence;
+#[cfg(feature = "cuda")]
+mod cuda;
+pub mod error;
+pub mod frame_selection;
+pub mod fusion;
+pub mod plane;
+pub mod pool;
 pub mod reconstruction;
+pub mod registration;
+pub mod stereo;
+pub mod tracking;
 pub mod utils;
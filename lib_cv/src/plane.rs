@@ -0,0 +1,263 @@
+use log::{debug, info};
+use opencv::{
+    Error,
+    core::{CV_64F, Mat, StsError, eigen},
+    prelude::*,
+};
+
+use crate::reconstruction::{Point3D, PointCloud};
+
+/// Наибольшее число итераций RANSAC; обычно поиск останавливается раньше,
+/// когда доля найденных инлаеров делает новую гипотезу маловероятной
+const MAX_ITERATIONS: usize = 1000;
+/// Требуемая вероятность хотя бы раз выбрать тройку без выбросов
+const CONFIDENCE: f64 = 0.99;
+
+/// Плоскость `a*x + b*y + c*z + d = 0` с единичной нормалью `(a, b, c)`
+pub type Plane = (f64, f64, f64, f64);
+
+/// Результат поиска доминирующей плоскости
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaneFit {
+    pub plane: Plane,
+    /// Число точек облака не дальше порога от плоскости
+    pub inliers: usize,
+}
+
+/// Ищет доминирующую плоскость облака (пол, стену) методом RANSAC: точка считается
+/// лежащей на плоскости, если она ближе `ransac_threshold` (в единицах облака).
+/// Найденная плоскость уточняется методом наименьших квадратов по всем её инлаерам.
+/// Выборка детерминирована, поэтому повторный запуск на том же облаке даёт тот же результат
+pub fn fit_dominant_plane(cloud: &PointCloud, ransac_threshold: f64) -> Result<PlaneFit, Error> {
+    let points = &cloud.points;
+    if points.len() < 3 {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Для поиска плоскости нужно минимум 3 точки, есть {}",
+                points.len()
+            ),
+        ));
+    }
+
+    let mut rng = XorShift::new(0x9E37_79B9_7F4A_7C15);
+    let mut best: Option<(Plane, usize)> = None;
+    let mut iterations = MAX_ITERATIONS;
+    let mut i = 0;
+    while i < iterations {
+        i += 1;
+        let sample = [
+            &points[rng.below(points.len())],
+            &points[rng.below(points.len())],
+            &points[rng.below(points.len())],
+        ];
+        let Some(plane) = plane_through(sample[0], sample[1], sample[2]) else {
+            continue;
+        };
+        let inliers = count_inliers(points, plane, ransac_threshold);
+        if best.is_none_or(|(_, best_inliers)| inliers > best_inliers) {
+            best = Some((plane, inliers));
+            let ratio = inliers as f64 / points.len() as f64;
+            iterations = required_iterations(ratio).min(MAX_ITERATIONS);
+        }
+    }
+
+    let Some((plane, _)) = best else {
+        return Err(Error::new(
+            StsError,
+            "Все выбранные тройки точек вырождены: плоскость не найдена",
+        ));
+    };
+    debug!("RANSAC плоскости: {} итераций", i);
+
+    let plane = refine_plane(points, plane, ransac_threshold)?.unwrap_or(plane);
+    let inliers = count_inliers(points, plane, ransac_threshold);
+    info!(
+        "Доминирующая плоскость: {:.4}x + {:.4}y + {:.4}z + {:.4} = 0, на ней {} из {} точек",
+        plane.0,
+        plane.1,
+        plane.2,
+        plane.3,
+        inliers,
+        points.len()
+    );
+    Ok(PlaneFit { plane, inliers })
+}
+
+/// Облако без точек, лежащих ближе `threshold` к плоскости `plane`:
+/// например, объект без пола, на котором он стоит
+pub fn remove_plane(cloud: &PointCloud, plane: Plane, threshold: f64) -> PointCloud {
+    let points: Vec<Point3D> = cloud
+        .points
+        .iter()
+        .filter(|p| distance_to_plane(p, plane) > threshold)
+        .cloned()
+        .collect();
+    debug!(
+        "Удалено {} точек плоскости из {}",
+        cloud.points.len() - points.len(),
+        cloud.points.len()
+    );
+    PointCloud {
+        points,
+        timestamp: cloud.timestamp,
+    }
+}
+
+pub fn distance_to_plane(point: &Point3D, (a, b, c, d): Plane) -> f64 {
+    (a * point.x + b * point.y + c * point.z + d).abs()
+}
+
+fn count_inliers(points: &[Point3D], plane: Plane, threshold: f64) -> usize {
+    points
+        .iter()
+        .filter(|p| distance_to_plane(p, plane) <= threshold)
+        .count()
+}
+
+/// Плоскость через три точки или `None`, если они лежат на одной прямой
+fn plane_through(p1: &Point3D, p2: &Point3D, p3: &Point3D) -> Option<Plane> {
+    let u = [p2.x - p1.x, p2.y - p1.y, p2.z - p1.z];
+    let v = [p3.x - p1.x, p3.y - p1.y, p3.z - p1.z];
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if len < 1e-12 {
+        return None;
+    }
+    let (a, b, c) = (n[0] / len, n[1] / len, n[2] / len);
+    Some((a, b, c, -(a * p1.x + b * p1.y + c * p1.z)))
+}
+
+/// Плоскость наименьших квадратов по инлаерам `plane`: нормаль - собственный вектор
+/// ковариации точек с наименьшим собственным значением
+fn refine_plane(points: &[Point3D], plane: Plane, threshold: f64) -> Result<Option<Plane>, Error> {
+    let inliers: Vec<&Point3D> = points
+        .iter()
+        .filter(|p| distance_to_plane(p, plane) <= threshold)
+        .collect();
+    if inliers.len() < 3 {
+        return Ok(None);
+    }
+    let n = inliers.len() as f64;
+    let centroid = inliers.iter().fold([0.0; 3], |acc, p| {
+        [acc[0] + p.x / n, acc[1] + p.y / n, acc[2] + p.z / n]
+    });
+
+    let mut covariance = Mat::zeros(3, 3, CV_64F)?.to_mat()?;
+    for p in &inliers {
+        let delta = [p.x - centroid[0], p.y - centroid[1], p.z - centroid[2]];
+        for r in 0..3 {
+            for c in 0..3 {
+                *covariance.at_2d_mut::<f64>(r as i32, c as i32)? += delta[r] * delta[c];
+            }
+        }
+    }
+
+    let mut eigenvalues = Mat::default();
+    let mut eigenvectors = Mat::default();
+    if !eigen(&covariance, &mut eigenvalues, &mut eigenvectors)? {
+        return Ok(None);
+    }
+    // Собственные значения упорядочены по убыванию: нормаль - последняя строка
+    let (mut a, mut b, mut c) = (
+        *eigenvectors.at_2d::<f64>(2, 0)?,
+        *eigenvectors.at_2d::<f64>(2, 1)?,
+        *eigenvectors.at_2d::<f64>(2, 2)?,
+    );
+    // Сохраняем направление нормали исходной гипотезы
+    if a * plane.0 + b * plane.1 + c * plane.2 < 0.0 {
+        (a, b, c) = (-a, -b, -c);
+    }
+    let d = -(a * centroid[0] + b * centroid[1] + c * centroid[2]);
+    Ok(Some((a, b, c, d)))
+}
+
+/// Число итераций, после которого с вероятностью `CONFIDENCE` выбрана тройка инлаеров
+fn required_iterations(inlier_ratio: f64) -> usize {
+    let all_inliers = inlier_ratio.powi(3);
+    if all_inliers >= 1.0 {
+        return 1;
+    }
+    if all_inliers <= f64::EPSILON {
+        return MAX_ITERATIONS;
+    }
+    ((1.0 - CONFIDENCE).ln() / (1.0 - all_inliers).ln()).ceil() as usize
+}
+
+/// Простой генератор псевдослучайных чисел для выборки RANSAC и прореживания облаков.
+/// Одно и то же зерно всегда даёт одну и ту же последовательность
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Равномерное число из [0, 1)
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plane_of_sampled_points_is_recovered() {
+        // Плоскость x + 2y + 2z = 6 с нормалью (1, 2, 2) / 3 и выбросы над ней
+        let normal = [1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0];
+        let mut rng = XorShift::new(7);
+        let mut points: Vec<Point3D> = (0..300)
+            .map(|_| {
+                let (x, y) = (rng.unit() * 4.0 - 2.0, rng.unit() * 4.0 - 2.0);
+                Point3D::new(x, y, (6.0 - x - 2.0 * y) / 2.0, 1.0)
+            })
+            .collect();
+        points.extend(
+            (0..30).map(|_| Point3D::new(rng.unit(), rng.unit(), 5.0 + rng.unit() * 3.0, 1.0)),
+        );
+        let cloud = PointCloud {
+            points,
+            timestamp: 0,
+        };
+
+        let fit = fit_dominant_plane(&cloud, 0.01).unwrap();
+        let (a, b, c, d) = fit.plane;
+        // Нормаль определена с точностью до знака
+        let sign = if a * normal[0] + b * normal[1] + c * normal[2] < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        for (found, expected) in [a, b, c].into_iter().zip(normal) {
+            assert!((sign * found - expected).abs() < 1e-6);
+        }
+        assert!((sign * d + 2.0).abs() < 1e-6);
+        assert_eq!(fit.inliers, 300);
+    }
+
+    #[test]
+    fn too_few_points_are_an_error() {
+        let cloud = PointCloud {
+            points: vec![Point3D::new(0.0, 0.0, 0.0, 1.0); 2],
+            timestamp: 0,
+        };
+        assert!(fit_dominant_plane(&cloud, 0.01).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use opencv::{
+    Error,
+    core::{Mat, Scalar},
+    prelude::*,
+};
+
+/// Сколько свободных матриц одного размера и типа хранить. Покадровому циклу нужно
+/// по матрице на камеру, лишние просто освобождаются
+const MAX_FREE_PER_SHAPE: usize = 16;
+
+/// Размер и тип матрицы: (строки, столбцы, тип OpenCV)
+type Shape = (i32, i32, i32);
+
+/// Пул переиспользуемых матриц. Функции OpenCV не выделяют память заново, если выходная
+/// матрица уже нужного размера и типа, поэтому матрица из пула, вернувшаяся после кадра,
+/// на следующем кадре заполняется без выделения.
+///
+/// Без пула покадровая триангуляция по K камерам выделяет 2K + 1 матриц на кадр:
+/// K транспонированных наборов точек, результат `triangulate_points` и K буферов
+/// `undistort_points`. С пулом при неизменном числе точек (треки оптического потока
+/// без отбрасывания) все они выделяются только на первом кадре. Фактические числа
+/// за запуск показывает [`MatPool::stats`]
+#[derive(Debug, Default)]
+pub struct MatPool {
+    free: HashMap<Shape, Vec<Mat>>,
+    stats: PoolStats,
+}
+
+/// Счётчики пула: сколько матриц выделено заново и сколько выдано повторно
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub allocated: usize,
+    pub reused: usize,
+}
+
+impl std::ops::Add for PoolStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            allocated: self.allocated + other.allocated,
+            reused: self.reused + other.reused,
+        }
+    }
+}
+
+impl MatPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Выдаёт матрицу `rows`x`cols` типа `typ`. Содержимое повторно выданной матрицы
+    /// осталось от прошлого использования: её нужно перезаписать целиком
+    pub fn take(&mut self, rows: i32, cols: i32, typ: i32) -> Result<Mat, Error> {
+        if let Some(mat) = self
+            .free
+            .get_mut(&(rows, cols, typ))
+            .and_then(|free| free.pop())
+        {
+            self.stats.reused += 1;
+            return Ok(mat);
+        }
+        self.stats.allocated += 1;
+        Mat::new_rows_cols_with_default(rows, cols, typ, Scalar::all(0.0))
+    }
+
+    /// Возвращает матрицу в пул. Пустые матрицы и представления чужих данных
+    /// (например, строки другой матрицы) не сохраняются
+    pub fn recycle(&mut self, mat: Mat) {
+        if mat.empty() || !mat.is_continuous() || mat.is_submatrix() {
+            return;
+        }
+        let free = self
+            .free
+            .entry((mat.rows(), mat.cols(), mat.typ()))
+            .or_default();
+        if free.len() < MAX_FREE_PER_SHAPE {
+            free.push(mat);
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+}
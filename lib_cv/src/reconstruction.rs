@@ -2,17 +2,30 @@ use log::{debug, error, info, warn};
 use opencv::{
     Error,
     calib3d::undistort_points,
-    core::{DMatch, KeyPoint, Mat, Point3d, StsError, Vec2d, Vector, gemm},
+    core::{
+        CV_8UC3, CV_32F, DMatch, KeyPoint, Mat, Point3d, Scalar, Size, StsError, Vec2d, Vec3b,
+        Vector, gemm,
+    },
     prelude::*,
     sfm::triangulate_points,
 };
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, Write};
-use std::path::Path;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use crate::{
-    calibration::CameraParameters,
-    correspondence::{bf_match_knn, sift},
+    calibration::{CameraParameters, camera_distance_matrix, format_rms},
+    correspondence::{
+        BT601_LUMA_WEIGHTS, DEFAULT_MATCH_RATIO, SiftParams, bf_match_knn, sift_with_mask,
+        sift_with_params, to_grayscale_weighted,
+    },
+    plane::XorShift,
+    pool::{MatPool, PoolStats},
+    utils::{annotate, combine_grid, write_atomically},
 };
 
 #[derive(Debug, Clone)]
@@ -23,6 +36,7 @@ pub struct Point3D {
     pub color: Option<(u8, u8, u8)>, // RGB цвет точки
     pub track_id: Option<usize>,     // ID для отслеживания точки во времени
     pub confidence: f32,             // Уверенность в позиции точки
+    pub reproj_error: Option<f64>,   // Средняя ошибка перепроекции по камерам, пикс.
 }
 
 impl Point3D {
@@ -34,6 +48,7 @@ impl Point3D {
             color: None,
             track_id: None,
             confidence,
+            reproj_error: None,
         }
     }
 
@@ -45,6 +60,7 @@ impl Point3D {
             color: None,
             track_id: None,
             confidence,
+            reproj_error: None,
         }
     }
 
@@ -60,111 +76,501 @@ pub struct PointCloud {
     pub timestamp: usize, // Временная метка кадра
 }
 
+impl PointCloud {
+    /// Случайно оставляет каждую точку с вероятностью `fraction` (0 < fraction <= 1).
+    /// Выборка определяется зерном `seed`: одинаковые аргументы дают одно и то же облако.
+    /// Быстрее воксельного прореживания, подходит для лёгкого предпросмотра больших облаков
+    pub fn subsample(&self, fraction: f64, seed: u64) -> Result<PointCloud, Error> {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(Error::new(
+                StsError,
+                format!("Доля прореживания {} вне диапазона (0, 1]", fraction),
+            ));
+        }
+        let mut rng = XorShift::new(seed);
+        let points = self
+            .points
+            .iter()
+            .filter(|_| rng.unit() < fraction)
+            .cloned()
+            .collect();
+        Ok(PointCloud {
+            points,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
 pub fn triangulate_points_multiple(
     points_2d: &Vector<Mat>,
     camera_params: &[CameraParameters],
 ) -> Result<Vec<Point3D>, Error> {
-    if points_2d.len() < 2 || camera_params.len() < 2 {
-        error!("Недостаточно камер или наборов точек");
-        return Err(Error::new(
-            StsError as i32,
-            "Требуется минимум 2 камеры для триангуляции".to_string(),
-        ));
+    triangulate_points_multiple_with_stats(points_2d, camera_params).map(|(points, _)| points)
+}
+
+/// Как `triangulate_points_multiple`, но дополнительно возвращает статистику ошибки перепроекции
+pub fn triangulate_points_multiple_with_stats(
+    points_2d: &Vector<Mat>,
+    camera_params: &[CameraParameters],
+) -> Result<(Vec<Point3D>, ErrorStats), Error> {
+    TriangulationContext::new(camera_params)?.triangulate(points_2d)
+}
+
+/// Триангуляция только по камерам, отмеченным в `active` как участвующие в кадре.
+/// Перекрытые камеры пропускаются, точки восстанавливаются по оставшимся,
+/// если их хотя бы две
+pub fn triangulate_points_active_cameras(
+    points_2d: &Vector<Mat>,
+    camera_params: &[CameraParameters],
+    active: &[bool],
+) -> Result<(Vec<Point3D>, ErrorStats), Error> {
+    TriangulationContext::new(camera_params)?.triangulate_active(points_2d, active)
+}
+
+/// Матрицы проекций камер, построенные один раз по калибровке.
+/// Внешние параметры между кадрами не меняются, поэтому в покадровом цикле
+/// достаточно подавать только новые 2D точки
+pub struct TriangulationContext {
+    projection_matrices: Vector<Mat>,
+    /// Те же матрицы в виде строк для быстрого расчёта ошибки перепроекции
+    projection_rows: Vec<ProjectionRows>,
+    /// Буферы точек, переиспользуемые между кадрами
+    pool: Mutex<MatPool>,
+}
+
+type ProjectionRows = [[f64; 4]; 3];
+
+impl TriangulationContext {
+    pub fn new(camera_params: &[CameraParameters]) -> Result<Self, Error> {
+        if camera_params.len() < 2 {
+            error!("Недостаточно камер или наборов точек");
+            return Err(Error::new(
+                StsError,
+                "Требуется минимум 2 камеры для триангуляции".to_string(),
+            ));
+        }
+        check_primary_camera_pose(&camera_params[0])?;
+
+        let mut projection_matrices = Vector::<Mat>::with_capacity(camera_params.len());
+        let mut projection_rows = Vec::with_capacity(camera_params.len());
+        for (camera_i, cam) in camera_params.iter().enumerate() {
+            let projection = projection_matrix(camera_i, cam)?;
+            projection_rows.push(to_projection_rows(&projection)?);
+            projection_matrices.push(projection);
+        }
+        Ok(Self {
+            projection_matrices,
+            projection_rows,
+            pool: Mutex::new(MatPool::new()),
+        })
     }
 
-    if points_2d.len() != camera_params.len() {
-        error!("Количество наборов точек не соответствует количеству камер");
-        return Err(Error::new(
-            StsError as i32,
-            "Количество списков точек должно совпадать с количеством камер".to_string(),
-        ));
+    pub fn num_cameras(&self) -> usize {
+        self.projection_rows.len()
     }
 
-    // Количество точек (предполагаем, что все матрицы имеют одинаковое количество строк)
-    let num_points = points_2d.get(0)?.rows();
-    debug!("Количество точек для триангуляции: {}", num_points);
+    /// Счётчики выделений буферов триангуляции за всё время жизни контекста
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.lock().unwrap().stats()
+    }
 
-    // Проверка, что все матрицы имеют правильный размер
-    for (i, points) in points_2d.iter().enumerate() {
-        if points.rows() != num_points || points.cols() != 2 {
-            error!("Неверный размер матрицы точек для камеры {}", i);
+    /// Триангулирует точки по всем камерам. `points_2d` - по матрице Nx2 на камеру
+    pub fn triangulate(
+        &self,
+        points_2d: &Vector<Mat>,
+    ) -> Result<(Vec<Point3D>, ErrorStats), Error> {
+        if points_2d.len() < 2 {
+            error!("Недостаточно камер или наборов точек");
             return Err(Error::new(
-                StsError as i32,
+                StsError,
+                "Требуется минимум 2 камеры для триангуляции".to_string(),
+            ));
+        }
+
+        if points_2d.len() != self.num_cameras() {
+            error!("Количество наборов точек не соответствует количеству камер");
+            return Err(Error::new(
+                StsError,
+                "Количество списков точек должно совпадать с количеством камер".to_string(),
+            ));
+        }
+
+        // Количество точек (предполагаем, что все матрицы имеют одинаковое количество строк)
+        let num_points = points_2d.get(0)?.rows();
+        debug!("Количество точек для триангуляции: {}", num_points);
+
+        // Проверка, что все матрицы имеют правильный размер
+        for (i, points) in points_2d.iter().enumerate() {
+            if points.rows() != num_points || points.cols() != 2 {
+                error!("Неверный размер матрицы точек для камеры {}", i);
+                return Err(Error::new(
+                    StsError,
+                    format!(
+                        "Матрица точек камеры {} имеет неверный размер. Ожидается {}x2, получено {}x{}",
+                        i,
+                        num_points,
+                        points.rows(),
+                        points.cols()
+                    ),
+                ));
+            }
+        }
+
+        triangulate_with_projections(
+            points_2d,
+            &self.projection_matrices,
+            &self.projection_rows,
+            &mut self.pool.lock().unwrap(),
+        )
+    }
+
+    /// Триангулирует точки только по камерам, отмеченным в `active`
+    pub fn triangulate_active(
+        &self,
+        points_2d: &Vector<Mat>,
+        active: &[bool],
+    ) -> Result<(Vec<Point3D>, ErrorStats), Error> {
+        if points_2d.len() != self.num_cameras() || active.len() != self.num_cameras() {
+            error!("Маска камер не соответствует количеству камер");
+            return Err(Error::new(
+                StsError,
+                format!(
+                    "Ожидается по одному набору точек и флагу активности на камеру: камер {}, наборов точек {}, флагов {}",
+                    self.num_cameras(),
+                    points_2d.len(),
+                    active.len()
+                ),
+            ));
+        }
+        if active.iter().all(|&a| a) {
+            return self.triangulate(points_2d);
+        }
+
+        let mut active_points = Vector::<Mat>::default();
+        let mut projection_matrices = Vector::<Mat>::default();
+        let mut projection_rows = Vec::new();
+        for (i, _) in active.iter().enumerate().filter(|&(_, &a)| a) {
+            active_points.push(points_2d.get(i)?);
+            projection_matrices.push(self.projection_matrices.get(i)?);
+            projection_rows.push(self.projection_rows[i]);
+        }
+
+        if active_points.len() < 2 {
+            error!("В кадре активно меньше двух камер");
+            return Err(Error::new(
+                StsError,
                 format!(
-                    "Матрица точек камеры {} имеет неверный размер. Ожидается {}x2, получено {}x{}",
-                    i,
-                    num_points,
-                    points.rows(),
-                    points.cols()
+                    "Требуется минимум 2 активные камеры для триангуляции, активно {}",
+                    active_points.len()
                 ),
             ));
         }
+
+        let num_points = active_points.get(0)?.rows();
+        if active_points
+            .iter()
+            .any(|points| points.rows() != num_points)
+        {
+            return Err(Error::new(
+                StsError,
+                "Наборы точек активных камер имеют разное количество строк".to_string(),
+            ));
+        }
+
+        debug!(
+            "Триангуляция по камерам {:?}",
+            active
+                .iter()
+                .enumerate()
+                .filter(|&(_, &a)| a)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        );
+        triangulate_with_projections(
+            &active_points,
+            &projection_matrices,
+            &projection_rows,
+            &mut self.pool.lock().unwrap(),
+        )
     }
 
-    // Подготовка матриц проекций для всех камер
-    let mut projection_matrices = Vector::<Mat>::default();
+    /// Триангулирует каждую точку по тем камерам, в которых она видна: `visibility[i][c]` -
+    /// видна ли точка `i` в камере `c` (см. [`partial_visible_match_set`]). Координаты в
+    /// камерах, где точки нет, не используются. Точки, видимые меньше чем в двух камерах,
+    /// возвращаются с координатами NaN и нулевой уверенностью, чтобы порядок точек
+    /// совпадал со строками `points_2d`
+    pub fn triangulate_visible(
+        &self,
+        points_2d: &Vector<Mat>,
+        visibility: &[Vec<bool>],
+    ) -> Result<(Vec<Point3D>, ErrorStats), Error> {
+        if points_2d.len() != self.num_cameras() {
+            return Err(Error::new(
+                StsError,
+                format!(
+                    "Ожидается по одному набору точек на камеру: камер {}, наборов точек {}",
+                    self.num_cameras(),
+                    points_2d.len()
+                ),
+            ));
+        }
+        let num_points = visibility.len();
+        for (i, points) in points_2d.iter().enumerate() {
+            if points.rows() as usize != num_points {
+                return Err(Error::new(
+                    StsError,
+                    format!(
+                        "Камера {}: {} точек, а флагов видимости {}",
+                        i,
+                        points.rows(),
+                        num_points
+                    ),
+                ));
+            }
+        }
 
-    for (i, cam) in camera_params.iter().enumerate() {
-        // Проверка первой камеры
-        if i == 0 {
-            // Проверяем, является ли матрица вращения единичной
-            let mut is_identity = true;
-            for r in 0..3 {
-                for c in 0..3 {
-                    let expected = if r == c { 1.0 } else { 0.0 };
-                    let actual = cam.rotation.at_2d::<f64>(r, c)?;
-                    if (actual - expected).abs() > 1e-5 {
-                        is_identity = false;
-                        break;
-                    }
-                }
-                if !is_identity {
-                    break;
-                }
+        // Точки с одинаковым набором камер триангулируются одним вызовом
+        let mut groups: HashMap<&[bool], Vec<usize>> = HashMap::new();
+        for (point_i, mask) in visibility.iter().enumerate() {
+            groups.entry(mask.as_slice()).or_default().push(point_i);
+        }
+
+        let mut result: Vec<Option<Point3D>> = vec![None; num_points];
+        for (mask, rows) in groups {
+            if mask.iter().filter(|&&v| v).count() < 2 {
+                continue;
+            }
+            let mut group_points = Vector::<Mat>::with_capacity(points_2d.len());
+            for points in points_2d.iter() {
+                group_points.push(select_rows(&points, &rows)?);
             }
+            let (points, _) = self.triangulate_active(&group_points, mask)?;
+            for (row, point) in rows.into_iter().zip(points) {
+                result[row] = Some(point);
+            }
+        }
 
-            // Проверяем, является ли вектор трансляции нулевым
-            let mut is_zero_translation = true;
-            for r in 0..3 {
-                let val = cam.translation.at_2d::<f64>(r, 0)?;
-                if val.abs() > 1e-5 {
-                    is_zero_translation = false;
-                    break;
-                }
+        let unobserved = result.iter().filter(|p| p.is_none()).count();
+        if unobserved > 0 {
+            debug!(
+                "{} точек видны меньше чем в двух камерах и не триангулированы",
+                unobserved
+            );
+        }
+        let points: Vec<Point3D> = result
+            .into_iter()
+            .map(|p| p.unwrap_or_else(|| Point3D::new(f64::NAN, f64::NAN, f64::NAN, 0.0)))
+            .collect();
+        let errors: Vec<f64> = points.iter().filter_map(|p| p.reproj_error).collect();
+        let stats = ErrorStats::from_errors(&errors, BAD_POINT_ERROR);
+        Ok((points, stats))
+    }
+}
+
+/// Копирует строки `rows` матрицы `points` в новую матрицу
+fn select_rows(points: &Mat, rows: &[usize]) -> Result<Mat, Error> {
+    let mut selected = Mat::default();
+    for &row in rows {
+        selected.push_back(&points.row(row as i32)?)?;
+    }
+    Ok(selected)
+}
+
+/// Главная камера должна задавать систему координат: единичный поворот и нулевой сдвиг
+fn check_primary_camera_pose(cam: &CameraParameters) -> Result<(), Error> {
+    // Проверяем, является ли матрица вращения единичной
+    let mut is_identity = true;
+    for r in 0..3 {
+        for c in 0..3 {
+            let expected = if r == c { 1.0 } else { 0.0 };
+            let actual = cam.rotation.at_2d::<f64>(r, c)?;
+            if (actual - expected).abs() > 1e-5 {
+                is_identity = false;
+                break;
             }
+        }
+        if !is_identity {
+            break;
+        }
+    }
 
-            if !is_identity || !is_zero_translation {
-                warn!(
-                    "Вектор трансляции не нулевой или матрица вращения не единичная для главной камеры"
+    // Проверяем, является ли вектор трансляции нулевым
+    let mut is_zero_translation = true;
+    for r in 0..3 {
+        let val = cam.translation.at_2d::<f64>(r, 0)?;
+        if val.abs() > 1e-5 {
+            is_zero_translation = false;
+            break;
+        }
+    }
+
+    if !is_identity || !is_zero_translation {
+        warn!("Вектор трансляции не нулевой или матрица вращения не единичная для главной камеры");
+    }
+    Ok(())
+}
+
+/// Простейший детектор перекрытых камер: кадр, почти однотонный по яркости
+/// (закрытый объектив, засветка, чёрный кадр), считается неинформативным.
+/// Возвращает маску активных камер
+pub fn detect_active_cameras(frames: &[Mat], min_std_dev: f64) -> Result<Vec<bool>, Error> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            if frame.empty() {
+                return Ok(false);
+            }
+            let gray = to_grayscale_weighted(frame, BT601_LUMA_WEIGHTS)?;
+            let mut mean = Mat::default();
+            let mut std_dev = Mat::default();
+            opencv::core::mean_std_dev_def(&gray, &mut mean, &mut std_dev)?;
+            let std_dev = *std_dev.at_2d::<f64>(0, 0)?;
+            if std_dev < min_std_dev {
+                debug!(
+                    "Камера {}: кадр почти однотонный (СКО яркости {:.2}), камера исключена",
+                    i, std_dev
                 );
             }
+            Ok(std_dev >= min_std_dev)
+        })
+        .collect()
+}
+
+/// Строит матрицу проекции P = K [R | t] для камеры `camera_i`.
+/// Вырожденные параметры камеры возвращаются ошибкой с номером камеры, а не паникой
+fn projection_matrix(camera_i: usize, cam: &CameraParameters) -> Result<Mat, Error> {
+    let camera_error = |message: String| {
+        error!("Камера {}: {}", camera_i, message);
+        Error::new(StsError, format!("Камера {}: {}", camera_i, message))
+    };
+
+    let sizes = [
+        ("матрица K", &cam.intrinsic, (3, 3)),
+        ("матрица R", &cam.rotation, (3, 3)),
+        ("вектор t", &cam.translation, (3, 1)),
+    ];
+    for (name, mat, (rows, cols)) in sizes {
+        if mat.rows() != rows || mat.cols() != cols {
+            return Err(camera_error(format!(
+                "{} имеет размер {}x{}, ожидается {}x{}",
+                name,
+                mat.rows(),
+                mat.cols(),
+                rows,
+                cols
+            )));
+        }
+    }
+    let fx = *cam.intrinsic.at_2d::<f64>(0, 0)?;
+    let fy = *cam.intrinsic.at_2d::<f64>(1, 1)?;
+    if !(fx.is_finite() && fy.is_finite() && fx > 0.0 && fy > 0.0) {
+        return Err(camera_error(format!(
+            "вырожденная матрица K (fx = {}, fy = {})",
+            fx, fy
+        )));
+    }
+
+    let mut projection_matrix = Mat::default();
+    opencv::sfm::projection_from_k_rt(
+        &cam.intrinsic,
+        &cam.rotation,
+        &cam.translation,
+        &mut projection_matrix,
+    )
+    .map_err(|e| camera_error(format!("не удалось построить матрицу проекции: {}", e)))?;
+    Ok(projection_matrix)
+}
+
+fn to_projection_rows(projection: &Mat) -> Result<ProjectionRows, Error> {
+    let mut rows = [[0.0; 4]; 3];
+    for (r, row) in rows.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = *projection.at_2d::<f64>(r as i32, c as i32)?;
         }
+    }
+    Ok(rows)
+}
 
-        let mut r_t = Mat::default();
-        opencv::core::hconcat2(&cam.rotation, &cam.translation, &mut r_t)?;
+/// Ошибка перепроекции и уверенность `i`-й триангулированной точки
+fn reproject_point(
+    projection_rows: &[ProjectionRows],
+    observations: &[Vec<[f64; 2]>],
+    positions: &[[f64; 3]],
+    i: usize,
+) -> Point3D {
+    let [x, y, z] = positions[i];
 
-        let mut projection_matrix = Mat::default();
-        opencv::sfm::projection_from_k_rt(
-            &cam.intrinsic,
-            &cam.rotation,
-            &cam.translation,
-            &mut projection_matrix,
-        )
-        .unwrap();
-        projection_matrices.push(projection_matrix);
+    // Вычисление перепроекционной ошибки для оценки качества триангуляции
+    let mut total_reproj_error = 0.0;
+    for (p, camera_observations) in projection_rows.iter().zip(observations) {
+        // Проекция на изображение: x' = P * (X, Y, Z, 1)
+        let project = |row: &[f64; 4]| row[0] * x + row[1] * y + row[2] * z + row[3];
+        let w = project(&p[2]);
+        let p_x = project(&p[0]) / w;
+        let p_y = project(&p[1]) / w;
+
+        // Исходная точка на изображении
+        let [orig_x, orig_y] = camera_observations[i];
+
+        // Вычисляем ошибку (евклидово расстояние)
+        total_reproj_error += ((p_x - orig_x).powi(2) + (p_y - orig_y).powi(2)).sqrt();
+    }
+
+    // Средняя ошибка репроекции для этой точки
+    let avg_error = total_reproj_error / projection_rows.len() as f64;
+
+    // Преобразуем в нормализованную уверенность (1.0 - хорошо, 0.0 - плохо)
+    // Порог ошибки - настраиваемый параметр (например, 5 пикселей)
+    let confidence = (1.0 - (avg_error / 5.0).min(1.0)) as f32;
+
+    let mut point = Point3D::new(x, y, z, confidence);
+    point.reproj_error = Some(avg_error);
+    point
+}
+
+/// Точки с ошибкой перепроекции; с feature `parallel` считаются в нескольких потоках.
+/// Порядок точек сохраняется: indexed-итератор rayon собирает результат по индексам
+fn reproject_points(
+    projection_rows: &[ProjectionRows],
+    observations: &[Vec<[f64; 2]>],
+    positions: &[[f64; 3]],
+) -> Vec<Point3D> {
+    let reproject = |i| reproject_point(projection_rows, observations, positions, i);
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        (0..positions.len())
+            .into_par_iter()
+            .map(reproject)
+            .collect()
     }
+    #[cfg(not(feature = "parallel"))]
+    (0..positions.len()).map(reproject).collect()
+}
+
+/// Триангулирует точки по готовым матрицам проекций и оценивает ошибку перепроекции.
+/// Промежуточные матрицы берутся из `pool` и возвращаются в него
+fn triangulate_with_projections(
+    points_2d: &Vector<Mat>,
+    projection_matrices: &Vector<Mat>,
+    projection_rows: &[ProjectionRows],
+    pool: &mut MatPool,
+) -> Result<(Vec<Point3D>, ErrorStats), Error> {
+    let num_points = points_2d.get(0)?.rows();
 
     // Преобразование точек в формат для trianguluate_points (2xN матрицы)
     let converted_points: Vector<Mat> = points_2d
         .iter()
         .map(|points| {
-            let mut transposed = Mat::default();
+            let mut transposed = pool.take(points.cols(), points.rows(), points.typ())?;
             opencv::core::transpose(&points, &mut transposed)?;
             Ok(transposed)
         })
         .collect::<Result<Vector<Mat>, Error>>()?;
 
-    let mut points_3d = Mat::default();
+    // sfm::triangulate_points возвращает 3xN CV_64F
+    let mut points_3d = pool.take(3, num_points, opencv::core::CV_64F)?;
 
     match triangulate_points(&converted_points, &projection_matrices, &mut points_3d) {
         Ok(_) => {
@@ -179,176 +585,673 @@ pub fn triangulate_points_multiple(
         }
     }
 
-    let mut result = Vec::with_capacity(num_points as usize);
+    // Координаты копируются из Mat заранее: ошибка перепроекции каждой точки считается
+    // независимо и с feature `parallel` - в нескольких потоках, без обращения к Mat
+    let positions = (0..num_points)
+        .map(|i| {
+            Ok([
+                *points_3d.at_2d::<f64>(0, i)?,
+                *points_3d.at_2d::<f64>(1, i)?,
+                *points_3d.at_2d::<f64>(2, i)?,
+            ])
+        })
+        .collect::<Result<Vec<[f64; 3]>, Error>>()?;
+    let observations = points_2d
+        .iter()
+        .map(|points| {
+            (0..num_points)
+                .map(|i| Ok([*points.at_2d::<f64>(i, 0)?, *points.at_2d::<f64>(i, 1)?]))
+                .collect::<Result<Vec<[f64; 2]>, Error>>()
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let result = reproject_points(projection_rows, &observations, &positions);
 
-    let mut total_errors = Vec::new();
-    let mut num_bad_points = 0;
+    for mat in converted_points {
+        pool.recycle(mat);
+    }
+    pool.recycle(points_3d);
 
-    for i in 0..num_points {
-        let x = *points_3d.at_2d::<f64>(0, i)?;
-        let y = *points_3d.at_2d::<f64>(1, i)?;
-        let z = *points_3d.at_2d::<f64>(2, i)?;
+    let total_errors: Vec<f64> = result.iter().filter_map(|p| p.reproj_error).collect();
+    // Считаем плохие точки (с большой ошибкой)
+    let num_bad_points = total_errors
+        .iter()
+        .filter(|&&e| e > BAD_POINT_ERROR)
+        .count();
 
-        // Вычисление перепроекционной ошибки для оценки качества триангуляции
-        let mut total_reproj_error = 0.0;
-        let mut errors_by_camera = Vec::new();
+    // Вывод статистики по ошибкам
+    let stats = ErrorStats::from_errors(&total_errors, BAD_POINT_ERROR);
+    if !total_errors.is_empty() {
+        info!("Минимальная ошибка: {:.2} пикс.", stats.min);
+        info!("Медианная ошибка:  {:.2} пикс.", stats.median);
+        info!("Средняя ошибка:    {:.2} пикс.", stats.mean);
+        info!("Максимальная ошибка: {:.2} пикс.", stats.max);
+        info!(
+            "Количество точек с ошибкой > 5 пикс.: {} из {} ({:.1}%)",
+            num_bad_points, num_points, stats.bad_pct
+        );
+    }
+    Ok((result, stats))
+}
 
-        for (j, projection) in projection_matrices.iter().enumerate() {
-            // Создаем 4D точку (X, Y, Z, 1)
-            let mut point_4d = Mat::zeros(4, 1, opencv::core::CV_64F)?.to_mat()?;
-            *point_4d.at_2d_mut::<f64>(0, 0)? = x;
-            *point_4d.at_2d_mut::<f64>(1, 0)? = y;
-            *point_4d.at_2d_mut::<f64>(2, 0)? = z;
-            *point_4d.at_2d_mut::<f64>(3, 0)? = 1.0;
+/// Ошибка перепроекции, пикс., выше которой точка считается плохой в [`ErrorStats::bad_pct`]
+pub const BAD_POINT_ERROR: f64 = 5.0;
 
-            // Проекция на изображение: x' = P * X
-            let mut projected = Mat::default();
-            gemm(
-                &projection,
-                &point_4d,
-                1.0,
-                &Mat::default(),
-                0.0,
-                &mut projected,
-                0,
-            )?;
+/// Статистика ошибки перепроекции точек одного кадра (в пикселях)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ErrorStats {
+    pub min: f64,
+    pub median: f64,
+    pub mean: f64,
+    pub max: f64,
+    /// Доля точек (в процентах) с ошибкой больше порога
+    pub bad_pct: f64,
+}
 
-            // Нормализуем проекцию
-            let p_x = *projected.at_2d::<f64>(0, 0)? / *projected.at_2d::<f64>(2, 0)?;
-            let p_y = *projected.at_2d::<f64>(1, 0)? / *projected.at_2d::<f64>(2, 0)?;
+impl ErrorStats {
+    /// Считает статистику по ошибкам точек; для пустого набора все значения нулевые
+    pub fn from_errors(errors: &[f64], bad_threshold: f64) -> Self {
+        if errors.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = errors.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let num_bad = sorted.iter().filter(|&&e| e > bad_threshold).count();
+        Self {
+            min: sorted[0],
+            median: sorted[sorted.len() / 2],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            max: sorted[sorted.len() - 1],
+            bad_pct: 100.0 * num_bad as f64 / sorted.len() as f64,
+        }
+    }
+}
 
-            // Исходная точка на изображении
-            let orig_x = *points_2d.get(j)?.at_2d::<f64>(i, 0)?;
-            let orig_y = *points_2d.get(j)?.at_2d::<f64>(i, 1)?;
+/// Записывает статистику ошибок по кадрам в CSV: `frame,min,median,mean,max,bad_pct`
+pub fn save_error_stats_csv<P: AsRef<Path>>(
+    stats: &[(usize, ErrorStats)],
+    path: P,
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "frame,min,median,mean,max,bad_pct")?;
+    for (frame, s) in stats {
+        writeln!(
+            file,
+            "{},{},{},{},{},{}",
+            frame, s.min, s.median, s.mean, s.max, s.bad_pct
+        )?;
+    }
+    Ok(())
+}
 
-            // Вычисляем ошибку (евклидово расстояние)
-            let error = ((p_x - orig_x).powi(2) + (p_y - orig_y).powi(2)).sqrt();
-            errors_by_camera.push(error);
-            total_reproj_error += error;
+/// Читает статистику, записанную [`save_error_stats_csv`]. Строки, которые не
+/// разбираются, - ошибка `InvalidData` с номером строки
+pub fn load_error_stats_csv<P: AsRef<Path>>(path: P) -> io::Result<Vec<(usize, ErrorStats)>> {
+    let text = std::fs::read_to_string(path.as_ref())?;
+    let invalid = |line_i: usize| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: строка {} не разобрана",
+                path.as_ref().display(),
+                line_i + 1
+            ),
+        )
+    };
+    let mut stats = Vec::new();
+    for (line_i, line) in text.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
         }
+        let values: Vec<&str> = line.split(',').collect();
+        let [frame, min, median, mean, max, bad_pct] = values[..] else {
+            return Err(invalid(line_i));
+        };
+        let number = |value: &str| value.trim().parse::<f64>().map_err(|_| invalid(line_i));
+        let frame = frame.trim().parse().map_err(|_| invalid(line_i))?;
+        stats.push((
+            frame,
+            ErrorStats {
+                min: number(min)?,
+                median: number(median)?,
+                mean: number(mean)?,
+                max: number(max)?,
+                bad_pct: number(bad_pct)?,
+            },
+        ));
+    }
+    Ok(stats)
+}
 
-        // Средняя ошибка репроекции для этой точки
-        let avg_error = total_reproj_error / camera_params.len() as f64;
-        total_errors.push(avg_error);
+/// Итоги обработки одного кадра для отчёта о запуске
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSummary {
+    pub frame: usize,
+    /// Точек в сохранённом облаке (после фильтрации)
+    pub points: usize,
+    /// Ошибки перепроекции триангулированных точек (до фильтрации)
+    pub errors: ErrorStats,
+}
 
-        // Преобразуем в нормализованную уверенность (1.0 - хорошо, 0.0 - плохо)
-        // Порог ошибки - настраиваемый параметр (например, 5 пикселей)
-        let confidence = (1.0 - (avg_error / 5.0).min(1.0)) as f32;
+/// Сводка запуска реконструкции: ошибки калибровки камер, число точек и ошибки по кадрам,
+/// время работы. Сохраняется одним текстовым файлом, чтобы запуск можно было проверить
+/// без журнала
+#[derive(Debug, Clone, Default)]
+pub struct ReconstructionReport {
+    /// RMS калибровки внутренних параметров и стереопары с основной камерой по камерам
+    pub cameras: Vec<(Option<f64>, Option<f64>)>,
+    pub frames: Vec<FrameSummary>,
+    /// Кадры, пропущенные из-за уже записанных облаков (продолжение прерванного запуска)
+    pub skipped_frames: usize,
+    pub runtime: Duration,
+}
 
-        // Считаем плохие точки (с большой ошибкой)
-        if avg_error > 5.0 {
-            num_bad_points += 1;
+impl ReconstructionReport {
+    pub fn new(camera_params: &[CameraParameters]) -> Self {
+        Self {
+            cameras: camera_params
+                .iter()
+                .map(|c| (c.rms_error, c.stereo_rms_error))
+                .collect(),
+            ..Default::default()
         }
+    }
 
-        result.push(Point3D::new(x, y, z, confidence));
+    pub fn add_frame(&mut self, frame: usize, points: usize, errors: ErrorStats) {
+        self.frames.push(FrameSummary {
+            frame,
+            points,
+            errors,
+        });
     }
 
-    // Вывод статистики по ошибкам
-    if !total_errors.is_empty() {
-        total_errors.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-        let min_error = total_errors[0];
-        let max_error = total_errors[total_errors.len() - 1];
-        let median_error = total_errors[total_errors.len() / 2];
-        let mean_error = total_errors.iter().sum::<f64>() / total_errors.len() as f64;
-
-        info!("Минимальная ошибка: {:.2} пикс.", min_error);
-        info!("Медианная ошибка:  {:.2} пикс.", median_error);
-        info!("Средняя ошибка:    {:.2} пикс.", mean_error);
-        info!("Максимальная ошибка: {:.2} пикс.", max_error);
-        info!(
-            "Количество точек с ошибкой > 5 пикс.: {} из {} ({:.1}%)",
-            num_bad_points,
-            num_points,
-            100.0 * num_bad_points as f64 / num_points as f64
-        );
+    pub fn total_points(&self) -> usize {
+        self.frames.iter().map(|f| f.points).sum()
+    }
+
+    /// Средняя по кадрам ошибка триангуляции, пикс.; `None`, если кадров нет
+    pub fn mean_error(&self) -> Option<f64> {
+        (!self.frames.is_empty()).then(|| {
+            self.frames.iter().map(|f| f.errors.mean).sum::<f64>() / self.frames.len() as f64
+        })
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("Cameras: {}", self.cameras.len())];
+        for (i, (rms, stereo_rms)) in self.cameras.iter().enumerate() {
+            let mut line = format!("Cam {}: RMS {}", i + 1, format_rms(*rms));
+            if i > 0 {
+                line.push_str(&format!(
+                    ", stereo 1-{} RMS {}",
+                    i + 1,
+                    format_rms(*stereo_rms)
+                ));
+            }
+            lines.push(line);
+        }
+        lines.push(String::new());
+        lines.push(format!(
+            "Frames processed: {} (skipped as already written: {})",
+            self.frames.len(),
+            self.skipped_frames
+        ));
+        lines.push(format!("Total points: {}", self.total_points()));
+        lines.push(format!(
+            "Mean triangulation error: {}",
+            self.mean_error()
+                .map(|e| format!("{:.3} px", e))
+                .unwrap_or_else(|| "n/a".to_string())
+        ));
+        lines.push(format!("Runtime: {:.1} s", self.runtime.as_secs_f64()));
+        if !self.frames.is_empty() {
+            lines.push(String::new());
+            lines.push("frame  points  mean px  median px  max px  bad %".to_string());
+            for f in &self.frames {
+                lines.push(format!(
+                    "{:>5}  {:>6}  {:>7.3}  {:>9.3}  {:>6.2}  {:>5.1}",
+                    f.frame,
+                    f.points,
+                    f.errors.mean,
+                    f.errors.median,
+                    f.errors.max,
+                    f.errors.bad_pct
+                ));
+            }
+        }
+        lines
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut text = self.lines().join("\n");
+        text.push('\n');
+        write_atomically(path.as_ref(), |tmp| std::fs::write(tmp, text))
     }
-    Ok(result)
 }
 
-pub fn save_point_cloud<P: AsRef<Path>>(cloud: &PointCloud, path: P) -> io::Result<()> {
-    let mut file = File::create(path)?;
+/// Сохраняет отчёт о запуске в Markdown для передачи вместе с результатами: сводку
+/// (кадры, точки, средняя ошибка), RMS калибровки и базы между камерами `cameras`,
+/// таблицу кадров и изображения `images` (подпись, путь), например карты покрытия
+/// калибровки. Изображения, которых нет на диске, пропускаются; пути внутри папки отчёта
+/// записываются относительными, чтобы отчёт можно было переносить вместе с ней
+pub fn write_reconstruction_report(
+    report: &ReconstructionReport,
+    cameras: &[CameraParameters],
+    images: &[(String, PathBuf)],
+    out: &Path,
+) -> io::Result<()> {
+    let unit = cameras
+        .first()
+        .map(|c| c.unit.to_string())
+        .unwrap_or_default();
+    let mut text = String::from("# Reconstruction report\n\n## Summary\n\n");
+    text.push_str(&format!(
+        "- Frames processed: {} (skipped as already written: {})\n",
+        report.frames.len(),
+        report.skipped_frames
+    ));
+    text.push_str(&format!("- Total points: {}\n", report.total_points()));
+    text.push_str(&format!(
+        "- Mean triangulation error: {}\n",
+        report
+            .mean_error()
+            .map(|e| format!("{:.3} px", e))
+            .unwrap_or_else(|| "n/a".to_string())
+    ));
+    text.push_str(&format!(
+        "- Runtime: {:.1} s\n",
+        report.runtime.as_secs_f64()
+    ));
+
+    text.push_str("\n## Cameras\n\n| Camera | RMS | Stereo RMS with camera 1 |\n|---|---|---|\n");
+    for (i, (rms, stereo_rms)) in report.cameras.iter().enumerate() {
+        let stereo = if i > 0 {
+            format_rms(*stereo_rms)
+        } else {
+            "-".to_string()
+        };
+        text.push_str(&format!(
+            "| {} | {} | {} |\n",
+            i + 1,
+            format_rms(*rms),
+            stereo
+        ));
+    }
 
-    // Определяем, сколько точек имеют цвет (для заголовка PLY)
-    let points_with_color = cloud.points.iter().filter(|p| p.color.is_some()).count();
-    let has_color = points_with_color > 0;
+    text.push_str(&format!("\n## Baselines, {}\n\n", unit));
+    match camera_distance_matrix(cameras) {
+        Ok(matrix) if !matrix.is_empty() => {
+            text.push_str("| |");
+            for cam in 1..=matrix.len() {
+                text.push_str(&format!(" Cam {} |", cam));
+            }
+            text.push_str(&format!("\n|---|{}\n", "---|".repeat(matrix.len())));
+            for (i, row) in matrix.iter().enumerate() {
+                text.push_str(&format!("| Cam {} |", i + 1));
+                for distance in row {
+                    text.push_str(&format!(" {:.1} |", distance));
+                }
+                text.push('\n');
+            }
+        }
+        Ok(_) => text.push_str("No cameras\n"),
+        Err(e) => {
+            warn!("Расстояния между камерами для отчёта не посчитаны: {}", e);
+            text.push_str("n/a\n");
+        }
+    }
 
-    // Записываем заголовок PLY
-    writeln!(file, "ply")?;
-    writeln!(file, "format ascii 1.0")?;
-    writeln!(file, "element vertex {}", cloud.points.len())?;
-    writeln!(file, "property float x")?;
-    writeln!(file, "property float y")?;
-    writeln!(file, "property float z")?;
+    let base = out.parent().unwrap_or(Path::new(""));
+    let images: Vec<_> = images.iter().filter(|(_, path)| path.exists()).collect();
+    if !images.is_empty() {
+        text.push_str("\n## Images\n");
+        for (caption, path) in images {
+            let link = path.strip_prefix(base).unwrap_or(path);
+            text.push_str(&format!(
+                "\n### {}\n\n![{}](<{}>)\n",
+                caption,
+                caption,
+                link.display()
+            ));
+        }
+    }
 
-    // Добавляем свойства цвета, если они есть
-    if has_color {
-        writeln!(file, "property uchar red")?;
-        writeln!(file, "property uchar green")?;
-        writeln!(file, "property uchar blue")?;
+    if !report.frames.is_empty() {
+        text.push_str(
+            "\n## Frames\n\n| Frame | Points | Mean px | Median px | Max px | Bad % |\n\
+             |---|---|---|---|---|---|\n",
+        );
+        for f in &report.frames {
+            text.push_str(&format!(
+                "| {} | {} | {:.3} | {:.3} | {:.2} | {:.1} |\n",
+                f.frame, f.points, f.errors.mean, f.errors.median, f.errors.max, f.errors.bad_pct
+            ));
+        }
     }
 
-    // Добавляем свойство уверенности
-    writeln!(file, "property float confidence")?;
+    write_atomically(out, |tmp| std::fs::write(tmp, text))
+}
 
-    // Конец заголовка
-    writeln!(file, "end_header")?;
+/// Ширина плитки камеры на листе найденных признаков, пикс.
+const CONTACT_SHEET_TILE_WIDTH: i32 = 640;
 
-    // Записываем данные
-    for point in &cloud.points {
-        if has_color {
-            // С цветом
-            let (r, g, b) = point.color.unwrap_or((128, 128, 128));
-            writeln!(
-                file,
-                "{} {} {} {} {} {} {}",
-                point.x, point.y, point.z, r, g, b, point.confidence
+/// Лист найденных признаков: кадры камер с отмеченными точками `keypoints[i]` камеры `i`,
+/// уменьшенные до одной ширины и собранные в сетку по `cols` столбцов. Показывает,
+/// где на кадрах нашлись признаки, и попадает в отчёт о запуске
+pub fn detection_contact_sheet(
+    frames: &[Mat],
+    keypoints: &[Vector<KeyPoint>],
+    cols: usize,
+) -> Result<Mat, Error> {
+    let mut tiles = Vec::with_capacity(frames.len());
+    for (camera_i, frame) in frames.iter().enumerate() {
+        let mut tile = frame.clone();
+        let camera_keypoints = keypoints.get(camera_i).cloned().unwrap_or_default();
+        for keypoint in camera_keypoints.iter() {
+            let p = keypoint.pt();
+            opencv::imgproc::circle(
+                &mut tile,
+                opencv::core::Point::new(p.x.round() as i32, p.y.round() as i32),
+                3,
+                Scalar::new(0.0, 255.0, 0.0, 255.0),
+                -1,
+                opencv::imgproc::LINE_8,
+                0,
             )?;
-        } else {
-            // Без цвета
-            writeln!(
-                file,
-                "{} {} {} {}",
-                point.x, point.y, point.z, point.confidence
+        }
+        if tile.cols() > CONTACT_SHEET_TILE_WIDTH {
+            let scale = CONTACT_SHEET_TILE_WIDTH as f64 / tile.cols() as f64;
+            let mut resized = Mat::default();
+            opencv::imgproc::resize(
+                &tile,
+                &mut resized,
+                Size::default(),
+                scale,
+                scale,
+                opencv::imgproc::INTER_AREA,
             )?;
+            tile = resized;
         }
+        annotate(
+            &mut tile,
+            &[format!(
+                "Camera {}: {} features",
+                camera_i + 1,
+                camera_keypoints.len()
+            )],
+        )?;
+        tiles.push(tile);
     }
+    combine_grid(&tiles, cols)
+}
 
-    Ok(())
+pub fn save_point_cloud<P: AsRef<Path>>(cloud: &PointCloud, path: P) -> io::Result<()> {
+    save_point_cloud_with_comments(cloud, &[], path)
 }
 
-pub fn match_first_camera_features_to_all(
-    images: &Vec<Mat>,
-) -> (Vec<Vector<Vector<DMatch>>>, Vec<Vector<KeyPoint>>, Vec<Mat>) {
-    let mut keypoints_list = Vec::new();
-    let mut descriptors_list = Vec::new();
+/// Как [`save_point_cloud`], но строки `comments` пишутся в заголовок как `comment ...`
+/// сразу после строки формата (например, источник и настройки реконструкции); просмотрщики
+/// их пропускают. Переводы строк в комментариях заменяются пробелами
+pub fn save_point_cloud_with_comments<P: AsRef<Path>>(
+    cloud: &PointCloud,
+    comments: &[String],
+    path: P,
+) -> io::Result<()> {
+    save_point_cloud_extended(cloud, comments, &[], path)
+}
 
-    for (i, image) in images.iter().enumerate() {
-        info!("Обработка изображения {} из {}", i + 1, images.len());
-        let (keypoints, descriptors) = match sift(&image, 0, 4, 0.04, 10f64, 1.6, false) {
-            Ok(it) => {
-                info!("  -> Найдено {} ключевых точек", it.0.len());
-                it
+/// Дополнительное свойство вершины с номером трека точки (-1 - точка без трека),
+/// которое [`load_point_cloud`] возвращает в `track_id`
+pub const PLY_TRACK_ID_PROPERTY: &str = "track_id";
+
+/// Свойства вершины, которые [`save_point_cloud`] пишет сам
+const PLY_BUILTIN_PROPERTIES: [&str; 7] = ["x", "y", "z", "red", "green", "blue", "confidence"];
+
+/// Как [`save_point_cloud_with_comments`], но с дополнительными скалярами точек, например скоростью трека:
+/// каждое поле `(имя, значения)` пишется после уверенности как `property float <имя>`,
+/// значение `i` относится к точке `i`. Имя должно быть одним словом без пробелов и не
+/// совпадать со встроенными свойствами и другими полями, а значений должно быть столько же,
+/// сколько точек, иначе возвращается `InvalidInput` и файл не создаётся
+pub fn save_point_cloud_extended<P: AsRef<Path>>(
+    cloud: &PointCloud,
+    comments: &[String],
+    extra_fields: &[(String, Vec<f32>)],
+    path: P,
+) -> io::Result<()> {
+    for (i, (name, values)) in extra_fields.iter().enumerate() {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(invalid(format!("Некорректное имя поля PLY {:?}", name)));
+        }
+        if PLY_BUILTIN_PROPERTIES.contains(&name.as_str())
+            || extra_fields[..i].iter().any(|(other, _)| other == name)
+        {
+            return Err(invalid(format!("Поле PLY {} уже есть в облаке", name)));
+        }
+        if values.len() != cloud.points.len() {
+            return Err(invalid(format!(
+                "В поле PLY {} {} значений, а точек {}",
+                name,
+                values.len(),
+                cloud.points.len()
+            )));
+        }
+    }
+
+    // Пишем во временный файл и переименовываем, чтобы прерванная запись не портила облако
+    write_atomically(path.as_ref(), |tmp_path| {
+        let mut file = BufWriter::new(File::create(tmp_path)?);
+
+        // Определяем, сколько точек имеют цвет (для заголовка PLY)
+        let points_with_color = cloud.points.iter().filter(|p| p.color.is_some()).count();
+        let has_color = points_with_color > 0;
+
+        // Записываем заголовок PLY
+        writeln!(file, "ply")?;
+        writeln!(file, "format ascii 1.0")?;
+        for comment in comments {
+            writeln!(file, "comment {}", comment.replace(['\r', '\n'], " "))?;
+        }
+        writeln!(file, "element vertex {}", cloud.points.len())?;
+        writeln!(file, "property float x")?;
+        writeln!(file, "property float y")?;
+        writeln!(file, "property float z")?;
+
+        // Добавляем свойства цвета, если они есть
+        if has_color {
+            writeln!(file, "property uchar red")?;
+            writeln!(file, "property uchar green")?;
+            writeln!(file, "property uchar blue")?;
+        }
+
+        // Добавляем свойство уверенности
+        writeln!(file, "property float confidence")?;
+        for (name, _) in extra_fields {
+            writeln!(file, "property float {}", name)?;
+        }
+
+        // Конец заголовка
+        writeln!(file, "end_header")?;
+
+        // Записываем данные
+        for (point_i, point) in cloud.points.iter().enumerate() {
+            if has_color {
+                // С цветом
+                let (r, g, b) = point.color.unwrap_or((128, 128, 128));
+                write!(
+                    file,
+                    "{} {} {} {} {} {} {}",
+                    point.x, point.y, point.z, r, g, b, point.confidence
+                )?;
+            } else {
+                // Без цвета
+                write!(
+                    file,
+                    "{} {} {} {}",
+                    point.x, point.y, point.z, point.confidence
+                )?;
             }
-            Err(e) => {
-                error!("  -> Ошибка при выполнении SIFT: {:?}", e);
-                continue;
+            for (_, values) in extra_fields {
+                write!(file, " {}", values[point_i])?;
             }
-        };
-        keypoints_list.push(keypoints);
-        descriptors_list.push(descriptors);
-    }
+            writeln!(file)?;
+        }
 
-    let mut all_matches = Vec::new();
-    // Первая камера - референсная
-    let ref_descriptor = &descriptors_list[0];
+        file.flush()?;
+        file.get_ref().sync_all()
+    })
+}
 
-    for i in 1..descriptors_list.len() {
+/// Читает облако, сохранённое [`save_point_cloud`] или [`save_point_cloud_extended`]:
+/// ASCII PLY с координатами, необязательными цветом, уверенностью и номером трека
+/// ([`PLY_TRACK_ID_PROPERTY`]). Прочие свойства вершин пропускаются, уверенность по умолчанию 1. Номер кадра берётся из комментария
+/// `frame N`, без него `timestamp` равен 0. Бинарные PLY не поддерживаются (`InvalidData`)
+pub fn load_point_cloud<P: AsRef<Path>>(path: P) -> io::Result<PointCloud> {
+    let path = path.as_ref();
+    let invalid = |message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), message),
+        )
+    };
+    let text = std::fs::read_to_string(path)?;
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(invalid("не файл PLY".to_string()));
+    }
+
+    let mut vertex_count = None;
+    let mut in_vertex = false;
+    let mut properties: Vec<&str> = Vec::new();
+    let mut timestamp = 0;
+    for line in lines.by_ref() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("format") if words.next() != Some("ascii") => {
+                return Err(invalid("поддерживается только формат ascii".to_string()));
+            }
+            Some("comment") => {
+                if words.next() == Some("frame")
+                    && let Some(Ok(frame)) = words.next().map(str::parse)
+                {
+                    timestamp = frame;
+                }
+            }
+            Some("element") => {
+                in_vertex = words.next() == Some("vertex");
+                if in_vertex {
+                    vertex_count = words.next().and_then(|n| n.parse::<usize>().ok());
+                }
+            }
+            Some("property") if in_vertex => {
+                if let Some(name) = words.last() {
+                    properties.push(name);
+                }
+            }
+            Some("end_header") => break,
+            _ => {}
+        }
+    }
+    let vertex_count = vertex_count.ok_or_else(|| invalid("нет элемента vertex".to_string()))?;
+    let column = |name: &str| properties.iter().position(|&p| p == name);
+    let (Some(x), Some(y), Some(z)) = (column("x"), column("y"), column("z")) else {
+        return Err(invalid("нет координат x, y, z".to_string()));
+    };
+    let color = match (column("red"), column("green"), column("blue")) {
+        (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+        _ => None,
+    };
+    let confidence = column("confidence");
+    let track_id = column(PLY_TRACK_ID_PROPERTY);
+
+    let mut points = Vec::with_capacity(vertex_count);
+    for (line_i, line) in lines.take(vertex_count).enumerate() {
+        let values: Vec<&str> = line.split_whitespace().collect();
+        if values.len() < properties.len() {
+            return Err(invalid(format!("в вершине {} не хватает значений", line_i)));
+        }
+        let number = |column: usize| {
+            values[column]
+                .parse::<f64>()
+                .map_err(|e| invalid(format!("вершина {}: {}", line_i, e)))
+        };
+        let mut point = Point3D::new(
+            number(x)?,
+            number(y)?,
+            number(z)?,
+            confidence.map(number).transpose()?.unwrap_or(1.0) as f32,
+        );
+        if let Some((r, g, b)) = color {
+            point.color = Some((number(r)? as u8, number(g)? as u8, number(b)? as u8));
+        }
+        if let Some(track_id) = track_id {
+            let id = number(track_id)?;
+            point.track_id = (id >= 0.0).then_some(id as usize);
+        }
+        points.push(point);
+    }
+    if points.len() != vertex_count {
+        return Err(invalid(format!(
+            "вершин {} вместо {}",
+            points.len(),
+            vertex_count
+        )));
+    }
+    Ok(PointCloud { points, timestamp })
+}
+
+/// Сопоставления камеры 0 с каждой следующей камерой, ключевые точки и дескрипторы
+/// всех камер
+pub type FirstCameraMatches = (Vec<Vector<Vector<DMatch>>>, Vec<Vector<KeyPoint>>, Vec<Mat>);
+
+pub fn match_first_camera_features_to_all(
+    images: &Vec<Mat>,
+    sift_params: &SiftParams,
+) -> FirstCameraMatches {
+    match_first_camera_features_to_all_masked(images, sift_params, DEFAULT_MATCH_RATIO, &[])
+}
+
+/// Как [`match_first_camera_features_to_all`], но признаки камеры `i` ищутся только вне
+/// закрытой маской `masks[i]` области (см. [`sift_with_mask`]), а сопоставления проходят
+/// тест отношения с порогом `ratio`. Камеры без маски (`None` или за концом `masks`)
+/// обрабатываются целиком
+pub fn match_first_camera_features_to_all_masked(
+    images: &[Mat],
+    sift_params: &SiftParams,
+    ratio: f32,
+    masks: &[Option<Mat>],
+) -> FirstCameraMatches {
+    let mut keypoints_list = Vec::new();
+    let mut descriptors_list = Vec::new();
+
+    let no_mask = Mat::default();
+    for (i, image) in images.iter().enumerate() {
+        info!("Обработка изображения {} из {}", i + 1, images.len());
+        let mask = masks.get(i).and_then(Option::as_ref).unwrap_or(&no_mask);
+        let (keypoints, descriptors) = match sift_with_mask(image, sift_params, mask) {
+            Ok(it) => {
+                info!("  -> Найдено {} ключевых точек", it.0.len());
+                it
+            }
+            Err(e) => {
+                error!("  -> Ошибка при выполнении SIFT: {:?}", e);
+                continue;
+            }
+        };
+        keypoints_list.push(keypoints);
+        descriptors_list.push(descriptors);
+    }
+
+    let mut all_matches = Vec::new();
+    // Первая камера - референсная
+    let ref_descriptor = &descriptors_list[0];
+
+    for i in 1..descriptors_list.len() {
         info!("Сопоставление камеры 1 с камерой {}", i + 1);
         let matches = match bf_match_knn(
             &ref_descriptor,
             &descriptors_list[i],
-            2,   // k = 2 соседа
-            0.7, // ratio = 0.7
+            2, // k = 2 соседа
+            ratio,
         ) {
             Ok(it) => {
                 info!("Найдено {} сопоставлений", it.len());
@@ -365,6 +1268,75 @@ pub fn match_first_camera_features_to_all(
     // TODO добавить вывод ошибки при отсутсвии сопоставлений
 }
 
+/// Какие точки референсной камеры (камеры 0) попадают в триангуляцию
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum VisibilityMode {
+    /// Только точки, найденные во всех камерах ([`min_visible_match_set`]). Точки
+    /// триангулируются по максимуму ракурсов и надёжнее, но в системах с широкой базой
+    /// общих для всех камер точек мало и большая часть сцены теряется
+    #[default]
+    AllCameras,
+    /// Точки, найденные в референсной камере и хотя бы в `min_views - 1` других
+    /// ([`partial_visible_match_set`]); каждая триангулируется по своим камерам.
+    /// Точек больше, но точки с двумя ракурсами менее точны по глубине и сильнее
+    /// страдают от ложных сопоставлений
+    MinViews(usize),
+}
+
+/// Как [`min_visible_match_set`], но оставляет точки, найденные хотя бы в `min_views`
+/// камерах, включая референсную. Возвращает сопоставления, выровненные по точкам, и для
+/// каждой точки флаги видимости по камерам (камера 0 всегда видит свою точку). В камерах,
+/// где точки нет, стоит сопоставление с `train_idx == -1` (см. [`gather_points_2d_from_matches`])
+pub fn partial_visible_match_set(
+    all_matches: &[Vector<Vector<DMatch>>],
+    keypoints_list: &[Vector<KeyPoint>],
+    min_views: usize,
+) -> (Vec<Vector<Vector<DMatch>>>, Vec<Vec<bool>>) {
+    // Ближайший сосед для каждой точки референсной камеры по камерам
+    let by_query: Vec<HashMap<usize, Vector<DMatch>>> = all_matches
+        .iter()
+        .map(|camera_matches| {
+            let mut map = HashMap::new();
+            for m in camera_matches.iter() {
+                if let Ok(nearest) = m.get(0) {
+                    map.entry(nearest.query_idx as usize).or_insert(m);
+                }
+            }
+            map
+        })
+        .collect();
+
+    let mut filtered_matches = vec![Vector::<Vector<DMatch>>::new(); all_matches.len()];
+    let mut visibility = Vec::new();
+    for i in 0..keypoints_list[0].len() {
+        let mut mask = Vec::with_capacity(all_matches.len() + 1);
+        mask.push(true);
+        mask.extend(by_query.iter().map(|map| map.contains_key(&i)));
+        if mask.iter().filter(|&&v| v).count() < min_views.max(2) {
+            continue;
+        }
+        for (camera_matches, map) in filtered_matches.iter_mut().zip(&by_query) {
+            let m = map.get(&i).cloned().unwrap_or_else(|| {
+                Vector::from_iter([DMatch {
+                    query_idx: i as i32,
+                    train_idx: -1,
+                    img_idx: -1,
+                    distance: f32::INFINITY,
+                }])
+            });
+            camera_matches.push(m);
+        }
+        visibility.push(mask);
+    }
+
+    info!(
+        "Найдено {} точек, видимых хотя бы в {} камерах",
+        visibility.len(),
+        min_views.max(2)
+    );
+    (filtered_matches, visibility)
+}
+
 pub fn min_visible_match_set(
     all_matches: &Vec<Vector<Vector<DMatch>>>,
     keypoints_list: &Vec<Vector<KeyPoint>>,
@@ -421,33 +1393,176 @@ pub fn min_visible_match_set(
     filtered_matches
 }
 
+/// Удаляет точки, которые не удалось триангулировать (координаты NaN, см.
+/// [`TriangulationContext::triangulate_visible`])
+pub fn drop_untriangulated_points(cloud: &mut PointCloud) {
+    cloud
+        .points
+        .retain(|point| point.x.is_finite() && point.y.is_finite() && point.z.is_finite());
+}
+
 pub fn filter_point_cloud_by_confindence(cloud: &mut PointCloud, confidence_threshold: f32) {
     cloud
         .points
         .retain(|point| point.confidence >= confidence_threshold);
 }
 
+/// Жёстко отбрасывает точки со средней ошибкой перепроекции больше `max_px` пикселей.
+/// В отличие от `filter_point_cloud_by_confindence` порог задаётся прямо в пикселях.
+/// Точки без известной ошибки сохраняются
+pub fn filter_point_cloud_by_max_reproj(cloud: &mut PointCloud, max_px: f64) {
+    cloud
+        .points
+        .retain(|point| point.reproj_error.is_none_or(|error| error <= max_px));
+}
+
+/// Фотометрический фильтр: верно сопоставленная точка выглядит одинаково во всех камерах,
+/// которые её видят. Точка `i` облака соответствует строке `i` каждой матрицы `points_2d`
+/// (Nx2, координаты кадров `frames` в BGR); камера считается видящей точку, если координаты
+/// попадают в её кадр. Удаляются точки, у которых наибольшее попарное различие цвета
+/// (евклидово расстояние в RGB, от 0 до 441) больше `max_color_diff`. Точки, видимые меньше
+/// чем в двух камерах, сохраняются. Вызывать до фильтров, которые удаляют точки, иначе
+/// точки облака разойдутся со строками `points_2d`
+pub fn filter_by_color_consistency(
+    cloud: &mut PointCloud,
+    frames: &[Mat],
+    points_2d: &Vector<Mat>,
+    max_color_diff: f64,
+) -> Result<(), Error> {
+    if let Some(camera_i) = frames.iter().position(|f| f.typ() != CV_8UC3) {
+        return Err(Error::new(
+            StsError,
+            format!("Кадр камеры {} не трёхканальный 8-битный", camera_i),
+        ));
+    }
+
+    let mut keep = vec![true; cloud.points.len()];
+    let mut colors = Vec::with_capacity(frames.len());
+    for (point_i, keep) in keep.iter_mut().enumerate() {
+        colors.clear();
+        for (frame, points) in frames.iter().zip(points_2d.iter()) {
+            if point_i as i32 >= points.rows() {
+                continue;
+            }
+            let x = *points.at_2d::<f64>(point_i as i32, 0)?;
+            let y = *points.at_2d::<f64>(point_i as i32, 1)?;
+            // NaN тоже не проходит проверку
+            if !(x >= 0.0 && y >= 0.0 && x < frame.cols() as f64 && y < frame.rows() as f64) {
+                continue;
+            }
+            let color = frame.at_2d::<Vec3b>(y as i32, x as i32)?;
+            colors.push([color[0] as f64, color[1] as f64, color[2] as f64]);
+        }
+        let max_diff = colors
+            .iter()
+            .enumerate()
+            .flat_map(|(a, color_a)| {
+                colors[a + 1..].iter().map(move |color_b| {
+                    color_a
+                        .iter()
+                        .zip(color_b)
+                        .map(|(ca, cb)| (ca - cb) * (ca - cb))
+                        .sum::<f64>()
+                        .sqrt()
+                })
+            })
+            .fold(0.0, f64::max);
+        *keep = max_diff <= max_color_diff;
+    }
+
+    let removed = keep.iter().filter(|&&k| !k).count();
+    debug!(
+        "Фильтр по цвету: удалено {} точек с различием цвета больше {}",
+        removed, max_color_diff
+    );
+    let mut flags = keep.iter();
+    cloud.points.retain(|_| *flags.next().unwrap_or(&true));
+    Ok(())
+}
+
 pub fn add_color_to_point_cloud(
     cloud: &mut PointCloud,
     distorted_points: &Vector<Mat>,
     ref_image: &Mat,
 ) {
+    add_color_to_point_cloud_from_camera(cloud, distorted_points, 0, ref_image);
+}
+
+/// Отбрасывает точки облака, которые в какой-либо камере попадают в закрытую маской
+/// область (нулевые пиксели `masks[camera]`, например крепления камер). Точка `i` облака
+/// соответствует строке `i` каждой матрицы `points_2d` (Nx2); камеры, где точки нет
+/// по `visibility`, и камеры без маски не проверяются. Точки не удаляются, а помечаются
+/// как нетриангулированные: их убирает [`drop_untriangulated_points`], поэтому порядок точек
+/// до этого совпадает с `points_2d`. Возвращает число отброшенных точек
+pub fn reject_masked_points(
+    cloud: &mut PointCloud,
+    points_2d: &Vector<Mat>,
+    masks: &[Option<Mat>],
+    visibility: Option<&[Vec<bool>]>,
+) -> Result<usize, Error> {
+    let mut rejected = 0;
+    for (camera_i, points) in points_2d.iter().enumerate() {
+        let Some(mask) = masks.get(camera_i).and_then(Option::as_ref) else {
+            continue;
+        };
+        for (point_i, point) in cloud
+            .points
+            .iter_mut()
+            .enumerate()
+            .take(points.rows() as usize)
+        {
+            let visible = visibility
+                .and_then(|v| v.get(point_i))
+                .and_then(|flags| flags.get(camera_i))
+                .copied()
+                .unwrap_or(true);
+            if !visible || !point.x.is_finite() {
+                continue;
+            }
+            let x = points.at_2d::<f64>(point_i as i32, 0)?.round() as i32;
+            let y = points.at_2d::<f64>(point_i as i32, 1)?.round() as i32;
+            if x >= 0
+                && y >= 0
+                && x < mask.cols()
+                && y < mask.rows()
+                && *mask.at_2d::<u8>(y, x)? == 0
+            {
+                point.x = f64::NAN;
+                point.y = f64::NAN;
+                point.z = f64::NAN;
+                rejected += 1;
+            }
+        }
+    }
+    Ok(rejected)
+}
+
+/// Раскрашивает облако по кадру камеры `camera`: цвет берётся в текущих (а не начальных)
+/// координатах точек этой камеры. `distorted_points` - наборы точек Nx2 по камерам
+/// в координатах кадра `camera_image`
+pub fn add_color_to_point_cloud_from_camera(
+    cloud: &mut PointCloud,
+    distorted_points: &Vector<Mat>,
+    camera: usize,
+    camera_image: &Mat,
+) {
+    let Ok(points) = distorted_points.get(camera) else {
+        warn!("Нет точек камеры {} для раскраски облака", camera);
+        return;
+    };
     // Добавляем цвет из исходного изображения
-    for (i, point) in cloud.points.iter_mut().enumerate() {
-        let x = *distorted_points
-            .get(0)
-            .unwrap()
-            .at_2d::<f64>(i as i32, 0)
-            .unwrap() as i32;
-        let y = *distorted_points
-            .get(0)
-            .unwrap()
-            .at_2d::<f64>(i as i32, 1)
-            .unwrap() as i32;
+    for (i, point) in cloud
+        .points
+        .iter_mut()
+        .enumerate()
+        .take(points.rows() as usize)
+    {
+        let x = *points.at_2d::<f64>(i as i32, 0).unwrap() as i32;
+        let y = *points.at_2d::<f64>(i as i32, 1).unwrap() as i32;
 
         // Проверяем, что координаты в пределах изображения
-        if x >= 0 && y >= 0 && x < ref_image.cols() && y < ref_image.rows() {
-            let color = ref_image.at_2d::<opencv::core::Vec3b>(y, x).unwrap();
+        if x >= 0 && y >= 0 && x < camera_image.cols() && y < camera_image.rows() {
+            let color = camera_image.at_2d::<opencv::core::Vec3b>(y, x).unwrap();
             point.color = Some((color[2], color[1], color[0])); // BGR -> RGB
         }
     }
@@ -457,11 +1572,44 @@ pub fn undistort_points_single_camera(
     points: &Mat, // Nx2, CV_64F
     camera: &CameraParameters,
 ) -> Result<Mat, Error> {
+    undistort_points_pooled(points, camera, &mut MatPool::new())
+}
+
+/// Как [`undistort_points_single_camera`], но буферы берутся из `pool`: в покадровом цикле
+/// они не выделяются заново, если вызывающий вернёт результат в пул после кадра
+pub fn undistort_points_pooled(
+    points: &Mat, // Nx2, CV_64F
+    camera: &CameraParameters,
+    pool: &mut MatPool,
+) -> Result<Mat, Error> {
+    if points.typ() != opencv::core::CV_64F || points.cols() != 2 {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Точки для исправления дисторсии должны быть матрицей Nx2 CV_64F, получено {}x{} {}",
+                points.rows(),
+                points.cols(),
+                opencv::core::type_to_string(points.typ())?
+            ),
+        ));
+    }
     let num_points = points.rows();
-    let mut undistorted_points = Mat::zeros(num_points, 1, opencv::core::CV_64FC2)?.to_mat()?;
+    if num_points == 0 {
+        return pool.take(0, 2, opencv::core::CV_64F);
+    }
+    // undistort_points ждёт Nx1 CV_64FC2: те же данные без копирования, если матрица непрерывна
+    let continuous;
+    let points = if points.is_continuous() {
+        points
+    } else {
+        continuous = points.try_clone()?;
+        &continuous
+    };
+    let interleaved = points.reshape(2, num_points)?;
+    let mut undistorted_points = pool.take(num_points, 1, opencv::core::CV_64FC2)?;
 
     undistort_points(
-        points,
+        &interleaved,
         &mut undistorted_points,
         &camera.intrinsic,
         &camera.distortion,
@@ -469,11 +1617,1182 @@ pub fn undistort_points_single_camera(
         &camera.intrinsic,
     )?;
 
-    let mut undistorted_nx2 = Mat::zeros(num_points, 2, opencv::core::CV_64F)?.to_mat()?;
+    let mut undistorted_nx2 = pool.take(num_points, 2, opencv::core::CV_64F)?;
     for j in 0..num_points {
         let pt = undistorted_points.at_2d::<Vec2d>(j, 0)?;
         *undistorted_nx2.at_2d_mut::<f64>(j, 0)? = pt[0];
         *undistorted_nx2.at_2d_mut::<f64>(j, 1)? = pt[1];
     }
+    pool.recycle(undistorted_points);
     Ok(undistorted_nx2)
 }
+
+/// Рендерит облако как карту глубины с точки зрения камеры `camera`: каждая точка
+/// проецируется матрицей проекции K[R|t], и в пиксель записывается глубина (координата z
+/// в системе камеры, в единицах облака) ближайшей к камере точки. Карта `CV_32F`
+/// размера `size`, пиксели без точек равны 0. Дисторсия не учитывается, поэтому карта
+/// соответствует кадру после `undistort_image`. Точки позади камеры пропускаются
+pub fn render_depth_map(
+    cloud: &PointCloud,
+    camera: &CameraParameters,
+    size: Size,
+) -> Result<Mat, Error> {
+    let rows = to_projection_rows(&projection_matrix(0, camera)?)?;
+    let mut depth =
+        Mat::new_rows_cols_with_default(size.height, size.width, CV_32F, Scalar::all(0.0))?;
+
+    let mut filled = 0usize;
+    for point in &cloud.points {
+        let p = [point.x, point.y, point.z, 1.0];
+        let project = |row: &[f64; 4]| row.iter().zip(&p).map(|(a, b)| a * b).sum::<f64>();
+        let w = project(&rows[2]);
+        if w <= f64::EPSILON {
+            continue;
+        }
+        let u = (project(&rows[0]) / w).round();
+        let v = (project(&rows[1]) / w).round();
+        if u < 0.0 || v < 0.0 || u >= size.width as f64 || v >= size.height as f64 {
+            continue;
+        }
+        let pixel = depth.at_2d_mut::<f32>(v as i32, u as i32)?;
+        if *pixel == 0.0 {
+            filled += 1;
+        }
+        if *pixel == 0.0 || (w as f32) < *pixel {
+            *pixel = w as f32;
+        }
+    }
+    debug!(
+        "Карта глубины {}x{}: заполнено {} пикселей по {} точкам",
+        size.width,
+        size.height,
+        filled,
+        cloud.points.len()
+    );
+    Ok(depth)
+}
+
+/// Устраняет дисторсию всего кадра. Матрица камеры сохраняется, поэтому пиксели
+/// исправленного кадра совпадают с результатом `undistort_points_single_camera`
+pub fn undistort_image(image: &Mat, camera: &CameraParameters) -> Result<Mat, Error> {
+    let mut undistorted = Mat::default();
+    opencv::calib3d::undistort(
+        image,
+        &mut undistorted,
+        &camera.intrinsic,
+        &camera.distortion,
+        &camera.intrinsic,
+    )?;
+    Ok(undistorted)
+}
+
+/// Устранение дисторсии маски камеры (см. [`crate::utils::load_mask`]). Интерполяция
+/// размывает границу маски в промежуточные значения, поэтому результат снова бинаризуется
+/// по середине диапазона: 0 - закрыто, 255 - рабочая область
+pub fn undistort_mask(mask: &Mat, camera: &CameraParameters) -> Result<Mat, Error> {
+    let undistorted = undistort_image(mask, camera)?;
+    let mut binary = Mat::default();
+    opencv::imgproc::threshold(
+        &undistorted,
+        &mut binary,
+        127.0,
+        255.0,
+        opencv::imgproc::THRESH_BINARY,
+    )?;
+    Ok(binary)
+}
+
+/// Устранение дисторсии кадров одной камеры по заранее построенным картам `remap`.
+/// Результат совпадает с `undistort_image`, но карты строятся один раз,
+/// поэтому для многих кадров одного размера это заметно быстрее
+pub struct Undistorter {
+    map1: Mat,
+    map2: Mat,
+    size: Size,
+}
+
+impl Undistorter {
+    pub fn new(camera: &CameraParameters, size: Size) -> Result<Self, Error> {
+        let mut map1 = Mat::default();
+        let mut map2 = Mat::default();
+        opencv::calib3d::init_undistort_rectify_map(
+            &camera.intrinsic,
+            &camera.distortion,
+            &Mat::default(),
+            &camera.intrinsic,
+            size,
+            opencv::core::CV_16SC2,
+            &mut map1,
+            &mut map2,
+        )?;
+        Ok(Self { map1, map2, size })
+    }
+
+    pub fn undistort(&self, image: &Mat) -> Result<Mat, Error> {
+        if image.size()? != self.size {
+            return Err(Error::new(
+                StsError,
+                format!(
+                    "Размер кадра {:?} не совпадает с размером карт {:?}",
+                    image.size()?,
+                    self.size
+                ),
+            ));
+        }
+        let mut undistorted = Mat::default();
+        opencv::imgproc::remap(
+            image,
+            &mut undistorted,
+            &self.map1,
+            &self.map2,
+            opencv::imgproc::INTER_LINEAR,
+            opencv::core::BORDER_CONSTANT,
+            opencv::core::Scalar::default(),
+        )?;
+        Ok(undistorted)
+    }
+}
+
+/// Параметры камеры для кадров, уже прошедших `undistort_image`: та же матрица камеры
+/// и поза, но нулевая дисторсия
+pub fn rectilinear_camera(camera: &CameraParameters) -> Result<CameraParameters, Error> {
+    let mut rectilinear = camera.clone();
+    rectilinear.distortion = Mat::zeros(
+        1,
+        camera.distortion.total().max(5) as i32,
+        opencv::core::CV_64F,
+    )?
+    .to_mat()?;
+    Ok(rectilinear)
+}
+
+/// Топология расположения камер, определяющая, какие пары камер сопоставляются
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CameraTopology {
+    /// Все камеры сопоставляются с первой (референсной) камерой
+    #[default]
+    Star,
+    /// Камеры стоят по кругу: каждая видит только двух соседей,
+    /// поэтому сопоставляются только соседние пары (i, i + 1)
+    Ring,
+}
+
+/// Сопоставления, ключевые точки и дескрипторы всех камер
+pub type FeatureMatches = (Vec<Vector<Vector<DMatch>>>, Vec<Vector<KeyPoint>>, Vec<Mat>);
+
+/// Сопоставляет признаки соседних камер кольца: камеру i с камерой (i + 1) % n.
+/// В возвращаемых сопоставлениях `query_idx` относится к камере i, `train_idx` - к камере i + 1.
+/// Сопоставления проходят тест отношения с порогом `ratio` (см. [`DEFAULT_MATCH_RATIO`])
+pub fn match_ring_features(
+    images: &[Mat],
+    sift_params: &SiftParams,
+    ratio: f32,
+) -> Result<FeatureMatches, Error> {
+    if images.len() < 3 {
+        return Err(Error::new(
+            StsError,
+            "Для кольцевой топологии требуется минимум 3 камеры".to_string(),
+        ));
+    }
+
+    let mut keypoints_list = Vec::with_capacity(images.len());
+    let mut descriptors_list = Vec::with_capacity(images.len());
+
+    for (i, image) in images.iter().enumerate() {
+        info!("Обработка изображения {} из {}", i + 1, images.len());
+        let (keypoints, descriptors) = sift_with_params(image, sift_params)?;
+        info!("  -> Найдено {} ключевых точек", keypoints.len());
+        keypoints_list.push(keypoints);
+        descriptors_list.push(descriptors);
+    }
+
+    let mut all_matches = Vec::with_capacity(images.len());
+    for i in 0..images.len() {
+        let next = (i + 1) % images.len();
+        info!("Сопоставление камеры {} с камерой {}", i + 1, next + 1);
+        let matches = bf_match_knn(&descriptors_list[i], &descriptors_list[next], 2, ratio)?;
+        info!("Найдено {} сопоставлений", matches.len());
+        all_matches.push(matches);
+    }
+
+    Ok((all_matches, keypoints_list, descriptors_list))
+}
+
+/// Вычисляет относительную позу камеры `to` относительно камеры `from`:
+/// x_to = R * x_from + t
+pub fn relative_pose(from: &CameraParameters, to: &CameraParameters) -> Result<(Mat, Mat), Error> {
+    // R = R_to * R_from^T
+    let mut r = Mat::default();
+    gemm(
+        &to.rotation,
+        &from.rotation,
+        1.0,
+        &Mat::default(),
+        0.0,
+        &mut r,
+        opencv::core::GEMM_2_T,
+    )?;
+
+    // t = t_to - R * t_from
+    let mut t = Mat::default();
+    gemm(&r, &from.translation, -1.0, &to.translation, 1.0, &mut t, 0)?;
+
+    Ok((r, t))
+}
+
+/// Минимальное количество сопоставлений пары камер для оценки её позы по эпиполярной геометрии
+pub const MIN_PAIR_POSE_MATCHES: usize = 8;
+
+/// Независимо оценивает относительную позу камеры `to` относительно камеры `from`
+/// (x_to = R * x_from + t) по сопоставленным точкам пары (матрицы Nx2, пиксели исходных изображений).
+/// Вращение берётся из существенной матрицы, направление сдвига - тоже; эпиполярная геометрия
+/// не задаёт масштаб, поэтому длина сдвига приводится к калиброванной базе пары
+pub fn pair_pose_from_matches(
+    points_from: &Mat,
+    points_to: &Mat,
+    from: &CameraParameters,
+    to: &CameraParameters,
+) -> Result<(Mat, Mat), Error> {
+    if (points_from.rows() as usize) < MIN_PAIR_POSE_MATCHES {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Недостаточно сопоставлений для оценки позы пары: {} (нужно {})",
+                points_from.rows(),
+                MIN_PAIR_POSE_MATCHES
+            ),
+        ));
+    }
+
+    let mut essential = Mat::default();
+    let mut r = Mat::default();
+    let mut t = Mat::default();
+    let mut mask = Mat::default();
+    let inliers = opencv::calib3d::recover_pose_2_cameras(
+        points_from,
+        points_to,
+        &from.intrinsic,
+        &from.distortion,
+        &to.intrinsic,
+        &to.distortion,
+        &mut essential,
+        &mut r,
+        &mut t,
+        opencv::calib3d::RANSAC,
+        0.999,
+        1.0,
+        &mut mask,
+    )?;
+    if (inliers as usize) < MIN_PAIR_POSE_MATCHES {
+        return Err(Error::new(
+            StsError,
+            format!("Поза пары подтверждена лишь {} точками", inliers),
+        ));
+    }
+
+    let (_, calibrated_t) = relative_pose(from, to)?;
+    let baseline = opencv::core::norm(&calibrated_t, opencv::core::NORM_L2, &Mat::default())?;
+    let mut scaled_t = Mat::default();
+    t.convert_to(&mut scaled_t, opencv::core::CV_64F, baseline, 0.0)?;
+    let mut r_64 = Mat::default();
+    r.convert_to(&mut r_64, opencv::core::CV_64F, 1.0, 0.0)?;
+
+    Ok((r_64, scaled_t))
+}
+
+/// Проходит по кольцу камер, последовательно накапливая независимо оценённые относительные позы
+/// соседних пар (`pair_poses[i]` переводит камеру `i` в камеру `i + 1`, последняя - в нулевую),
+/// и возвращает ошибку замыкания петли: угол остаточного вращения (в градусах)
+/// и норму остаточного смещения. Для согласованного кольца оба значения близки к нулю
+pub fn ring_loop_closure_error(pair_poses: &[(Mat, Mat)]) -> Result<(f64, f64), Error> {
+    if pair_poses.len() < 3 {
+        return Err(Error::new(
+            StsError,
+            "Для кольцевой топологии требуется минимум 3 камеры".to_string(),
+        ));
+    }
+
+    let mut accumulated_r = Mat::eye(3, 3, opencv::core::CV_64F)?.to_mat()?;
+    let mut accumulated_t = Mat::zeros(3, 1, opencv::core::CV_64F)?.to_mat()?;
+
+    for (r, t) in pair_poses {
+        let mut new_r = Mat::default();
+        gemm(r, &accumulated_r, 1.0, &Mat::default(), 0.0, &mut new_r, 0)?;
+        let mut new_t = Mat::default();
+        gemm(r, &accumulated_t, 1.0, t, 1.0, &mut new_t, 0)?;
+
+        accumulated_r = new_r;
+        accumulated_t = new_t;
+    }
+
+    let trace = opencv::core::trace(&accumulated_r)?[0];
+    let rotation_error = ((trace - 1.0) / 2.0).clamp(-1.0, 1.0).acos().to_degrees();
+    let translation_error =
+        opencv::core::norm(&accumulated_t, opencv::core::NORM_L2, &Mat::default())?;
+
+    debug!(
+        "Ошибка замыкания кольца: вращение {:.4}°, смещение {:.4}",
+        rotation_error, translation_error
+    );
+    if rotation_error > 1.0 {
+        warn!(
+            "Большая ошибка замыкания кольца по вращению: {:.2}°",
+            rotation_error
+        );
+    }
+
+    Ok((rotation_error, translation_error))
+}
+
+/// Извлекает координаты сопоставленных точек пары камер в виде двух матриц Nx2 (CV_64F)
+fn gather_pair_points(
+    matches: &Vector<Vector<DMatch>>,
+    keypoints_query: &Vector<KeyPoint>,
+    keypoints_train: &Vector<KeyPoint>,
+) -> Result<Vector<Mat>, Error> {
+    let num_matches = matches.len() as i32;
+    let mut points_query = Mat::zeros(num_matches, 2, opencv::core::CV_64F)?.to_mat()?;
+    let mut points_train = Mat::zeros(num_matches, 2, opencv::core::CV_64F)?.to_mat()?;
+
+    for (j, m) in matches.iter().enumerate() {
+        let m = m.get(0)?;
+        let kp_query = keypoints_query.get(m.query_idx as usize)?;
+        let kp_train = keypoints_train.get(m.train_idx as usize)?;
+        *points_query.at_2d_mut::<f64>(j as i32, 0)? = kp_query.pt().x as f64;
+        *points_query.at_2d_mut::<f64>(j as i32, 1)? = kp_query.pt().y as f64;
+        *points_train.at_2d_mut::<f64>(j as i32, 0)? = kp_train.pt().x as f64;
+        *points_train.at_2d_mut::<f64>(j as i32, 1)? = kp_train.pt().y as f64;
+    }
+
+    let mut points = Vector::<Mat>::default();
+    points.push(points_query);
+    points.push(points_train);
+    Ok(points)
+}
+
+/// Реконструкция одного кадра для кольцевой топологии: каждая точка триангулируется
+/// той соседней парой камер, которая её наблюдала. Внешние параметры всех камер
+/// должны быть заданы относительно общей (нулевой) камеры, результат - в её системе координат.
+/// `ratio` - порог теста отношения при сопоставлении соседних камер
+pub fn reconstruct_ring_frame(
+    images: &[Mat],
+    camera_params: &[CameraParameters],
+    sift_params: &SiftParams,
+    ratio: f32,
+) -> Result<Vec<Point3D>, Error> {
+    if images.len() != camera_params.len() {
+        return Err(Error::new(
+            StsError,
+            "Количество изображений должно совпадать с количеством камер".to_string(),
+        ));
+    }
+
+    let (all_matches, keypoints_list, _descriptors_list) =
+        match_ring_features(images, sift_params, ratio)?;
+
+    let mut pair_poses = Vec::with_capacity(images.len());
+    for (i, matches) in all_matches.iter().enumerate() {
+        let next = (i + 1) % images.len();
+        let points_2d = gather_pair_points(matches, &keypoints_list[i], &keypoints_list[next])?;
+        match pair_pose_from_matches(
+            &points_2d.get(0)?,
+            &points_2d.get(1)?,
+            &camera_params[i],
+            &camera_params[next],
+        ) {
+            Ok(pose) => pair_poses.push(pose),
+            Err(e) => {
+                warn!(
+                    "Не удалось оценить позу пары камер {} и {}: {}",
+                    i + 1,
+                    next + 1,
+                    e.message
+                );
+                break;
+            }
+        }
+    }
+    if pair_poses.len() == images.len() {
+        let (rotation_error, translation_error) = ring_loop_closure_error(&pair_poses)?;
+        info!(
+            "Ошибка замыкания кольца: {:.4}°, {:.4}",
+            rotation_error, translation_error
+        );
+    } else {
+        warn!("Проверка замыкания кольца пропущена");
+    }
+
+    let mut result = Vec::new();
+    let mut pool = MatPool::new();
+    for (i, matches) in all_matches.iter().enumerate() {
+        let next = (i + 1) % images.len();
+        if matches.is_empty() {
+            warn!("Нет сопоставлений между камерами {} и {}", i + 1, next + 1);
+            continue;
+        }
+
+        let points_2d = gather_pair_points(matches, &keypoints_list[i], &keypoints_list[next])?;
+
+        let mut undistorted_points_2d = Vector::<Mat>::default();
+        undistorted_points_2d.push(undistort_points_single_camera(
+            &points_2d.get(0)?,
+            &camera_params[i],
+        )?);
+        undistorted_points_2d.push(undistort_points_single_camera(
+            &points_2d.get(1)?,
+            &camera_params[next],
+        )?);
+
+        let mut projection_matrices = Vector::<Mat>::default();
+        projection_matrices.push(projection_matrix(i, &camera_params[i])?);
+        projection_matrices.push(projection_matrix(next, &camera_params[next])?);
+        let projection_rows = projection_matrices
+            .iter()
+            .map(|p| to_projection_rows(&p))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut pair_cloud = PointCloud {
+            points: triangulate_with_projections(
+                &undistorted_points_2d,
+                &projection_matrices,
+                &projection_rows,
+                &mut pool,
+            )?
+            .0,
+            timestamp: 0,
+        };
+        add_color_to_point_cloud(&mut pair_cloud, &points_2d, &images[i]);
+
+        info!(
+            "Пара камер {}-{}: триангулировано {} точек",
+            i + 1,
+            next + 1,
+            pair_cloud.points.len()
+        );
+        result.extend(pair_cloud.points);
+    }
+
+    Ok(result)
+}
+
+/// Система координат сцены, связанная с доской ChArUco. Реконструкция строится в системе
+/// камеры 0, положение которой произвольно; перевод в систему доски делает облака разных
+/// сессий непосредственно сравнимыми: начало координат - угол доски, оси - её стороны
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardFrame {
+    /// Поза доски в системе камеры 0: x_cam0 = R * x_board + t
+    rotation: [[f64; 3]; 3],
+    translation: [f64; 3],
+}
+
+impl BoardFrame {
+    /// Строит систему доски по её позе в системе камеры 0 (результат `estimate_board_pose`:
+    /// вектор Родрига и смещение)
+    pub fn from_board_pose(rvec: &Mat, tvec: &Mat) -> Result<Self, Error> {
+        let mut r = Mat::default();
+        opencv::calib3d::rodrigues_def(rvec, &mut r)?;
+        let mut r64 = Mat::default();
+        r.convert_to(&mut r64, opencv::core::CV_64F, 1.0, 0.0)?;
+        let mut t64 = Mat::default();
+        tvec.convert_to(&mut t64, opencv::core::CV_64F, 1.0, 0.0)?;
+        let t64 = t64.reshape(1, 3)?;
+
+        let mut rotation = [[0.0; 3]; 3];
+        let mut translation = [0.0; 3];
+        for (i, row) in rotation.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = *r64.at_2d::<f64>(i as i32, j as i32)?;
+            }
+            translation[i] = *t64.at_2d::<f64>(i as i32, 0)?;
+        }
+        Ok(Self {
+            rotation,
+            translation,
+        })
+    }
+
+    /// Переводит точку из системы камеры 0 в систему доски: x_board = R^T * (x_cam0 - t)
+    pub fn transform_point(&self, point: &mut Point3D) {
+        let d = [
+            point.x - self.translation[0],
+            point.y - self.translation[1],
+            point.z - self.translation[2],
+        ];
+        let r = &self.rotation;
+        point.x = r[0][0] * d[0] + r[1][0] * d[1] + r[2][0] * d[2];
+        point.y = r[0][1] * d[0] + r[1][1] * d[1] + r[2][1] * d[2];
+        point.z = r[0][2] * d[0] + r[1][2] * d[1] + r[2][2] * d[2];
+    }
+
+    pub fn transform_cloud(&self, cloud: &mut PointCloud) {
+        for point in &mut cloud.points {
+            self.transform_point(point);
+        }
+    }
+
+    /// Параметры камеры с позой в системе доски: x_cam = R' * x_board + t', где
+    /// R' = R_cam * R и t' = R_cam * t + t_cam. Внутренние параметры не меняются
+    pub fn transform_camera(&self, camera: &CameraParameters) -> Result<CameraParameters, Error> {
+        let rotation = Mat::from_slice_2d(&self.rotation)?;
+        let translation = Mat::from_slice(&self.translation)?
+            .reshape(1, 3)?
+            .try_clone()?;
+
+        let mut transformed = camera.clone();
+        gemm(
+            &camera.rotation,
+            &rotation,
+            1.0,
+            &Mat::default(),
+            0.0,
+            &mut transformed.rotation,
+            0,
+        )?;
+        gemm(
+            &camera.rotation,
+            &translation,
+            1.0,
+            &camera.translation,
+            1.0,
+            &mut transformed.translation,
+            0,
+        )?;
+        Ok(transformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_reprojection_matches_serial() {
+        let projection_rows: Vec<ProjectionRows> = vec![
+            [
+                [800.0, 0.0, 320.0, 0.0],
+                [0.0, 800.0, 240.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+            [
+                [800.0, 0.0, 320.0, -80.0],
+                [0.0, 800.0, 240.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+        ];
+        let positions: Vec<[f64; 3]> = (0..500)
+            .map(|i| {
+                let i = i as f64;
+                [(i * 0.37).sin(), (i * 0.11).cos(), 4.0 + (i * 0.05).sin()]
+            })
+            .collect();
+        // Наблюдения с небольшим шумом, чтобы ошибки точек были разными
+        let observations: Vec<Vec<[f64; 2]>> = projection_rows
+            .iter()
+            .map(|rows| {
+                positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &[x, y, z])| {
+                        let project = |r: &[f64; 4]| r[0] * x + r[1] * y + r[2] * z + r[3];
+                        let w = project(&rows[2]);
+                        let noise = (i % 7) as f64 * 0.3;
+                        [project(&rows[0]) / w + noise, project(&rows[1]) / w - noise]
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let points = reproject_points(&projection_rows, &observations, &positions);
+        let serial: Vec<Point3D> = (0..positions.len())
+            .map(|i| reproject_point(&projection_rows, &observations, &positions, i))
+            .collect();
+        assert_eq!(points.len(), serial.len());
+        for (point, expected) in points.iter().zip(&serial) {
+            assert_eq!(
+                (point.x, point.y, point.z),
+                (expected.x, expected.y, expected.z)
+            );
+            assert_eq!(point.reproj_error, expected.reproj_error);
+            assert_eq!(point.confidence, expected.confidence);
+        }
+        assert!(points.iter().any(|p| p.reproj_error.unwrap() > 0.0));
+    }
+
+    fn rotation_about_y(degrees: f64) -> Mat {
+        let rvec = Mat::from_slice(&[0.0, degrees.to_radians(), 0.0])
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        let mut r = Mat::default();
+        opencv::calib3d::rodrigues_def(&rvec, &mut r).unwrap();
+        r
+    }
+
+    fn column(values: [f64; 3]) -> Mat {
+        Mat::from_slice(&values)
+            .unwrap()
+            .reshape(1, 3)
+            .unwrap()
+            .try_clone()
+            .unwrap()
+    }
+
+    /// Четыре камеры на окружности радиуса 1, смотрящие в центр, с шагом 90°
+    fn ring_pair_poses(step_degrees: [f64; 4]) -> Vec<(Mat, Mat)> {
+        step_degrees
+            .iter()
+            .map(|&step| {
+                let r = rotation_about_y(step);
+                // Центр кольца в системе каждой камеры - (0, 0, 1)
+                let centre = column([0.0, 0.0, 1.0]);
+                let mut rotated_centre = Mat::default();
+                gemm(
+                    &r,
+                    &centre,
+                    1.0,
+                    &Mat::default(),
+                    0.0,
+                    &mut rotated_centre,
+                    0,
+                )
+                .unwrap();
+                let mut t = Mat::default();
+                opencv::core::subtract_def(&centre, &rotated_centre, &mut t).unwrap();
+                (r, t)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn consistent_ring_closes() {
+        let poses = ring_pair_poses([90.0; 4]);
+        let (rotation_error, translation_error) = ring_loop_closure_error(&poses).unwrap();
+        assert!(rotation_error < 1e-6, "{rotation_error}");
+        assert!(translation_error < 1e-9, "{translation_error}");
+    }
+
+    #[test]
+    fn inconsistent_pair_breaks_closure() {
+        let poses = ring_pair_poses([90.0, 90.0, 92.0, 90.0]);
+        let (rotation_error, translation_error) = ring_loop_closure_error(&poses).unwrap();
+        assert!((rotation_error - 2.0).abs() < 1e-6, "{rotation_error}");
+        assert!(translation_error > 1e-3, "{translation_error}");
+    }
+
+    #[test]
+    fn ring_needs_three_pairs() {
+        let poses = ring_pair_poses([90.0; 4]);
+        assert!(ring_loop_closure_error(&poses[..2]).is_err());
+    }
+
+    fn camera_with_distortion(k1: f64) -> CameraParameters {
+        let mut camera = CameraParameters::new().unwrap();
+        camera.intrinsic =
+            Mat::from_slice_2d(&[[200.0, 0.0, 80.0], [0.0, 200.0, 60.0], [0.0, 0.0, 1.0]]).unwrap();
+        camera.distortion = Mat::from_slice(&[k1, 0.0, 0.0, 0.0, 0.0])
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        camera
+    }
+
+    /// Проекции точек `points` в пикселях кадра `camera` с его дисторсией
+    fn project_distorted(
+        camera: &CameraParameters,
+        points: &Vector<opencv::core::Point3d>,
+    ) -> Vector<opencv::core::Point2d> {
+        let mut rvec = Mat::default();
+        opencv::calib3d::rodrigues_def(&camera.rotation, &mut rvec).unwrap();
+        let mut projected = Vector::new();
+        opencv::calib3d::project_points_def(
+            points,
+            &rvec,
+            &camera.translation,
+            &camera.intrinsic,
+            &camera.distortion,
+            &mut projected,
+        )
+        .unwrap();
+        projected
+    }
+
+    fn points_nx2(points: &Vector<opencv::core::Point2d>) -> Mat {
+        let rows: Vec<[f64; 2]> = points.iter().map(|p| [p.x, p.y]).collect();
+        Mat::from_slice_2d(&rows).unwrap()
+    }
+
+    #[test]
+    fn pre_undistorted_points_triangulate_like_undistorted_per_point() {
+        let primary = camera_with_distortion(-0.15);
+        let mut secondary = camera_with_distortion(-0.15);
+        secondary.translation = column([-1.0, 0.0, 0.0]);
+        let cameras = [primary, secondary];
+        let mut scene = Vector::<opencv::core::Point3d>::new();
+        for i in 0..4 {
+            for j in 0..3 {
+                scene.push(opencv::core::Point3d::new(
+                    -0.9 + 0.6 * i as f64,
+                    -0.5 + 0.5 * j as f64,
+                    5.0 + 0.25 * (i + j) as f64,
+                ));
+            }
+        }
+        let mut pool = MatPool::new();
+
+        // Исправление дисторсии отдельных точек исходного кадра
+        let mut per_point = Vector::<Mat>::new();
+        // Точки кадра после undistort_image: та же матрица камеры, дисторсии нет
+        let mut pre_undistorted = Vector::<Mat>::new();
+        for camera in &cameras {
+            let distorted = project_distorted(camera, &scene);
+            per_point
+                .push(undistort_points_pooled(&points_nx2(&distorted), camera, &mut pool).unwrap());
+            let mut rectified = Vector::<opencv::core::Point2d>::new();
+            opencv::calib3d::undistort_image_points_def(
+                &distorted,
+                &mut rectified,
+                &camera.intrinsic,
+                &camera.distortion,
+            )
+            .unwrap();
+            let rectilinear = rectilinear_camera(camera).unwrap();
+            pre_undistorted.push(
+                undistort_points_pooled(&points_nx2(&rectified), &rectilinear, &mut pool).unwrap(),
+            );
+        }
+        let rectilinear_cameras: Vec<CameraParameters> = cameras
+            .iter()
+            .map(|camera| rectilinear_camera(camera).unwrap())
+            .collect();
+
+        let (from_per_point, _) = TriangulationContext::new(&cameras)
+            .unwrap()
+            .triangulate(&per_point)
+            .unwrap();
+        let (from_pre_undistorted, _) = TriangulationContext::new(&rectilinear_cameras)
+            .unwrap()
+            .triangulate(&pre_undistorted)
+            .unwrap();
+        assert_eq!(from_per_point.len(), scene.len());
+        assert_eq!(from_pre_undistorted.len(), scene.len());
+        for ((a, b), truth) in from_per_point.iter().zip(&from_pre_undistorted).zip(&scene) {
+            let gap = ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt();
+            assert!(gap < 1e-3, "облака разошлись на {}", gap);
+            assert!((a.z - truth.z).abs() < 0.02, "{} вместо {}", a.z, truth.z);
+        }
+    }
+
+    #[test]
+    fn depth_map_holds_nearest_point_depth() {
+        // Точка (0.5, -0.3, 4) проецируется в пиксель (105, 45); точка (1, -0.6, 8)
+        // лежит на том же луче дальше и заслонена первой
+        let cloud = PointCloud {
+            points: vec![
+                Point3D::new(1.0, -0.6, 8.0, 1.0),
+                Point3D::new(0.5, -0.3, 4.0, 1.0),
+                Point3D::new(0.0, 0.0, -2.0, 1.0),
+            ],
+            timestamp: 0,
+        };
+        let depth =
+            render_depth_map(&cloud, &camera_with_distortion(0.0), Size::new(160, 120)).unwrap();
+        assert_eq!(depth.typ(), CV_32F);
+        assert_eq!(*depth.at_2d::<f32>(45, 105).unwrap(), 4.0);
+        // Точка позади камеры не попадает в карту
+        assert_eq!(*depth.at_2d::<f32>(60, 80).unwrap(), 0.0);
+        assert_eq!(opencv::core::count_non_zero(&depth).unwrap(), 1);
+    }
+
+    #[test]
+    fn color_mismatched_points_are_removed() {
+        let red = Scalar::new(0.0, 0.0, 255.0, 0.0);
+        let mut left_red =
+            Mat::new_rows_cols_with_default(20, 20, opencv::core::CV_8UC3, red).unwrap();
+        left_red
+            .roi_mut(opencv::core::Rect::new(10, 0, 10, 20))
+            .unwrap()
+            .set_to_def(&Scalar::new(0.0, 255.0, 0.0, 0.0))
+            .unwrap();
+        let all_red = Mat::new_rows_cols_with_default(20, 20, opencv::core::CV_8UC3, red).unwrap();
+        // Точка 0 красная в обеих камерах, точка 1 зелёная в первой и красная во второй,
+        // точка 2 вне кадра второй камеры и видна только в одной
+        let points_2d: Vector<Mat> = vec![
+            Mat::from_slice_2d(&[[5.0, 5.0], [15.0, 5.0], [15.0, 5.0]]).unwrap(),
+            Mat::from_slice_2d(&[[5.0, 5.0], [15.0, 5.0], [-1.0, 5.0]]).unwrap(),
+        ]
+        .into();
+        let mut cloud = PointCloud {
+            points: (0..3)
+                .map(|i| Point3D::new(i as f64, 0.0, 1.0, 1.0))
+                .collect(),
+            timestamp: 0,
+        };
+
+        filter_by_color_consistency(&mut cloud, &[left_red, all_red], &points_2d, 30.0).unwrap();
+        let kept: Vec<f64> = cloud.points.iter().map(|p| p.x).collect();
+        assert_eq!(kept, vec![0.0, 2.0]);
+    }
+
+    #[test]
+    fn points_survive_identity_undistortion() {
+        let points: Vector<opencv::core::Point2f> =
+            [(0.0, 0.0), (80.0, 60.0), (12.5, 101.25), (159.0, 3.0)]
+                .into_iter()
+                .map(|(x, y)| opencv::core::Point2f::new(x, y))
+                .collect();
+        let mat = crate::utils::vector_point2f_to_mat(&points).unwrap();
+        assert_eq!(
+            (mat.rows(), mat.cols(), mat.typ()),
+            (4, 2, opencv::core::CV_64F)
+        );
+
+        let undistorted =
+            undistort_points_single_camera(&mat, &camera_with_distortion(0.0)).unwrap();
+        assert_eq!((undistorted.rows(), undistorted.cols()), (4, 2));
+        for (i, point) in points.iter().enumerate() {
+            let x = *undistorted.at_2d::<f64>(i as i32, 0).unwrap();
+            let y = *undistorted.at_2d::<f64>(i as i32, 1).unwrap();
+            assert!((x - point.x as f64).abs() < 1e-6, "{i}: {x}");
+            assert!((y - point.y as f64).abs() < 1e-6, "{i}: {y}");
+        }
+
+        // Двухканальная матрица Nx1 - не тот формат: ошибка вместо перепутанных координат
+        let two_channel = mat.reshape(2, 4).unwrap().try_clone().unwrap();
+        assert!(
+            undistort_points_single_camera(&two_channel, &camera_with_distortion(0.0)).is_err()
+        );
+    }
+
+    /// Маска 160x120, закрывающая левую половину кадра
+    fn half_mask() -> Mat {
+        let mut mask = Mat::new_rows_cols_with_default(
+            120,
+            160,
+            opencv::core::CV_8UC1,
+            opencv::core::Scalar::all(255.0),
+        )
+        .unwrap();
+        mask.roi_mut(opencv::core::Rect::new(0, 0, 80, 120))
+            .unwrap()
+            .set_to_def(&opencv::core::Scalar::all(0.0))
+            .unwrap();
+        mask
+    }
+
+    #[test]
+    fn undistorted_mask_stays_binary() {
+        let mask = undistort_mask(&half_mask(), &camera_with_distortion(-0.3)).unwrap();
+        assert_eq!(mask.size().unwrap(), half_mask().size().unwrap());
+        for y in 0..mask.rows() {
+            for x in 0..mask.cols() {
+                let value = *mask.at_2d::<u8>(y, x).unwrap();
+                assert!(value == 0 || value == 255, "({x}, {y}) = {value}");
+            }
+        }
+        // Центр правой половины остаётся открытым, левой - закрытым
+        assert_eq!(*mask.at_2d::<u8>(60, 120).unwrap(), 255);
+        assert_eq!(*mask.at_2d::<u8>(60, 40).unwrap(), 0);
+    }
+
+    #[test]
+    fn half_mask_rejects_points_in_closed_half() {
+        let mut cloud = PointCloud {
+            points: (0..4)
+                .map(|i| Point3D::new(i as f64, 0.0, 1.0, 1.0))
+                .collect(),
+            timestamp: 0,
+        };
+        // Проекции: две точки слева (закрыто), две справа
+        let projections =
+            Mat::from_slice_2d(&[[10.0, 60.0], [79.0, 10.0], [80.0, 60.0], [150.0, 100.0]])
+                .unwrap();
+        let mut points_2d = Vector::<Mat>::new();
+        points_2d.push(projections);
+
+        let rejected =
+            reject_masked_points(&mut cloud, &points_2d, &[Some(half_mask())], None).unwrap();
+        assert_eq!(rejected, 2);
+        let kept: Vec<bool> = cloud.points.iter().map(|p| p.x.is_finite()).collect();
+        assert_eq!(kept, vec![false, false, true, true]);
+    }
+
+    #[test]
+    fn report_contains_section_headers() {
+        let dir = std::env::temp_dir().join(format!("report_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut second = CameraParameters::new().unwrap();
+        second.translation = column([100.0, 0.0, 0.0]);
+        let cameras = vec![CameraParameters::new().unwrap(), second];
+
+        let mut report = ReconstructionReport::new(&cameras);
+        report.add_frame(0, 120, ErrorStats::default());
+        report.add_frame(1, 118, ErrorStats::default());
+        let sheet = dir.join("detections_frame_0.png");
+        std::fs::write(&sheet, b"png").unwrap();
+        let images = vec![
+            ("Detected features, first frame".to_string(), sheet),
+            ("Missing".to_string(), dir.join("missing.png")),
+        ];
+
+        let out = dir.join("report.md");
+        write_reconstruction_report(&report, &cameras, &images, &out).unwrap();
+        let text = std::fs::read_to_string(&out).unwrap();
+        for header in [
+            "# Reconstruction report",
+            "## Summary",
+            "## Cameras",
+            "## Baselines",
+            "## Images",
+            "## Frames",
+        ] {
+            assert!(text.contains(header), "нет раздела {header}");
+        }
+        assert!(text.contains("(<detections_frame_0.png>)"));
+        assert!(!text.contains("missing.png"));
+        assert!(text.contains("- Total points: 238"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn contact_sheet_tiles_scaled_cameras() {
+        let frame = Mat::new_rows_cols_with_default(720, 1280, CV_8UC3, Scalar::all(80.0)).unwrap();
+        let mut keypoints = Vector::<KeyPoint>::new();
+        keypoints.push(KeyPoint::new_coords_def(100.0, 100.0, 5.0).unwrap());
+        let frames = vec![frame.clone(), frame.clone(), frame];
+        let sheet = detection_contact_sheet(&frames, &[keypoints], 2).unwrap();
+        assert_eq!(sheet.cols(), 2 * CONTACT_SHEET_TILE_WIDTH);
+        assert_eq!(sheet.rows(), 2 * 360);
+    }
+
+    #[test]
+    fn degenerate_intrinsic_is_clean_error() {
+        let first = camera_with_distortion(0.0);
+        let mut second = camera_with_distortion(0.0);
+        second.intrinsic = Mat::zeros(3, 3, opencv::core::CV_64F)
+            .unwrap()
+            .to_mat()
+            .unwrap();
+        second.translation = column([-1.0, 0.0, 0.0]);
+
+        let mut points_2d = Vector::<Mat>::new();
+        for _ in 0..2 {
+            points_2d.push(Mat::from_slice_2d(&[[80.0f32, 60.0]]).unwrap());
+        }
+        let err = triangulate_points_multiple(&points_2d, &[first, second]).unwrap_err();
+        assert!(err.message.contains("Камера 1"), "{}", err.message);
+    }
+
+    #[test]
+    fn ply_comments_go_between_format_and_element() {
+        let path = std::env::temp_dir().join(format!("ply_comments_{}.ply", std::process::id()));
+        let cloud = PointCloud {
+            points: vec![Point3D::new(1.0, 2.0, 3.0, 0.5)],
+            timestamp: 0,
+        };
+        let comments = vec!["source video.mp4".to_string(), "frame 7\nsplit".to_string()];
+        save_point_cloud_with_comments(&cloud, &comments, &path).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let format = lines.iter().position(|l| l.starts_with("format ")).unwrap();
+        let element = lines
+            .iter()
+            .position(|l| l.starts_with("element "))
+            .unwrap();
+        assert_eq!(
+            &lines[format + 1..element],
+            &["comment source video.mp4", "comment frame 7 split"]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn speed_field_is_written_to_header_and_data() {
+        let path = std::env::temp_dir().join(format!("ply_speed_{}.ply", std::process::id()));
+        let cloud = PointCloud {
+            points: vec![
+                Point3D::new(1.0, 2.0, 3.0, 0.5),
+                Point3D::new(4.0, 5.0, 6.0, 1.0),
+            ],
+            timestamp: 0,
+        };
+        let speed = vec![("speed".to_string(), vec![0.25, 3.5])];
+        save_point_cloud_extended(&cloud, &[], &speed, &path).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let (header, body) = text.split_once("end_header\n").unwrap();
+        let properties: Vec<&str> = header
+            .lines()
+            .filter_map(|l| l.strip_prefix("property float "))
+            .collect();
+        assert_eq!(properties.last(), Some(&"speed"));
+        let speed_column = properties.len() - 1;
+        let speeds: Vec<f32> = body
+            .lines()
+            .map(|line| {
+                let values: Vec<&str> = line.split_whitespace().collect();
+                assert_eq!(values.len(), properties.len());
+                values[speed_column].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(speeds, vec![0.25, 3.5]);
+        std::fs::remove_file(&path).unwrap();
+
+        // Значений меньше, чем точек: ошибка, и файл не создаётся
+        let short = vec![("speed".to_string(), vec![0.25])];
+        let error = save_point_cloud_extended(&cloud, &[], &short, &path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn error_stats_survive_save_and_load() {
+        let path = std::env::temp_dir().join(format!("error_stats_{}.csv", std::process::id()));
+        let stats = vec![
+            (
+                3,
+                ErrorStats {
+                    min: 0.125,
+                    median: 0.5,
+                    mean: 0.75,
+                    max: 4.0,
+                    bad_pct: 12.5,
+                },
+            ),
+            (7, ErrorStats::default()),
+        ];
+        save_error_stats_csv(&stats, &path).unwrap();
+        assert_eq!(load_error_stats_csv(&path).unwrap(), stats);
+
+        std::fs::write(&path, "frame,min,median,mean,max,bad_pct\n3,1,2\n").unwrap();
+        let error = load_error_stats_csv(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn point_cloud_survives_save_and_load() {
+        let path = std::env::temp_dir().join(format!("ply_round_trip_{}.ply", std::process::id()));
+        let mut colored = Point3D::new(1.5, -2.25, 3.0, 0.75);
+        colored.color = Some((10, 20, 30));
+        let cloud = PointCloud {
+            points: vec![colored, Point3D::new(-0.5, 0.0, 12.125, 1.0)],
+            timestamp: 42,
+        };
+        let comments = vec!["frame 42".to_string()];
+        let speed = vec![("speed".to_string(), vec![0.5, 2.0])];
+        save_point_cloud_extended(&cloud, &comments, &speed, &path).unwrap();
+
+        let loaded = load_point_cloud(&path).unwrap();
+        assert_eq!(loaded.timestamp, 42);
+        assert_eq!(loaded.points.len(), 2);
+        for (loaded, saved) in loaded.points.iter().zip(&cloud.points) {
+            assert_eq!((loaded.x, loaded.y, loaded.z), (saved.x, saved.y, saved.z));
+            assert_eq!(loaded.confidence, saved.confidence);
+        }
+        assert_eq!(loaded.points[0].color, Some((10, 20, 30)));
+        // Точки без цвета в облаке с цветом пишутся серыми
+        assert_eq!(loaded.points[1].color, Some((128, 128, 128)));
+
+        let plain = PointCloud {
+            points: vec![Point3D::new(4.0, 5.0, 6.0, 0.25)],
+            timestamp: 0,
+        };
+        save_point_cloud(&plain, &path).unwrap();
+        let loaded = load_point_cloud(&path).unwrap();
+        assert_eq!(loaded.timestamp, 0);
+        assert_eq!(loaded.points[0].color, None);
+        assert_eq!(loaded.points[0].confidence, 0.25);
+        assert_eq!(loaded.points[0].track_id, None);
+
+        let tracks = vec![(PLY_TRACK_ID_PROPERTY.to_string(), vec![7.0, -1.0])];
+        save_point_cloud_extended(&cloud, &[], &tracks, &path).unwrap();
+        let loaded = load_point_cloud(&path).unwrap();
+        assert_eq!(loaded.points[0].track_id, Some(7));
+        assert_eq!(loaded.points[1].track_id, None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn max_reproj_filter_keeps_points_within_threshold_and_unknown() {
+        let mut cloud = PointCloud {
+            points: [Some(1.0), Some(3.0), Some(7.0), None]
+                .into_iter()
+                .enumerate()
+                .map(|(i, reproj_error)| {
+                    let mut point = Point3D::new(i as f64, 0.0, 1.0, 1.0);
+                    point.reproj_error = reproj_error;
+                    point
+                })
+                .collect(),
+            timestamp: 0,
+        };
+        filter_point_cloud_by_max_reproj(&mut cloud, 3.0);
+        let errors: Vec<Option<f64>> = cloud.points.iter().map(|p| p.reproj_error).collect();
+        assert_eq!(errors, vec![Some(1.0), Some(3.0), None]);
+        let xs: Vec<f64> = cloud.points.iter().map(|p| p.x).collect();
+        assert_eq!(xs, vec![0.0, 1.0, 3.0]);
+    }
+
+    /// Кадр 100x100 из четырёх цветных квадрантов (BGR): красный, зелёный, синий и белый
+    /// по часовой стрелке от левого верхнего
+    fn quadrant_frame() -> Mat {
+        let mut frame = Mat::new_rows_cols_with_default(
+            100,
+            100,
+            opencv::core::CV_8UC3,
+            opencv::core::Scalar::all(0.0),
+        )
+        .unwrap();
+        let patches = [
+            (0, 0, [0.0, 0.0, 255.0]),
+            (50, 0, [0.0, 255.0, 0.0]),
+            (50, 50, [255.0, 0.0, 0.0]),
+            (0, 50, [255.0, 255.0, 255.0]),
+        ];
+        for (x, y, [b, g, r]) in patches {
+            frame
+                .roi_mut(opencv::core::Rect::new(x, y, 50, 50))
+                .unwrap()
+                .set_to_def(&opencv::core::Scalar::new(b, g, r, 0.0))
+                .unwrap();
+        }
+        frame
+    }
+
+    fn colors(cloud: &PointCloud) -> Vec<Option<(u8, u8, u8)>> {
+        cloud.points.iter().map(|p| p.color).collect()
+    }
+
+    #[test]
+    fn colors_follow_current_point_coordinates() {
+        const RED: Option<(u8, u8, u8)> = Some((255, 0, 0));
+        const GREEN: Option<(u8, u8, u8)> = Some((0, 255, 0));
+        const BLUE: Option<(u8, u8, u8)> = Some((0, 0, 255));
+        const WHITE: Option<(u8, u8, u8)> = Some((255, 255, 255));
+        let frame = quadrant_frame();
+        let mut cloud = PointCloud {
+            points: vec![Point3D::new(0.0, 0.0, 1.0, 1.0); 2],
+            timestamp: 0,
+        };
+        let points = |rows: &[[f64; 2]]| Mat::from_slice_2d(rows).unwrap();
+
+        let mut tracked = Vector::<Mat>::new();
+        tracked.push(points(&[[25.0, 25.0], [75.0, 25.0]]));
+        add_color_to_point_cloud_from_camera(&mut cloud, &tracked, 0, &frame);
+        assert_eq!(colors(&cloud), vec![RED, GREEN]);
+
+        // Точки сместились в другие квадранты: цвет берётся в новых координатах
+        let mut tracked = Vector::<Mat>::new();
+        tracked.push(points(&[[75.0, 75.0], [25.0, 75.0]]));
+        add_color_to_point_cloud_from_camera(&mut cloud, &tracked, 0, &frame);
+        assert_eq!(colors(&cloud), vec![BLUE, WHITE]);
+
+        // Камера 1 раскрашивает по своему набору точек, а не по набору камеры 0
+        let mut per_camera = Vector::<Mat>::new();
+        per_camera.push(points(&[[25.0, 25.0], [75.0, 25.0]]));
+        per_camera.push(points(&[[25.0, 75.0], [75.0, 75.0]]));
+        add_color_to_point_cloud_from_camera(&mut cloud, &per_camera, 1, &frame);
+        assert_eq!(colors(&cloud), vec![WHITE, BLUE]);
+    }
+}
@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+
+use log::{debug, info, warn};
+use opencv::{
+    Error,
+    core::{Mat, StsError, sv_decomp_def},
+    prelude::*,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::reconstruction::PointCloud;
+
+type Vec3 = [f64; 3];
+type Mat3 = [[f64; 3]; 3];
+
+/// Жёсткое преобразование x' = R * x + t
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rigid {
+    rotation: Mat3,
+    translation: Vec3,
+}
+
+impl Rigid {
+    fn identity() -> Self {
+        Self {
+            rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            translation: [0.0; 3],
+        }
+    }
+
+    fn apply(&self, p: &Vec3) -> Vec3 {
+        let r = &self.rotation;
+        let t = &self.translation;
+        [
+            r[0][0] * p[0] + r[0][1] * p[1] + r[0][2] * p[2] + t[0],
+            r[1][0] * p[0] + r[1][1] * p[1] + r[1][2] * p[2] + t[1],
+            r[2][0] * p[0] + r[2][1] * p[1] + r[2][2] * p[2] + t[2],
+        ]
+    }
+
+    /// Преобразование "сначала `self`, затем `next`"
+    fn then(&self, next: &Rigid) -> Rigid {
+        let mut rotation = [[0.0; 3]; 3];
+        for (i, row) in rotation.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|k| next.rotation[i][k] * self.rotation[k][j])
+                    .sum();
+            }
+        }
+        Rigid {
+            rotation,
+            translation: next.apply(&self.translation),
+        }
+    }
+
+    fn inverse(&self) -> Rigid {
+        let r = &self.rotation;
+        let rotation = [
+            [r[0][0], r[1][0], r[2][0]],
+            [r[0][1], r[1][1], r[2][1]],
+            [r[0][2], r[1][2], r[2][2]],
+        ];
+        let t = &self.translation;
+        let translation = [
+            -(rotation[0][0] * t[0] + rotation[0][1] * t[1] + rotation[0][2] * t[2]),
+            -(rotation[1][0] * t[0] + rotation[1][1] * t[1] + rotation[1][2] * t[2]),
+            -(rotation[2][0] * t[0] + rotation[2][1] * t[1] + rotation[2][2] * t[2]),
+        ];
+        Rigid {
+            rotation,
+            translation,
+        }
+    }
+
+    /// Однородная матрица 4x4 CV_64F
+    fn to_mat(self) -> Result<Mat, Error> {
+        let r = &self.rotation;
+        let t = &self.translation;
+        Mat::from_slice_2d(&[
+            [r[0][0], r[0][1], r[0][2], t[0]],
+            [r[1][0], r[1][1], r[1][2], t[1]],
+            [r[2][0], r[2][1], r[2][2], t[2]],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+/// Совмещает облако `source` с облаком `target` методом ICP (точка-точка): на каждой
+/// итерации каждой точке `source` сопоставляется ближайшая точка `target`, и по этим
+/// парам методом Умеямы ищется жёсткое преобразование (поворот и сдвиг, без масштаба).
+/// Итерации прекращаются после `max_iterations` или когда среднеквадратичное расстояние
+/// между парами изменилось меньше чем на `tolerance` (в единицах облака).
+///
+/// Возвращает матрицу 4x4 (CV_64F), переводящую `source` в систему `target`, и
+/// преобразованную копию `source`. ICP сходится к ближайшему локальному минимуму,
+/// поэтому облака должны быть уже грубо совмещены, например переведены в систему доски
+pub fn align_clouds_icp(
+    source: &PointCloud,
+    target: &PointCloud,
+    max_iterations: usize,
+    tolerance: f64,
+) -> Result<(Mat, PointCloud), Error> {
+    if source.points.len() < 3 || target.points.len() < 3 {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Для ICP нужно минимум 3 точки в каждом облаке, есть {} и {}",
+                source.points.len(),
+                target.points.len()
+            ),
+        ));
+    }
+
+    let source_positions: Vec<Vec3> = source.points.iter().map(|p| [p.x, p.y, p.z]).collect();
+    let tree = KdTree::new(target.points.iter().map(|p| [p.x, p.y, p.z]).collect());
+
+    let mut transform = Rigid::identity();
+    let mut current = source_positions.clone();
+    let mut previous_rms = f64::INFINITY;
+    let mut converged = false;
+    for iteration in 0..max_iterations {
+        let nearest = |p: &Vec3| {
+            let (index, distance_sq) = tree.nearest(p);
+            (tree.points[index], distance_sq)
+        };
+        #[cfg(feature = "parallel")]
+        let matches: Vec<(Vec3, f64)> = {
+            use rayon::prelude::*;
+            current.par_iter().map(nearest).collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let matches: Vec<(Vec3, f64)> = current.iter().map(nearest).collect();
+
+        let rms = (matches.iter().map(|(_, d)| d).sum::<f64>() / matches.len() as f64).sqrt();
+        debug!("ICP, итерация {}: RMS {:.6}", iteration, rms);
+        if (previous_rms - rms).abs() < tolerance {
+            converged = true;
+            break;
+        }
+        previous_rms = rms;
+
+        let targets: Vec<Vec3> = matches.iter().map(|(p, _)| *p).collect();
+        let step = umeyama_rigid(&current, &targets)?;
+        for p in &mut current {
+            *p = step.apply(p);
+        }
+        transform = transform.then(&step);
+    }
+    if converged {
+        info!(
+            "ICP сошёлся, RMS расстояния до ближайших точек {:.4}",
+            previous_rms
+        );
+    } else {
+        warn!(
+            "ICP не сошёлся за {} итераций, RMS {:.4}",
+            max_iterations, previous_rms
+        );
+    }
+
+    let mut aligned = source.clone();
+    for (point, p) in aligned.points.iter_mut().zip(&source_positions) {
+        [point.x, point.y, point.z] = transform.apply(p);
+    }
+    Ok((transform.to_mat()?, aligned))
+}
+
+/// Сколько раз уточняется общее движение по точкам, которые ему следуют
+const MOTION_REFINEMENTS: usize = 3;
+
+/// Параметры стабилизации облаков: вычитание общего движения установки камер
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StabilizationParams {
+    /// Наибольшее отклонение точки (в единицах облака, обычно мм) от общего движения,
+    /// при котором она считается движущейся вместе со сценой, а не с объектом
+    pub inlier_threshold: f64,
+    /// Наименьшее число точек, следующих общему движению, чтобы ему можно было доверять
+    pub min_inliers: usize,
+}
+
+impl Default for StabilizationParams {
+    fn default() -> Self {
+        Self {
+            inlier_threshold: 5.0,
+            min_inliers: 10,
+        }
+    }
+}
+
+/// Положения точек облака по `track_id`; точки без трека пропускаются
+fn track_positions(cloud: &PointCloud) -> HashMap<usize, Vec3> {
+    cloud
+        .points
+        .iter()
+        .filter_map(|p| Some((p.track_id?, [p.x, p.y, p.z])))
+        .collect()
+}
+
+/// Общее жёсткое движение между облаками по точкам с одинаковыми `track_id`. Сначала
+/// движение ищется по всем общим трекам, затем уточняется по трекам, которые отклоняются
+/// от него не больше `inlier_threshold`: так движение объекта не сбивает движение сцены
+fn dominant_motion(
+    previous: &HashMap<usize, Vec3>,
+    current: &HashMap<usize, Vec3>,
+    params: &StabilizationParams,
+) -> Result<(Rigid, usize), Error> {
+    let (from, to): (Vec<Vec3>, Vec<Vec3>) = current
+        .iter()
+        .filter_map(|(track_id, to)| Some((*previous.get(track_id)?, *to)))
+        .unzip();
+    let required = params.min_inliers.max(3);
+    if from.len() < required {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Общих треков между кадрами {}, нужно минимум {}",
+                from.len(),
+                required
+            ),
+        ));
+    }
+
+    let mut motion = umeyama_rigid(&from, &to)?;
+    let mut inliers = from.len();
+    for _ in 0..MOTION_REFINEMENTS {
+        let (inlier_from, inlier_to): (Vec<Vec3>, Vec<Vec3>) = from
+            .iter()
+            .zip(&to)
+            .filter(|(f, t)| {
+                let moved = motion.apply(f);
+                let distance_sq: f64 = (0..3).map(|k| (moved[k] - t[k]).powi(2)).sum();
+                distance_sq <= params.inlier_threshold.powi(2)
+            })
+            .map(|(f, t)| (*f, *t))
+            .unzip();
+        if inlier_from.len() < required {
+            return Err(Error::new(
+                StsError,
+                format!(
+                    "Общему движению следуют {} треков из {}, нужно минимум {}",
+                    inlier_from.len(),
+                    from.len(),
+                    required
+                ),
+            ));
+        }
+        let stable = inlier_from.len() == inliers;
+        inliers = inlier_from.len();
+        motion = umeyama_rigid(&inlier_from, &inlier_to)?;
+        if stable {
+            break;
+        }
+    }
+    Ok((motion, inliers))
+}
+
+/// Общее (доминирующее) жёсткое движение сцены от облака `previous` к облаку `current`
+/// по точкам с одинаковыми `track_id`, например смещение ручной установки камер между
+/// кадрами. Точки, которые отклоняются от движения больше `inlier_threshold`, считаются
+/// движущимся объектом и в оценку не входят.
+///
+/// Возвращает матрицу 4x4 (CV_64F), переводящую точки `previous` в положения
+/// в `current`, и число треков, которые ей следуют
+pub fn estimate_dominant_motion(
+    previous: &PointCloud,
+    current: &PointCloud,
+    params: &StabilizationParams,
+) -> Result<(Mat, usize), Error> {
+    let (motion, inliers) = dominant_motion(
+        &track_positions(previous),
+        &track_positions(current),
+        params,
+    )?;
+    Ok((motion.to_mat()?, inliers))
+}
+
+/// Стабилизация последовательности облаков: общее движение сцены между соседними кадрами
+/// (см. [`estimate_dominant_motion`]) накапливается и вычитается, так что облака остаются
+/// в системе первого облака и в них видно только движение объекта. В отличие от
+/// сглаживания траекторий убирает не дрожание точек, а смещение всей сцены.
+/// Облака подаются по порядку кадров
+pub struct MotionStabilizer {
+    params: StabilizationParams,
+    /// Положения треков предыдущего облака до стабилизации
+    previous: Option<HashMap<usize, Vec3>>,
+    /// Движение сцены от первого облака до предыдущего
+    accumulated: Rigid,
+}
+
+impl MotionStabilizer {
+    pub fn new(params: StabilizationParams) -> Self {
+        Self {
+            params,
+            previous: None,
+            accumulated: Rigid::identity(),
+        }
+    }
+
+    /// Переводит `cloud` в систему первого облака. Если общее движение оценить не удалось,
+    /// считается, что сцена не сдвинулась с предыдущего кадра
+    pub fn stabilize(&mut self, cloud: &mut PointCloud) {
+        let current = track_positions(cloud);
+        if let Some(previous) = &self.previous {
+            match dominant_motion(previous, &current, &self.params) {
+                Ok((motion, inliers)) => {
+                    debug!(
+                        "Кадр {}: общее движение по {} трекам, сдвиг ({:.3}, {:.3}, {:.3})",
+                        cloud.timestamp,
+                        inliers,
+                        motion.translation[0],
+                        motion.translation[1],
+                        motion.translation[2]
+                    );
+                    self.accumulated = self.accumulated.then(&motion);
+                }
+                Err(e) => warn!(
+                    "Кадр {}: общее движение не оценено, стабилизация по предыдущему кадру: {}",
+                    cloud.timestamp, e
+                ),
+            }
+        }
+        self.previous = Some(current);
+
+        let to_reference = self.accumulated.inverse();
+        for point in &mut cloud.points {
+            [point.x, point.y, point.z] = to_reference.apply(&[point.x, point.y, point.z]);
+        }
+    }
+}
+
+/// Жёсткое преобразование, переводящее точки `from` в соответствующие точки `to` с
+/// наименьшей суммой квадратов расстояний (Umeyama, 1991, без масштаба)
+fn umeyama_rigid(from: &[Vec3], to: &[Vec3]) -> Result<Rigid, Error> {
+    let n = from.len() as f64;
+    let centroid = |points: &[Vec3]| {
+        points.iter().fold([0.0; 3], |acc, p| {
+            [acc[0] + p[0] / n, acc[1] + p[1] / n, acc[2] + p[2] / n]
+        })
+    };
+    let (mu_from, mu_to) = (centroid(from), centroid(to));
+
+    // Ковариация H = sum (to - mu_to) * (from - mu_from)^T
+    let mut covariance = [[0.0; 3]; 3];
+    for (f, t) in from.iter().zip(to) {
+        for (r, row) in covariance.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value += (t[r] - mu_to[r]) * (f[c] - mu_from[c]);
+            }
+        }
+    }
+
+    let mut w = Mat::default();
+    let mut u = Mat::default();
+    let mut vt = Mat::default();
+    sv_decomp_def(&Mat::from_slice_2d(&covariance)?, &mut w, &mut u, &mut vt)?;
+    let u = read_mat3(&u)?;
+    let vt = read_mat3(&vt)?;
+
+    // R = U * diag(1, 1, d) * V^T, d = det(U * V^T) исключает отражение
+    let d = determinant(&u) * determinant(&vt);
+    let s = [1.0, 1.0, d.signum()];
+    let mut rotation = [[0.0; 3]; 3];
+    for (i, row) in rotation.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| u[i][k] * s[k] * vt[k][j]).sum();
+        }
+    }
+    let rotated = Rigid {
+        rotation,
+        translation: [0.0; 3],
+    }
+    .apply(&mu_from);
+    Ok(Rigid {
+        rotation,
+        translation: [
+            mu_to[0] - rotated[0],
+            mu_to[1] - rotated[1],
+            mu_to[2] - rotated[2],
+        ],
+    })
+}
+
+fn read_mat3(mat: &Mat) -> Result<Mat3, Error> {
+    let mut result = [[0.0; 3]; 3];
+    for (r, row) in result.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = *mat.at_2d::<f64>(r as i32, c as i32)?;
+        }
+    }
+    Ok(result)
+}
+
+fn determinant(m: &Mat3) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// k-d дерево для поиска ближайшей точки. Хранится неявно: индексы точек упорядочены
+/// так, что медиана каждого диапазона - его узел, левее - меньшие по оси узла точки
+struct KdTree {
+    points: Vec<Vec3>,
+    order: Vec<usize>,
+}
+
+impl KdTree {
+    fn new(points: Vec<Vec3>) -> Self {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        Self::build(&points, &mut order, 0);
+        Self { points, order }
+    }
+
+    fn build(points: &[Vec3], order: &mut [usize], depth: usize) {
+        if order.len() <= 1 {
+            return;
+        }
+        let axis = depth % 3;
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by(mid, |&a, &b| points[a][axis].total_cmp(&points[b][axis]));
+        let (left, right) = order.split_at_mut(mid);
+        Self::build(points, left, depth + 1);
+        Self::build(points, &mut right[1..], depth + 1);
+    }
+
+    /// Индекс ближайшей к `query` точки и квадрат расстояния до неё
+    fn nearest(&self, query: &Vec3) -> (usize, f64) {
+        let mut best = (self.order[0], f64::INFINITY);
+        self.search(query, 0, self.order.len(), 0, &mut best);
+        best
+    }
+
+    fn search(&self, query: &Vec3, lo: usize, hi: usize, depth: usize, best: &mut (usize, f64)) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let index = self.order[mid];
+        let p = &self.points[index];
+        let distance_sq = (0..3).map(|k| (p[k] - query[k]).powi(2)).sum::<f64>();
+        if distance_sq < best.1 {
+            *best = (index, distance_sq);
+        }
+
+        let axis = depth % 3;
+        let diff = query[axis] - p[axis];
+        let (near, far) = if diff < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(query, near.0, near.1, depth + 1, best);
+        if diff * diff < best.1 {
+            self.search(query, far.0, far.1, depth + 1, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reconstruction::Point3D;
+
+    use super::*;
+
+    /// Несимметричное облако: решётка на неравных осях с изгибом по z
+    fn sample_cloud() -> PointCloud {
+        let mut points = Vec::new();
+        for i in 0..12 {
+            for j in 0..8 {
+                let (x, y) = (i as f64 * 0.1, j as f64 * 0.15);
+                points.push(Point3D::new(x, y, 0.2 * x * x + 0.1 * y, 1.0));
+            }
+        }
+        PointCloud {
+            points,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn icp_recovers_inverse_of_applied_motion() {
+        let (sin, cos) = 1f64.to_radians().sin_cos();
+        let motion = Rigid {
+            rotation: [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+            translation: [0.01, -0.005, 0.004],
+        };
+        let target = sample_cloud();
+        let mut source = target.clone();
+        for point in &mut source.points {
+            [point.x, point.y, point.z] = motion.apply(&[point.x, point.y, point.z]);
+        }
+
+        let (transform, aligned) = align_clouds_icp(&source, &target, 100, 1e-12).unwrap();
+        let expected = motion.inverse().to_mat().unwrap();
+        for r in 0..4 {
+            for c in 0..4 {
+                let found = *transform.at_2d::<f64>(r, c).unwrap();
+                let wanted = *expected.at_2d::<f64>(r, c).unwrap();
+                assert!(
+                    (found - wanted).abs() < 1e-6,
+                    "[{r}][{c}] {found} != {wanted}"
+                );
+            }
+        }
+        for (point, original) in aligned.points.iter().zip(&target.points) {
+            assert!((point.x - original.x).abs() < 1e-6);
+            assert!((point.y - original.y).abs() < 1e-6);
+            assert!((point.z - original.z).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn icp_needs_three_points() {
+        let mut cloud = sample_cloud();
+        cloud.points.truncate(2);
+        assert!(align_clouds_icp(&cloud, &sample_cloud(), 10, 1e-6).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use log::{debug, info};
+use opencv::{
+    Error,
+    calib3d::{
+        CALIB_ZERO_DISPARITY, StereoSGBM, StereoSGBM_MODE_SGBM_3WAY, init_undistort_rectify_map,
+        reproject_image_to_3d, stereo_rectify,
+    },
+    core::{
+        CV_16SC2, CV_32F, CV_64F, GEMM_1_T, GEMM_2_T, Mat, Rect, Size, StsError, Vec3b, Vec3f, gemm,
+    },
+    imgproc,
+    prelude::*,
+};
+
+use crate::{
+    calibration::CameraParameters,
+    reconstruction::{Point3D, PointCloud},
+};
+
+/// Параметры плотного стереосопоставления StereoSGBM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DenseStereoParams {
+    /// Наименьшее искомое смещение, пикс.
+    pub min_disparity: i32,
+    /// Ширина диапазона поиска смещения, пикс., кратна 16. Должна покрывать смещение
+    /// самых близких точек сцены
+    pub num_disparities: i32,
+    /// Размер окна сопоставления, нечётный
+    pub block_size: i32,
+    /// Порог уникальности лучшего смещения, %
+    pub uniqueness_ratio: i32,
+    /// Наибольший размер пятна смещений, которое считается шумом и отбрасывается
+    pub speckle_window_size: i32,
+    /// Наибольший разброс смещений внутри связного пятна
+    pub speckle_range: i32,
+}
+
+impl Default for DenseStereoParams {
+    fn default() -> Self {
+        Self {
+            min_disparity: 0,
+            num_disparities: 128,
+            block_size: 5,
+            uniqueness_ratio: 10,
+            speckle_window_size: 100,
+            speckle_range: 2,
+        }
+    }
+}
+
+/// Плотное облако по паре кадров с параметрами StereoSGBM по умолчанию,
+/// см. [`reconstruct_stereo_dense_with_params`]
+pub fn reconstruct_stereo_dense(
+    img_left: &Mat,
+    img_right: &Mat,
+    cam_left: &CameraParameters,
+    cam_right: &CameraParameters,
+) -> Result<PointCloud, Error> {
+    reconstruct_stereo_dense_with_params(
+        img_left,
+        img_right,
+        cam_left,
+        cam_right,
+        &DenseStereoParams::default(),
+    )
+}
+
+/// Плотное облако по паре кадров: кадры ректифицируются по внешним параметрам камер
+/// (`stereo_rectify`), смещение считается StereoSGBM для каждого пикселя левого кадра
+/// и переводится в 3D матрицей Q. В отличие от разреженной реконструкции по SIFT точка
+/// получается для каждого пикселя с найденным смещением. Точки возвращаются в той же
+/// системе координат, что и при триангуляции (мировой системе калибровки), и окрашены
+/// по левому кадру. Кадры BGR или серые, одного размера
+pub fn reconstruct_stereo_dense_with_params(
+    img_left: &Mat,
+    img_right: &Mat,
+    cam_left: &CameraParameters,
+    cam_right: &CameraParameters,
+    params: &DenseStereoParams,
+) -> Result<PointCloud, Error> {
+    if img_left.empty() || img_right.empty() {
+        return Err(Error::new(StsError, "Пустой кадр стереопары".to_string()));
+    }
+    let size = img_left.size()?;
+    if img_right.size()? != size {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Кадры стереопары разного размера: {:?} и {:?}",
+                size,
+                img_right.size()?
+            ),
+        ));
+    }
+    if params.num_disparities <= 0 || params.num_disparities % 16 != 0 {
+        return Err(Error::new(
+            StsError,
+            format!(
+                "Диапазон смещений {} должен быть положительным и кратным 16",
+                params.num_disparities
+            ),
+        ));
+    }
+
+    // Поза правой камеры относительно левой: R = R_r * R_l^T, t = t_r - R * t_l
+    let rotation_left = to_f64(&cam_left.rotation)?;
+    let translation_left = to_f64(&cam_left.translation)?.reshape(1, 3)?.try_clone()?;
+    let rotation_right = to_f64(&cam_right.rotation)?;
+    let translation_right = to_f64(&cam_right.translation)?.reshape(1, 3)?.try_clone()?;
+    let mut rotation = Mat::default();
+    gemm(
+        &rotation_right,
+        &rotation_left,
+        1.0,
+        &Mat::default(),
+        0.0,
+        &mut rotation,
+        GEMM_2_T,
+    )?;
+    let mut translation = Mat::default();
+    gemm(
+        &rotation,
+        &translation_left,
+        -1.0,
+        &translation_right,
+        1.0,
+        &mut translation,
+        0,
+    )?;
+
+    let mut r1 = Mat::default();
+    let mut r2 = Mat::default();
+    let mut p1 = Mat::default();
+    let mut p2 = Mat::default();
+    let mut q = Mat::default();
+    let mut roi_left = Rect::default();
+    let mut roi_right = Rect::default();
+    stereo_rectify(
+        &cam_left.intrinsic,
+        &cam_left.distortion,
+        &cam_right.intrinsic,
+        &cam_right.distortion,
+        size,
+        &rotation,
+        &translation,
+        &mut r1,
+        &mut r2,
+        &mut p1,
+        &mut p2,
+        &mut q,
+        CALIB_ZERO_DISPARITY,
+        0.0,
+        size,
+        &mut roi_left,
+        &mut roi_right,
+    )?;
+    debug!(
+        "Ректификация пары: область без пустых пикселей слева {:?}, справа {:?}",
+        roi_left, roi_right
+    );
+
+    let rectified_left = rectify(img_left, cam_left, &r1, &p1, size)?;
+    let rectified_right = rectify(img_right, cam_right, &r2, &p2, size)?;
+
+    let block_area = params.block_size * params.block_size;
+    let mut matcher = StereoSGBM::create(
+        params.min_disparity,
+        params.num_disparities,
+        params.block_size,
+        8 * block_area,
+        32 * block_area,
+        1,
+        63,
+        params.uniqueness_ratio,
+        params.speckle_window_size,
+        params.speckle_range,
+        StereoSGBM_MODE_SGBM_3WAY,
+    )?;
+    let mut disparity_fixed = Mat::default();
+    matcher.compute(
+        &to_gray(&rectified_left)?,
+        &to_gray(&rectified_right)?,
+        &mut disparity_fixed,
+    )?;
+    // StereoSGBM возвращает смещение с 4 дробными битами
+    let mut disparity = Mat::default();
+    disparity_fixed.convert_to(&mut disparity, CV_32F, 1.0 / 16.0, 0.0)?;
+
+    let mut points_rectified = Mat::default();
+    reproject_image_to_3d(&disparity, &mut points_rectified, &q, false, CV_32F)?;
+
+    // Из системы ректифицированной левой камеры в мировую:
+    // X_world = R_l^T * (R1^T * X_rect - t_l)
+    let mut to_camera = Mat::default();
+    gemm(
+        &rotation_left,
+        &r1,
+        1.0,
+        &Mat::default(),
+        0.0,
+        &mut to_camera,
+        GEMM_1_T | GEMM_2_T,
+    )?;
+    let to_world = rows3(&to_camera)?;
+    let mut offset = Mat::default();
+    gemm(
+        &rotation_left,
+        &translation_left,
+        -1.0,
+        &Mat::default(),
+        0.0,
+        &mut offset,
+        GEMM_1_T,
+    )?;
+    let offset = [
+        *offset.at_2d::<f64>(0, 0)?,
+        *offset.at_2d::<f64>(1, 0)?,
+        *offset.at_2d::<f64>(2, 0)?,
+    ];
+
+    let min_valid = params.min_disparity as f32;
+    let mut points = Vec::new();
+    for y in 0..size.height {
+        let disparities = disparity.at_row::<f32>(y)?;
+        let xyz = points_rectified.at_row::<Vec3f>(y)?;
+        let colors = rectified_left.at_row::<Vec3b>(y)?;
+        for x in 0..size.width as usize {
+            if disparities[x] <= min_valid {
+                continue;
+            }
+            let p = xyz[x];
+            let p = [p[0] as f64, p[1] as f64, p[2] as f64];
+            if !p.iter().all(|v| v.is_finite()) {
+                continue;
+            }
+            let world = |row: usize| {
+                to_world[row][0] * p[0]
+                    + to_world[row][1] * p[1]
+                    + to_world[row][2] * p[2]
+                    + offset[row]
+            };
+            let mut point = Point3D::new(world(0), world(1), world(2), 1.0);
+            let color = colors[x];
+            point.color = Some((color[2], color[1], color[0])); // BGR -> RGB
+            points.push(point);
+        }
+    }
+    info!(
+        "Плотная стереореконструкция: {} точек из {}x{} пикселей",
+        points.len(),
+        size.width,
+        size.height
+    );
+    Ok(PointCloud {
+        points,
+        timestamp: 0,
+    })
+}
+
+/// Ректифицированный кадр камеры в цвете BGR
+fn rectify(
+    image: &Mat,
+    camera: &CameraParameters,
+    rectification: &Mat,
+    projection: &Mat,
+    size: Size,
+) -> Result<Mat, Error> {
+    let mut map1 = Mat::default();
+    let mut map2 = Mat::default();
+    init_undistort_rectify_map(
+        &camera.intrinsic,
+        &camera.distortion,
+        rectification,
+        projection,
+        size,
+        CV_16SC2,
+        &mut map1,
+        &mut map2,
+    )?;
+    let mut color = Mat::default();
+    if image.channels() == 1 {
+        imgproc::cvt_color_def(image, &mut color, imgproc::COLOR_GRAY2BGR)?;
+    } else {
+        color = image.clone();
+    }
+    let mut rectified = Mat::default();
+    imgproc::remap(
+        &color,
+        &mut rectified,
+        &map1,
+        &map2,
+        imgproc::INTER_LINEAR,
+        opencv::core::BORDER_CONSTANT,
+        opencv::core::Scalar::default(),
+    )?;
+    Ok(rectified)
+}
+
+fn to_gray(image: &Mat) -> Result<Mat, Error> {
+    let mut gray = Mat::default();
+    imgproc::cvt_color_def(image, &mut gray, imgproc::COLOR_BGR2GRAY)?;
+    Ok(gray)
+}
+
+fn to_f64(mat: &Mat) -> Result<Mat, Error> {
+    let mut converted = Mat::default();
+    mat.convert_to(&mut converted, CV_64F, 1.0, 0.0)?;
+    Ok(converted)
+}
+
+fn rows3(mat: &Mat) -> Result<[[f64; 3]; 3], Error> {
+    let mut rows = [[0.0; 3]; 3];
+    for (i, row) in rows.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = *mat.at_2d::<f64>(i as i32, j as i32)?;
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{CV_8UC1, Scalar};
+
+    /// Фокусное расстояние, пикс., база пары и глубина плоскости (в единицах калибровки)
+    const FOCAL: f64 = 400.0;
+    const BASELINE: f64 = 10.0;
+    const DEPTH: f64 = 200.0;
+
+    fn camera(translation_x: f64) -> CameraParameters {
+        let mut camera = CameraParameters::new().unwrap();
+        camera.intrinsic =
+            Mat::from_slice_2d(&[[FOCAL, 0.0, 160.0], [0.0, FOCAL, 120.0], [0.0, 0.0, 1.0]])
+                .unwrap();
+        camera.distortion = Mat::zeros(1, 5, CV_64F).unwrap().to_mat().unwrap();
+        camera.translation = Mat::from_slice(&[translation_x, 0.0, 0.0])
+            .unwrap()
+            .reshape(1, 3)
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        camera
+    }
+
+    #[test]
+    fn fronto_parallel_plane_has_constant_depth() {
+        // Плоскость с текстурой на глубине DEPTH: правая камера сдвинута на BASELINE,
+        // поэтому её кадр - кадр левой, сдвинутый на FOCAL * BASELINE / DEPTH = 20 пикс.
+        let disparity = (FOCAL * BASELINE / DEPTH) as i32;
+        opencv::core::set_rng_seed(5).unwrap();
+        let mut noise =
+            Mat::new_rows_cols_with_default(240, 320 + disparity, CV_8UC1, Scalar::all(0.0))
+                .unwrap();
+        opencv::core::randu(&mut noise, &Scalar::all(0.0), &Scalar::all(255.0)).unwrap();
+        let mut texture = Mat::default();
+        imgproc::gaussian_blur_def(&noise, &mut texture, Size::new(5, 5), 1.2).unwrap();
+        let left = texture
+            .roi(Rect::new(0, 0, 320, 240))
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        let right = texture
+            .roi(Rect::new(disparity, 0, 320, 240))
+            .unwrap()
+            .try_clone()
+            .unwrap();
+
+        let params = DenseStereoParams {
+            num_disparities: 48,
+            ..DenseStereoParams::default()
+        };
+        let cloud = reconstruct_stereo_dense_with_params(
+            &left,
+            &right,
+            &camera(0.0),
+            &camera(-BASELINE),
+            &params,
+        )
+        .unwrap();
+        assert!(cloud.points.len() > 10_000, "{}", cloud.points.len());
+        let mut depths: Vec<f64> = cloud.points.iter().map(|p| p.z).collect();
+        depths.sort_by(f64::total_cmp);
+        let median = depths[depths.len() / 2];
+        assert!((median - DEPTH).abs() < 0.02 * DEPTH, "{median}");
+        let near_plane = depths
+            .iter()
+            .filter(|&&z| (z - DEPTH).abs() < 0.05 * DEPTH)
+            .count();
+        assert!(near_plane as f64 > 0.9 * depths.len() as f64);
+    }
+}
@@ -0,0 +1,726 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use log::{debug, info};
+use opencv::core::{
+    CV_32S, DMatch, FileStorage, FileStorage_Mode, Mat, Point2f, StsError, Vector, vconcat,
+};
+use opencv::prelude::*;
+use opencv::{self, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::correspondence::{DescriptorKind, SiftParams, bf_match_knn_with_kind, sift_with_params};
+use crate::reconstruction::PointCloud;
+use crate::utils::{UtilsError, path_to_str, write_atomically};
+
+/// Параметры отбрасывания неподвижных треков (фон, стойка с камерами)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StaticTrackFilter {
+    /// Суммарное смещение трека в пикселях за окно, ниже которого трек считается неподвижным
+    pub min_motion_px: f32,
+    /// Количество последних кадров, по которым считается смещение
+    pub window: usize,
+}
+
+impl Default for StaticTrackFilter {
+    fn default() -> Self {
+        Self {
+            min_motion_px: 2.0,
+            window: 30,
+        }
+    }
+}
+
+/// Учёт треков, прослеживаемых оптическим потоком во всех камерах.
+/// Строка `i` во входных наборах точек каждой камеры соответствует треку `track_ids()[i]`
+pub struct TrackManager {
+    ids: Vec<usize>,
+    /// Для каждого трека - последние положения во всех камерах, не длиннее `window`
+    history: Vec<VecDeque<Vec<Point2f>>>,
+    window: usize,
+}
+
+impl TrackManager {
+    pub fn new(num_tracks: usize, window: usize) -> Self {
+        Self {
+            ids: (0..num_tracks).collect(),
+            history: vec![VecDeque::with_capacity(window); num_tracks],
+            window: window.max(2),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Идентификаторы треков в порядке строк наборов точек
+    pub fn track_ids(&self) -> &[usize] {
+        &self.ids
+    }
+
+    /// Запоминает положения треков на очередном кадре (по набору точек на камеру)
+    pub fn record(&mut self, points: &[Vector<Point2f>]) -> Result<(), Error> {
+        if let Some(camera_i) = points.iter().position(|p| p.len() != self.ids.len()) {
+            return Err(Error::new(
+                StsError,
+                format!(
+                    "Камера {}: {} точек, а треков {}",
+                    camera_i,
+                    points[camera_i].len(),
+                    self.ids.len()
+                ),
+            ));
+        }
+        for (track_i, history) in self.history.iter_mut().enumerate() {
+            if history.len() == self.window {
+                history.pop_front();
+            }
+            history.push_back(
+                points
+                    .iter()
+                    .map(|camera_points| camera_points.get(track_i))
+                    .collect::<Result<Vec<_>, Error>>()?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Длина пути трека за окно в пикселях; берётся максимум по камерам,
+    /// чтобы трек, движущийся хотя бы в одной камере, не считался фоном
+    pub fn motion(&self, track_i: usize) -> f32 {
+        let history = &self.history[track_i];
+        let num_cameras = history.front().map_or(0, Vec::len);
+        (0..num_cameras)
+            .map(|camera_i| {
+                history
+                    .iter()
+                    .zip(history.iter().skip(1))
+                    .map(|(a, b)| {
+                        let d = b[camera_i] - a[camera_i];
+                        (d.x * d.x + d.y * d.y).sqrt()
+                    })
+                    .sum::<f32>()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    /// Удаляет треки, сместившиеся за полное окно (заданное в `new`) меньше порога, из менеджера
+    /// и из наборов точек `points`. Треки, прожившие меньше окна, не трогаются.
+    /// Возвращает количество удалённых треков
+    pub fn drop_static_tracks(
+        &mut self,
+        points: &mut [Vector<Point2f>],
+        filter: &StaticTrackFilter,
+    ) -> usize {
+        let keep: Vec<bool> = (0..self.ids.len())
+            .map(|track_i| {
+                self.history[track_i].len() < self.window
+                    || self.motion(track_i) >= filter.min_motion_px
+            })
+            .collect();
+        let dropped = keep.iter().filter(|&&k| !k).count();
+        if dropped > 0 {
+            self.retain(&keep);
+            for camera_points in points.iter_mut() {
+                *camera_points = camera_points
+                    .iter()
+                    .zip(&keep)
+                    .filter_map(|(p, &k)| k.then_some(p))
+                    .collect();
+            }
+            debug!(
+                "Отброшено {} неподвижных треков, осталось {}",
+                dropped,
+                self.ids.len()
+            );
+        }
+        dropped
+    }
+
+    /// Оставляет только треки, отмеченные в `keep`
+    pub fn retain(&mut self, keep: &[bool]) {
+        let mut flags = keep.iter();
+        self.ids.retain(|_| *flags.next().unwrap_or(&true));
+        let mut flags = keep.iter();
+        self.history.retain(|_| *flags.next().unwrap_or(&true));
+    }
+}
+
+/// Сохраняет 2D треки одной камеры в CSV: строка на каждое положение трека на кадре
+/// (кадр, идентификатор трека, x, y)
+pub fn save_tracks_2d_csv<P: AsRef<Path>>(
+    rows: &[(usize, usize, Point2f)],
+    path: P,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "frame,track_id,x,y")?;
+    for (frame, track_id, point) in rows {
+        writeln!(file, "{},{},{},{}", frame, track_id, point.x, point.y)?;
+    }
+    file.flush()
+}
+
+/// Время жизни треков по последовательности облаков: первый и последний кадр
+/// (`PointCloud::timestamp`), в которых встретилась точка трека. Облака можно подавать
+/// по одному, не держа всю последовательность в памяти
+#[derive(Debug, Clone, Default)]
+pub struct TrackLifespans {
+    spans: HashMap<usize, (usize, usize)>,
+}
+
+impl TrackLifespans {
+    pub fn observe(&mut self, cloud: &PointCloud) {
+        for track_id in cloud.points.iter().filter_map(|p| p.track_id) {
+            let span = self
+                .spans
+                .entry(track_id)
+                .or_insert((cloud.timestamp, cloud.timestamp));
+            span.0 = span.0.min(cloud.timestamp);
+            span.1 = span.1.max(cloud.timestamp);
+        }
+    }
+
+    /// Элемент `[n]` - число треков, проживших `n` кадров (от первого до последнего появления)
+    pub fn histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        for &(first, last) in self.spans.values() {
+            let length = last - first + 1;
+            if histogram.len() <= length {
+                histogram.resize(length + 1, 0);
+            }
+            histogram[length] += 1;
+        }
+        histogram
+    }
+}
+
+/// Параметры временного сглаживания траекторий точек по `track_id`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrajectorySmoothing {
+    /// Полуширина окна в кадрах: положение точки усредняется по `radius` кадрам до
+    /// и после неё. Больше - меньше дрожание, но сильнее сглаживаются быстрые движения
+    pub radius: usize,
+}
+
+impl Default for TrajectorySmoothing {
+    fn default() -> Self {
+        Self { radius: 2 }
+    }
+}
+
+/// Сглаживание дрожания треков во времени скользящим средним: положение точки трека
+/// заменяется средним положений того же `track_id` в соседних облаках окна. Облака
+/// подаются по одному в порядке кадров и возвращаются с задержкой на `radius` облаков,
+/// когда известны следующие за ними; последние облака отдаёт [`Self::finish`].
+/// В памяти держится не больше `2 * radius + 1` облаков. Точки без `track_id`
+/// не меняются
+pub struct TrajectorySmoother {
+    radius: usize,
+    /// Облака окна с положениями их точек по `track_id`
+    window: VecDeque<(PointCloud, HashMap<usize, [f64; 3]>)>,
+    /// Позиция в `window` следующего облака на выдачу
+    next: usize,
+}
+
+impl TrajectorySmoother {
+    pub fn new(params: TrajectorySmoothing) -> Self {
+        Self {
+            radius: params.radius,
+            window: VecDeque::with_capacity(2 * params.radius + 1),
+            next: 0,
+        }
+    }
+
+    /// Добавляет облако следующего кадра; возвращает сглаженное облако, для которого
+    /// набралось `radius` следующих облаков
+    pub fn push(&mut self, cloud: PointCloud) -> Option<PointCloud> {
+        let positions = cloud
+            .points
+            .iter()
+            .filter_map(|p| Some((p.track_id?, [p.x, p.y, p.z])))
+            .collect();
+        self.window.push_back((cloud, positions));
+        if self.window.len() <= self.next + self.radius {
+            return None;
+        }
+        let smoothed = self.smoothed(self.next);
+        self.next += 1;
+        while self.next > self.radius {
+            self.window.pop_front();
+            self.next -= 1;
+        }
+        Some(smoothed)
+    }
+
+    /// Сглаженные облака, оставшиеся в окне, по кадрам
+    pub fn finish(self) -> Vec<PointCloud> {
+        (self.next..self.window.len())
+            .map(|i| self.smoothed(i))
+            .collect()
+    }
+
+    fn smoothed(&self, index: usize) -> PointCloud {
+        let first = index.saturating_sub(self.radius);
+        let last = (index + self.radius).min(self.window.len() - 1);
+        let mut cloud = self.window[index].0.clone();
+        let mut moved = 0usize;
+        for point in &mut cloud.points {
+            let Some(track_id) = point.track_id else {
+                continue;
+            };
+            let mut sum = [0.0; 3];
+            let mut count = 0usize;
+            for (_, positions) in self.window.range(first..=last) {
+                if let Some(p) = positions.get(&track_id) {
+                    sum = [sum[0] + p[0], sum[1] + p[1], sum[2] + p[2]];
+                    count += 1;
+                }
+            }
+            if count > 1 {
+                point.x = sum[0] / count as f64;
+                point.y = sum[1] / count as f64;
+                point.z = sum[2] / count as f64;
+                moved += 1;
+            }
+        }
+        debug!(
+            "Кадр {}: сглажено {} точек по кадрам окна {}",
+            cloud.timestamp,
+            moved,
+            last - first + 1
+        );
+        cloud
+    }
+}
+
+/// Гистограмма длин треков последовательности облаков, см. [`TrackLifespans::histogram`].
+/// Короткие треки означают, что оптический поток рано теряет точки
+pub fn track_length_histogram(clouds: &[PointCloud]) -> Vec<usize> {
+    let mut lifespans = TrackLifespans::default();
+    for cloud in clouds {
+        lifespans.observe(cloud);
+    }
+    lifespans.histogram()
+}
+
+/// Пишет в лог число треков и среднюю, медианную и наибольшую длину по гистограмме
+pub fn log_track_length_summary(histogram: &[usize]) {
+    let tracks: usize = histogram.iter().sum();
+    if tracks == 0 {
+        info!("Длины треков: треков нет");
+        return;
+    }
+    let total: usize = histogram
+        .iter()
+        .enumerate()
+        .map(|(length, count)| length * count)
+        .sum();
+    let mut seen = 0;
+    let median = histogram
+        .iter()
+        .position(|&count| {
+            seen += count;
+            seen * 2 >= tracks
+        })
+        .unwrap_or(0);
+    info!(
+        "Длины треков: {} треков, в среднем {:.1} кадров, медиана {}, наибольшая {}",
+        tracks,
+        total as f64 / tracks as f64,
+        median,
+        histogram.len().saturating_sub(1)
+    );
+}
+
+/// Сохраняет гистограмму длин треков в CSV (длина в кадрах, число треков), без пустых длин
+pub fn save_track_length_histogram_csv<P: AsRef<Path>>(
+    histogram: &[usize],
+    path: P,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "length,tracks")?;
+    for (length, count) in histogram.iter().enumerate().filter(|(_, c)| **c > 0) {
+        writeln!(file, "{},{}", length, count)?;
+    }
+    file.flush()
+}
+
+/// Дескрипторы референсной камеры (камеры 0) для треков облака точек: по ним трек,
+/// потерянный оптическим потоком, можно найти снова. Строка `i` матрицы `descriptors`
+/// описывает трек `track_ids[i]`
+#[derive(Debug, Clone)]
+pub struct TrackDescriptors {
+    pub track_ids: Vec<usize>,
+    pub descriptors: Mat,
+}
+
+impl TrackDescriptors {
+    /// Дескрипторы для совпадений референсной камеры после `min_visible_match_set`.
+    /// Трек `i` - `i`-е совпадение, как при нумерации точек первого облака
+    pub fn from_matches(
+        reference_matches: &Vector<Vector<DMatch>>,
+        reference_descriptors: &Mat,
+    ) -> Result<Self, Error> {
+        let mut rows = Vector::<Mat>::new();
+        for matches in reference_matches {
+            rows.push(
+                reference_descriptors
+                    .row(matches.get(0)?.query_idx)?
+                    .try_clone()?,
+            );
+        }
+        let mut descriptors = Mat::default();
+        if !rows.is_empty() {
+            vconcat(&rows, &mut descriptors)?;
+        }
+        Ok(Self {
+            track_ids: (0..rows.len()).collect(),
+            descriptors,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.track_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.track_ids.is_empty()
+    }
+
+    /// Оставляет только треки, точки которых остались в облаке после фильтрации
+    pub fn retain_cloud(&mut self, cloud: &PointCloud) -> Result<(), Error> {
+        let present: HashSet<usize> = cloud.points.iter().filter_map(|p| p.track_id).collect();
+        let keep: Vec<usize> = (0..self.track_ids.len())
+            .filter(|&i| present.contains(&self.track_ids[i]))
+            .collect();
+        *self = self.select(&keep)?;
+        Ok(())
+    }
+
+    /// Треки с номерами строк `rows`
+    fn select(&self, rows: &[usize]) -> Result<Self, Error> {
+        let mut selected = Vector::<Mat>::new();
+        for &row in rows {
+            selected.push(self.descriptors.row(row as i32)?.try_clone()?);
+        }
+        let mut descriptors = Mat::default();
+        if !selected.is_empty() {
+            vconcat(&selected, &mut descriptors)?;
+        }
+        Ok(Self {
+            track_ids: rows.iter().map(|&row| self.track_ids[row]).collect(),
+            descriptors,
+        })
+    }
+
+    /// Сохраняет идентификаторы треков (`track_ids`) и дескрипторы (`descriptors`) через FileStorage
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let ids: Vec<i32> = self.track_ids.iter().map(|&id| id as i32).collect();
+        let ids = Mat::from_slice(&ids)?;
+        write_atomically(path.as_ref(), |tmp_path| -> Result<(), UtilsError> {
+            let mut fs =
+                FileStorage::new(path_to_str(tmp_path)?, FileStorage_Mode::WRITE as i32, "")?;
+            fs.write_mat("track_ids", &ids)?;
+            fs.write_mat("descriptors", &self.descriptors)?;
+            fs.release()?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut fs = FileStorage::new(
+            path_to_str(path.as_ref())?,
+            FileStorage_Mode::READ as i32,
+            "",
+        )?;
+        let mut ids = fs.get_node("track_ids")?.mat()?;
+        let descriptors = fs.get_node("descriptors")?.mat()?;
+        fs.release()?;
+        if ids.typ() != CV_32S {
+            let mut converted = Mat::default();
+            ids.convert_to_def(&mut converted, CV_32S)?;
+            ids = converted;
+        }
+        let track_ids: Vec<usize> = if ids.empty() {
+            Vec::new()
+        } else {
+            ids.data_typed::<i32>()?
+                .iter()
+                .map(|&id| id as usize)
+                .collect()
+        };
+        if track_ids.len() != descriptors.rows() as usize {
+            return Err(Error::new(
+                StsError,
+                format!(
+                    "Число треков ({}) не совпадает с числом дескрипторов ({})",
+                    track_ids.len(),
+                    descriptors.rows()
+                ),
+            ));
+        }
+        Ok(Self {
+            track_ids,
+            descriptors,
+        })
+    }
+}
+
+/// Ищет на кадре `new_frame` референсной камеры треки из `descriptors`, которых нет
+/// в облаке `cloud` (потеряны оптическим потоком или отброшены фильтрами).
+/// Признаки кадра ищутся SIFT с `sift_params` и сопоставляются с дескрипторами потерянных
+/// треков с тестом отношения `ratio`. Если одна ключевая точка подошла нескольким трекам,
+/// она достаётся ближайшему по дескриптору. Возвращает пары (трек, положение на кадре),
+/// упорядоченные по треку
+pub fn rematch_by_descriptor(
+    cloud: &PointCloud,
+    descriptors: &TrackDescriptors,
+    new_frame: &Mat,
+    sift_params: &SiftParams,
+    ratio: f32,
+) -> Result<Vec<(usize, Point2f)>, Error> {
+    let present: HashSet<usize> = cloud.points.iter().filter_map(|p| p.track_id).collect();
+    let lost_rows: Vec<usize> = (0..descriptors.len())
+        .filter(|&i| !present.contains(&descriptors.track_ids[i]))
+        .collect();
+    if lost_rows.is_empty() {
+        return Ok(Vec::new());
+    }
+    let lost = descriptors.select(&lost_rows)?;
+
+    let (keypoints, frame_descriptors) = sift_with_params(new_frame, sift_params)?;
+    if keypoints.len() < 2 {
+        return Ok(Vec::new());
+    }
+    let matches = bf_match_knn_with_kind(
+        &lost.descriptors,
+        &frame_descriptors,
+        2,
+        ratio,
+        DescriptorKind::of(&lost.descriptors),
+    )?;
+
+    // Ключевая точка кадра -> (трек, расстояние дескрипторов)
+    let mut best: HashMap<i32, (usize, f32)> = HashMap::new();
+    for neighbours in &matches {
+        let m = neighbours.get(0)?;
+        let track_id = lost.track_ids[m.query_idx as usize];
+        best.entry(m.train_idx)
+            .and_modify(|entry| {
+                if m.distance < entry.1 {
+                    *entry = (track_id, m.distance);
+                }
+            })
+            .or_insert((track_id, m.distance));
+    }
+
+    let mut recovered = Vec::with_capacity(best.len());
+    for (keypoint_i, (track_id, _)) in best {
+        recovered.push((track_id, keypoints.get(keypoint_i as usize)?.pt()));
+    }
+    recovered.sort_by_key(|&(track_id, _)| track_id);
+    info!(
+        "По дескрипторам найдено {} из {} потерянных треков",
+        recovered.len(),
+        lost.len()
+    );
+    Ok(recovered)
+}
+
+/// Переносит найденные [`rematch_by_descriptor`] треки в точки референсной камеры
+/// `points`, строка `i` которых относится к треку `track_ids[i]`: оптический поток
+/// продолжит их с нового положения. Треки, которых уже нет среди строк (отброшены
+/// как неподвижные), пропускаются. Возвращает число перенесённых треков
+pub fn apply_recovered_tracks(
+    points: &mut Vector<Point2f>,
+    track_ids: &[usize],
+    recovered: &[(usize, Point2f)],
+) -> Result<usize, Error> {
+    let rows: HashMap<usize, usize> = track_ids
+        .iter()
+        .enumerate()
+        .map(|(row, &track_id)| (track_id, row))
+        .collect();
+    let mut applied = 0;
+    for &(track_id, point) in recovered {
+        if let Some(&row) = rows.get(&track_id)
+            && row < points.len()
+        {
+            points.set(row, point)?;
+            applied += 1;
+        }
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reconstruction::Point3D;
+    use opencv::core::{CV_8UC1, Rect, Scalar, Size};
+
+    fn cloud_with_tracks(timestamp: usize, track_ids: &[usize]) -> PointCloud {
+        PointCloud {
+            points: track_ids
+                .iter()
+                .map(|&id| {
+                    let mut point = Point3D::new(id as f64, timestamp as f64, 1.0, 1.0);
+                    point.track_id = Some(id);
+                    point
+                })
+                .collect(),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn smoother_averages_track_over_window_and_flushes_tail() {
+        let mut smoother = TrajectorySmoother::new(TrajectorySmoothing { radius: 1 });
+        // Трек 7 дрожит по x между 0 и 3; точка без трека не сглаживается
+        let clouds: Vec<PointCloud> = [0.0, 3.0, 0.0, 3.0]
+            .into_iter()
+            .enumerate()
+            .map(|(frame, x)| {
+                let mut cloud = cloud_with_tracks(frame, &[7]);
+                cloud.points[0].x = x;
+                cloud.points.push(Point3D::new(x, 0.0, 1.0, 1.0));
+                cloud
+            })
+            .collect();
+
+        let mut smoothed: Vec<PointCloud> = Vec::new();
+        for (i, cloud) in clouds.into_iter().enumerate() {
+            let ready = smoother.push(cloud);
+            // Облако отдаётся, когда известен следующий за ним кадр
+            assert_eq!(ready.is_some(), i >= 1, "{i}");
+            smoothed.extend(ready);
+        }
+        smoothed.extend(smoother.finish());
+
+        let frames: Vec<usize> = smoothed.iter().map(|c| c.timestamp).collect();
+        assert_eq!(frames, vec![0, 1, 2, 3]);
+        let track_x: Vec<f64> = smoothed.iter().map(|c| c.points[0].x).collect();
+        assert_eq!(track_x, vec![1.5, 1.0, 2.0, 1.5]);
+        let untracked_x: Vec<f64> = smoothed.iter().map(|c| c.points[1].x).collect();
+        assert_eq!(untracked_x, vec![0.0, 3.0, 0.0, 3.0]);
+    }
+
+    #[test]
+    fn track_spanning_three_frames_falls_in_bucket_three() {
+        // Трек 1 живёт три кадра, трек 2 - два, трек 3 - один; точка без трека не считается
+        let mut untracked = cloud_with_tracks(2, &[1, 3]);
+        untracked.points.push(Point3D::new(0.0, 0.0, 1.0, 1.0));
+        let clouds = [
+            cloud_with_tracks(0, &[1, 2]),
+            cloud_with_tracks(1, &[1, 2]),
+            untracked,
+        ];
+        let histogram = track_length_histogram(&clouds);
+        assert_eq!(histogram, vec![0, 1, 1, 1]);
+    }
+
+    /// Сдвиг кадра после перекрытия относительно кадра, на котором сохранены дескрипторы
+    const SHIFT: (i32, i32) = (9, 4);
+
+    fn textured_frames() -> (Mat, Mat) {
+        opencv::core::set_rng_seed(11).unwrap();
+        let mut noise =
+            Mat::new_rows_cols_with_default(360, 360, CV_8UC1, Scalar::all(0.0)).unwrap();
+        opencv::core::randu(&mut noise, &Scalar::all(0.0), &Scalar::all(255.0)).unwrap();
+        let mut texture = Mat::default();
+        opencv::imgproc::gaussian_blur_def(&noise, &mut texture, Size::new(5, 5), 1.5).unwrap();
+        let first = texture
+            .roi(Rect::new(SHIFT.0, SHIFT.1, 320, 320))
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        let later = texture
+            .roi(Rect::new(0, 0, 320, 320))
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        (first, later)
+    }
+
+    #[test]
+    fn occluded_track_is_found_again_by_descriptor() {
+        let (first, later) = textured_frames();
+        let params = SiftParams::default();
+        let (keypoints, frame_descriptors) = sift_with_params(&first, &params).unwrap();
+        assert!(keypoints.len() >= 5);
+        let mut rows = Vector::<Mat>::new();
+        for i in 0..5 {
+            rows.push(frame_descriptors.row(i).unwrap().try_clone().unwrap());
+        }
+        let mut descriptors = Mat::default();
+        vconcat(&rows, &mut descriptors).unwrap();
+        let descriptors = TrackDescriptors {
+            track_ids: (10..15).collect(),
+            descriptors,
+        };
+
+        // Трек 12 перекрыт: его нет в облаке, остальные прослежены
+        let cloud = PointCloud {
+            points: [10, 11, 13, 14]
+                .map(|track_id| Point3D {
+                    track_id: Some(track_id),
+                    ..Point3D::new(0.0, 0.0, 1.0, 1.0)
+                })
+                .to_vec(),
+            timestamp: 5,
+        };
+        let recovered = rematch_by_descriptor(&cloud, &descriptors, &later, &params, 0.8).unwrap();
+        assert_eq!(recovered.len(), 1, "{:?}", recovered);
+        let (track_id, point) = recovered[0];
+        assert_eq!(track_id, 12);
+        let expected = keypoints.get(2).unwrap().pt();
+        assert!(
+            (point.x - expected.x - SHIFT.0 as f32).abs() < 1.0,
+            "{:?}",
+            point
+        );
+        assert!(
+            (point.y - expected.y - SHIFT.1 as f32).abs() < 1.0,
+            "{:?}",
+            point
+        );
+
+        // Найденное положение заменяет точку трека 12, остальные строки не меняются
+        let mut points: Vector<Point2f> = (0..5).map(|i| Point2f::new(i as f32, 0.0)).collect();
+        let applied = apply_recovered_tracks(&mut points, &[14, 12, 10], &recovered).unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(points.get(1).unwrap(), point);
+        assert_eq!(points.get(0).unwrap(), Point2f::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn descriptors_survive_save_and_load() {
+        let descriptors = TrackDescriptors {
+            track_ids: vec![3, 8],
+            descriptors: Mat::from_slice_2d(&[[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]]).unwrap(),
+        };
+        let path =
+            std::env::temp_dir().join(format!("track_descriptors_{}.yml", std::process::id()));
+        descriptors.save(&path).unwrap();
+        let loaded = TrackDescriptors::load(&path).unwrap();
+        assert_eq!(loaded.track_ids, vec![3, 8]);
+        assert_eq!(
+            opencv::core::norm2(
+                &loaded.descriptors,
+                &descriptors.descriptors,
+                opencv::core::NORM_L2,
+                &Mat::default()
+            )
+            .unwrap(),
+            0.0
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,57 +1,346 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
 use std::path::{Path, PathBuf};
 
-use log::debug;
+use log::{debug, info, warn};
 use opencv::{
     Error,
-    core::{Point2f, Vector, hconcat, vconcat},
+    core::{Point2f, Size, Vector, hconcat, vconcat},
     prelude::*,
-    videoio::{CAP_ANY, CAP_PROP_FRAME_COUNT, VideoCapture},
+    videoio::{
+        CAP_ANY, CAP_PROP_FPS, CAP_PROP_FRAME_COUNT, CAP_PROP_POS_FRAMES, CAP_PROP_POS_MSEC,
+        VideoCapture, VideoWriter,
+    },
 };
+use serde::{Deserialize, Serialize};
+
+use crate::board::CharucoBoardConfig;
+
+/// Ошибки вспомогательных функций работы с видео и файлами
+#[derive(Debug, thiserror::Error)]
+pub enum UtilsError {
+    #[error("Неправильный путь: {}", .0.display())]
+    InvalidPath(PathBuf),
+    #[error("Не удалось открыть видео {} (backend {backend})", .path.display())]
+    VideoOpen { path: PathBuf, backend: i32 },
+    #[error("Ошибка ввода-вывода: {0}")]
+    Io(#[from] io::Error),
+    #[error("Ошибка OpenCV: {0}")]
+    OpenCv(#[from] opencv::Error),
+    #[error(
+        "В {} уже извлечены кадры другого видео ({found}), а не {expected}. \
+         Укажите другую папку или перезапишите кадры принудительно",
+        .dir.display()
+    )]
+    ForeignFrames {
+        dir: PathBuf,
+        found: String,
+        expected: String,
+    },
+    #[error("Некорректный JSON в {}: {source}", .path.display())]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("Не удалось прочитать кадр {index} видео {}", .path.display())]
+    FrameRead { path: PathBuf, index: usize },
+    #[error("Операция отменена")]
+    Cancelled,
+    #[error(
+        "В {} несколько изображений одного кадра одной камеры: {}",
+        .dir.display(),
+        .files.join(", ")
+    )]
+    DuplicatePickedImages { dir: PathBuf, files: Vec<String> },
+}
+
+// Временное преобразование, пока вызывающий код не перешёл на UtilsError
+impl From<UtilsError> for opencv::Error {
+    fn from(e: UtilsError) -> Self {
+        match e {
+            UtilsError::OpenCv(e) => e,
+            other => opencv::Error::new(opencv::core::StsError, other.to_string()),
+        }
+    }
+}
+
+pub(crate) fn path_to_str(path: &Path) -> Result<&str, UtilsError> {
+    path.to_str()
+        .ok_or_else(|| UtilsError::InvalidPath(path.to_path_buf()))
+}
+
+/// Записывает файл атомарно: `write` пишет во временный файл в той же папке,
+/// который при успехе переименовывается в `target` (rename атомарен в пределах одной ФС).
+/// При ошибке временный файл удаляется, а прежнее содержимое `target` остаётся нетронутым.
+/// Расширение временного файла совпадает с целевым, поэтому FileStorage выбирает тот же формат
+pub fn write_atomically<T, E, F>(target: &Path, write: F) -> Result<T, E>
+where
+    F: FnOnce(&Path) -> Result<T, E>,
+    E: From<io::Error>,
+{
+    let file_name = target
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp_name = match target.extension() {
+        Some(ext) => format!(".{}.tmp.{}", file_name, ext.to_string_lossy()),
+        None => format!(".{}.tmp", file_name),
+    };
+    let tmp_path = target.with_file_name(tmp_name);
+
+    match write(&tmp_path) {
+        Ok(value) => {
+            if let Err(e) = std::fs::rename(&tmp_path, target) {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(e.into());
+            }
+            Ok(value)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Если `path` существует, копирует его рядом с меткой времени в имени
+/// (`calibration_params.yml` -> `calibration_params.1760000000.yml`), чтобы перезапись
+/// не уничтожила прежний результат. Возвращает путь копии
+pub fn backup_existing(path: &Path) -> Result<Option<PathBuf>, UtilsError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let backup_name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, timestamp, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, timestamp),
+    };
+    let backup = path.with_file_name(backup_name);
+    std::fs::copy(path, &backup)?;
+    info!("Прежний {} сохранён в {}", path.display(), backup.display());
+    Ok(Some(backup))
+}
+
+/// Текущие дата и время UTC в виде `20261015_143005`: для имён файлов и папок,
+/// которые сортируются по времени создания
+pub fn datetime_stamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, time) = (secs / 86_400, secs % 86_400);
+    // Дата по числу дней от 1970-01-01 (алгоритм civil_from_days Говарда Хиннанта)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}_{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+fn open_video(path: &Path) -> Result<VideoCapture, UtilsError> {
+    let cap = VideoCapture::from_file(path_to_str(path)?, CAP_ANY)?;
+    if !cap.is_opened()? {
+        return Err(UtilsError::VideoOpen {
+            path: path.to_path_buf(),
+            backend: CAP_ANY,
+        });
+    }
+    Ok(cap)
+}
+
+/// Раскладка камер в общем кадре: `rows` рядов по `cols` ячеек,
+/// камеры нумеруются слева направо, сверху вниз
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridLayout {
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl Default for GridLayout {
+    fn default() -> Self {
+        Self { rows: 2, cols: 2 }
+    }
+}
+
+impl GridLayout {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self { rows, cols }
+    }
+
+    /// Количество камер
+    pub fn cells(&self) -> usize {
+        self.rows * self.cols
+    }
+}
+
+/// Допустимое отношение сторон (ширина / высота) кадра одной камеры при подборе раскладки:
+/// от вертикального 9:16 до широкого 21:9 с запасом
+const MIN_CELL_ASPECT: f64 = 0.5;
+const MAX_CELL_ASPECT: f64 = 2.4;
+/// Отношение сторон обычной камеры (между 4:3 и 16:9), к которому тянется выбор
+/// между одинаково квадратными раскладками
+const TYPICAL_CELL_ASPECT: f64 = 1.5;
+
+/// Подбирает раскладку `num_cameras` камер в общем кадре размера `frame_size`.
+/// Из разложений числа камер на ряды и столбцы остаются те, где кадр одной камеры имеет
+/// правдоподобное отношение сторон; среди них выбирается самая квадратная раскладка,
+/// а при равенстве - с отношением сторон камеры ближе к обычному. Так 4 камеры в кадре
+/// 16:9 дают 2x2, а 6 камер - 2x3 или 3x2 в зависимости от формы кадра.
+/// Ошибка, если ни одна раскладка не подходит
+pub fn infer_grid_layout(num_cameras: usize, frame_size: Size) -> Result<GridLayout, Error> {
+    if num_cameras == 0 {
+        return Err(Error::new(
+            opencv::core::StsBadArg,
+            "Раскладка не подбирается: нет ни одной камеры".to_string(),
+        ));
+    }
+    if frame_size.width <= 0 || frame_size.height <= 0 {
+        return Err(Error::new(
+            opencv::core::StsBadArg,
+            format!("Некорректный размер кадра {:?}", frame_size),
+        ));
+    }
+    let cell_aspect = |layout: &GridLayout| {
+        (frame_size.width as f64 / layout.cols as f64)
+            / (frame_size.height as f64 / layout.rows as f64)
+    };
+    let layout = (1..=num_cameras)
+        .filter(|rows| num_cameras.is_multiple_of(*rows))
+        .map(|rows| GridLayout::new(rows, num_cameras / rows))
+        .filter(|layout| (MIN_CELL_ASPECT..=MAX_CELL_ASPECT).contains(&cell_aspect(layout)))
+        .min_by(|a, b| {
+            let squareness = |layout: &GridLayout| layout.rows.abs_diff(layout.cols);
+            let deviation =
+                |layout: &GridLayout| (cell_aspect(layout) / TYPICAL_CELL_ASPECT).ln().abs();
+            squareness(a)
+                .cmp(&squareness(b))
+                .then(deviation(a).total_cmp(&deviation(b)))
+        })
+        .ok_or_else(|| {
+            Error::new(
+                opencv::core::StsBadArg,
+                format!(
+                    "Для {} камер в кадре {}x{} нет раскладки с правдоподобным отношением \
+                     сторон кадра камеры",
+                    num_cameras, frame_size.width, frame_size.height
+                ),
+            )
+        })?;
+    debug!(
+        "Раскладка {} для {} камер в кадре {}x{} (кадр камеры {:.2}:1)",
+        layout,
+        num_cameras,
+        frame_size.width,
+        frame_size.height,
+        cell_aspect(&layout)
+    );
+    Ok(layout)
+}
+
+impl std::str::FromStr for GridLayout {
+    type Err = String;
+
+    /// Разбирает запись вида `2x2` (ряды x столбцы)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s
+            .split_once(['x', 'X'])
+            .and_then(|(rows, cols)| Some((rows.trim().parse().ok()?, cols.trim().parse().ok()?)));
+        match parsed {
+            Some((rows, cols)) if rows > 0 && cols > 0 => Ok(Self { rows, cols }),
+            _ => Err(format!(
+                "Некорректная раскладка {}, ожидается РЯДЫxСТОЛБЦЫ, например 2x2 или 1x2",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for GridLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.rows, self.cols)
+    }
+}
+
+/// Разрезает кадр на ячейки сетки `layout` (слева направо, сверху вниз).
+/// Остаток от деления размеров кадра на число ячеек отбрасывается
+pub fn split_image_into_grid(img: &Mat, layout: &GridLayout) -> Result<Vec<Mat>, Error> {
+    if layout.cells() == 0 {
+        return Err(Error::new(
+            opencv::core::StsBadArg,
+            "В раскладке нет ни одной ячейки".to_string(),
+        ));
+    }
+    let cell_width = img.cols() / layout.cols as i32;
+    let cell_height = img.rows() / layout.rows as i32;
+
+    let mut cells = Vec::with_capacity(layout.cells());
+    for row in 0..layout.rows as i32 {
+        for col in 0..layout.cols as i32 {
+            let roi = Mat::roi(
+                img,
+                opencv::core::Rect::new(
+                    col * cell_width,
+                    row * cell_height,
+                    cell_width,
+                    cell_height,
+                ),
+            )?;
+            let mut cropped = Mat::default();
+            roi.copy_to(&mut cropped)?;
+            cells.push(cropped);
+        }
+    }
+    Ok(cells)
+}
 
 pub fn split_image_into_quadrants(img: &Mat) -> Result<Vec<Mat>, Error> {
-    let roi_1 = Mat::roi(
-        img,
-        opencv::core::Rect::new(0, 0, img.cols() / 2, img.rows() / 2),
-    )?;
-    let roi_2 = Mat::roi(
-        img,
-        opencv::core::Rect::new(img.cols() / 2, 0, img.cols() / 2, img.rows() / 2),
-    )?;
-    let roi_3 = Mat::roi(
-        img,
-        opencv::core::Rect::new(0, img.rows() / 2, img.cols() / 2, img.rows() / 2),
-    )?;
-    let roi_4 = Mat::roi(
-        img,
-        opencv::core::Rect::new(
-            img.cols() / 2,
-            img.rows() / 2,
-            img.cols() / 2,
-            img.rows() / 2,
-        ),
-    )?;
-    let mut cropped_1 = Mat::default();
-    roi_1.copy_to(&mut cropped_1).unwrap();
-    let mut cropped_2 = Mat::default();
-    roi_2.copy_to(&mut cropped_2).unwrap();
-    let mut cropped_3 = Mat::default();
-    roi_3.copy_to(&mut cropped_3).unwrap();
-    let mut cropped_4 = Mat::default();
-    roi_4.copy_to(&mut cropped_4).unwrap();
-    Ok(vec![cropped_1, cropped_2, cropped_3, cropped_4])
+    split_image_into_grid(img, &GridLayout::default())
 }
 
 pub fn split_video_into_quadrants(
     path_to_video: &Path,
     path_to_save: &Path,
     file_name: &str,
-) -> Result<Vec<PathBuf>, Error> {
-    let mut cap = VideoCapture::from_file(
-        path_to_video
-            .to_str()
-            .ok_or_else(|| Error::new(-1, "Неправильный путь к видео"))?,
-        opencv::videoio::CAP_ANY,
-    )?;
+) -> Result<Vec<PathBuf>, UtilsError> {
+    split_video_into_grid(
+        path_to_video,
+        path_to_save,
+        file_name,
+        &GridLayout::default(),
+    )
+}
+
+/// Разрезает общее видео на видео камер `{file_name}_{i}.mp4` по раскладке `layout`
+pub fn split_video_into_grid(
+    path_to_video: &Path,
+    path_to_save: &Path,
+    file_name: &str,
+    layout: &GridLayout,
+) -> Result<Vec<PathBuf>, UtilsError> {
+    if !path_to_save.is_dir() {
+        return Err(UtilsError::InvalidPath(path_to_save.to_path_buf()));
+    }
+    let mut cap = open_video(path_to_video)?;
     let mut frame = opencv::core::Mat::default();
     let mut frame_index = 0;
 
@@ -60,20 +349,18 @@ pub fn split_video_into_quadrants(
     let width = cap.get(opencv::videoio::CAP_PROP_FRAME_WIDTH)? as i32;
     let height = cap.get(opencv::videoio::CAP_PROP_FRAME_HEIGHT)? as i32;
 
-    let quadrant_width = width / 2;
-    let quadrant_height = height / 2;
+    let cell_width = width / layout.cols as i32;
+    let cell_height = height / layout.rows as i32;
 
     let mut writers = Vec::new();
     let mut paths = Vec::new();
-    for i in 0..4 {
+    for i in 0..layout.cells() {
         let output_path = path_to_save.join(format!("{}_{}.mp4", file_name, i));
         let writer = opencv::videoio::VideoWriter::new(
-            output_path
-                .to_str()
-                .ok_or_else(|| Error::new(-1, "Неправильный путь для сохранения"))?,
+            path_to_str(&output_path)?,
             fourcc,
             fps,
-            opencv::core::Size::new(quadrant_width, quadrant_height),
+            opencv::core::Size::new(cell_width, cell_height),
             true,
         )?;
         writers.push(writer);
@@ -81,9 +368,9 @@ pub fn split_video_into_quadrants(
     }
 
     while cap.read(&mut frame)? {
-        let quadrants = split_image_into_quadrants(&frame)?;
-        for (i, quadrant) in quadrants.into_iter().enumerate() {
-            writers[i].write(&quadrant)?;
+        let cells = split_image_into_grid(&frame, layout)?;
+        for (writer, cell) in writers.iter_mut().zip(cells) {
+            writer.write(&cell)?;
         }
 
         frame_index += 1;
@@ -127,31 +414,479 @@ pub fn combine_quadrants(
     Ok(combined)
 }
 
-pub fn video_to_frames(path_to_video: &Path, parsed_image_folder_path: &Path) -> Result<(), Error> {
-    let mut cap = VideoCapture::from_file(
-        path_to_video
-            .to_str()
-            .ok_or_else(|| Error::new(-1, "Неправильный путь к видео"))?,
-        opencv::videoio::CAP_ANY,
-    )?;
+/// Собирает изображения в сетку по `cols` столбцов (слева направо, сверху вниз).
+/// Все плитки приводятся к размеру первой, недостающие ячейки последнего ряда заполняются чёрным
+pub fn combine_grid(images: &[Mat], cols: usize) -> Result<Mat, Error> {
+    if images.is_empty() || cols == 0 {
+        return Err(Error::new(
+            opencv::core::StsBadArg,
+            "Для сетки нужно хотя бы одно изображение и один столбец".to_string(),
+        ));
+    }
+    let tile_size = images[0].size()?;
+    let tile_type = images[0].typ();
+
+    let mut rows = Vector::<Mat>::default();
+    for chunk in images.chunks(cols) {
+        let mut tiles = Vector::<Mat>::default();
+        for image in chunk {
+            if image.size()? == tile_size {
+                tiles.push(image.clone());
+            } else {
+                let mut resized = Mat::default();
+                opencv::imgproc::resize(
+                    image,
+                    &mut resized,
+                    tile_size,
+                    0.0,
+                    0.0,
+                    opencv::imgproc::INTER_AREA,
+                )?;
+                tiles.push(resized);
+            }
+        }
+        for _ in chunk.len()..cols {
+            tiles.push(Mat::zeros_size(tile_size, tile_type)?.to_mat()?);
+        }
+        let mut row = Mat::default();
+        hconcat(&tiles, &mut row)?;
+        rows.push(row);
+    }
+
+    let mut combined = Mat::default();
+    vconcat(&rows, &mut combined)?;
+    Ok(combined)
+}
+
+/// Пишет отладочное видео: кадры всех камер с нарисованными отслеживаемыми точками,
+/// собранные в сетку. Размер кадра видео определяется по первому записанному кадру
+pub struct DebugVideoWriter {
+    path: PathBuf,
+    fps: f64,
+    cols: usize,
+    writer: Option<VideoWriter>,
+}
+
+impl DebugVideoWriter {
+    pub fn new(path: &Path, fps: f64, cols: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            fps,
+            cols,
+            writer: None,
+        }
+    }
+
+    pub fn write_frame(
+        &mut self,
+        frame_index: usize,
+        frames: &[Mat],
+        points: &[Vector<Point2f>],
+    ) -> Result<(), UtilsError> {
+        let mut annotated = Vec::with_capacity(frames.len());
+        for (camera_i, frame) in frames.iter().enumerate() {
+            let mut image = frame.clone();
+            if let Some(camera_points) = points.get(camera_i) {
+                for p in camera_points.iter() {
+                    opencv::imgproc::circle(
+                        &mut image,
+                        opencv::core::Point::new(p.x.round() as i32, p.y.round() as i32),
+                        3,
+                        opencv::core::Scalar::new(0.0, 255.0, 0.0, 255.0),
+                        -1,
+                        opencv::imgproc::LINE_8,
+                        0,
+                    )?;
+                }
+            }
+            annotate(&mut image, &[format!("Camera {}", camera_i + 1)])?;
+            annotated.push(image);
+        }
+
+        let mut grid = combine_grid(&annotated, self.cols)?;
+        annotate(&mut grid, &[format!("Frame {}", frame_index)])?;
+
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let writer = VideoWriter::new(
+                    path_to_str(&self.path)?,
+                    VideoWriter::fourcc('m', 'p', '4', 'v')?,
+                    self.fps,
+                    grid.size()?,
+                    true,
+                )?;
+                if !writer.is_opened()? {
+                    return Err(UtilsError::InvalidPath(self.path.clone()));
+                }
+                self.writer.insert(writer)
+            }
+        };
+        writer.write(&grid)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), UtilsError> {
+        if let Some(writer) = &mut self.writer {
+            writer.release()?;
+            info!("Отладочное видео сохранено в {}", self.path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Записывает кадры в видео `path` с кодеком `fourcc` (см. `VideoWriter::fourcc`).
+/// Размер видео определяется по первому кадру, кадры другого размера приводятся к нему.
+/// Ошибка из `frames` (например, [`UtilsError::Cancelled`]) прерывает запись, уже
+/// записанная часть видео остаётся. Возвращает число записанных кадров
+pub fn frames_to_video<I>(
+    frames: I,
+    path: &Path,
+    fps: f64,
+    fourcc: i32,
+) -> Result<usize, UtilsError>
+where
+    I: IntoIterator<Item = Result<Mat, UtilsError>>,
+{
+    let mut writer: Option<(VideoWriter, opencv::core::Size)> = None;
+    let mut written = 0;
+    let result = frames.into_iter().try_for_each(|frame| {
+        let mut frame = frame?;
+        let (writer, size) = match &mut writer {
+            Some(opened) => opened,
+            None => {
+                let size = frame.size()?;
+                let opened = VideoWriter::new(path_to_str(path)?, fourcc, fps, size, true)?;
+                if !opened.is_opened()? {
+                    return Err(UtilsError::InvalidPath(path.to_path_buf()));
+                }
+                writer.insert((opened, size))
+            }
+        };
+        if frame.size()? != *size {
+            let mut resized = Mat::default();
+            opencv::imgproc::resize(
+                &frame,
+                &mut resized,
+                *size,
+                0.0,
+                0.0,
+                opencv::imgproc::INTER_AREA,
+            )?;
+            frame = resized;
+        }
+        writer.write(&frame)?;
+        written += 1;
+        Ok(())
+    });
+    if let Some((writer, _)) = &mut writer {
+        writer.release()?;
+    }
+    result.map(|_| written)
+}
+
+/// Подписывает изображение строками текста в левом верхнем углу на тёмной подложке.
+/// Шрифты Hershey не содержат кириллицы, поэтому текст должен быть латиницей
+pub fn annotate(image: &mut Mat, lines: &[String]) -> Result<(), Error> {
+    let style = TextStyle::for_image(image);
+    draw_text_block(image, lines, &style, style.margin)
+}
+
+/// Как `annotate`, но прижимает подпись к левому нижнему углу
+pub fn annotate_bottom(image: &mut Mat, lines: &[String]) -> Result<(), Error> {
+    let style = TextStyle::for_image(image);
+    let mut height = 0;
+    for line in lines {
+        height += style.line_height(line)?;
+    }
+    let top = image.rows() - height;
+    draw_text_block(image, lines, &style, top)
+}
+
+struct TextStyle {
+    font: i32,
+    scale: f64,
+    thickness: i32,
+    margin: i32,
+}
+
+impl TextStyle {
+    fn for_image(image: &Mat) -> Self {
+        let scale = (image.rows() as f64 / 720.0).max(0.5);
+        Self {
+            font: opencv::imgproc::FONT_HERSHEY_SIMPLEX,
+            scale,
+            thickness: (scale * 2.0).round() as i32,
+            margin: (10.0 * scale) as i32,
+        }
+    }
+
+    fn text_size(&self, line: &str) -> Result<(opencv::core::Size, i32), Error> {
+        let mut baseline = 0;
+        let size = opencv::imgproc::get_text_size(
+            line,
+            self.font,
+            self.scale,
+            self.thickness,
+            &mut baseline,
+        )?;
+        Ok((size, baseline))
+    }
+
+    fn line_height(&self, line: &str) -> Result<i32, Error> {
+        let (size, baseline) = self.text_size(line)?;
+        Ok(size.height + self.margin / 4 + baseline + self.margin)
+    }
+}
+
+fn draw_text_block(
+    image: &mut Mat,
+    lines: &[String],
+    style: &TextStyle,
+    top: i32,
+) -> Result<(), Error> {
+    let margin = style.margin;
+    let mut y = top;
+    for line in lines {
+        let (size, baseline) = style.text_size(line)?;
+        opencv::imgproc::rectangle(
+            image,
+            opencv::core::Rect::new(
+                margin / 2,
+                y,
+                size.width + margin,
+                size.height + baseline + margin / 2,
+            ),
+            opencv::core::Scalar::new(0.0, 0.0, 0.0, 255.0),
+            -1,
+            opencv::imgproc::LINE_8,
+            0,
+        )?;
+        y += size.height + margin / 4;
+        opencv::imgproc::put_text(
+            image,
+            line,
+            opencv::core::Point::new(margin, y),
+            style.font,
+            style.scale,
+            opencv::core::Scalar::new(255.0, 255.0, 255.0, 255.0),
+            style.thickness,
+            opencv::imgproc::LINE_AA,
+            false,
+        )?;
+        y += baseline + margin;
+    }
+    Ok(())
+}
+
+/// Извлекает кадры видео в папку. `progress` получает число извлечённых кадров и
+/// ожидаемое по метаданным видео; `false` прерывает извлечение с [`UtilsError::Cancelled`].
+/// Каждый кадр пишется атомарно, так что прерывание не оставляет недописанных файлов
+pub fn video_to_frames(
+    path_to_video: &Path,
+    parsed_image_folder_path: &Path,
+    progress: &mut dyn FnMut(usize, usize) -> bool,
+) -> Result<usize, UtilsError> {
+    if !parsed_image_folder_path.is_dir() {
+        return Err(UtilsError::InvalidPath(
+            parsed_image_folder_path.to_path_buf(),
+        ));
+    }
+    let mut cap = open_video(path_to_video)?;
+    let expected = cap.get(CAP_PROP_FRAME_COUNT)? as usize;
     let mut frame = opencv::core::Mat::default();
     let mut frame_index = 0;
 
     while cap.read(&mut frame)? {
-        let filename = format!(
-            "{}/{}.png",
-            parsed_image_folder_path
-                .to_str()
-                .ok_or_else(|| Error::new(-1, "Неправильный путь к папке для изображений"))?,
-            frame_index
-        );
-        opencv::imgcodecs::imwrite(&filename, &frame, &opencv::core::Vector::new())?;
+        if !progress(frame_index, expected) {
+            info!("Извлечение кадров прервано на кадре {}", frame_index);
+            return Err(UtilsError::Cancelled);
+        }
+        let filename = parsed_image_folder_path.join(format!("{}.png", frame_index));
+        write_atomically(&filename, |tmp| -> Result<(), UtilsError> {
+            opencv::imgcodecs::imwrite(path_to_str(tmp)?, &frame, &opencv::core::Vector::new())?;
+            Ok(())
+        })?;
         frame_index += 1;
         debug!("Обработано {}", frame_index);
+        if frame_index % 100 == 0 {
+            info!("Извлечено {} из ~{} кадров", frame_index, expected);
+        }
     }
-    Ok(())
+    progress(frame_index, expected);
+    info!("Извлечено кадров: {}", frame_index);
+    Ok(frame_index)
 }
 
+/// Имя файла, в котором папка извлечённых кадров помнит, из какого видео они получены
+pub const FRAMES_MANIFEST_FILE: &str = "frames_manifest.json";
+
+/// Описание видео, из которого извлечены кадры папки
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FramesManifest {
+    /// Имя файла видео
+    pub video: String,
+    /// Размер файла видео в байтах: отличает перезаписанное видео с тем же именем
+    pub video_size: u64,
+    /// Количество извлечённых кадров, `None` - извлечение не завершено
+    pub frame_count: Option<usize>,
+}
+
+impl FramesManifest {
+    pub fn for_video(video: &Path) -> Result<Self, UtilsError> {
+        Ok(Self {
+            video: video
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            video_size: std::fs::metadata(video)?.len(),
+            frame_count: None,
+        })
+    }
+
+    pub fn load(dir: &Path) -> Result<Option<Self>, UtilsError> {
+        let path = dir.join(FRAMES_MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|source| UtilsError::Json { path, source })
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), UtilsError> {
+        let path = dir.join(FRAMES_MANIFEST_FILE);
+        let text = serde_json::to_string_pretty(self).map_err(|source| UtilsError::Json {
+            path: path.clone(),
+            source,
+        })?;
+        write_atomically(&path, |tmp| std::fs::write(tmp, text))?;
+        Ok(())
+    }
+
+    pub fn same_video(&self, other: &Self) -> bool {
+        self.video == other.video && self.video_size == other.video_size
+    }
+
+    fn describe(&self) -> String {
+        format!("{}, {} байт", self.video, self.video_size)
+    }
+}
+
+/// Насколько число кадров в папке может отличаться от `CAP_PROP_FRAME_COUNT`, чтобы кадры
+/// без манифеста считались кадрами этого видео: свойство оценивается по длительности
+/// и частоте кадров и у многих контейнеров расходится с декодированными кадрами
+const FRAME_COUNT_TOLERANCE: usize = 2;
+/// То же, в долях от числа кадров видео, для длинных видео
+const FRAME_COUNT_TOLERANCE_RATIO: f64 = 0.01;
+
+/// Похоже ли `found` извлечённых кадров на приблизительное число кадров видео `reported`
+fn frame_count_matches(found: usize, reported: usize) -> bool {
+    let tolerance =
+        FRAME_COUNT_TOLERANCE.max((reported as f64 * FRAME_COUNT_TOLERANCE_RATIO).round() as usize);
+    reported > 0 && found.abs_diff(reported) <= tolerance
+}
+
+/// Извлекает кадры видео, только если в папке ещё нет полного набора кадров этого видео.
+/// Кадры другого видео в папке считаются ошибкой, чтобы не смешать их с новыми;
+/// `force` удаляет прежние кадры и извлекает заново. `progress` передаётся в [`video_to_frames`].
+/// Возвращает `true`, если кадры извлекались
+pub fn extract_frames_if_needed(
+    path_to_video: &Path,
+    parsed_image_folder_path: &Path,
+    force: bool,
+    progress: &mut dyn FnMut(usize, usize) -> bool,
+) -> Result<bool, UtilsError> {
+    let mut manifest = FramesManifest::for_video(path_to_video)?;
+    let existing = list_frames(parsed_image_folder_path, "", "png")?;
+
+    if !force {
+        match FramesManifest::load(parsed_image_folder_path)? {
+            Some(previous) if !previous.same_video(&manifest) => {
+                return Err(UtilsError::ForeignFrames {
+                    dir: parsed_image_folder_path.to_path_buf(),
+                    found: previous.describe(),
+                    expected: manifest.describe(),
+                });
+            }
+            Some(previous) if previous.frame_count == Some(existing.frames.len()) => {
+                info!(
+                    "Кадры {} уже извлечены ({} шт.), пропускаем извлечение",
+                    manifest.video,
+                    existing.frames.len()
+                );
+                return Ok(false);
+            }
+            Some(_) => info!("Извлечение кадров не было завершено, извлекаем заново"),
+            None if !existing.frames.is_empty() => {
+                // Кадры без манифеста: принимаем их, только если они идут без пропусков и их
+                // примерно столько же, сколько в видео (точное число знает только декодер)
+                let reported = get_video_frame_count(&path_to_video.to_path_buf())?;
+                if existing.gaps.is_empty() && frame_count_matches(existing.frames.len(), reported)
+                {
+                    manifest.frame_count = Some(existing.frames.len());
+                    manifest.save(parsed_image_folder_path)?;
+                    info!(
+                        "Найдено {} ранее извлечённых кадров, пропускаем извлечение",
+                        existing.frames.len()
+                    );
+                    return Ok(false);
+                }
+                return Err(UtilsError::ForeignFrames {
+                    dir: parsed_image_folder_path.to_path_buf(),
+                    found: format!("{} кадров без манифеста", existing.frames.len()),
+                    expected: manifest.describe(),
+                });
+            }
+            None => {}
+        }
+    } else {
+        for entry in &existing.frames {
+            std::fs::remove_file(&entry.path)?;
+        }
+        info!("Удалено {} прежних кадров", existing.frames.len());
+    }
+
+    // Манифест без количества кадров пишется заранее: прерванное извлечение
+    // того же видео (в том числе отменённое) при следующем запуске просто повторится
+    manifest.save(parsed_image_folder_path)?;
+    let frame_count = video_to_frames(path_to_video, parsed_image_folder_path, progress)?;
+    manifest.frame_count = Some(frame_count);
+    manifest.save(parsed_image_folder_path)?;
+    Ok(true)
+}
+
+/// Читает маску камеры из PNG в оттенках серого: ненулевые пиксели - рабочая область,
+/// нулевые закрывают, например, крепления камер. Размер маски должен совпадать с `size`.
+/// Маску кадров с исправленной дисторсией исправляет [`crate::reconstruction::undistort_mask`]
+pub fn load_mask(path: &Path, size: opencv::core::Size) -> Result<Mat, Error> {
+    let mask = opencv::imgcodecs::imread(path_to_str(path)?, opencv::imgcodecs::IMREAD_GRAYSCALE)?;
+    if mask.empty() {
+        return Err(Error::new(
+            opencv::core::StsError,
+            format!("Не удалось прочитать маску {}", path.display()),
+        ));
+    }
+    if mask.size()? != size {
+        return Err(Error::new(
+            opencv::core::StsError,
+            format!(
+                "Размер маски {} {}x{} не совпадает с кадром {}x{}",
+                path.display(),
+                mask.cols(),
+                mask.rows(),
+                size.width,
+                size.height
+            ),
+        ));
+    }
+    Ok(mask)
+}
+
+/// Точки в виде матрицы Nx2 CV_64F (строка на точку) - формат, в котором точки кадров
+/// проходят через всю реконструкцию. Перед `undistort_points` её переводит в Nx1 CV_64FC2
+/// [`crate::reconstruction::undistort_points_pooled`]
 pub fn vector_point2f_to_mat(points: &Vector<Point2f>) -> Result<Mat, Error> {
     let num_points = points.len() as i32;
     let mut mat = Mat::zeros(num_points, 2, opencv::core::CV_64F)?.to_mat()?;
@@ -166,29 +901,979 @@ pub fn vector_point2f_to_mat(points: &Vector<Point2f>) -> Result<Mat, Error> {
 pub fn open_video_captures(
     caps: &mut Vec<VideoCapture>,
     video_files: &Vec<Option<PathBuf>>,
-) -> Result<(), Error> {
+) -> Result<(), UtilsError> {
     Ok(for video_file in video_files.iter() {
-        let cap = VideoCapture::from_file(
-            video_file
-                .as_ref()
-                .ok_or_else(|| Error::new(-1, "Неправильный путь к видео"))?
-                .to_str()
-                .ok_or_else(|| Error::new(-1, "Путь к видео не является валидной UTF-8 строкой"))?,
-            opencv::videoio::CAP_ANY,
-        )?;
-        caps.push(cap);
+        let video_file = video_file
+            .as_ref()
+            .ok_or_else(|| UtilsError::InvalidPath(PathBuf::new()))?;
+        caps.push(open_video(video_file)?);
     })
 }
 
-pub fn read_frames(caps: &mut Vec<VideoCapture>, frames: &mut Vec<Mat>) -> Result<(), Error> {
-    for (i, cap) in caps.iter_mut().enumerate() {
-        let mut frame = &mut frames[i];
-        cap.read(&mut frame)?;
+/// Источник синхронных кадров нескольких камер
+pub trait FrameSource {
+    /// Количество камер
+    fn cameras(&self) -> usize;
+
+    /// Читает следующий кадр каждой камеры в `frames` (по элементу на камеру).
+    /// В конце видео кадры становятся пустыми
+    fn read_frames(&mut self, frames: &mut Vec<Mat>) -> Result<(), Error>;
+}
+
+/// Отдельное видео на каждую камеру
+impl FrameSource for Vec<VideoCapture> {
+    fn cameras(&self) -> usize {
+        self.len()
+    }
+
+    fn read_frames(&mut self, frames: &mut Vec<Mat>) -> Result<(), Error> {
+        frames.resize_with(self.len(), Mat::default);
+        for (cap, frame) in self.iter_mut().zip(frames.iter_mut()) {
+            cap.read(frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// Одно общее видео, в кадре которого камеры расположены сеткой `layout`. Кадры камер
+/// вырезаются из каждого прочитанного кадра, поэтому видео не нужно заранее делить
+/// на отдельные файлы (см. [`split_video_into_quadrants`]), занимающие столько же места
+pub struct CombinedVideoSource {
+    capture: VideoCapture,
+    layout: GridLayout,
+    frame: Mat,
+}
+
+impl CombinedVideoSource {
+    pub fn open(path: &Path, layout: GridLayout) -> Result<Self, UtilsError> {
+        Ok(Self {
+            capture: open_video(path)?,
+            layout,
+            frame: Mat::default(),
+        })
     }
-    Ok(())
 }
 
-pub fn get_video_frame_count(video_file: &PathBuf) -> Result<usize, Error> {
-    let cap = VideoCapture::from_file(&video_file.to_string_lossy(), CAP_ANY)?;
+impl FrameSource for CombinedVideoSource {
+    fn cameras(&self) -> usize {
+        self.layout.cells()
+    }
+
+    fn read_frames(&mut self, frames: &mut Vec<Mat>) -> Result<(), Error> {
+        if self.capture.read(&mut self.frame)? && !self.frame.empty() {
+            *frames = split_image_into_grid(&self.frame, &self.layout)?;
+        } else {
+            frames.clear();
+            frames.resize_with(self.layout.cells(), Mat::default);
+        }
+        Ok(())
+    }
+}
+
+/// Читает следующий кадр всех камер из отдельных видео или из общего видео
+pub fn read_frames<S: FrameSource + ?Sized>(
+    source: &mut S,
+    frames: &mut Vec<Mat>,
+) -> Result<(), Error> {
+    source.read_frames(frames)
+}
+
+pub fn get_video_fps(video_file: &Path) -> Result<f64, UtilsError> {
+    let cap = open_video(video_file)?;
+    Ok(cap.get(CAP_PROP_FPS)?)
+}
+
+pub fn get_video_frame_count(video_file: &PathBuf) -> Result<usize, UtilsError> {
+    let cap = open_video(video_file)?;
     Ok(cap.get(CAP_PROP_FRAME_COUNT)? as usize)
 }
+
+/// Размер кадра видео по его свойствам, без декодирования кадров
+pub fn get_video_frame_size(video_file: &Path) -> Result<Size, UtilsError> {
+    let cap = open_video(video_file)?;
+    Ok(Size::new(
+        cap.get(opencv::videoio::CAP_PROP_FRAME_WIDTH)? as i32,
+        cap.get(opencv::videoio::CAP_PROP_FRAME_HEIGHT)? as i32,
+    ))
+}
+
+/// Сколько кадров вперёд дешевле декодировать подряд, чем перематывать
+const MAX_SEQUENTIAL_DECODE: usize = 60;
+/// Первый отступ перемотки перед нужным кадром, если видео перематывается только
+/// к ключевым кадрам и попадает дальше нужного
+const SEEK_BACKOFF: usize = 30;
+
+/// Чтение кадров видео по номеру, без извлечения всех кадров на диск. Последние
+/// `cache_size` декодированных кадров хранятся в памяти, так что шаг назад мгновенный.
+/// Близкие кадры впереди декодируются подряд, к далёким видео перематывается
+pub struct SeekableVideo {
+    path: PathBuf,
+    capture: VideoCapture,
+    frame_count: usize,
+    /// Номер кадра, который вернёт следующее чтение
+    next: usize,
+    cache: VecDeque<(usize, Mat)>,
+    cache_size: usize,
+}
+
+impl SeekableVideo {
+    pub fn open(path: &Path, cache_size: usize) -> Result<Self, UtilsError> {
+        let capture = open_video(path)?;
+        let frame_count = capture.get(CAP_PROP_FRAME_COUNT)? as usize;
+        Ok(Self {
+            path: path.to_path_buf(),
+            capture,
+            frame_count,
+            next: 0,
+            cache: VecDeque::with_capacity(cache_size),
+            cache_size: cache_size.max(1),
+        })
+    }
+
+    /// Число кадров по метаданным видео (у некоторых контейнеров приблизительное)
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    pub fn frame(&mut self, index: usize) -> Result<Mat, UtilsError> {
+        if let Some((_, frame)) = self.cache.iter().find(|(i, _)| *i == index) {
+            return Ok(frame.clone());
+        }
+        if index < self.next || index - self.next > MAX_SEQUENTIAL_DECODE {
+            self.seek(index)?;
+        }
+        let read_error = || UtilsError::FrameRead {
+            path: self.path.clone(),
+            index,
+        };
+        while self.next < index {
+            if !self.capture.grab()? {
+                return Err(read_error());
+            }
+            self.next += 1;
+        }
+        if self.next > index {
+            // Проверочный кадр перемотки и был нужным
+            if let Some((_, frame)) = self.cache.iter().find(|(i, _)| *i == index) {
+                return Ok(frame.clone());
+            }
+        }
+        let mut frame = Mat::default();
+        if !self.capture.read(&mut frame)? || frame.empty() {
+            return Err(read_error());
+        }
+        self.next += 1;
+        self.remember(index, frame.clone());
+        Ok(frame)
+    }
+
+    fn remember(&mut self, index: usize, frame: Mat) {
+        if self.cache.len() >= self.cache_size {
+            self.cache.pop_front();
+        }
+        self.cache.push_back((index, frame));
+    }
+
+    /// Перематывает так, чтобы следующее чтение было не дальше кадра `index`; оставшиеся
+    /// кадры `frame` декодирует подряд. Некоторые кодеки (например, H.264) перематываются
+    /// только к ключевым кадрам, а `CAP_PROP_POS_FRAMES` после перемотки возвращает
+    /// запрошенный номер, а не фактический. Поэтому после перемотки декодируется один кадр,
+    /// и его номер вычисляется по метке времени; при перелёте перемотка повторяется
+    /// с удваивающимся отступом назад. Если метки времени нет или перемотка так и не
+    /// попала до `index`, кадры читаются подряд: с текущей позиции или с начала видео
+    fn seek(&mut self, index: usize) -> Result<(), UtilsError> {
+        let fps = self.capture.get(CAP_PROP_FPS)?;
+        let mut moved = false;
+        let mut backoff = 0;
+        loop {
+            let target = index.saturating_sub(backoff);
+            if fps <= 0.0 || target == 0 {
+                break;
+            }
+            moved = true;
+            self.capture.set(CAP_PROP_POS_FRAMES, target as f64)?;
+            let mut probe = Mat::default();
+            if !self.capture.read(&mut probe)? || probe.empty() {
+                break;
+            }
+            let timestamp_ms = self.capture.get(CAP_PROP_POS_MSEC)?;
+            if timestamp_ms < 0.0 {
+                break;
+            }
+            let decoded = (timestamp_ms * fps / 1000.0).round() as usize;
+            if decoded <= index {
+                self.next = decoded + 1;
+                self.remember(decoded, probe);
+                return Ok(());
+            }
+            debug!(
+                "Перемотка к кадру {} попала на {}, отступаю на {} кадров",
+                target, decoded, backoff
+            );
+            backoff = if backoff == 0 {
+                SEEK_BACKOFF
+            } else {
+                backoff * 2
+            };
+        }
+
+        if !moved && index >= self.next {
+            debug!(
+                "Видео {} не перематывается, читаю подряд до кадра {}",
+                self.path.display(),
+                index
+            );
+            return Ok(());
+        }
+        // Позиция после неудачной перемотки неизвестна: читаем видео с начала заново
+        debug!(
+            "Видео {} не перематывается к кадру {}, открываю заново",
+            self.path.display(),
+            index
+        );
+        self.capture = open_video(&self.path)?;
+        self.next = 0;
+        Ok(())
+    }
+}
+
+/// Кадр, найденный в директории, с числовым индексом из имени файла
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameEntry {
+    pub index: usize,
+    pub path: PathBuf,
+}
+
+/// Результат перечисления кадров в директории
+#[derive(Debug, Default, Clone)]
+pub struct FrameListing {
+    /// Кадры, отсортированные по числовому индексу (а не лексикографически)
+    pub frames: Vec<FrameEntry>,
+    /// Индексы, пропущенные между первым и последним найденным кадром
+    pub gaps: Vec<usize>,
+    /// Индексы, встретившиеся в нескольких файлах (например 7.png и 07.png).
+    /// В `frames` остаётся только первый из них
+    pub duplicates: Vec<usize>,
+}
+
+impl FrameListing {
+    pub fn is_contiguous(&self) -> bool {
+        self.gaps.is_empty() && self.duplicates.is_empty()
+    }
+}
+
+/// Перечисляет файлы вида `{prefix}{index}.{ext}` и сортирует их по числовому индексу,
+/// сообщая о пропусках и дубликатах
+pub fn list_frames(dir: &Path, prefix: &str, ext: &str) -> Result<FrameListing, UtilsError> {
+    if !dir.is_dir() {
+        return Err(UtilsError::InvalidPath(dir.to_path_buf()));
+    }
+
+    let suffix = format!(".{}", ext);
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(index) = file_name
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(&suffix))
+            .and_then(|number| number.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        entries.push(FrameEntry { index, path });
+    }
+    entries.sort_by(|a, b| a.index.cmp(&b.index).then_with(|| a.path.cmp(&b.path)));
+
+    let mut listing = FrameListing::default();
+    for entry in entries {
+        match listing.frames.last() {
+            Some(last) if last.index == entry.index => {
+                if listing.duplicates.last() != Some(&entry.index) {
+                    listing.duplicates.push(entry.index);
+                }
+            }
+            Some(last) => {
+                listing.gaps.extend(last.index + 1..entry.index);
+                listing.frames.push(entry);
+            }
+            None => listing.frames.push(entry),
+        }
+    }
+
+    if !listing.gaps.is_empty() {
+        debug!("В {} пропущены кадры: {:?}", dir.display(), listing.gaps);
+    }
+    if !listing.duplicates.is_empty() {
+        debug!(
+            "В {} повторяются кадры: {:?}",
+            dir.display(),
+            listing.duplicates
+        );
+    }
+    Ok(listing)
+}
+
+/// Разбирает калибровочные изображения вида `img_{cam}_{frame}.png` (между камерой и кадром
+/// могут быть и другие части, например дата) в `dir` и в папках сессий внутри неё
+/// (см. [`PickedFrame::session`]). Возвращает отображение номер камеры ->
+/// (номер кадра -> путь), отсортированное по номерам. Если кадр есть в нескольких сессиях,
+/// берётся сессия с наибольшим именем, то есть самая поздняя. Если в одной папке
+/// совпадают номера кадров с разными дополнительными частями имени, кадр `f` части `i`
+/// (по порядку имён) получает номер `i * CLIP_FRAME_STRIDE + f`. Имена, которые не удалось
+/// разобрать, ищутся в манифесте (см. [`PICKED_MANIFEST_FILE`])
+pub fn list_picked_calibration_images(
+    dir: &Path,
+) -> Result<BTreeMap<usize, BTreeMap<usize, PathBuf>>, UtilsError> {
+    if !dir.is_dir() {
+        return Err(UtilsError::InvalidPath(dir.to_path_buf()));
+    }
+
+    let manifest_keys: BTreeMap<PathBuf, (usize, usize)> = PickedManifest::load(dir)?
+        .map(|manifest| {
+            manifest
+                .frames
+                .iter()
+                .flat_map(|entry| {
+                    entry
+                        .files
+                        .iter()
+                        .map(move |(&cam, name)| (dir.join(name), (cam, entry.frame)))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut cameras: BTreeMap<usize, BTreeMap<usize, PathBuf>> = BTreeMap::new();
+    collect_picked_images(dir, &manifest_keys, &mut cameras)?;
+    let mut sessions: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    sessions.sort();
+    for session in sessions {
+        collect_picked_images(&session, &manifest_keys, &mut cameras)?;
+    }
+    Ok(cameras)
+}
+
+fn collect_picked_images(
+    dir: &Path,
+    manifest_keys: &BTreeMap<PathBuf, (usize, usize)>,
+    cameras: &mut BTreeMap<usize, BTreeMap<usize, PathBuf>>,
+) -> Result<(), UtilsError> {
+    // (камера, кадр) -> путь в этой папке
+    let mut found: BTreeMap<(usize, usize), PathBuf> = BTreeMap::new();
+    let mut duplicates = Vec::new();
+    let mut add = |cam: usize, frame: usize, path: PathBuf| {
+        if let Some(previous) = found.get(&(cam, frame)) {
+            duplicates.push(format!(
+                "{} и {}",
+                file_name_of(previous),
+                file_name_of(&path)
+            ));
+        } else {
+            found.insert((cam, frame), path);
+        }
+    };
+    let mut parsed = Vec::new();
+    let mut unparsed = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !file_name.starts_with("img_") || !file_name.ends_with(".png") {
+            continue;
+        }
+        match parse_picked_image_name(file_name) {
+            Some(name) => parsed.push((name, path)),
+            None => match manifest_keys.get(&path) {
+                Some(&(cam, frame)) => add(cam, frame, path),
+                None => unparsed.push(file_name.to_string()),
+            },
+        }
+    }
+    if !unparsed.is_empty() {
+        unparsed.sort();
+        warn!(
+            "В {} не разобраны имена {} изображений (ожидается img_{{cam}}_..._{{frame}}.png): {:?}",
+            dir.display(),
+            unparsed.len(),
+            unparsed
+        );
+    }
+
+    // Номер кадра -> дополнительные части имён, с которыми он встречается
+    let mut tags_by_frame: BTreeMap<usize, BTreeSet<&str>> = BTreeMap::new();
+    for (name, _) in &parsed {
+        tags_by_frame
+            .entry(name.frame)
+            .or_default()
+            .insert(name.tag.as_str());
+    }
+    let collides = tags_by_frame.values().any(|tags| tags.len() > 1);
+    let tags: Vec<String> = parsed
+        .iter()
+        .map(|(name, _)| name.tag.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if collides {
+        warn!(
+            "В {} номера кадров повторяются в именах с разными частями {:?}: кадр f части i получает номер i * {} + f",
+            dir.display(),
+            tags,
+            CLIP_FRAME_STRIDE
+        );
+    }
+    parsed.sort_by(|a, b| a.1.cmp(&b.1));
+    for (name, path) in parsed {
+        let scene = if collides {
+            let rank = tags.binary_search(&name.tag).unwrap_or_default();
+            rank * CLIP_FRAME_STRIDE + name.frame
+        } else {
+            name.frame
+        };
+        add(name.cam, scene, path);
+    }
+    if !duplicates.is_empty() {
+        return Err(UtilsError::DuplicatePickedImages {
+            dir: dir.to_path_buf(),
+            files: duplicates,
+        });
+    }
+    for ((cam, frame), path) in found {
+        cameras.entry(cam).or_default().insert(frame, path);
+    }
+    Ok(())
+}
+
+fn file_name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Разобранное имя калибровочного изображения
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PickedImageName {
+    cam: usize,
+    /// Части между камерой и кадром (например, дата и время); пусто у `img_{cam}_{frame}.png`
+    tag: String,
+    frame: usize,
+}
+
+/// Номер камеры и кадра из имени `img_{cam}_{frame}.png`. Между ними допускаются
+/// дополнительные части через `_` (например, дата и время: `img_2_2025-06-03_113751_17.png`):
+/// камера - число сразу после `img_`, кадр - последнее число перед `.png`
+fn parse_picked_image_name(file_name: &str) -> Option<PickedImageName> {
+    let rest = file_name.strip_prefix("img_")?.strip_suffix(".png")?;
+    let (cam, rest) = rest.split_once('_')?;
+    let (tag, frame) = rest.rsplit_once('_').unwrap_or(("", rest));
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_number(cam) || !is_number(frame) {
+        return None;
+    }
+    Some(PickedImageName {
+        cam: cam.parse().ok()?,
+        tag: tag.to_string(),
+        frame: frame.parse().ok()?,
+    })
+}
+
+/// Имя файла манифеста выбранных кадров в папке калибровочных изображений
+pub const PICKED_MANIFEST_FILE: &str = "picked_manifest.json";
+
+/// Сдвиг номеров кадров между записями при выборе из нескольких видео: кадр `f` записи `i`
+/// получает номер `i * CLIP_FRAME_STRIDE + f`, так что имена img_{cam}_{frame}.png
+/// разных записей не совпадают. У единственной записи номера не меняются
+pub const CLIP_FRAME_STRIDE: usize = 1_000_000;
+
+/// Один выбранный кадр: сколько углов доски найдено в каждой камере и какие файлы сохранены
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PickedFrame {
+    /// Номер кадра видео
+    pub frame: usize,
+    /// Количество углов ChArUco по камерам (пусто для кадров, сохранённых до появления манифеста)
+    pub corners: Vec<usize>,
+    /// Номер камеры (с 1) -> путь сохранённого файла относительно папки выбранных кадров.
+    /// Камеры, не увидевшие доску, отсутствуют
+    pub files: BTreeMap<usize, String>,
+    /// Сессия (запуск программы), в которой выбран кадр: файлы лежат в папке с этим именем.
+    /// `None` для кадров, сохранённых прямо в папке выбранных кадров прежними версиями
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Видео, из которого взят кадр, при выборе из нескольких записей
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<String>,
+    /// Сколько углов каждой камеры найдено и в камере 1 (пусто для кадров,
+    /// сохранённых до появления этого поля)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shared_with_first: Vec<usize>,
+}
+
+/// Манифест выбранных калибровочных кадров в порядке выбора
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PickedManifest {
+    pub frames: Vec<PickedFrame>,
+    /// Доска, с которой выбраны кадры (`None` в манифестах прежних версий)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub board: Option<CharucoBoardConfig>,
+    /// Сессия, в которую сохраняются новые кадры; в файл не пишется, задаётся при каждом запуске
+    #[serde(skip)]
+    pub session: Option<String>,
+    /// Записи, из которых выбираются кадры этого запуска, по порядку (см. [`CLIP_FRAME_STRIDE`]);
+    /// пусто для одного видео. В файл не пишется
+    #[serde(skip)]
+    pub clips: Vec<String>,
+}
+
+impl PickedManifest {
+    /// Читает манифест из папки. `None`, если манифеста нет
+    pub fn load(dir: &Path) -> Result<Option<Self>, UtilsError> {
+        let path = dir.join(PICKED_MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|source| UtilsError::Json { path, source })
+    }
+
+    /// Собирает манифест по уже сохранённым в `dir` файлам `img_{cam}_{frame}.png`
+    /// (см. [`list_picked_calibration_images`])
+    pub fn from_picked_images(
+        dir: &Path,
+        picked: &BTreeMap<usize, BTreeMap<usize, PathBuf>>,
+    ) -> Self {
+        let mut frames: BTreeMap<usize, PickedFrame> = BTreeMap::new();
+        for (&cam, images) in picked {
+            for (&frame, path) in images {
+                let Ok(relative) = path.strip_prefix(dir) else {
+                    continue;
+                };
+                let session = relative
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty())
+                    .map(|parent| parent.to_string_lossy().into_owned());
+                let entry = frames.entry(frame).or_insert_with(|| PickedFrame {
+                    frame,
+                    corners: Vec::new(),
+                    files: BTreeMap::new(),
+                    shared_with_first: Vec::new(),
+                    session: session.clone(),
+                    clip: None,
+                });
+                entry
+                    .files
+                    .insert(cam, relative.to_string_lossy().replace('\\', "/"));
+            }
+        }
+        Self {
+            frames: frames.into_values().collect(),
+            board: None,
+            session: None,
+            clips: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), UtilsError> {
+        let path = dir.join(PICKED_MANIFEST_FILE);
+        let text = serde_json::to_string_pretty(self).map_err(|source| UtilsError::Json {
+            path: path.clone(),
+            source,
+        })?;
+        write_atomically(&path, |tmp| std::fs::write(tmp, text))?;
+        Ok(())
+    }
+
+    pub fn contains(&self, frame: usize) -> bool {
+        self.frames.iter().any(|f| f.frame == frame)
+    }
+
+    /// Для каждой камеры пары с камерой 1 (номера с 1, начиная со 2): сколько выбранных
+    /// кадров сохранены для обеих камер и имеют не меньше `min_shared` общих углов.
+    /// Только такие кадры улучшают стереокалибровку пары. Кадры без сведений об общих
+    /// углах не учитываются
+    pub fn stereo_pair_frames(&self, cameras: usize, min_shared: usize) -> BTreeMap<usize, usize> {
+        (2..=cameras)
+            .map(|cam| {
+                let frames = self
+                    .frames
+                    .iter()
+                    .filter(|f| f.files.contains_key(&1) && f.files.contains_key(&cam))
+                    .filter(|f| {
+                        f.shared_with_first
+                            .get(cam - 1)
+                            .is_some_and(|&n| n >= min_shared)
+                    })
+                    .count();
+                (cam, frames)
+            })
+            .collect()
+    }
+
+    /// Группирует файлы манифеста так же, как `list_picked_calibration_images`:
+    /// номер камеры -> (номер кадра -> путь). Отсутствующие на диске файлы пропускаются
+    pub fn picked_images(&self, dir: &Path) -> BTreeMap<usize, BTreeMap<usize, PathBuf>> {
+        let mut cameras: BTreeMap<usize, BTreeMap<usize, PathBuf>> = BTreeMap::new();
+        for entry in &self.frames {
+            for (&cam, name) in &entry.files {
+                let path = dir.join(name);
+                if path.is_file() {
+                    cameras.entry(cam).or_default().insert(entry.frame, path);
+                } else {
+                    debug!("Файл {} из манифеста не найден", path.display());
+                }
+            }
+        }
+        cameras
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{CV_8UC3, Rect, Scalar, Vec3b};
+
+    /// Ширина полосы одного бита номера кадра, пикс.
+    const BIT_WIDTH: i32 = 40;
+
+    /// Кадр, на котором номер записан восемью чёрными и белыми полосами: полосы
+    /// переживают сжатие, так что номер читается и после декодирования
+    fn numbered_frame(index: usize) -> Mat {
+        let mut frame = Mat::new_rows_cols_with_default(
+            2 * BIT_WIDTH,
+            8 * BIT_WIDTH,
+            CV_8UC3,
+            Scalar::all(0.0),
+        )
+        .unwrap();
+        for bit in 0..8 {
+            if (index >> bit) & 1 == 1 {
+                let rect = Rect::new(bit * BIT_WIDTH, 0, BIT_WIDTH, 2 * BIT_WIDTH);
+                frame
+                    .roi_mut(rect)
+                    .unwrap()
+                    .set_to_def(&Scalar::all(255.0))
+                    .unwrap();
+            }
+        }
+        frame
+    }
+
+    fn frame_number(frame: &Mat) -> usize {
+        (0..8)
+            .filter(|&bit| {
+                let pixel = frame
+                    .at_2d::<Vec3b>(BIT_WIDTH, bit * BIT_WIDTH + BIT_WIDTH / 2)
+                    .unwrap();
+                pixel[0] > 127
+            })
+            .map(|bit| 1 << bit)
+            .sum()
+    }
+
+    /// Пишет `count` пронумерованных кадров; H.264, если кодер доступен
+    fn write_numbered_video(path: &Path, count: usize) {
+        let size = Size::new(8 * BIT_WIDTH, 2 * BIT_WIDTH);
+        let mut writer = [
+            ('a', 'v', 'c', '1'),
+            ('H', '2', '6', '4'),
+            ('m', 'p', '4', 'v'),
+        ]
+        .into_iter()
+        .find_map(|(a, b, c, d)| {
+            let fourcc = VideoWriter::fourcc(a, b, c, d).ok()?;
+            let writer = VideoWriter::new(path.to_str()?, fourcc, 25.0, size, true).ok()?;
+            writer.is_opened().ok()?.then_some(writer)
+        })
+        .expect("нет кодера для тестового видео");
+        for index in 0..count {
+            writer.write(&numbered_frame(index)).unwrap();
+        }
+        writer.release().unwrap();
+    }
+
+    #[test]
+    fn seek_returns_requested_frames() {
+        let path = std::env::temp_dir().join(format!("seek_test_{}.mp4", std::process::id()));
+        write_numbered_video(&path, 200);
+
+        let mut video = SeekableVideo::open(&path, 4).unwrap();
+        for index in [150, 20, 120, 121, 5, 199, 90, 0] {
+            let frame = video.frame(index).unwrap();
+            assert_eq!(frame_number(&frame), index, "запрошен кадр {}", index);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Папка с пустыми файлами с заданными именами
+    fn picked_dir(test: &str, names: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in names {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        dir
+    }
+
+    fn scenes(cameras: &BTreeMap<usize, BTreeMap<usize, PathBuf>>, cam: usize) -> Vec<usize> {
+        cameras[&cam].keys().copied().collect()
+    }
+
+    #[test]
+    fn parses_old_picked_name() {
+        let name = parse_picked_image_name("img_1_17.png").unwrap();
+        assert_eq!((name.cam, name.tag.as_str(), name.frame), (1, "", 17));
+    }
+
+    #[test]
+    fn parses_timestamped_picked_name() {
+        let name = parse_picked_image_name("img_2_2025-06-03_113751_17.png").unwrap();
+        assert_eq!(
+            (name.cam, name.tag.as_str(), name.frame),
+            (2, "2025-06-03_113751", 17)
+        );
+    }
+
+    #[test]
+    fn rejects_garbage_picked_names() {
+        for name in [
+            "img_.png",
+            "img_1.png",
+            "img_a_17.png",
+            "img_1_x.png",
+            "img_1_17_.png",
+            "img_1_17.jpg",
+            "picture_1_17.png",
+        ] {
+            assert_eq!(parse_picked_image_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn timestamped_frames_with_same_number_stay_apart() {
+        let dir = picked_dir(
+            "picked_timestamps",
+            &[
+                "img_1_2025-06-03_113751_17.png",
+                "img_2_2025-06-03_113751_17.png",
+                "img_1_2025-06-04_090000_17.png",
+                "img_2_2025-06-04_090000_17.png",
+                "img_1_garbage.png",
+            ],
+        );
+        let cameras = list_picked_calibration_images(&dir).unwrap();
+        let expected = vec![17, CLIP_FRAME_STRIDE + 17];
+        assert_eq!(scenes(&cameras, 1), expected);
+        assert_eq!(scenes(&cameras, 2), expected);
+        assert!(
+            cameras[&2][&(CLIP_FRAME_STRIDE + 17)]
+                .to_string_lossy()
+                .contains("2025-06-04")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn old_picked_names_keep_frame_numbers() {
+        let dir = picked_dir(
+            "picked_old_names",
+            &[
+                "img_1_3.png",
+                "img_2_3.png",
+                "img_1_2025-06-03_113751_8.png",
+            ],
+        );
+        let cameras = list_picked_calibration_images(&dir).unwrap();
+        assert_eq!(scenes(&cameras, 1), vec![3, 8]);
+        assert_eq!(scenes(&cameras, 2), vec![3]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unparsed_picked_names_fall_back_to_manifest() {
+        let dir = picked_dir(
+            "picked_manifest_fallback",
+            &["img_1_left.png", "img_2_17.png"],
+        );
+        let manifest = PickedManifest {
+            frames: vec![PickedFrame {
+                frame: 17,
+                corners: Vec::new(),
+                files: BTreeMap::from([(1, "img_1_left.png".to_string())]),
+                session: None,
+                clip: None,
+                shared_with_first: Vec::new(),
+            }],
+            ..Default::default()
+        };
+        manifest.save(&dir).unwrap();
+        let cameras = list_picked_calibration_images(&dir).unwrap();
+        assert_eq!(scenes(&cameras, 1), vec![17]);
+        assert_eq!(scenes(&cameras, 2), vec![17]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_video_is_video_open_error() {
+        let path = std::env::temp_dir().join("no_such_video.mp4");
+        let err = get_video_frame_count(&path).unwrap_err();
+        assert!(
+            matches!(&err, UtilsError::VideoOpen { path: p, .. } if *p == path),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("no_such_video.mp4"));
+    }
+
+    #[test]
+    fn approximate_frame_count_is_tolerated() {
+        assert!(frame_count_matches(300, 300));
+        assert!(frame_count_matches(298, 300));
+        assert!(frame_count_matches(302, 300));
+        assert!(!frame_count_matches(290, 300));
+        // У длинных видео допуск растёт с числом кадров
+        assert!(frame_count_matches(9_920, 10_000));
+        assert!(!frame_count_matches(9_800, 10_000));
+        assert!(!frame_count_matches(0, 0));
+    }
+
+    #[test]
+    fn missing_output_dir_is_invalid_path_error() {
+        let video = std::env::temp_dir().join("no_such_video.mp4");
+        let dir = std::env::temp_dir().join("no_such_frames_dir");
+        let err = video_to_frames(&video, &dir, &mut |_, _| true).unwrap_err();
+        assert!(
+            matches!(&err, UtilsError::InvalidPath(p) if *p == dir),
+            "{:?}",
+            err
+        );
+
+        let err = split_video_into_quadrants(&video, &dir, "camera").unwrap_err();
+        assert!(
+            matches!(&err, UtilsError::InvalidPath(p) if *p == dir),
+            "{:?}",
+            err
+        );
+
+        let err = list_frames(&dir, "", "png").unwrap_err();
+        assert!(
+            matches!(&err, UtilsError::InvalidPath(p) if *p == dir),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn utils_error_converts_to_opencv_error_with_path() {
+        let err: Error = UtilsError::InvalidPath(PathBuf::from("/missing/dir")).into();
+        assert!(err.message.contains("/missing/dir"), "{}", err.message);
+    }
+
+    #[test]
+    fn list_frames_sorts_numerically_and_reports_gaps() {
+        let dir = picked_dir(
+            "list_frames",
+            &[
+                "frame_10.png",
+                "frame_2.png",
+                "frame_1.png",
+                "frame_4.png",
+                "other.png",
+            ],
+        );
+        let listing = list_frames(&dir, "frame_", "png").unwrap();
+        let indices: Vec<usize> = listing.frames.iter().map(|f| f.index).collect();
+        assert_eq!(indices, vec![1, 2, 4, 10]);
+        assert_eq!(listing.gaps, vec![3, 5, 6, 7, 8, 9]);
+        assert!(listing.duplicates.is_empty());
+        assert!(!listing.is_contiguous());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn picked_images_sort_numerically() {
+        let dir = picked_dir(
+            "picked_order",
+            &["img_2_10.png", "img_2_2.png", "img_1_2.png", "img_10_1.png"],
+        );
+        let cameras = list_picked_calibration_images(&dir).unwrap();
+        assert_eq!(cameras.keys().copied().collect::<Vec<_>>(), vec![1, 2, 10]);
+        assert_eq!(scenes(&cameras, 2), vec![2, 10]);
+        assert!(cameras[&2][&10].ends_with("img_2_10.png"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_picked_frame_index_is_an_error() {
+        let dir = picked_dir(
+            "picked_duplicates",
+            &["img_1_7.png", "img_1_07.png", "img_2_7.png"],
+        );
+        let err = list_picked_calibration_images(&dir).unwrap_err();
+        match &err {
+            UtilsError::DuplicatePickedImages { files, .. } => {
+                assert_eq!(files, &vec!["img_1_07.png и img_1_7.png".to_string()]);
+            }
+            other => panic!("{:?}", other),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Временные файлы `write_atomically`, оставшиеся в `dir`
+    fn atomic_leftovers(dir: &Path, stem: &str) -> Vec<PathBuf> {
+        let prefix = format!(".{}.tmp", stem);
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with(&prefix)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_atomic_write_keeps_previous_file() {
+        let dir = picked_dir("atomic_failed", &[]);
+        let target = dir.join("params.yml");
+        std::fs::write(&target, "old").unwrap();
+
+        let result: Result<(), io::Error> = write_atomically(&target, |tmp_path| {
+            std::fs::write(tmp_path, "partial")?;
+            Err(io::Error::other("запись прервана"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "old");
+        assert!(atomic_leftovers(&dir, "params").is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn successful_atomic_write_replaces_file() {
+        let dir = picked_dir("atomic_replaced", &[]);
+        let target = dir.join("params.yml");
+        std::fs::write(&target, "old").unwrap();
+
+        let value = write_atomically(&target, |tmp_path| -> Result<usize, io::Error> {
+            assert_ne!(tmp_path, target.as_path());
+            assert_eq!(tmp_path.extension(), target.extension());
+            std::fs::write(tmp_path, "new")?;
+            Ok(3)
+        })
+        .unwrap();
+        assert_eq!(value, 3);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "new");
+        assert!(atomic_leftovers(&dir, "params").is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,27 +1,91 @@
-use lib_cv::calibration::load_camera_parameters;
-use lib_cv::correspondence::gather_points_2d_from_matches;
+use lib_cv::board::BoardConfig;
+use lib_cv::board::CharucoBoardConfig;
+use lib_cv::calibration::{
+    CalibrationFormat, CameraParameters, essential_matrix_residual, estimate_board_pose,
+    get_charuco, load_camera_parameters, save_camera_parameters, save_camera_poses,
+};
+use lib_cv::correspondence::{
+    correspondences_from_matches, gather_points_2d_from_matches, refine_subpixel,
+    save_correspondences_csv, sift_with_mask,
+};
+use lib_cv::fusion::FusedMap;
+use lib_cv::pool::MatPool;
 use lib_cv::reconstruction::{
-    PointCloud, add_color_to_point_cloud, filter_point_cloud_by_confindence,
-    match_first_camera_features_to_all, min_visible_match_set, save_point_cloud,
-    undistort_points_single_camera,
+    BAD_POINT_ERROR, BoardFrame, CameraTopology, ErrorStats, PLY_TRACK_ID_PROPERTY, Point3D,
+    PointCloud, ReconstructionReport, TriangulationContext, VisibilityMode,
+    add_color_to_point_cloud_from_camera, detect_active_cameras, detection_contact_sheet,
+    drop_untriangulated_points, filter_by_color_consistency, filter_point_cloud_by_confindence,
+    filter_point_cloud_by_max_reproj, load_error_stats_csv, load_point_cloud,
+    match_first_camera_features_to_all_masked, min_visible_match_set, partial_visible_match_set,
+    reconstruct_ring_frame, rectilinear_camera, reject_masked_points, save_error_stats_csv,
+    save_point_cloud_extended, save_point_cloud_with_comments, undistort_image, undistort_mask,
+    undistort_points_pooled, write_reconstruction_report,
+};
+use lib_cv::registration::MotionStabilizer;
+use lib_cv::tracking::{
+    TrackDescriptors, TrackLifespans, TrackManager, TrajectorySmoother, apply_recovered_tracks,
+    log_track_length_summary, rematch_by_descriptor, save_track_length_histogram_csv,
+    save_tracks_2d_csv,
 };
 use lib_cv::utils::{
-    open_video_captures, read_frames, split_video_into_quadrants, vector_point2f_to_mat,
+    DebugVideoWriter, FrameSource, GridLayout, UtilsError, get_video_fps, get_video_frame_size,
+    infer_grid_layout, load_mask, read_frames, split_video_into_grid, vector_point2f_to_mat,
+    write_atomically,
 };
-use log::{debug, error, info};
-use opencv::core::{Point2f, Vector};
+use log::{debug, error, info, warn};
+use opencv::core::{DMatch, KeyPoint, Mat, Point2f, Size, Vector};
+use opencv::imgcodecs::imwrite;
 use opencv::video::calc_optical_flow_pyr_lk;
-use opencv::videoio::VideoCapture;
 use opencv::{Error, prelude::*};
 
-use std::{fs::create_dir_all, path::PathBuf};
+use std::{
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use crate::model::{CalibrationData, PipelineState, ProjectResources, VideoData};
+use crate::model::{
+    COMBINED_VIDEO_FILE, CalibrationData, PipelineState, ProgressEvent, ProjectResources,
+    ReconstructionSettings, VideoData,
+};
 use crate::ui::UiRenderer;
+use crate::viewer::{CloudViewer, DEFAULT_PLAYBACK_FPS};
+
+/// Позы камер в системе доски, если она включена
+const BOARD_FRAME_POSES_FILE: &str = "camera_poses_board_frame.yml";
+/// Дескрипторы камеры 0 для треков первого облака, если их сохранение включено
+const TRACK_DESCRIPTORS_FILE: &str = "track_descriptors.yml";
+/// 2D треки в режиме одной камеры (в папке отчётов)
+const TRACKS_2D_FILE: &str = "tracks_2d.csv";
+/// Сводка запуска реконструкции (в папке отчётов)
+const RUN_REPORT_FILE: &str = "report.txt";
+/// Отчёт о запуске в Markdown с таблицами и картами покрытия калибровки
+const MARKDOWN_REPORT_FILE: &str = "report.md";
+/// Лист признаков, найденных на первом кадре (в папке отчётов)
+const DETECTION_SHEET_FILE: &str = "detections_frame_0.png";
+/// Гистограмма длин треков (в папке отчётов)
+const TRACK_LENGTHS_FILE: &str = "track_lengths.csv";
+
+/// Как часто перерисовывать окно, пока идёт реконструкция
+const PROGRESS_REPAINT: Duration = Duration::from_millis(100);
 
 pub(crate) struct ReconstructionApp {
     pub resources: ProjectResources,
     pub pipeline_state: PipelineState,
+    pub topology: CameraTopology,
+    pub settings: ReconstructionSettings,
+    /// Идущая в фоне реконструкция
+    job: Option<PipelineJob>,
+    /// Итог последнего запуска: сообщение о завершении или текст ошибки
+    pub last_run: Option<Result<String, String>>,
+    /// Открытое окно просмотра облаков точек
+    pub viewer: Option<CloudViewer>,
 }
 
 impl Default for ReconstructionApp {
@@ -29,24 +93,58 @@ impl Default for ReconstructionApp {
         Self {
             resources: Default::default(),
             pipeline_state: Default::default(),
+            topology: Default::default(),
+            settings: Default::default(),
+            job: None,
+            last_run: None,
+            viewer: None,
         }
     }
 }
 
 impl eframe::App for ReconstructionApp {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_pipeline();
+        if self.job.is_some() {
+            ctx.request_repaint_after(PROGRESS_REPAINT);
+        }
         UiRenderer::render_content(self, ctx);
+        if let Some(viewer) = &mut self.viewer
+            && !viewer.show(ctx)
+        {
+            self.viewer = None;
+        }
     }
 }
 
+/// Реконструкция в фоновом потоке. Поток работает с копией ресурсов и настроек проекта,
+/// поэтому окно остаётся отзывчивым, а изменения настроек во время работы на неё не влияют
+struct PipelineJob {
+    progress: Receiver<ProgressEvent>,
+    cancel: Arc<AtomicBool>,
+    handle: JoinHandle<Result<(), Error>>,
+}
+
+/// Всё, что нужно пайплайну реконструкции в фоновом потоке
+struct Pipeline {
+    resources: ProjectResources,
+    topology: CameraTopology,
+    settings: ReconstructionSettings,
+    progress: Sender<ProgressEvent>,
+    /// Флаг остановки: проверяется перед каждым кадром
+    cancel: Arc<AtomicBool>,
+}
+
 impl ReconstructionApp {
     pub fn new() -> Self {
         Self::default()
     }
 
     pub(crate) fn set_project_folder(&mut self, p: std::path::PathBuf) {
+        // Расположение файлов задаётся до выбора проекта и сохраняется при смене папки
         self.resources = ProjectResources {
             project_path: Some(p),
+            layout: std::mem::take(&mut self.resources.layout),
             calibration_data: None,
             video_data: None,
         };
@@ -54,18 +152,66 @@ impl ReconstructionApp {
     }
 
     pub(crate) fn load_camera_parameters(&mut self, path: PathBuf) {
-        let project_path = self.resources.project_path.as_ref().unwrap();
-        let dest_path = project_path.join("camera_parameters.yml");
+        let Some(project_path) = self.resources.project_path.as_ref() else {
+            error!("Папка проекта не выбрана");
+            return;
+        };
+        let dest_path = self.resources.layout.camera_parameters(project_path);
 
-        if let Err(_) = std::fs::copy(&path, &dest_path) {
+        // Файл другого формата (например XML других программ) переводится в формат проекта
+        let same_format = CalibrationFormat::from_path(&path)
+            .and_then(|source| Ok(source == CalibrationFormat::from_path(&dest_path)?));
+        let imported = match same_format {
+            Ok(true) => std::fs::copy(&path, &dest_path)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Ok(false) => load_camera_parameters(&path)
+                .and_then(|cameras| save_camera_parameters(&cameras, &dest_path))
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = imported {
+            error!(
+                "Не удалось скопировать {} в {}: {}",
+                path.display(),
+                dest_path.display(),
+                e
+            );
             return;
         }
 
-        let cam_params = match load_camera_parameters(dest_path.to_str().unwrap()) {
+        // Доска калибровки переезжает в проект вместе с параметрами камер
+        let board_source = CharucoBoardConfig::path_for_calibration(&path);
+        let board_dest = CharucoBoardConfig::path_for_calibration(&dest_path);
+        let board_copied =
+            board_source.exists() && std::fs::copy(&board_source, &board_dest).is_ok();
+        if !board_copied
+            && board_dest.exists()
+            && let Err(e) = std::fs::remove_file(&board_dest)
+        {
+            warn!("Не удалось удалить прежний файл доски: {}", e);
+        }
+
+        let cam_params = match load_camera_parameters(&dest_path) {
             Ok(c) => c,
-            Err(_) => return,
+            Err(e) => {
+                error!(
+                    "Не удалось прочитать параметры камер {}: {}",
+                    dest_path.display(),
+                    e
+                );
+                return;
+            }
         };
-        self.resources.calibration_data = Some(CalibrationData::new(dest_path, cam_params));
+        let mut calibration_data = CalibrationData::new(dest_path, cam_params);
+        // Проект уже мог использовать другую доску: тогда новые параметры дадут другой масштаб
+        if let (Some(previous), Some(board)) = (
+            self.resources.calibration_data.as_ref(),
+            calibration_data.board.as_ref(),
+        ) {
+            calibration_data.board_warning = previous.board_mismatch(board);
+        }
+        self.resources.calibration_data = Some(calibration_data);
     }
 
     pub(crate) fn pick_camera_video(&mut self, cam_num: usize) {
@@ -76,21 +222,29 @@ impl ReconstructionApp {
             .pick_file()
         {
             Some(file_path) => {
-                let project_path = self.resources.project_path.as_ref().unwrap();
-                let dest_path = project_path.join("data/video");
-                if let Err(_) = create_dir_all(&dest_path) {
+                let Some(project_path) = self.resources.project_path.as_ref() else {
+                    error!("Папка проекта не выбрана");
+                    return;
+                };
+                let dest_path = self.resources.layout.video_dir(project_path);
+                if let Err(e) = create_dir_all(&dest_path) {
+                    error!("Не удалось создать {}: {}", dest_path.display(), e);
                     return;
                 }
+                // Отдельные видео камер заменяют общее видео, иначе оно снова
+                // открылось бы при следующей загрузке проекта
+                remove_combined_video(&dest_path);
                 let dest_path = dest_path.join(format!("camera_{cam_num}.mp4"));
 
                 if let Err(_) = std::fs::copy(&file_path, &dest_path) {
                     return;
                 }
                 match &mut self.resources.video_data {
-                    Some(vd) => {
+                    // Отдельное видео камеры заменяет общее видео целиком
+                    Some(vd) if vd.combined_layout.is_none() => {
                         vd.video_files[cam_num] = Some(dest_path);
                     }
-                    None => {
+                    _ => {
                         let num_cams = match &self.resources.calibration_data {
                             Some(cb) => cb.num_cameras,
                             None => return,
@@ -113,13 +267,47 @@ impl ReconstructionApp {
             .set_title("Выбрать видео")
             .pick_file()
         {
-            let project_path = self.resources.project_path.as_ref().unwrap();
-            let dest_path = project_path.join("data/video");
-            if let Err(_) = create_dir_all(&dest_path) {
+            let Some(project_path) = self.resources.project_path.as_ref() else {
+                error!("Папка проекта не выбрана");
+                return;
+            };
+            let dest_path = self.resources.layout.video_dir(project_path);
+            if let Err(e) = create_dir_all(&dest_path) {
+                error!("Не удалось создать {}: {}", dest_path.display(), e);
+                return;
+            }
+            // Новое общее видео заменяет все выбранные раньше источники
+            remove_camera_videos(&dest_path);
+            remove_combined_video(&dest_path);
+
+            if self.settings.keep_combined_video {
+                let dest_path = dest_path.join(COMBINED_VIDEO_FILE);
+                if let Err(e) = std::fs::copy(&file_path, &dest_path) {
+                    error!("Не удалось скопировать {}: {}", file_path.display(), e);
+                    return;
+                }
+                let layout = match self.combined_layout(&dest_path) {
+                    Ok(layout) => layout,
+                    Err(e) => {
+                        error!("{}", e);
+                        return;
+                    }
+                };
+                match VideoData::combined(&dest_path, layout) {
+                    Ok(vd) => self.resources.video_data = Some(vd),
+                    Err(e) => error!("Не удалось открыть {}: {}", dest_path.display(), e),
+                }
                 return;
             }
 
-            if let Ok(paths) = split_video_into_quadrants(&file_path, &dest_path, "camera") {
+            let layout = match self.combined_layout(&file_path) {
+                Ok(layout) => layout,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            if let Ok(paths) = split_video_into_grid(&file_path, &dest_path, "camera", &layout) {
                 let paths: Vec<Option<PathBuf>> = paths.iter().map(|p| Some(p.clone())).collect();
                 if let Ok(vd) = VideoData::from_vec(paths) {
                     self.resources.video_data = Some(vd);
@@ -128,42 +316,294 @@ impl ReconstructionApp {
         }
     }
 
+    /// Раскладка камер в общем видео по числу откалиброванных камер и размеру кадра.
+    /// Без калибровки видео считается сеткой 2x2. Если раскладка не подбирается - ошибка:
+    /// кадры, разрезанные не по числу камер, испортили бы всю реконструкцию
+    fn combined_layout(&self, video_file: &Path) -> Result<GridLayout, String> {
+        let num_cameras = self
+            .resources
+            .calibration_data
+            .as_ref()
+            .map(|calibration_data| calibration_data.num_cameras);
+        let size = get_video_frame_size(video_file).map_err(|e| {
+            format!(
+                "Не удалось узнать размер кадра {}: {}",
+                video_file.display(),
+                e
+            )
+        })?;
+        let layout = combined_layout_for(num_cameras, size)
+            .map_err(|e| format!("Раскладка общего видео {}: {}", video_file.display(), e))?;
+        info!(
+            "Раскладка общего видео {}: {}",
+            video_file.display(),
+            layout
+        );
+        Ok(layout)
+    }
+
+    /// Открывает просмотр облаков точек проекта с частотой кадров видео
+    pub(crate) fn open_viewer(&mut self) {
+        let Some(project_path) = self.resources.project_path.as_ref() else {
+            error!("Папка проекта не выбрана");
+            return;
+        };
+        let fps = self
+            .resources
+            .video_data
+            .as_ref()
+            .and_then(|video_data| video_data.video_files.iter().flatten().next())
+            .and_then(|video| get_video_fps(video).ok())
+            .unwrap_or(DEFAULT_PLAYBACK_FPS);
+        self.viewer = Some(CloudViewer::new(
+            &self.resources.layout.point_clouds_dir(project_path),
+            fps,
+        ));
+    }
+
     pub(crate) fn fetch_project(&mut self) {
+        self.fetch_settings();
         self.fetch_camera_params();
         self.fetch_video_data();
         self.pipeline_state = PipelineState::SetupMenu;
     }
 
+    /// Читает параметры обработки проекта; без файла настроек остаются настройки по умолчанию
+    pub(crate) fn fetch_settings(&mut self) {
+        let Some(project_path) = self.resources.project_path.as_ref() else {
+            error!("Папка проекта не выбрана");
+            return;
+        };
+        let path = self.resources.layout.settings(project_path);
+        match ReconstructionSettings::load(&path) {
+            Ok(settings) => self.settings = settings,
+            Err(e) => error!("{}", e),
+        }
+    }
+
+    /// Сохраняет параметры обработки в файл настроек проекта
+    pub(crate) fn save_settings(&self) -> Result<(), String> {
+        let project_path = self
+            .resources
+            .project_path
+            .as_ref()
+            .ok_or("Папка проекта не выбрана")?;
+        self.settings
+            .save(&self.resources.layout.settings(project_path))
+    }
+
     pub(crate) fn fetch_camera_params(&mut self) {
-        let project_path = self.resources.project_path.as_ref().unwrap();
-        let file_path = project_path.join("camera_parameters.yml");
+        let Some(project_path) = self.resources.project_path.as_ref() else {
+            error!("Папка проекта не выбрана");
+            return;
+        };
+        let file_path = self.resources.layout.camera_parameters(project_path);
 
         if file_path.exists() {
-            let cam_params = match load_camera_parameters(file_path.to_str().unwrap()) {
+            let cam_params = match load_camera_parameters(&file_path) {
                 Ok(c) => c,
-                Err(_) => return,
+                Err(e) => {
+                    error!(
+                        "Не удалось прочитать параметры камер {}: {}",
+                        file_path.display(),
+                        e
+                    );
+                    return;
+                }
             };
             self.resources.calibration_data = Some(CalibrationData::new(file_path, cam_params));
         }
     }
 
     pub(crate) fn fetch_video_data(&mut self) {
-        let project_path = self.resources.project_path.as_ref().unwrap();
-        let video_files: Vec<Option<PathBuf>> = match project_path.join("data/video").read_dir() {
-            Ok(read_dir) => read_dir
-                .filter_map(|entry| entry.ok())
-                .map(|entry| Some(entry.path()))
-                .collect(),
-            Err(_) => vec![],
+        let Some(project_path) = self.resources.project_path.as_ref() else {
+            error!("Папка проекта не выбрана");
+            return;
         };
+        let video_dir = self.resources.layout.video_dir(project_path);
+        let combined = video_dir.join(COMBINED_VIDEO_FILE);
+        let camera_videos = camera_videos(&video_dir);
+        if combined.exists() && combined_is_newest(&combined, &camera_videos) {
+            let layout = match self.combined_layout(&combined) {
+                Ok(layout) => layout,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            match VideoData::combined(&combined, layout) {
+                Ok(video_data) => self.resources.video_data = Some(video_data),
+                Err(e) => error!("Не удалось открыть {}: {}", combined.display(), e),
+            }
+            return;
+        }
+        let video_files: Vec<Option<PathBuf>> = camera_videos.into_iter().map(Some).collect();
         if let Ok(video_data) = VideoData::from_vec(video_files) {
             self.resources.video_data = Some(video_data);
         }
     }
 
-    pub(crate) fn run_pipeline(&self) -> Result<(), opencv::Error> {
-        let mut caps: Vec<VideoCapture> = Vec::new();
+    /// Реконструкция проекта без окна. Параметры обработки, в том числе режим видимости
+    /// точек (`visibility = "AllCameras"` или `visibility = { MinViews = 2 }`), берутся из
+    /// файла настроек проекта. Ход работы пишется в лог
+    pub(crate) fn run_headless(
+        project_path: PathBuf,
+        topology: CameraTopology,
+    ) -> Result<(), String> {
+        let mut app = Self::new();
+        app.topology = topology;
+        app.set_project_folder(project_path);
+        app.fetch_project();
+        if app.resources.calibration_data.is_none() {
+            return Err("Не удалось загрузить параметры камер проекта".to_string());
+        }
+        if app.resources.video_data.is_none() {
+            return Err("Не удалось загрузить видео проекта".to_string());
+        }
+        match app.settings.to_toml() {
+            Ok(text) => info!("Параметры обработки:\n{}", text),
+            Err(e) => warn!("{}", e),
+        }
+        // Этапы и кадры и так пишутся в лог пайплайном, поэтому события прогресса не читаются
+        let (sender, _receiver) = channel();
+        let pipeline = Pipeline {
+            resources: app.resources,
+            topology: app.topology,
+            settings: app.settings,
+            progress: sender,
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        pipeline.run_pipeline().map_err(|e| e.message)
+    }
+
+    /// Запускает реконструкцию в фоновом потоке
+    pub(crate) fn start_pipeline(&mut self) {
+        if self.job.is_some() {
+            return;
+        }
+        match self.settings.to_toml() {
+            Ok(text) => info!("Параметры обработки:\n{}", text),
+            Err(e) => warn!("{}", e),
+        }
+        // Параметры запуска запоминаются в проекте, чтобы следующий запуск начинался с них
+        if let Err(e) = self.save_settings() {
+            warn!("{}", e);
+        }
+        let (sender, receiver) = channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let pipeline = Pipeline {
+            resources: self.resources.clone(),
+            topology: self.topology,
+            settings: self.settings.clone(),
+            progress: sender,
+            cancel: Arc::clone(&cancel),
+        };
+        let handle = match std::thread::Builder::new()
+            .name("reconstruction".to_string())
+            .spawn(move || pipeline.run_pipeline())
+        {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Не удалось запустить поток реконструкции: {}", e);
+                self.last_run = Some(Err(format!("Не удалось запустить поток: {}", e)));
+                return;
+            }
+        };
+        self.job = Some(PipelineJob {
+            progress: receiver,
+            cancel,
+            handle,
+        });
+        self.last_run = None;
+        self.pipeline_state = PipelineState::Processing {
+            progress: 0.0,
+            message: "Подготовка".to_string(),
+        };
+    }
+
+    /// Просит фоновую реконструкцию остановиться после текущего кадра
+    pub(crate) fn cancel_pipeline(&self) {
+        if let Some(job) = &self.job {
+            info!("Остановка реконструкции после текущего кадра");
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn is_cancelling(&self) -> bool {
+        self.job
+            .as_ref()
+            .is_some_and(|job| job.cancel.load(Ordering::Relaxed))
+    }
+
+    /// Забирает сообщения фоновой реконструкции и, когда поток завершился, её итог
+    fn poll_pipeline(&mut self) {
+        let Some(job) = &self.job else {
+            return;
+        };
+        for event in job.progress.try_iter() {
+            let PipelineState::Processing { progress, message } = &mut self.pipeline_state else {
+                continue;
+            };
+            match event {
+                ProgressEvent::Stage(stage) => *message = stage,
+                ProgressEvent::Frame {
+                    frame,
+                    total,
+                    points,
+                } => {
+                    *progress = (frame + 1) as f32 / total.max(1) as f32;
+                    *message = match points {
+                        Some(points) => {
+                            format!("Кадр {} из {}: {} точек", frame + 1, total, points)
+                        }
+                        None => format!("Кадр {} из {}: уже обработан", frame + 1, total),
+                    };
+                }
+            }
+        }
+        if !job.handle.is_finished() {
+            return;
+        }
+        let Some(job) = self.job.take() else {
+            return;
+        };
+        let cancelled = job.cancel.load(Ordering::Relaxed);
+        self.last_run = Some(match job.handle.join() {
+            Ok(Ok(())) if cancelled => Ok("Реконструкция остановлена".to_string()),
+            Ok(Ok(())) => Ok("Реконструкция завершена".to_string()),
+            Ok(Err(e)) => {
+                error!("Ошибка при выполнении пайплайна реконструкции: {}", e);
+                Err(e.to_string())
+            }
+            Err(_) => {
+                error!("Поток реконструкции завершился аварийно");
+                Err("Поток реконструкции завершился аварийно, см. лог".to_string())
+            }
+        });
+        self.pipeline_state = PipelineState::SetupMenu;
+    }
+}
+
+impl Pipeline {
+    /// Остановлена ли реконструкция: проверяется перед обработкой каждого кадра
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    fn report_stage(&self, stage: &str) {
+        // Окно могло закрыться раньше потока: сообщения тогда некому показывать
+        let _ = self.progress.send(ProgressEvent::Stage(stage.to_string()));
+    }
+
+    fn report_frame(&self, frame: usize, total: usize, points: Option<usize>) {
+        let _ = self.progress.send(ProgressEvent::Frame {
+            frame,
+            total,
+            points,
+        });
+    }
 
+    fn run_pipeline(&self) -> Result<(), opencv::Error> {
         let video_data = self
             .resources
             .video_data
@@ -182,16 +622,71 @@ impl ReconstructionApp {
             .as_ref()
             .ok_or_else(|| Error::new(-1, "Нет пути проекта не загружена"))?;
 
-        open_video_captures(&mut caps, &video_data.video_files)?;
+        let started = Instant::now();
+        let mut report = ReconstructionReport::new(&calibration_data.camera_params);
+        let mut source = video_data.open_source()?;
+
+        let mut frames = vec![Mat::default(); source.cameras()];
+        let camera_params = self.pipeline_camera_params(calibration_data)?;
+
+        if calibration_data.num_cameras == 1 {
+            return self.run_tracking_only_pipeline(
+                source.as_mut(),
+                &mut frames,
+                video_data.total_frames,
+                calibration_data,
+                project_path,
+            );
+        }
+
+        if self.topology == CameraTopology::Ring {
+            self.run_ring_pipeline(
+                source.as_mut(),
+                &mut frames,
+                video_data.total_frames,
+                calibration_data,
+                project_path,
+                &mut report,
+            )?;
+            report.runtime = started.elapsed();
+            self.save_run_report(&report, project_path);
+            return Ok(());
+        }
 
-        let mut frames = vec![Mat::default(); caps.len()];
+        // Матрицы проекций не меняются между кадрами: строим их один раз
+        let triangulation = TriangulationContext::new(&camera_params)?;
+        // Буферы исправленных точек переиспользуются между кадрами
+        let mut pool = MatPool::new();
 
-        read_frames(&mut caps, &mut frames)?;
+        self.report_stage("Поиск признаков первого кадра");
+        self.read_pipeline_frames(source.as_mut(), &mut frames, calibration_data)?;
+        let board_frame = self.board_frame(&frames, &camera_params, calibration_data);
+        let masks = self.load_camera_masks(&frames, calibration_data, project_path);
 
-        let (mut all_matches, keypoints_list, _descriptors_list) =
-            match_first_camera_features_to_all(&frames);
+        let (mut all_matches, keypoints_list, descriptors_list) =
+            match_first_camera_features_to_all_masked(
+                &frames,
+                &self.settings.sift,
+                self.settings.match_ratio,
+                &masks,
+            );
+        self.check_essential_matrices(&camera_params, &all_matches, &keypoints_list);
+        self.save_detection_sheet(&frames, &keypoints_list, project_path);
 
-        all_matches = min_visible_match_set(&mut all_matches, &keypoints_list);
+        // Флаги видимости точек по камерам, если точки не обязаны быть видны во всех камерах.
+        // Строка `i` соответствует треку `i`: идентификаторы треков не меняются при отбрасывании
+        let visibility = match self.settings.visibility {
+            VisibilityMode::AllCameras => {
+                all_matches = min_visible_match_set(&all_matches, &keypoints_list);
+                None
+            }
+            VisibilityMode::MinViews(min_views) => {
+                let (matches, visibility) =
+                    partial_visible_match_set(&all_matches, &keypoints_list, min_views);
+                all_matches = matches;
+                Some(visibility)
+            }
+        };
 
         let points_2d: Vector<Mat> =
             match gather_points_2d_from_matches(&all_matches, &keypoints_list) {
@@ -207,64 +702,149 @@ impl ReconstructionApp {
                     return Err(Error::new(-1, "Не удалось извлечь 2D точки из совпадений"));
                 }
             };
+        let points_2d = if self.settings.subpixel_refinement {
+            Self::refine_frame_points(&points_2d, &frames)?
+        } else {
+            points_2d
+        };
         let mut undistorted_points_2d = Vector::<Mat>::default();
 
         for (i, points) in points_2d.iter().enumerate() {
-            let undistorted_nx2 =
-                match undistort_points_single_camera(&points, &calibration_data.camera_params[i]) {
-                    Ok(u_nx2) => u_nx2,
-                    Err(e) => {
-                        error!("Ошибка в undistort_points_single_camera: {}", e);
-                        return Err(e);
-                    }
-                };
+            let undistorted_nx2 = match self.undistort_frame_points(
+                &points,
+                &calibration_data.camera_params[i],
+                &mut pool,
+            ) {
+                Ok(u_nx2) => u_nx2,
+                Err(e) => {
+                    error!("Ошибка в undistort_points_single_camera: {}", e);
+                    return Err(e);
+                }
+            };
 
             undistorted_points_2d.push(undistorted_nx2);
         }
 
-        let points_3d = match lib_cv::reconstruction::triangulate_points_multiple(
-            &undistorted_points_2d,
-            &calibration_data.camera_params,
-        ) {
-            Ok(points) => points,
-            Err(e) => {
-                error!("Ошибка при триангуляции точек: {:?}", e);
-                return Err(e);
-            }
-        };
-
+        let mut error_stats: Vec<(usize, ErrorStats)> = Vec::new();
+        // Общая карта из облаков всех обработанных кадров, если слияние включено
+        let mut fused_map = self.settings.fusion.map(FusedMap::new);
+        let mut lifespans = TrackLifespans::default();
+        // Общее движение установки вычитается до слияния и сглаживания
+        let mut stabilizer = self.settings.stabilization.map(MotionStabilizer::new);
         let current_frame: usize = 0;
 
-        let mut cloud = PointCloud {
-            points: points_3d,
-            timestamp: current_frame,
-        };
-
-        add_color_to_point_cloud(&mut cloud, &points_2d, &frames[0]);
-
-        let initial_count = cloud.points.len();
-        filter_point_cloud_by_confindence(&mut cloud, 0.25);
-        info!(
-            "Отфильтровано {} точек (оставлено {})",
-            initial_count - cloud.points.len(),
-            cloud.points.len()
-        );
-        let dest_path = project_path.join(format!("data/point_clouds"));
+        let dest_path = self.resources.layout.point_clouds_dir(project_path);
         let filename = dest_path.join(format!("point_cloud_{current_frame}.ply"));
         if let Err(e) = create_dir_all(&dest_path) {
             return Err(opencv::Error::new(
                 -1,
-                &format!("Не удалось создать директорию: {}", e),
+                format!("Не удалось создать директорию: {}", e),
             ));
         }
 
-        match save_point_cloud(&cloud, &filename) {
-            Ok(_) => info!(
-                "Облако точек успешно сохранено в файл: {}",
+        if let Some(frame) = &board_frame {
+            self.save_board_frame_poses(frame, &camera_params, &dest_path);
+        }
+        // Облака сглаживаются до сохранения и потому сохраняются с задержкой; задержанные
+        // облака дописываются и при выходе по ошибке
+        let mut cloud_writer = CloudWriter::new(self, &dest_path);
+        // Дескрипторы треков первого облака, по которым находятся потерянные треки
+        let mut track_descriptors = None;
+
+        if self.is_frame_already_written(&filename) {
+            info!(
+                "Кадр {} уже обработан ({}), пропускаем",
+                current_frame,
                 filename.display()
-            ),
-            Err(e) => error!("Ошибка при сохранении облака точек: {:?}", e),
-        };
+            );
+            if self.settings.keep_descriptors {
+                track_descriptors = self.load_track_descriptors(&dest_path);
+            }
+            self.observe_written_frame(&filename, current_frame, &mut fused_map, &mut lifespans);
+            report.skipped_frames += 1;
+            self.report_frame(current_frame, video_data.total_frames, None);
+        } else {
+            let active_cameras = self.active_camera_mask(&frames, None)?;
+            let points_3d = match Self::triangulate_frame(
+                &undistorted_points_2d,
+                &triangulation,
+                active_cameras.as_deref(),
+                visibility.as_deref(),
+            ) {
+                Ok((points, stats)) => {
+                    error_stats.push((current_frame, stats));
+                    points
+                }
+                Err(e) => {
+                    error!("Ошибка при триангуляции точек: {:?}", e);
+                    return Err(e);
+                }
+            };
+
+            let mut cloud = PointCloud {
+                points: points_3d,
+                timestamp: current_frame,
+            };
+
+            // Идентификаторы совпадают с треками, которые начинаются с этих точек
+            for (track_id, point) in cloud.points.iter_mut().enumerate() {
+                point.track_id = Some(track_id);
+            }
+            Self::reject_masked(&mut cloud, &points_2d, &masks, visibility.as_deref());
+
+            self.color_cloud(&mut cloud, &points_2d, &frames);
+            self.filter_color_consistency(&mut cloud, &points_2d, &frames, visibility.as_deref());
+            drop_untriangulated_points(&mut cloud);
+
+            let initial_count = cloud.points.len();
+            self.filter_cloud(&mut cloud);
+            info!(
+                "Отфильтровано {} точек (оставлено {})",
+                initial_count - cloud.points.len(),
+                cloud.points.len()
+            );
+
+            if let Some(&(_, stats)) = error_stats.last() {
+                report.add_frame(current_frame, cloud.points.len(), stats);
+            }
+
+            if self.settings.keep_descriptors {
+                track_descriptors = self.save_track_descriptors(
+                    &all_matches,
+                    &descriptors_list,
+                    &cloud,
+                    &dest_path,
+                );
+            }
+
+            if self.settings.export_correspondences {
+                match correspondences_from_matches(&all_matches, &keypoints_list) {
+                    Ok(observations) => {
+                        self.save_correspondences(&observations, &dest_path, current_frame)
+                    }
+                    Err(e) => error!("Наблюдения треков не собраны: {}", e),
+                }
+            }
+
+            if let Some(frame) = &board_frame {
+                frame.transform_cloud(&mut cloud);
+            }
+            if let Some(stabilizer) = &mut stabilizer {
+                stabilizer.stabilize(&mut cloud);
+            }
+
+            if let Some(map) = &mut fused_map {
+                map.add_cloud(&cloud);
+            }
+            lifespans.observe(&cloud);
+
+            self.report_frame(
+                current_frame,
+                video_data.total_frames,
+                Some(cloud.points.len()),
+            );
+            cloud_writer.write(cloud);
+        }
 
         let mut prev_images = frames.clone();
 
@@ -286,20 +866,34 @@ impl ReconstructionApp {
             }
         }
 
+        let static_filter = self.settings.static_track_filter;
+        let mut tracks = TrackManager::new(
+            prev_points.first().map_or(0, |p| p.len()),
+            static_filter.map_or(2, |f| f.window),
+        );
+        tracks.record(&prev_points)?;
+
+        let mut debug_video = self.open_debug_video(video_data, project_path, frames.len())?;
+        if let Some(writer) = &mut debug_video {
+            writer.write_frame(current_frame, &frames, &prev_points)?;
+        }
+
         for current_frame in 1..video_data.total_frames {
-            read_frames(&mut caps, &mut frames)?;
-            let win_size = opencv::core::Size::new(13, 13);
-            let max_level = 3;
-            let criteria = opencv::core::TermCriteria::new(
-                opencv::core::TermCriteria_EPS + opencv::core::TermCriteria_COUNT,
-                1000_000,
-                0.000_001,
-            )
-            .unwrap();
+            if self.cancelled() {
+                info!("Реконструкция остановлена перед кадром {}", current_frame);
+                break;
+            }
+            self.read_pipeline_frames(source.as_mut(), &mut frames, calibration_data)?;
+            let flow = self.settings.optical_flow;
+            let criteria = flow.criteria()?;
             let flags = 0;
-            let min_eig_threshold = 1e-4;
 
-            let mut undistorted_points_2d = Vector::<Mat>::default();
+            // При продолжении прерванного запуска оптический поток всё равно считается,
+            // чтобы треки дошли до следующего необработанного кадра, а триангуляция пропускается
+            let filename = dest_path.join(format!("point_cloud_{current_frame}.ply"));
+            let skip_frame = self.is_frame_already_written(&filename);
+
+            let mut tracked_ratios = Vec::with_capacity(frames.len());
 
             for (camera_i, (prev, next)) in prev_images.iter().zip(frames.iter()).enumerate() {
                 // Подготавливаем данные для оптического потока
@@ -315,29 +909,61 @@ impl ReconstructionApp {
                     &mut next_points,
                     &mut status,
                     &mut err,
-                    win_size,
-                    max_level,
+                    flow.window(),
+                    flow.max_level,
                     criteria,
                     flags,
-                    min_eig_threshold,
+                    flow.min_eig_threshold,
                 )
                 .unwrap();
 
-                debug!(
-                    "Потеряно треков: {}",
-                    status.iter().filter(|&s| s == 0).count()
+                let lost = status.iter().filter(|&s| s == 0).count();
+                debug!("Потеряно треков: {}", lost);
+                tracked_ratios.push(if status.is_empty() {
+                    0.0
+                } else {
+                    (status.len() - lost) as f32 / status.len() as f32
+                });
+
+                prev_points[camera_i] = next_points;
+            }
+
+            tracks.record(&prev_points)?;
+            if let Some(filter) = &static_filter {
+                tracks.drop_static_tracks(&mut prev_points, filter);
+            }
+
+            if let Some(writer) = &mut debug_video {
+                writer.write_frame(current_frame, &frames, &prev_points)?;
+            }
+
+            if skip_frame {
+                debug!("Кадр {} уже обработан, пропускаем", current_frame);
+                self.observe_written_frame(
+                    &filename,
+                    current_frame,
+                    &mut fused_map,
+                    &mut lifespans,
                 );
+                self.report_frame(current_frame, video_data.total_frames, None);
+                prev_images = frames.clone();
+                continue;
+            }
 
-                let points_mat = match vector_point2f_to_mat(&next_points) {
+            let mut tracked_points_2d = Vector::<Mat>::default();
+            let mut undistorted_points_2d = Vector::<Mat>::default();
+            for (camera_i, points) in prev_points.iter().enumerate() {
+                let points_mat = match vector_point2f_to_mat(points) {
                     Ok(mat) => mat,
                     Err(e) => {
                         error!("Ошибка конвертации из vector в mat: {}", e);
                         return Err(e);
                     }
                 };
-                let undistorted_nx2 = match undistort_points_single_camera(
+                let undistorted_nx2 = match self.undistort_frame_points(
                     &points_mat,
                     &calibration_data.camera_params[camera_i],
+                    &mut pool,
                 ) {
                     Ok(u_nx2) => u_nx2,
                     Err(e) => {
@@ -346,19 +972,37 @@ impl ReconstructionApp {
                     }
                 };
                 undistorted_points_2d.push(undistorted_nx2);
-
-                prev_points[camera_i] = next_points;
+                tracked_points_2d.push(points_mat);
             }
 
-            let points_3d = match lib_cv::reconstruction::triangulate_points_multiple(
+            let active_cameras = self.active_camera_mask(&frames, Some(&tracked_ratios))?;
+            let track_visibility: Option<Vec<Vec<bool>>> = visibility.as_ref().map(|visibility| {
+                tracks
+                    .track_ids()
+                    .iter()
+                    .map(|&id| visibility[id].clone())
+                    .collect()
+            });
+            if self.settings.export_correspondences {
+                let observations = tracked_observations(
+                    &prev_points,
+                    tracks.track_ids(),
+                    track_visibility.as_deref(),
+                );
+                self.save_correspondences(&observations, &dest_path, current_frame);
+            }
+            let points_3d = match Self::triangulate_frame(
                 &undistorted_points_2d,
-                &calibration_data.camera_params,
+                &triangulation,
+                active_cameras.as_deref(),
+                track_visibility.as_deref(),
             ) {
-                Ok(points) => {
+                Ok((points, stats)) => {
                     info!(
                         "Триангуляция успешно выполнена. Получено {} 3D точек",
                         points.len()
                     );
+                    error_stats.push((current_frame, stats));
                     points
                 }
                 Err(e) => {
@@ -366,37 +1010,1443 @@ impl ReconstructionApp {
                     return Err(e);
                 }
             };
+            for points in undistorted_points_2d {
+                pool.recycle(points);
+            }
 
             let mut cloud = PointCloud {
                 points: points_3d,
                 timestamp: current_frame,
             };
+            for (point, &track_id) in cloud.points.iter_mut().zip(tracks.track_ids()) {
+                point.track_id = Some(track_id);
+            }
+            Self::reject_masked(
+                &mut cloud,
+                &tracked_points_2d,
+                &masks,
+                track_visibility.as_deref(),
+            );
 
-            add_color_to_point_cloud(&mut cloud, &points_2d, &frames[0]);
+            self.color_cloud(&mut cloud, &tracked_points_2d, &frames);
+            self.filter_color_consistency(
+                &mut cloud,
+                &tracked_points_2d,
+                &frames,
+                track_visibility.as_deref(),
+            );
+            drop_untriangulated_points(&mut cloud);
 
             // Фильтрация по уверенности
             let initial_count = cloud.points.len();
-            filter_point_cloud_by_confindence(&mut cloud, 0.25);
+            self.filter_cloud(&mut cloud);
             info!(
                 "Отфильтровано {} точек (оставлено {})",
                 initial_count - cloud.points.len(),
                 cloud.points.len()
             );
             info!("Обработка облака точек завершена");
+            if let Some(&(_, stats)) = error_stats.last() {
+                report.add_frame(current_frame, cloud.points.len(), stats);
+            }
 
-            let filename = dest_path.join(format!("point_cloud_{current_frame}.ply"));
+            if let Some(descriptors) = &track_descriptors {
+                self.recover_lost_tracks(
+                    &cloud,
+                    descriptors,
+                    &frames[0],
+                    tracks.track_ids(),
+                    &mut prev_points[0],
+                );
+            }
 
-            match save_point_cloud(&cloud, &filename) {
-                Ok(_) => info!(
-                    "Облако точек успешно сохранено в файл: {}",
-                    filename.display()
-                ),
-                Err(e) => error!("Ошибка при сохранении облака точек: {:?}", e),
-            };
+            if let Some(frame) = &board_frame {
+                frame.transform_cloud(&mut cloud);
+            }
+            if let Some(stabilizer) = &mut stabilizer {
+                stabilizer.stabilize(&mut cloud);
+            }
 
-            prev_images = frames.clone();
+            if let Some(map) = &mut fused_map {
+                map.add_cloud(&cloud);
+            }
+            lifespans.observe(&cloud);
+
+            self.report_frame(
+                current_frame,
+                video_data.total_frames,
+                Some(cloud.points.len()),
+            );
+            cloud_writer.write(cloud);
+
+            prev_images = frames.clone();
+        }
+
+        drop(cloud_writer);
+
+        if let Some(writer) = debug_video {
+            writer.finish()?;
+        }
+
+        let pool_stats = pool.stats() + triangulation.pool_stats();
+        debug!(
+            "Буферы точек: выделено {}, переиспользовано {}",
+            pool_stats.allocated, pool_stats.reused
+        );
+
+        if let Some(map) = &fused_map {
+            let fused_path = dest_path.join("fused_point_cloud.ply");
+            match save_point_cloud_with_comments(
+                &map.to_point_cloud(0),
+                &self.ply_comments(None),
+                &fused_path,
+            ) {
+                Ok(_) => info!(
+                    "Общая карта ({} точек) сохранена в {}",
+                    map.len(),
+                    fused_path.display()
+                ),
+                Err(e) => error!("Ошибка при сохранении общей карты: {:?}", e),
+            }
+        }
+
+        self.save_error_stats(&error_stats, project_path);
+
+        self.save_track_lengths(&lifespans, project_path);
+        report.runtime = started.elapsed();
+        self.save_run_report(&report, project_path);
+
+        Ok(())
+    }
+
+    /// Сохраняет статистику ошибок по кадрам в CSV, если он задан в настройках
+    fn save_error_stats(&self, error_stats: &[(usize, ErrorStats)], project_path: &Path) {
+        let Some(csv_path) = &self.settings.error_stats_csv else {
+            return;
+        };
+        let csv_path = self.resources.layout.report(project_path, csv_path);
+        if let Some(parent) = csv_path.parent()
+            && let Err(e) = create_dir_all(parent)
+        {
+            error!("Не удалось создать {}: {}", parent.display(), e);
+        }
+        // При продолжении запуска в файле уже есть строки обработанных раньше кадров:
+        // новые строки дополняют их, а не заменяют файл
+        let merged;
+        let error_stats = if self.settings.resume && csv_path.exists() {
+            match load_error_stats_csv(&csv_path) {
+                Ok(previous) => {
+                    merged = merge_error_stats(previous, error_stats);
+                    &merged[..]
+                }
+                Err(e) => {
+                    warn!(
+                        "Прежняя статистика ошибок не прочитана и будет заменена: {}",
+                        e
+                    );
+                    error_stats
+                }
+            }
+        } else {
+            error_stats
+        };
+        match save_error_stats_csv(error_stats, &csv_path) {
+            Ok(_) => info!(
+                "Статистика ошибок перепроекции сохранена в {}",
+                csv_path.display()
+            ),
+            Err(e) => error!("Ошибка при сохранении статистики ошибок: {:?}", e),
+        }
+    }
+
+    /// Сверяет существенные матрицы калибровки с сопоставлениями первого кадра: большая
+    /// невязка предупреждает о сбитой калибровке до того, как облака окажутся неверными.
+    /// `camera_params` - параметры, по которым найдены признаки (см. `pipeline_camera_params`)
+    fn check_essential_matrices(
+        &self,
+        camera_params: &[CameraParameters],
+        all_matches: &[Vector<Vector<DMatch>>],
+        keypoints_list: &[Vector<KeyPoint>],
+    ) {
+        let Some(reference) = camera_params.first() else {
+            return;
+        };
+        for (pair_i, matches) in all_matches.iter().enumerate() {
+            let camera_i = pair_i + 1;
+            let (Some(camera), Some(keypoints)) =
+                (camera_params.get(camera_i), keypoints_list.get(camera_i))
+            else {
+                continue;
+            };
+            // Лучший сосед каждого сопоставления
+            let best: Vector<DMatch> = matches.iter().filter_map(|m| m.get(0).ok()).collect();
+            info!("Проверка существенной матрицы камеры {}", camera_i + 1);
+            if let Err(e) =
+                essential_matrix_residual(reference, camera, &keypoints_list[0], keypoints, &best)
+            {
+                warn!(
+                    "Существенная матрица камеры {} не проверена: {}",
+                    camera_i + 1,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Пишет в лог сводку длин треков и сохраняет их гистограмму в папку отчётов
+    fn save_track_lengths(&self, lifespans: &TrackLifespans, project_path: &Path) {
+        let histogram = lifespans.histogram();
+        log_track_length_summary(&histogram);
+        let path = self
+            .resources
+            .layout
+            .report(project_path, Path::new(TRACK_LENGTHS_FILE));
+        if let Some(parent) = path.parent()
+            && let Err(e) = create_dir_all(parent)
+        {
+            error!("Не удалось создать {}: {}", parent.display(), e);
+        }
+        match save_track_length_histogram_csv(&histogram, &path) {
+            Ok(_) => info!("Гистограмма длин треков сохранена в {}", path.display()),
+            Err(e) => error!("Ошибка при сохранении гистограммы длин треков: {:?}", e),
+        }
+    }
+
+    /// Строки заголовка PLY о происхождении облака: видео, файл калибровки, кадр
+    /// (`None` - общая карта) и настройки реконструкции
+    fn ply_comments(&self, frame: Option<usize>) -> Vec<String> {
+        if !self.settings.ply_comments {
+            return Vec::new();
+        }
+        let mut comments = vec![format!(
+            "generated by reconstruction_app {}",
+            env!("CARGO_PKG_VERSION")
+        )];
+        if let Some(video_data) = &self.resources.video_data {
+            let mut videos: Vec<String> = video_data
+                .video_files
+                .iter()
+                .flatten()
+                .map(|path| path.display().to_string())
+                .collect();
+            videos.dedup();
+            comments.push(format!("video {}", videos.join(", ")));
         }
+        if let Some(calibration_data) = &self.resources.calibration_data {
+            comments.push(format!(
+                "calibration {}",
+                calibration_data.calibration_file.display()
+            ));
+            comments.push(format!("unit {}", calibration_data.unit()));
+        }
+        comments.push(match frame {
+            Some(frame) => format!("frame {}", frame),
+            None => "frame fused".to_string(),
+        });
+        comments.push(format!("settings {}", self.settings.describe()));
+        comments
+    }
 
+    /// Сохраняет облако кадра `cloud.timestamp` в `point_cloud_{кадр}.ply`
+    fn save_frame_cloud(&self, cloud: &PointCloud, dest_path: &Path) {
+        let filename = dest_path.join(format!("point_cloud_{}.ply", cloud.timestamp));
+        // Номера треков нужны, чтобы при продолжении запуска облако кадра снова слилось
+        // с общей картой по трекам
+        let track_ids = cloud
+            .points
+            .iter()
+            .map(|p| p.track_id.map_or(-1.0, |id| id as f32))
+            .collect();
+        match save_point_cloud_extended(
+            cloud,
+            &self.ply_comments(Some(cloud.timestamp)),
+            &[(PLY_TRACK_ID_PROPERTY.to_string(), track_ids)],
+            &filename,
+        ) {
+            Ok(_) => info!(
+                "Облако точек успешно сохранено в файл: {}",
+                filename.display()
+            ),
+            Err(e) => error!("Ошибка при сохранении облака точек: {:?}", e),
+        };
+    }
+
+    /// Сохраняет лист признаков первого кадра в папку отчётов для отчёта о запуске;
+    /// ошибка не прерывает реконструкцию
+    fn save_detection_sheet(
+        &self,
+        frames: &[Mat],
+        keypoints: &[Vector<KeyPoint>],
+        project_path: &Path,
+    ) {
+        let path = self
+            .resources
+            .layout
+            .report(project_path, Path::new(DETECTION_SHEET_FILE));
+        let cols = (frames.len() as f64).sqrt().ceil() as usize;
+        let result = detection_contact_sheet(frames, keypoints, cols).and_then(|sheet| {
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).map_err(|e| Error::new(-1, e.to_string()))?;
+            }
+            write_atomically(&path, |tmp| -> Result<(), UtilsError> {
+                imwrite(&tmp.to_string_lossy(), &sheet, &Vector::new())?;
+                Ok(())
+            })
+            .map_err(|e| Error::new(-1, e.to_string()))
+        });
+        match result {
+            Ok(_) => debug!("Лист признаков сохранён в {}", path.display()),
+            Err(e) => warn!("Лист признаков не сохранён: {}", e),
+        }
+    }
+
+    /// Сохраняет сводку запуска в папку отчётов; ошибка записи не прерывает реконструкцию
+    fn save_run_report(&self, report: &ReconstructionReport, project_path: &Path) {
+        let path = self
+            .resources
+            .layout
+            .report(project_path, Path::new(RUN_REPORT_FILE));
+        if let Some(parent) = path.parent()
+            && let Err(e) = create_dir_all(parent)
+        {
+            error!("Не удалось создать {}: {}", parent.display(), e);
+        }
+        match report.save(&path) {
+            Ok(_) => info!("Сводка запуска сохранена в {}", path.display()),
+            Err(e) => error!("Ошибка при сохранении сводки запуска: {:?}", e),
+        }
+
+        let Some(calibration_data) = &self.resources.calibration_data else {
+            return;
+        };
+        // Карты покрытия калибровка сохраняет рядом с файлом параметров камер
+        let calibration_dir = calibration_data
+            .calibration_file
+            .parent()
+            .unwrap_or(Path::new(""));
+        let mut images: Vec<(String, PathBuf)> = (1..=calibration_data.num_cameras)
+            .map(|cam| {
+                (
+                    format!("Calibration coverage, camera {}", cam),
+                    calibration_dir.join(format!("coverage_camera_{}.png", cam)),
+                )
+            })
+            .collect();
+        images.push((
+            "Detected features, first frame".to_string(),
+            path.with_file_name(DETECTION_SHEET_FILE),
+        ));
+        let path = path.with_file_name(MARKDOWN_REPORT_FILE);
+        match write_reconstruction_report(report, &calibration_data.camera_params, &images, &path) {
+            Ok(_) => info!("Отчёт о запуске сохранён в {}", path.display()),
+            Err(e) => error!("Ошибка при сохранении отчёта о запуске: {:?}", e),
+        }
+    }
+
+    /// Читает следующий кадр всех камер и, если включено, сразу устраняет дисторсию,
+    /// чтобы признаки искались и сопоставлялись в прямолинейном пространстве
+    fn read_pipeline_frames(
+        &self,
+        source: &mut dyn FrameSource,
+        frames: &mut Vec<Mat>,
+        calibration_data: &CalibrationData,
+    ) -> Result<(), Error> {
+        read_frames(source, frames)?;
+        if self.settings.undistort_frames {
+            for (frame, camera) in frames.iter_mut().zip(&calibration_data.camera_params) {
+                *frame = undistort_image(frame, camera)?;
+            }
+        }
         Ok(())
     }
+
+    /// Параметры камер для триангуляции: при исправленных кадрах дисторсия уже учтена
+    fn pipeline_camera_params(
+        &self,
+        calibration_data: &CalibrationData,
+    ) -> Result<Vec<CameraParameters>, Error> {
+        if self.settings.undistort_frames {
+            calibration_data
+                .camera_params
+                .iter()
+                .map(rectilinear_camera)
+                .collect()
+        } else {
+            Ok(calibration_data.camera_params.clone())
+        }
+    }
+
+    /// Уточняет сопоставленные точки каждой камеры до долей пикселя по её кадру
+    fn refine_frame_points(points_2d: &Vector<Mat>, frames: &[Mat]) -> Result<Vector<Mat>, Error> {
+        let mut refined = Vector::<Mat>::with_capacity(points_2d.len());
+        for (points, frame) in points_2d.iter().zip(frames) {
+            let mut camera_points = Vector::<Point2f>::with_capacity(points.rows() as usize);
+            for row in 0..points.rows() {
+                camera_points.push(Point2f::new(
+                    *points.at_2d::<f64>(row, 0)? as f32,
+                    *points.at_2d::<f64>(row, 1)? as f32,
+                ));
+            }
+            let camera_points = refine_subpixel(frame, &camera_points)?;
+            refined.push(vector_point2f_to_mat(&camera_points)?);
+        }
+        Ok(refined)
+    }
+
+    /// Исправляет дисторсию точек, если кадры не были исправлены целиком
+    fn undistort_frame_points(
+        &self,
+        points: &Mat,
+        camera: &CameraParameters,
+        pool: &mut MatPool,
+    ) -> Result<Mat, Error> {
+        if self.settings.undistort_frames {
+            Ok(points.clone())
+        } else {
+            undistort_points_pooled(points, camera, pool)
+        }
+    }
+
+    /// Маски камер из папки масок проекта (см. [`ProjectLayout::camera_mask`]), по одной
+    /// на камеру; `None` - маски нет или она не подходит к кадру. Маска задаётся
+    /// в координатах исходного кадра и при исправлении дисторсии кадров исправляется вместе с ним
+    fn load_camera_masks(
+        &self,
+        frames: &[Mat],
+        calibration_data: &CalibrationData,
+        project_path: &Path,
+    ) -> Vec<Option<Mat>> {
+        frames
+            .iter()
+            .zip(&calibration_data.camera_params)
+            .enumerate()
+            .map(|(camera_i, (frame, camera))| {
+                let path = self.resources.layout.camera_mask(project_path, camera_i);
+                if !path.exists() {
+                    return None;
+                }
+                let mask = frame.size().and_then(|size| load_mask(&path, size));
+                let mask = if self.settings.undistort_frames {
+                    mask.and_then(|mask| undistort_mask(&mask, camera))
+                } else {
+                    mask
+                };
+                match mask {
+                    Ok(mask) => {
+                        info!(
+                            "Маска камеры {} загружена из {}",
+                            camera_i + 1,
+                            path.display()
+                        );
+                        Some(mask)
+                    }
+                    Err(e) => {
+                        warn!("Маска камеры {} не применяется: {}", camera_i + 1, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Отбрасывает точки, попавшие в закрытую маской область какой-либо камеры
+    fn reject_masked(
+        cloud: &mut PointCloud,
+        points_2d: &Vector<Mat>,
+        masks: &[Option<Mat>],
+        visibility: Option<&[Vec<bool>]>,
+    ) {
+        if masks.iter().all(Option::is_none) {
+            return;
+        }
+        match reject_masked_points(cloud, points_2d, masks, visibility) {
+            Ok(0) => {}
+            Ok(rejected) => info!("Отброшено {} точек в закрытых масками областях", rejected),
+            Err(e) => warn!("Маски камер не применены к облаку: {}", e),
+        }
+    }
+
+    /// Раскрашивает облако по текущему кадру выбранной камеры
+    /// в текущих координатах отслеживаемых точек
+    fn color_cloud(&self, cloud: &mut PointCloud, points_2d: &Vector<Mat>, frames: &[Mat]) {
+        let camera = self
+            .settings
+            .color_camera
+            .min(frames.len().saturating_sub(1));
+        if let Some(frame) = frames.get(camera) {
+            add_color_to_point_cloud_from_camera(cloud, points_2d, camera, frame);
+        }
+    }
+
+    /// Сохраняет наблюдения треков кадра `frame` в `correspondences_{frame}.csv`;
+    /// ошибка записи не прерывает реконструкцию
+    fn save_correspondences(
+        &self,
+        observations: &[(usize, usize, Point2f)],
+        dest_path: &Path,
+        frame: usize,
+    ) {
+        let path = dest_path.join(format!("correspondences_{frame}.csv"));
+        match save_correspondences_csv(observations, &path) {
+            Ok(_) => debug!(
+                "Наблюдения треков ({}) сохранены в {}",
+                observations.len(),
+                path.display()
+            ),
+            Err(e) => error!("Ошибка при сохранении {}: {}", path.display(), e),
+        }
+    }
+
+    /// Отбрасывает точки, цвет которых заметно различается между камерами, если это включено
+    /// в настройках. С флагами видимости точки сравниваются только в видящих их камерах
+    fn filter_color_consistency(
+        &self,
+        cloud: &mut PointCloud,
+        points_2d: &Vector<Mat>,
+        frames: &[Mat],
+        visibility: Option<&[Vec<bool>]>,
+    ) {
+        let Some(max_diff) = self.settings.max_color_diff else {
+            return;
+        };
+        let result = match visibility {
+            Some(visibility) => hide_unobserved_points(points_2d, visibility)
+                .and_then(|points| filter_by_color_consistency(cloud, frames, &points, max_diff)),
+            None => filter_by_color_consistency(cloud, frames, points_2d, max_diff),
+        };
+        if let Err(e) = result {
+            warn!("Фильтр по цвету не применён: {}", e);
+        }
+    }
+
+    /// Система доски ChArUco по первому кадру камеры 0, если она включена в настройках.
+    /// Возвращает `None`, если доска калибровки неизвестна или не найдена на кадре:
+    /// тогда облака и позы остаются в системе камеры 0
+    fn board_frame(
+        &self,
+        frames: &[Mat],
+        camera_params: &[CameraParameters],
+        calibration_data: &CalibrationData,
+    ) -> Option<BoardFrame> {
+        if !self.settings.board_frame {
+            return None;
+        }
+        let Some(board) = &calibration_data.board else {
+            warn!("Доска калибровки неизвестна: облака остаются в системе камеры 0");
+            return None;
+        };
+        let (frame, camera) = (frames.first()?, camera_params.first()?);
+        let result = BoardConfig::from(board.clone())
+            .build()
+            .and_then(|charuco_board| {
+                let detection = get_charuco(&charuco_board, frame)?;
+                estimate_board_pose(&detection, &charuco_board, camera)
+            })
+            .and_then(|(rvec, tvec)| BoardFrame::from_board_pose(&rvec, &tvec));
+        match result {
+            Ok(board_frame) => {
+                info!("Облака точек будут переведены в систему доски");
+                Some(board_frame)
+            }
+            Err(e) => {
+                warn!(
+                    "Доска не найдена на первом кадре камеры 0 ({}): облака остаются в системе камеры 0",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Сохраняет позы камер в системе доски рядом с облаками точек
+    fn save_board_frame_poses(
+        &self,
+        board_frame: &BoardFrame,
+        camera_params: &[CameraParameters],
+        dest_path: &Path,
+    ) {
+        let path = dest_path.join(BOARD_FRAME_POSES_FILE);
+        let result = camera_params
+            .iter()
+            .map(|camera| board_frame.transform_camera(camera))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|cameras| save_camera_poses(&cameras, &path));
+        match result {
+            Ok(_) => info!("Позы камер в системе доски сохранены в {}", path.display()),
+            Err(e) => error!("Ошибка при сохранении поз камер: {:?}", e),
+        }
+    }
+
+    /// Сохраняет рядом с облаками дескрипторы камеры 0 для треков, оставшихся в первом
+    /// облаке после фильтрации: по ним `rematch_by_descriptor` находит потерянные треки.
+    /// Возвращает сохранённые дескрипторы
+    fn save_track_descriptors(
+        &self,
+        all_matches: &[Vector<Vector<DMatch>>],
+        descriptors_list: &[Mat],
+        cloud: &PointCloud,
+        dest_path: &Path,
+    ) -> Option<TrackDescriptors> {
+        let path = dest_path.join(TRACK_DESCRIPTORS_FILE);
+        let (Some(reference_matches), Some(reference_descriptors)) =
+            (all_matches.first(), descriptors_list.first())
+        else {
+            warn!("Нет совпадений камеры 0, дескрипторы треков не сохранены");
+            return None;
+        };
+        let result = TrackDescriptors::from_matches(reference_matches, reference_descriptors)
+            .and_then(|mut descriptors| {
+                descriptors.retain_cloud(cloud)?;
+                descriptors.save(&path)?;
+                Ok(descriptors)
+            });
+        match result {
+            Ok(descriptors) => {
+                info!(
+                    "Дескрипторы {} треков сохранены в {}",
+                    descriptors.len(),
+                    path.display()
+                );
+                Some(descriptors)
+            }
+            Err(e) => {
+                error!("Ошибка при сохранении дескрипторов треков: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Дескрипторы треков, сохранённые прерванным запуском вместе с первым облаком
+    fn load_track_descriptors(&self, dest_path: &Path) -> Option<TrackDescriptors> {
+        let path = dest_path.join(TRACK_DESCRIPTORS_FILE);
+        match TrackDescriptors::load(&path) {
+            Ok(descriptors) => {
+                info!(
+                    "Дескрипторы {} треков загружены из {}",
+                    descriptors.len(),
+                    path.display()
+                );
+                Some(descriptors)
+            }
+            Err(e) => {
+                warn!(
+                    "Дескрипторы треков не загружены ({}), потерянные треки не восстанавливаются",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Ищет по дескрипторам на кадре камеры 0 треки, которых нет в облаке `cloud`, и
+    /// переносит найденные в точки камеры 0, с которых оптический поток продолжит треки
+    fn recover_lost_tracks(
+        &self,
+        cloud: &PointCloud,
+        descriptors: &TrackDescriptors,
+        frame: &Mat,
+        track_ids: &[usize],
+        points: &mut Vector<Point2f>,
+    ) {
+        let recovered = rematch_by_descriptor(
+            cloud,
+            descriptors,
+            frame,
+            &self.settings.sift,
+            self.settings.match_ratio,
+        )
+        .and_then(|recovered| apply_recovered_tracks(points, track_ids, &recovered));
+        match recovered {
+            Ok(count) => debug!("Восстановлено треков по дескрипторам: {}", count),
+            Err(e) => warn!("Потерянные треки не восстановлены: {}", e),
+        }
+    }
+
+    /// Мягкий фильтр по уверенности и, если задан, жёсткий порог ошибки перепроекции
+    fn filter_cloud(&self, cloud: &mut PointCloud) {
+        filter_point_cloud_by_confindence(cloud, self.settings.min_confidence);
+        if let Some(max_px) = self.settings.max_reproj_error {
+            filter_point_cloud_by_max_reproj(cloud, max_px);
+        }
+    }
+
+    /// Открывает отладочное видео, если оно включено в настройках.
+    /// Частота кадров берётся из видео первой камеры
+    fn open_debug_video(
+        &self,
+        video_data: &VideoData,
+        project_path: &Path,
+        num_cameras: usize,
+    ) -> Result<Option<DebugVideoWriter>, Error> {
+        let Some(video_path) = &self.settings.debug_video else {
+            return Ok(None);
+        };
+        let first_video = video_data
+            .video_files
+            .first()
+            .and_then(|f| f.as_ref())
+            .ok_or_else(|| Error::new(-1, "Нет видео первой камеры"))?;
+        let fps = get_video_fps(first_video)?;
+        let cols = (num_cameras as f64).sqrt().ceil() as usize;
+        let video_path = self.resources.layout.report(project_path, video_path);
+        if let Some(parent) = video_path.parent() {
+            create_dir_all(parent)
+                .map_err(|e| Error::new(-1, format!("Не удалось создать директорию: {}", e)))?;
+        }
+        info!("Отладочное видео будет записано в {}", video_path.display());
+        Ok(Some(DebugVideoWriter::new(&video_path, fps, cols.max(1))))
+    }
+
+    /// Маска камер, участвующих в триангуляции кадра, или `None`, если маска отключена.
+    /// Камера исключается, если её кадр почти однотонный или на нём осталось
+    /// слишком мало отслеживаемых точек (`tracked_ratios` - доля успешно прослеженных треков)
+    fn active_camera_mask(
+        &self,
+        frames: &[Mat],
+        tracked_ratios: Option<&[f32]>,
+    ) -> Result<Option<Vec<bool>>, Error> {
+        if !self.settings.camera_mask {
+            return Ok(None);
+        }
+        let mut mask = detect_active_cameras(frames, self.settings.min_frame_std_dev)?;
+        if let Some(ratios) = tracked_ratios {
+            for (camera_i, (active, &ratio)) in mask.iter_mut().zip(ratios).enumerate() {
+                if *active && ratio < self.settings.min_tracked_ratio {
+                    debug!(
+                        "Камера {}: прослежено {:.0}% треков, камера исключена",
+                        camera_i,
+                        ratio * 100.0
+                    );
+                    *active = false;
+                }
+            }
+        }
+        Ok(Some(mask))
+    }
+
+    /// Триангулирует кадр по активным камерам; с флагами видимости каждая точка
+    /// триангулируется только по тем из них, где она найдена
+    fn triangulate_frame(
+        points_2d: &Vector<Mat>,
+        triangulation: &TriangulationContext,
+        active_cameras: Option<&[bool]>,
+        visibility: Option<&[Vec<bool>]>,
+    ) -> Result<(Vec<Point3D>, ErrorStats), Error> {
+        match (visibility, active_cameras) {
+            (Some(visibility), Some(active)) => {
+                let visibility: Vec<Vec<bool>> = visibility
+                    .iter()
+                    .map(|mask| mask.iter().zip(active).map(|(&v, &a)| v && a).collect())
+                    .collect();
+                triangulation.triangulate_visible(points_2d, &visibility)
+            }
+            (Some(visibility), None) => triangulation.triangulate_visible(points_2d, visibility),
+            (None, Some(active)) => triangulation.triangulate_active(points_2d, active),
+            (None, None) => triangulation.triangulate(points_2d),
+        }
+    }
+
+    /// В режиме продолжения кадр считается обработанным, если его облако точек уже записано
+    fn is_frame_already_written(&self, cloud_path: &Path) -> bool {
+        self.settings.resume && cloud_path.exists()
+    }
+
+    /// Добавляет облако кадра, пропущенного при продолжении запуска, в общую карту и длины
+    /// треков, читая его из файла: иначе они покрывали бы только кадры текущего запуска.
+    /// Облака без номеров треков (записанные старыми версиями) сливаются по расстоянию
+    fn observe_written_frame(
+        &self,
+        cloud_path: &Path,
+        frame: usize,
+        fused_map: &mut Option<FusedMap>,
+        lifespans: &mut TrackLifespans,
+    ) {
+        match load_point_cloud(cloud_path) {
+            Ok(mut cloud) => {
+                cloud.timestamp = frame;
+                if let Some(map) = fused_map {
+                    map.add_cloud(&cloud);
+                }
+                lifespans.observe(&cloud);
+            }
+            Err(e) => warn!(
+                "Облако кадра {} не прочитано и не попадёт в общую карту и длины треков: {}",
+                frame, e
+            ),
+        }
+    }
+
+    /// Режим одной камеры: триангулировать не по чему, поэтому признаки SIFT первого кадра
+    /// только прослеживаются оптическим потоком, а их положения сохраняются в CSV
+    /// (frame, track_id, x, y). Потерянный трек дальше не прослеживается
+    fn run_tracking_only_pipeline(
+        &self,
+        source: &mut dyn FrameSource,
+        frames: &mut Vec<Mat>,
+        total_frames: usize,
+        calibration_data: &CalibrationData,
+        project_path: &Path,
+    ) -> Result<(), opencv::Error> {
+        warn!("Загружена одна камера: реконструкция невозможна, сохраняются только 2D треки");
+        self.read_pipeline_frames(source, frames, calibration_data)?;
+        let masks = self.load_camera_masks(frames, calibration_data, project_path);
+        let mask = masks.first().cloned().flatten().unwrap_or_default();
+        let (keypoints, _) = sift_with_mask(&frames[0], &self.settings.sift, &mask)?;
+        let mut points: Vector<Point2f> = keypoints.iter().map(|kp| kp.pt()).collect();
+        info!("Найдено {} признаков для отслеживания", points.len());
+
+        let static_filter = self.settings.static_track_filter;
+        let mut tracks = TrackManager::new(points.len(), static_filter.map_or(2, |f| f.window));
+        tracks.record(std::slice::from_ref(&points))?;
+        let mut rows: Vec<(usize, usize, Point2f)> = tracks
+            .track_ids()
+            .iter()
+            .zip(&points)
+            .map(|(&track_id, point)| (0, track_id, point))
+            .collect();
+
+        let flow = self.settings.optical_flow;
+        let criteria = flow.criteria()?;
+        let mut prev_image = frames[0].clone();
+        for current_frame in 1..total_frames {
+            if self.cancelled() {
+                info!("Отслеживание остановлено перед кадром {}", current_frame);
+                break;
+            }
+            self.read_pipeline_frames(source, frames, calibration_data)?;
+            let mut next_points = Vector::<Point2f>::default();
+            let mut status = Vector::<u8>::default();
+            let mut err = Vector::<f32>::default();
+            calc_optical_flow_pyr_lk(
+                &prev_image,
+                &frames[0],
+                &points,
+                &mut next_points,
+                &mut status,
+                &mut err,
+                flow.window(),
+                flow.max_level,
+                criteria,
+                0,
+                flow.min_eig_threshold,
+            )?;
+
+            let keep: Vec<bool> = status.iter().map(|s| s != 0).collect();
+            tracks.retain(&keep);
+            points = next_points
+                .iter()
+                .zip(&keep)
+                .filter_map(|(p, &k)| k.then_some(p))
+                .collect();
+            tracks.record(std::slice::from_ref(&points))?;
+            if let Some(filter) = &static_filter {
+                tracks.drop_static_tracks(std::slice::from_mut(&mut points), filter);
+            }
+            debug!(
+                "Кадр {}: отслеживается {} треков",
+                current_frame,
+                tracks.len()
+            );
+            rows.extend(
+                tracks
+                    .track_ids()
+                    .iter()
+                    .zip(&points)
+                    .map(|(&track_id, point)| (current_frame, track_id, point)),
+            );
+            self.report_frame(current_frame, total_frames, Some(tracks.len()));
+
+            if tracks.is_empty() {
+                warn!("Все треки потеряны на кадре {}", current_frame);
+                break;
+            }
+            prev_image = frames[0].clone();
+        }
+
+        let csv_path = self
+            .resources
+            .layout
+            .report(project_path, Path::new(TRACKS_2D_FILE));
+        if let Some(parent) = csv_path.parent()
+            && let Err(e) = create_dir_all(parent)
+        {
+            error!("Не удалось создать {}: {}", parent.display(), e);
+        }
+        match save_tracks_2d_csv(&rows, &csv_path) {
+            Ok(_) => info!("2D треки сохранены в {}", csv_path.display()),
+            Err(e) => {
+                return Err(Error::new(
+                    -1,
+                    format!("Не удалось сохранить {}: {}", csv_path.display(), e),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Покадровая реконструкция для кольцевой топологии. Оптический поток не используется:
+    /// каждый кадр заново сопоставляется по соседним парам камер.
+    fn run_ring_pipeline(
+        &self,
+        source: &mut dyn FrameSource,
+        frames: &mut Vec<Mat>,
+        total_frames: usize,
+        calibration_data: &CalibrationData,
+        project_path: &Path,
+        report: &mut ReconstructionReport,
+    ) -> Result<(), opencv::Error> {
+        if self.settings.debug_video.is_some() {
+            warn!("Отладочное видео для кольцевой топологии не поддерживается: треки не строятся");
+        }
+        let masks_dir = project_path.join(&self.resources.layout.masks_dir);
+        if masks_dir.is_dir() {
+            warn!(
+                "Маски камер из {} в кольцевой топологии не применяются",
+                masks_dir.display()
+            );
+        }
+        let dest_path = self.resources.layout.point_clouds_dir(project_path);
+        if let Err(e) = create_dir_all(&dest_path) {
+            return Err(opencv::Error::new(
+                -1,
+                format!("Не удалось создать директорию: {}", e),
+            ));
+        }
+
+        let camera_params = self.pipeline_camera_params(calibration_data)?;
+
+        let mut board_frame = None;
+        let mut error_stats: Vec<(usize, ErrorStats)> = Vec::new();
+        for current_frame in 0..total_frames {
+            if self.cancelled() {
+                info!("Реконструкция остановлена перед кадром {}", current_frame);
+                break;
+            }
+            self.read_pipeline_frames(source, frames, calibration_data)?;
+            if current_frame == 0 {
+                board_frame = self.board_frame(frames, &camera_params, calibration_data);
+                if let Some(frame) = &board_frame {
+                    self.save_board_frame_poses(frame, &camera_params, &dest_path);
+                }
+            }
+
+            let filename = dest_path.join(format!("point_cloud_{current_frame}.ply"));
+            if self.is_frame_already_written(&filename) {
+                debug!("Кадр {} уже обработан, пропускаем", current_frame);
+                report.skipped_frames += 1;
+                self.report_frame(current_frame, total_frames, None);
+                continue;
+            }
+
+            let points_3d = match reconstruct_ring_frame(
+                frames,
+                &camera_params,
+                &self.settings.sift,
+                self.settings.match_ratio,
+            ) {
+                Ok(points) => points,
+                Err(e) => {
+                    error!("Ошибка при реконструкции кадра {}: {:?}", current_frame, e);
+                    return Err(e);
+                }
+            };
+            let errors: Vec<f64> = points_3d.iter().filter_map(|p| p.reproj_error).collect();
+            let stats = ErrorStats::from_errors(&errors, BAD_POINT_ERROR);
+            error_stats.push((current_frame, stats));
+
+            let mut cloud = PointCloud {
+                points: points_3d,
+                timestamp: current_frame,
+            };
+
+            let initial_count = cloud.points.len();
+            self.filter_cloud(&mut cloud);
+            info!(
+                "Отфильтровано {} точек (оставлено {})",
+                initial_count - cloud.points.len(),
+                cloud.points.len()
+            );
+            report.add_frame(current_frame, cloud.points.len(), stats);
+
+            if let Some(frame) = &board_frame {
+                frame.transform_cloud(&mut cloud);
+            }
+
+            match save_point_cloud_with_comments(
+                &cloud,
+                &self.ply_comments(Some(current_frame)),
+                &filename,
+            ) {
+                Ok(_) => info!(
+                    "Облако точек успешно сохранено в файл: {}",
+                    filename.display()
+                ),
+                Err(e) => error!("Ошибка при сохранении облака точек: {:?}", e),
+            };
+            self.report_frame(current_frame, total_frames, Some(cloud.points.len()));
+        }
+
+        self.save_error_stats(&error_stats, project_path);
+        Ok(())
+    }
+}
+
+/// Копия `points_2d`, в которой координаты точек в камерах, где их нет по `visibility`,
+/// заменены на -1: фильтр по цвету считает такие камеры не видящими точку
+/// Статистика прежнего запуска, дополненная новой; кадры, посчитанные заново, берутся
+/// из новой. Строки упорядочены по кадру
+fn merge_error_stats(
+    previous: Vec<(usize, ErrorStats)>,
+    current: &[(usize, ErrorStats)],
+) -> Vec<(usize, ErrorStats)> {
+    let mut by_frame: std::collections::BTreeMap<usize, ErrorStats> =
+        previous.into_iter().collect();
+    by_frame.extend(current.iter().copied());
+    by_frame.into_iter().collect()
+}
+
+/// Раскладка `num_cameras` камер в общем кадре размера `size`; без числа камер - 2x2
+fn combined_layout_for(num_cameras: Option<usize>, size: Size) -> Result<GridLayout, String> {
+    let Some(num_cameras) = num_cameras else {
+        warn!("Параметры камер не загружены: общее видео считается сеткой 2x2");
+        return Ok(GridLayout::default());
+    };
+    infer_grid_layout(num_cameras, size).map_err(|e| e.message)
+}
+
+/// Видео отдельных камер в папке видео проекта: все файлы, кроме общего видео
+fn camera_videos(video_dir: &Path) -> Vec<PathBuf> {
+    match video_dir.read_dir() {
+        Ok(read_dir) => read_dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && !path.ends_with(COMBINED_VIDEO_FILE))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Общее видео новее всех видео камер. В проектах, где остались оба источника,
+/// открывается тот, что выбран позже
+fn combined_is_newest(combined: &Path, camera_videos: &[PathBuf]) -> bool {
+    let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+    let Some(combined_time) = modified(combined) else {
+        return camera_videos.is_empty();
+    };
+    camera_videos
+        .iter()
+        .filter_map(|path| modified(path))
+        .all(|time| time <= combined_time)
+}
+
+fn remove_combined_video(video_dir: &Path) {
+    let combined = video_dir.join(COMBINED_VIDEO_FILE);
+    if combined.exists() {
+        match std::fs::remove_file(&combined) {
+            Ok(()) => info!("Общее видео {} заменено", combined.display()),
+            Err(e) => warn!("Не удалось удалить {}: {}", combined.display(), e),
+        }
+    }
+}
+
+fn remove_camera_videos(video_dir: &Path) {
+    for path in camera_videos(video_dir) {
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Не удалось удалить {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn hide_unobserved_points(
+    points_2d: &Vector<Mat>,
+    visibility: &[Vec<bool>],
+) -> Result<Vector<Mat>, Error> {
+    let mut hidden = Vector::<Mat>::with_capacity(points_2d.len());
+    for (camera_i, points) in points_2d.iter().enumerate() {
+        let mut points = points.try_clone()?;
+        for (point_i, mask) in visibility.iter().enumerate() {
+            if !mask.get(camera_i).copied().unwrap_or(true) && (point_i as i32) < points.rows() {
+                *points.at_2d_mut::<f64>(point_i as i32, 0)? = -1.0;
+                *points.at_2d_mut::<f64>(point_i as i32, 1)? = -1.0;
+            }
+        }
+        hidden.push(points);
+    }
+    Ok(hidden)
+}
+
+/// Наблюдения прослеживаемых треков: строка `i` наборов точек `points` соответствует треку
+/// `track_ids[i]`. Камеры, где трека нет по `visibility`, пропускаются
+fn tracked_observations(
+    points: &[Vector<Point2f>],
+    track_ids: &[usize],
+    visibility: Option<&[Vec<bool>]>,
+) -> Vec<(usize, usize, Point2f)> {
+    let mut observations = Vec::new();
+    for (track_i, &track_id) in track_ids.iter().enumerate() {
+        for (camera_i, camera_points) in points.iter().enumerate() {
+            let visible = visibility
+                .and_then(|v| v.get(track_i))
+                .and_then(|mask| mask.get(camera_i))
+                .copied()
+                .unwrap_or(true);
+            if let (true, Ok(point)) = (visible, camera_points.get(track_i)) {
+                observations.push((track_id, camera_i, point));
+            }
+        }
+    }
+    observations
+}
+
+/// Сохраняет облака кадров основного конвейера, при включённом сглаживании траекторий -
+/// с задержкой на радиус окна. Задержанные облака дописываются при уничтожении, поэтому
+/// не теряются и при выходе из конвейера по ошибке
+struct CloudWriter<'a> {
+    pipeline: &'a Pipeline,
+    dest_path: &'a Path,
+    smoother: Option<TrajectorySmoother>,
+}
+
+impl<'a> CloudWriter<'a> {
+    fn new(pipeline: &'a Pipeline, dest_path: &'a Path) -> Self {
+        Self {
+            pipeline,
+            dest_path,
+            smoother: pipeline
+                .settings
+                .trajectory_smoothing
+                .map(TrajectorySmoother::new),
+        }
+    }
+
+    fn write(&mut self, cloud: PointCloud) {
+        match &mut self.smoother {
+            Some(smoother) => {
+                if let Some(smoothed) = smoother.push(cloud) {
+                    self.pipeline.save_frame_cloud(&smoothed, self.dest_path);
+                }
+            }
+            None => self.pipeline.save_frame_cloud(&cloud, self.dest_path),
+        }
+    }
+}
+
+impl Drop for CloudWriter<'_> {
+    fn drop(&mut self) {
+        if let Some(smoother) = self.smoother.take() {
+            for cloud in smoother.finish() {
+                self.pipeline.save_frame_cloud(&cloud, self.dest_path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use lib_cv::fusion::FusionParams;
+    use lib_cv::tracking::TrajectorySmoothing;
+
+    use super::*;
+    use crate::model::ProjectLayout;
+
+    fn video_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("app_{}_{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &Path, seconds: u64) {
+        let file = std::fs::File::create(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    fn stats(mean: f64) -> ErrorStats {
+        ErrorStats {
+            mean,
+            ..ErrorStats::default()
+        }
+    }
+
+    /// Проект из двух камер с общим видео `frames` кадров: камера 1 сдвинута на 10 единиц
+    /// вдоль x, текстура сцены сдвигается на пиксель за кадр
+    fn synthetic_project(test: &str, frames: i32) -> PathBuf {
+        let project = video_dir(test);
+        let layout = ProjectLayout::default();
+        let mut cameras = Vec::new();
+        for translation_x in [0.0, -10.0] {
+            let mut camera = CameraParameters::new().unwrap();
+            camera.intrinsic =
+                Mat::from_slice_2d(&[[400.0, 0.0, 160.0], [0.0, 400.0, 120.0], [0.0, 0.0, 1.0]])
+                    .unwrap();
+            camera.distortion = Mat::zeros(1, 5, opencv::core::CV_64F)
+                .unwrap()
+                .to_mat()
+                .unwrap();
+            camera.translation = Mat::from_slice_2d(&[[translation_x], [0.0], [0.0]]).unwrap();
+            cameras.push(camera);
+        }
+        save_camera_parameters(&cameras, &layout.camera_parameters(&project)).unwrap();
+
+        let mut noise = Mat::new_rows_cols_with_default(
+            240,
+            340 + frames,
+            opencv::core::CV_8UC1,
+            opencv::core::Scalar::all(0.0),
+        )
+        .unwrap();
+        opencv::core::randu(
+            &mut noise,
+            &opencv::core::Scalar::all(0.0),
+            &opencv::core::Scalar::all(255.0),
+        )
+        .unwrap();
+        let mut texture = Mat::default();
+        opencv::imgproc::gaussian_blur_def(&noise, &mut texture, Size::new(5, 5), 1.5).unwrap();
+
+        let video_dir = layout.video_dir(&project);
+        create_dir_all(&video_dir).unwrap();
+        let mut writer = opencv::videoio::VideoWriter::new(
+            video_dir.join(COMBINED_VIDEO_FILE).to_str().unwrap(),
+            opencv::videoio::VideoWriter::fourcc('m', 'p', '4', 'v').unwrap(),
+            10.0,
+            Size::new(640, 240),
+            true,
+        )
+        .unwrap();
+        for k in 0..frames {
+            let mut cells = Vector::<Mat>::new();
+            for offset in [0, 20] {
+                let roi = Mat::roi(&texture, opencv::core::Rect::new(k + offset, 0, 320, 240))
+                    .unwrap()
+                    .try_clone()
+                    .unwrap();
+                cells.push(roi);
+            }
+            let mut gray = Mat::default();
+            opencv::core::hconcat(&cells, &mut gray).unwrap();
+            let mut frame = Mat::default();
+            opencv::imgproc::cvt_color_def(&gray, &mut frame, opencv::imgproc::COLOR_GRAY2BGR)
+                .unwrap();
+            writer.write(&frame).unwrap();
+        }
+        writer.release().unwrap();
+        project
+    }
+
+    /// Пайплайн проекта `project`, загруженного так же, как при запуске без окна
+    fn project_pipeline(project: &Path, settings: ReconstructionSettings) -> Pipeline {
+        let mut app = ReconstructionApp::new();
+        app.set_project_folder(project.to_path_buf());
+        app.fetch_project();
+        let (sender, _receiver) = channel();
+        Pipeline {
+            resources: app.resources,
+            topology: CameraTopology::Star,
+            settings,
+            progress: sender,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[test]
+    fn resume_keeps_written_frames_and_writes_the_rest() {
+        let project = synthetic_project("resume_frames", 4);
+        let dest = ProjectLayout::default().point_clouds_dir(&project);
+        create_dir_all(&dest).unwrap();
+        let written = dest.join("point_cloud_2.ply");
+        let bytes = b"ply\nformat ascii 1.0\nelement vertex 0\nend_header\n";
+        std::fs::write(&written, bytes).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        std::fs::File::options()
+            .write(true)
+            .open(&written)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let settings = ReconstructionSettings {
+            resume: true,
+            ..ReconstructionSettings::default()
+        };
+        project_pipeline(&project, settings).run_pipeline().unwrap();
+
+        assert_eq!(std::fs::read(&written).unwrap(), bytes);
+        assert_eq!(written.metadata().unwrap().modified().unwrap(), mtime);
+        for frame in [0, 1, 3] {
+            assert!(dest.join(format!("point_cloud_{}.ply", frame)).exists());
+        }
+        std::fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn resume_fuses_skipped_frames_from_their_clouds() {
+        let project = synthetic_project("resume_fusion", 4);
+        let layout = ProjectLayout::default();
+        let dest = layout.point_clouds_dir(&project);
+        let fused_path = dest.join("fused_point_cloud.ply");
+        let lengths_path = layout.report(&project, Path::new(TRACK_LENGTHS_FILE));
+        let settings = |resume| ReconstructionSettings {
+            resume,
+            fusion: Some(FusionParams::default()),
+            ..ReconstructionSettings::default()
+        };
+
+        project_pipeline(&project, settings(false))
+            .run_pipeline()
+            .unwrap();
+        let full_fused = load_point_cloud(&fused_path).unwrap();
+        let full_lengths = std::fs::read_to_string(&lengths_path).unwrap();
+        assert!(!full_fused.points.is_empty());
+
+        // Прерванный запуск: облако последнего кадра не успело записаться
+        std::fs::remove_file(dest.join("point_cloud_3.ply")).unwrap();
+        project_pipeline(&project, settings(true))
+            .run_pipeline()
+            .unwrap();
+        let resumed_fused = load_point_cloud(&fused_path).unwrap();
+        assert_eq!(resumed_fused.points.len(), full_fused.points.len());
+        assert_eq!(
+            std::fs::read_to_string(&lengths_path).unwrap(),
+            full_lengths
+        );
+        std::fs::remove_dir_all(&project).unwrap();
+    }
+
+    #[test]
+    fn cloud_is_colored_from_chosen_camera_frame() {
+        let frame = |b, g, r| {
+            Mat::new_rows_cols_with_default(
+                40,
+                40,
+                opencv::core::CV_8UC3,
+                opencv::core::Scalar::new(b, g, r, 0.0),
+            )
+            .unwrap()
+        };
+        let frames = [frame(0.0, 0.0, 0.0), frame(30.0, 20.0, 10.0)];
+        let mut points_2d = Vector::<Mat>::new();
+        points_2d.push(Mat::from_slice_2d(&[[5.0, 5.0]]).unwrap());
+        points_2d.push(Mat::from_slice_2d(&[[30.0, 30.0]]).unwrap());
+        for color_camera in [1, 5] {
+            let (sender, _receiver) = channel();
+            let pipeline = Pipeline {
+                resources: ProjectResources::default(),
+                topology: CameraTopology::Star,
+                settings: ReconstructionSettings {
+                    color_camera,
+                    ..ReconstructionSettings::default()
+                },
+                progress: sender,
+                cancel: Arc::new(AtomicBool::new(false)),
+            };
+            let mut cloud = PointCloud {
+                points: vec![Point3D::new(0.0, 0.0, 1.0, 1.0)],
+                timestamp: 0,
+            };
+            // Камера за пределами списка заменяется последней
+            pipeline.color_cloud(&mut cloud, &points_2d, &frames);
+            assert_eq!(cloud.points[0].color, Some((10, 20, 30)));
+        }
+    }
+
+    #[test]
+    fn delayed_clouds_are_written_when_writer_is_dropped() {
+        let dest = video_dir("cloud_writer");
+        let (sender, _receiver) = channel();
+        let pipeline = Pipeline {
+            resources: ProjectResources::default(),
+            topology: CameraTopology::Star,
+            settings: ReconstructionSettings {
+                trajectory_smoothing: Some(TrajectorySmoothing { radius: 2 }),
+                ..ReconstructionSettings::default()
+            },
+            progress: sender,
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        let cloud = |timestamp| PointCloud {
+            points: vec![Point3D::new(0.0, 0.0, 1.0, 1.0)],
+            timestamp,
+        };
+        {
+            let mut writer = CloudWriter::new(&pipeline, &dest);
+            for frame in 0..3 {
+                writer.write(cloud(frame));
+            }
+            // Окно ещё не набрано: записано только первое облако
+            assert!(dest.join("point_cloud_0.ply").exists());
+            assert!(!dest.join("point_cloud_1.ply").exists());
+            // Выход по ошибке: писатель уничтожается посреди последовательности
+        }
+        for frame in 0..3 {
+            assert!(dest.join(format!("point_cloud_{}.ply", frame)).exists());
+        }
+        let _ = std::fs::remove_dir_all(&dest);
+    }
+
+    #[test]
+    fn headless_run_requires_project_files() {
+        let project = video_dir("headless_empty");
+        let result = ReconstructionApp::run_headless(project, CameraTopology::Star);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn resumed_error_stats_keep_previous_frames() {
+        let previous = vec![(0, stats(1.0)), (1, stats(2.0)), (2, stats(3.0))];
+        let current = [(2, stats(30.0)), (3, stats(4.0))];
+        let merged = merge_error_stats(previous, &current);
+        let frames: Vec<(usize, f64)> = merged.iter().map(|(f, s)| (*f, s.mean)).collect();
+        assert_eq!(frames, vec![(0, 1.0), (1, 2.0), (2, 30.0), (3, 4.0)]);
+    }
+
+    #[test]
+    fn combined_layout_follows_camera_count() {
+        let layout = combined_layout_for(Some(4), Size::new(3840, 2160)).unwrap();
+        assert_eq!((layout.rows, layout.cols), (2, 2));
+        let layout = combined_layout_for(Some(6), Size::new(5760, 2160)).unwrap();
+        assert_eq!((layout.rows, layout.cols), (2, 3));
+        let layout = combined_layout_for(Some(6), Size::new(3840, 3240)).unwrap();
+        assert_eq!((layout.rows, layout.cols), (3, 2));
+        let layout = combined_layout_for(None, Size::new(1920, 1080)).unwrap();
+        assert_eq!((layout.rows, layout.cols), (2, 2));
+    }
+
+    #[test]
+    fn combined_layout_without_fit_is_an_error() {
+        // 4 камеры не раскладываются в очень широкий кадр ни 2x2, ни 1x4, ни 4x1
+        assert!(combined_layout_for(Some(4), Size::new(10000, 100)).is_err());
+        assert!(combined_layout_for(Some(0), Size::new(1920, 1080)).is_err());
+    }
+
+    #[test]
+    fn newest_video_source_wins() {
+        let dir = video_dir("newest_source");
+        let combined = dir.join(COMBINED_VIDEO_FILE);
+        touch(&combined, 1_000);
+        touch(&dir.join("camera_0.mp4"), 2_000);
+        let cameras = camera_videos(&dir);
+        assert_eq!(cameras, vec![dir.join("camera_0.mp4")]);
+        assert!(!combined_is_newest(&combined, &cameras));
+
+        touch(&combined, 3_000);
+        assert!(combined_is_newest(&combined, &cameras));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn picking_a_source_removes_the_other() {
+        let dir = video_dir("replace_source");
+        touch(&dir.join(COMBINED_VIDEO_FILE), 1_000);
+        touch(&dir.join("camera_0.mp4"), 1_000);
+        remove_camera_videos(&dir);
+        assert!(camera_videos(&dir).is_empty());
+        assert!(dir.join(COMBINED_VIDEO_FILE).exists());
+        remove_combined_video(&dir);
+        assert!(!dir.join(COMBINED_VIDEO_FILE).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -2,6 +2,38 @@ use eframe;
 mod app;
 mod model;
 mod ui;
+mod viewer;
+
+use lib_cv::reconstruction::CameraTopology;
+use std::path::PathBuf;
+
+const USAGE: &str = "reconstruction_app [--headless <папка проекта> [--ring]]";
+
+/// Режим запуска из аргументов командной строки: `None` — окно приложения,
+/// иначе реконструкция проекта без окна с настройками из его файла настроек
+fn parse_headless(args: &[String]) -> Result<Option<(PathBuf, CameraTopology)>, String> {
+    let mut project = None;
+    let mut topology = CameraTopology::Star;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => match args.next() {
+                Some(path) => project = Some(PathBuf::from(path)),
+                None => return Err(format!("Не указана папка проекта: {}", USAGE)),
+            },
+            "--ring" => topology = CameraTopology::Ring,
+            other => return Err(format!("Неизвестный аргумент {}: {}", other, USAGE)),
+        }
+    }
+    match project {
+        Some(project) => Ok(Some((project, topology))),
+        None if topology == CameraTopology::Ring => Err(format!(
+            "--ring используется только с --headless: {}",
+            USAGE
+        )),
+        None => Ok(None),
+    }
+}
 
 fn main() -> eframe::Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
@@ -9,6 +41,22 @@ fn main() -> eframe::Result<()> {
         .filter_module("lib_cv", log::LevelFilter::Info)
         .init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match parse_headless(&args) {
+        Ok(None) => {}
+        Ok(Some((project, topology))) => {
+            if let Err(e) = app::ReconstructionApp::run_headless(project, topology) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(2);
+        }
+    }
+
     let options = eframe::NativeOptions {
         viewport: eframe::egui::ViewportBuilder::default()
             .with_inner_size([1000.0, 700.0])
@@ -22,3 +70,28 @@ fn main() -> eframe::Result<()> {
         Box::new(|_cc| Ok(Box::new(app::ReconstructionApp::new()))),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn headless_arguments() {
+        assert_eq!(parse_headless(&[]).unwrap(), None);
+        assert_eq!(
+            parse_headless(&args(&["--headless", "project"])).unwrap(),
+            Some((PathBuf::from("project"), CameraTopology::Star))
+        );
+        assert_eq!(
+            parse_headless(&args(&["--ring", "--headless", "project"])).unwrap(),
+            Some((PathBuf::from("project"), CameraTopology::Ring))
+        );
+        assert!(parse_headless(&args(&["--headless"])).is_err());
+        assert!(parse_headless(&args(&["--ring"])).is_err());
+        assert!(parse_headless(&args(&["--verbose"])).is_err());
+    }
+}
@@ -1,34 +1,171 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use lib_cv::{calibration::CameraParameters, utils::get_video_frame_count};
+use lib_cv::{
+    board::{BOARD_CONFIG_FILE, CharucoBoardConfig, LengthUnit},
+    calibration::CameraParameters,
+    correspondence::{DEFAULT_MATCH_RATIO, SiftParams},
+    fusion::FusionParams,
+    reconstruction::VisibilityMode,
+    registration::StabilizationParams,
+    tracking::{StaticTrackFilter, TrajectorySmoothing},
+    utils::{
+        CombinedVideoSource, FrameSource, GridLayout, get_video_frame_count, open_video_captures,
+        write_atomically,
+    },
+};
+use log::{info, warn};
+use opencv::core::{Size, TermCriteria, TermCriteria_COUNT, TermCriteria_EPS};
+use serde::{Deserialize, Serialize};
 
-#[derive(Default)]
+/// Расположение входных и выходных файлов проекта - единственное место, где заданы пути
+/// внутри папки проекта. Относительные пути считаются от папки проекта, абсолютные
+/// используются как есть: так выходные данные можно вынести, например, на отдельный диск
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ProjectLayout {
+    /// Файл параметров камер
+    pub(crate) camera_parameters: PathBuf,
+    /// Папка видео камер `camera_{i}.mp4` или общего видео `combined.mp4`
+    pub(crate) video_dir: PathBuf,
+    /// Папка масок камер `mask_camera_{i}.png` (камеры с 1). Маски необязательны
+    pub(crate) masks_dir: PathBuf,
+    /// Папка покадровых облаков точек и общей карты
+    pub(crate) point_clouds_dir: PathBuf,
+    /// Папка статистики и отладочного видео: от неё считаются их пути в настройках
+    pub(crate) reports_dir: PathBuf,
+    /// Файл параметров обработки проекта
+    pub(crate) settings: PathBuf,
+}
+
+impl Default for ProjectLayout {
+    // Пути собираются по компонентам, чтобы разделители соответствовали платформе
+    fn default() -> Self {
+        Self {
+            camera_parameters: PathBuf::from("camera_parameters.yml"),
+            video_dir: Path::new("data").join("video"),
+            masks_dir: Path::new("data").join("masks"),
+            point_clouds_dir: Path::new("data").join("point_clouds"),
+            reports_dir: PathBuf::from("data"),
+            settings: PathBuf::from("settings.toml"),
+        }
+    }
+}
+
+impl ProjectLayout {
+    pub(crate) fn camera_parameters(&self, project_path: &Path) -> PathBuf {
+        project_path.join(&self.camera_parameters)
+    }
+
+    pub(crate) fn video_dir(&self, project_path: &Path) -> PathBuf {
+        project_path.join(&self.video_dir)
+    }
+
+    /// Маска камеры `camera_i` (с 0)
+    pub(crate) fn camera_mask(&self, project_path: &Path, camera_i: usize) -> PathBuf {
+        project_path
+            .join(&self.masks_dir)
+            .join(format!("mask_camera_{}.png", camera_i + 1))
+    }
+
+    pub(crate) fn settings(&self, project_path: &Path) -> PathBuf {
+        project_path.join(&self.settings)
+    }
+
+    pub(crate) fn point_clouds_dir(&self, project_path: &Path) -> PathBuf {
+        project_path.join(&self.point_clouds_dir)
+    }
+
+    /// Путь отчёта `file` (статистики, отладочного видео) в папке отчётов
+    pub(crate) fn report(&self, project_path: &Path, file: &Path) -> PathBuf {
+        project_path.join(&self.reports_dir).join(file)
+    }
+}
+
+#[derive(Default, Clone)]
 pub(crate) struct ProjectResources {
     pub project_path: Option<PathBuf>,
+    pub layout: ProjectLayout,
     pub calibration_data: Option<CalibrationData>,
     pub video_data: Option<VideoData>,
 }
 
+#[derive(Clone)]
 pub(crate) struct CalibrationData {
     pub(crate) calibration_file: PathBuf,
     pub(crate) camera_params: Vec<CameraParameters>,
     pub(crate) num_cameras: usize,
+    /// Доска, с которой сделана калибровка (board.toml рядом с файлом параметров).
+    /// Любой поиск доски при реконструкции должен сверяться с ней, иначе масштаб будет неверным
+    pub(crate) board: Option<CharucoBoardConfig>,
+    /// Расхождение досок, о котором нужно заметно предупредить в интерфейсе
+    pub(crate) board_warning: Option<String>,
 }
 
 impl CalibrationData {
     pub(crate) fn new(calibration_file: PathBuf, camera_params: Vec<CameraParameters>) -> Self {
         let num_cameras = camera_params.len();
+        let board = match CharucoBoardConfig::load_for_calibration(&calibration_file) {
+            Ok(Some(board)) => Some(board),
+            Ok(None) => {
+                warn!(
+                    "Рядом с {} нет файла доски {}: проверить соответствие доски невозможно",
+                    calibration_file.display(),
+                    BOARD_CONFIG_FILE
+                );
+                None
+            }
+            Err(e) => {
+                warn!("Файл доски калибровки не прочитан: {}", e);
+                None
+            }
+        };
         Self {
             calibration_file,
             camera_params,
             num_cameras,
+            board,
+            board_warning: None,
+        }
+    }
+
+    /// Единица координат облаков: из файла параметров, а для файлов прежних версий -
+    /// из доски калибровки
+    pub(crate) fn unit(&self) -> LengthUnit {
+        match self.camera_params.first().map(|camera| camera.unit) {
+            Some(unit) if unit != LengthUnit::Unspecified => unit,
+            _ => self
+                .board
+                .as_ref()
+                .map_or(LengthUnit::Unspecified, |board| board.unit),
         }
     }
+
+    /// Сверяет доску, найденную при реконструкции, с доской калибровки.
+    /// Возвращает описание расхождений, если доски разные
+    pub(crate) fn board_mismatch(&self, board: &CharucoBoardConfig) -> Option<String> {
+        let differences = self.board.as_ref()?.differences(board);
+        if differences.is_empty() {
+            return None;
+        }
+        let message = differences.join(", ");
+        warn!(
+            "ВНИМАНИЕ: доска не совпадает с доской калибровки: {}",
+            message
+        );
+        Some(message)
+    }
 }
 
+/// Имя общего видео всех камер в папке видео проекта
+pub(crate) const COMBINED_VIDEO_FILE: &str = "combined.mp4";
+
+#[derive(Clone)]
 pub(crate) struct VideoData {
+    /// Видео каждой камеры. Для общего видео у всех камер один и тот же файл
     pub(crate) video_files: Vec<Option<PathBuf>>,
     pub(crate) total_frames: usize,
+    /// Раскладка камер, если все камеры в одном общем видео: кадры камер вырезаются
+    /// при чтении, а не хранятся отдельными файлами
+    pub(crate) combined_layout: Option<GridLayout>,
 }
 
 impl VideoData {
@@ -43,9 +180,34 @@ impl VideoData {
         Ok(Self {
             video_files,
             total_frames,
+            combined_layout: None,
+        })
+    }
+
+    /// Общее видео, в кадре которого камеры расположены сеткой `layout`
+    pub(crate) fn combined(video_file: &Path, layout: GridLayout) -> Result<Self, opencv::Error> {
+        Ok(Self {
+            video_files: vec![Some(video_file.to_path_buf()); layout.cells()],
+            total_frames: get_video_frame_count(&video_file.to_path_buf())?,
+            combined_layout: Some(layout),
         })
     }
 
+    /// Открывает видео для покадрового чтения всех камер
+    pub(crate) fn open_source(&self) -> Result<Box<dyn FrameSource>, opencv::Error> {
+        if let Some(layout) = self.combined_layout {
+            let video_file = self
+                .video_files
+                .first()
+                .and_then(|f| f.as_ref())
+                .ok_or_else(|| opencv::Error::new(-1, "Нет общего видео"))?;
+            return Ok(Box::new(CombinedVideoSource::open(video_file, layout)?));
+        }
+        let mut caps = Vec::new();
+        open_video_captures(&mut caps, &self.video_files)?;
+        Ok(Box::new(caps))
+    }
+
     pub(crate) fn from_vec(video_files: Vec<Option<PathBuf>>) -> Result<Self, opencv::Error> {
         let total_frames = {
             let first_video = video_files
@@ -58,15 +220,284 @@ impl VideoData {
         Ok(Self {
             video_files,
             total_frames,
+            combined_layout: None,
         })
     }
 }
 
+/// Параметры пирамидального оптического потока Лукаса-Канаде, которым треки переносятся
+/// на следующий кадр
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct OpticalFlowParams {
+    /// Сторона окна поиска, пикс.
+    pub(crate) win_size: i32,
+    /// Номер последнего уровня пирамиды (0 - без пирамиды)
+    pub(crate) max_level: i32,
+    /// Наибольшее число итераций поиска точки
+    pub(crate) max_iterations: i32,
+    /// Смещение окна, при котором поиск точки останавливается
+    pub(crate) epsilon: f64,
+    /// Порог наименьшего собственного значения: точки в однородных областях теряются
+    pub(crate) min_eig_threshold: f64,
+}
+
+impl Default for OpticalFlowParams {
+    fn default() -> Self {
+        Self {
+            win_size: 13,
+            max_level: 3,
+            max_iterations: 1_000_000,
+            epsilon: 1e-6,
+            min_eig_threshold: 1e-4,
+        }
+    }
+}
+
+impl OpticalFlowParams {
+    pub(crate) fn window(&self) -> Size {
+        Size::new(self.win_size, self.win_size)
+    }
+
+    pub(crate) fn criteria(&self) -> Result<TermCriteria, opencv::Error> {
+        TermCriteria::new(
+            TermCriteria_EPS + TermCriteria_COUNT,
+            self.max_iterations,
+            self.epsilon,
+        )
+    }
+}
+
+/// Настройки запуска реконструкции. Параметры обработки хранятся в `settings.toml` проекта
+/// (см. [`ProjectLayout::settings`]); пропущенные в файле параметры берутся по умолчанию
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct ReconstructionSettings {
+    /// Куда сохранять покадровую статистику ошибки перепроекции (CSV).
+    /// Относительный путь считается от папки отчётов проекта, `None` - не сохранять.
+    /// Выбирается для каждого запуска и в файл настроек не попадает
+    #[serde(skip)]
+    pub(crate) error_stats_csv: Option<PathBuf>,
+    /// Продолжить прерванный запуск: кадры, для которых уже есть облако точек, не пересчитываются
+    #[serde(skip)]
+    pub(crate) resume: bool,
+    /// Исключать из триангуляции камеры, которые на текущем кадре перекрыты
+    pub(crate) camera_mask: bool,
+    /// СКО яркости кадра, ниже которого кадр считается пустым
+    pub(crate) min_frame_std_dev: f64,
+    /// Минимальная доля успешно прослеженных оптическим потоком точек камеры
+    pub(crate) min_tracked_ratio: f32,
+    /// Куда писать отладочное видео с отслеживаемыми точками всех камер (относительно папки
+    /// отчётов), `None` - не писать. Заметно замедляет обработку
+    pub(crate) debug_video: Option<PathBuf>,
+    /// Устранять дисторсию целых кадров перед поиском признаков вместо исправления
+    /// отдельных точек. Точнее при сильной дисторсии, но медленнее
+    pub(crate) undistort_frames: bool,
+    /// Отбрасывать неподвижные треки (фон) при реконструкции движущегося объекта,
+    /// `None` - оставлять все треки
+    pub(crate) static_track_filter: Option<StaticTrackFilter>,
+    /// Сглаживать дрожание точек во времени по трекам, `None` - сохранять облака как есть.
+    /// Облака сохраняются с задержкой на полуширину окна
+    pub(crate) trajectory_smoothing: Option<TrajectorySmoothing>,
+    /// Вычитать общее движение сцены между кадрами (съёмка с рук), оставляя облака в системе
+    /// первого кадра, `None` - не стабилизировать
+    pub(crate) stabilization: Option<StabilizationParams>,
+    /// Жёсткий порог средней ошибки перепроекции точки в пикселях, `None` - без порога
+    pub(crate) max_reproj_error: Option<f64>,
+    /// Наибольшее различие цвета точки между видящими её камерами (расстояние в RGB),
+    /// выше которого точка считается ложным сопоставлением, `None` - без проверки цвета
+    pub(crate) max_color_diff: Option<f64>,
+    /// Камера (с нуля), по кадру которой раскрашивается облако точек
+    pub(crate) color_camera: usize,
+    /// Сливать облака кадров в общую карту `fused_point_cloud.ply`, `None` - не сливать.
+    /// В режиме продолжения облака пропущенных кадров читаются из их файлов
+    pub(crate) fusion: Option<FusionParams>,
+    /// Переводить облака и позы камер в систему доски ChArUco, найденной на первом кадре
+    /// камеры 0. Если доска не найдена или неизвестна, результат остаётся в системе камеры 0
+    pub(crate) board_frame: bool,
+    /// Не делить общее видео камер на отдельные файлы, а вырезать кадры камер при чтении.
+    /// Экономит место на диске: отдельные файлы занимают столько же, сколько общее видео
+    pub(crate) keep_combined_video: bool,
+    /// Уточнять сопоставленные точки SIFT до долей пикселя (`corner_sub_pix`) перед триангуляцией.
+    /// Выключено по умолчанию, чтобы облака существующих проектов не менялись
+    pub(crate) subpixel_refinement: bool,
+    /// Сохранять дескрипторы камеры 0 для треков первого облака (`track_descriptors.yml`)
+    /// и находить по ним треки, выпавшие из облака: SIFT на каждом кадре камеры 0,
+    /// поэтому обработка заметно медленнее
+    pub(crate) keep_descriptors: bool,
+    /// Сохранять наблюдения треков по камерам каждого кадра (`correspondences_{кадр}.csv`:
+    /// номер трека, камера, координаты в пикселях исходного кадра) для внешних решателей
+    pub(crate) export_correspondences: bool,
+    /// Какие точки триангулировать: только видимые во всех камерах или видимые хотя бы
+    /// в нескольких (см. [`VisibilityMode`] о выборе между точностью и полнотой)
+    pub(crate) visibility: VisibilityMode,
+    /// Параметры детектора SIFT
+    pub(crate) sift: SiftParams,
+    /// Порог теста отношения при сопоставлении признаков между камерами
+    pub(crate) match_ratio: f32,
+    /// Параметры оптического потока
+    pub(crate) optical_flow: OpticalFlowParams,
+    /// Наименьшая уверенность точки, с которой она остаётся в облаке
+    pub(crate) min_confidence: f32,
+    /// Писать в заголовок PLY комментарии о происхождении облака (видео, калибровка, настройки)
+    pub(crate) ply_comments: bool,
+}
+
+impl ReconstructionSettings {
+    /// Настройки, влияющие на облако точек, одной строкой (для заголовка PLY)
+    pub(crate) fn describe(&self) -> String {
+        format!(
+            "visibility={:?} undistort_frames={} subpixel={} camera_mask={} \
+             max_reproj_error={:?} max_color_diff={:?} static_tracks={:?} smoothing={:?} \
+             stabilization={:?} board_frame={}",
+            self.visibility,
+            self.undistort_frames,
+            self.subpixel_refinement,
+            self.camera_mask,
+            self.max_reproj_error,
+            self.max_color_diff,
+            self.static_track_filter,
+            self.trajectory_smoothing,
+            self.stabilization,
+            self.board_frame
+        )
+    }
+
+    /// Настройки проекта из `path`; если файла нет, настройки по умолчанию
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Не удалось прочитать настройки {}: {}", path.display(), e))?;
+        let settings = toml::from_str(&text)
+            .map_err(|e| format!("Некорректные настройки {}: {}", path.display(), e))?;
+        info!("Настройки обработки загружены из {}", path.display());
+        Ok(settings)
+    }
+
+    /// Параметры обработки в формате `settings.toml`
+    pub(crate) fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| format!("Настройки не сериализованы: {}", e))
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), String> {
+        let text = self.to_toml()?;
+        write_atomically(path, |tmp| std::fs::write(tmp, &text))
+            .map_err(|e| format!("Не удалось сохранить настройки {}: {}", path.display(), e))?;
+        info!("Настройки обработки сохранены в {}", path.display());
+        Ok(())
+    }
+
+    pub(crate) fn default_error_stats_csv() -> PathBuf {
+        PathBuf::from("reprojection_errors.csv")
+    }
+
+    pub(crate) fn default_debug_video() -> PathBuf {
+        PathBuf::from("debug_tracks.mp4")
+    }
+}
+
+impl Default for ReconstructionSettings {
+    fn default() -> Self {
+        Self {
+            error_stats_csv: Some(Self::default_error_stats_csv()),
+            resume: false,
+            camera_mask: false,
+            min_frame_std_dev: 8.0,
+            min_tracked_ratio: 0.3,
+            debug_video: None,
+            undistort_frames: false,
+            static_track_filter: None,
+            trajectory_smoothing: None,
+            stabilization: None,
+            max_reproj_error: None,
+            max_color_diff: None,
+            color_camera: 0,
+            fusion: None,
+            board_frame: false,
+            keep_combined_video: false,
+            subpixel_refinement: false,
+            keep_descriptors: false,
+            export_correspondences: false,
+            visibility: VisibilityMode::default(),
+            sift: SiftParams::default(),
+            match_ratio: DEFAULT_MATCH_RATIO,
+            optical_flow: OpticalFlowParams::default(),
+            min_confidence: 0.25,
+            ply_comments: true,
+        }
+    }
+}
+
 #[derive(Default)]
 pub(crate) enum PipelineState {
     #[default]
     FolderSetup,
     FetchProject,
     SetupMenu,
-    ReadyToProcess,
+    /// Реконструкция идёт в фоновом потоке: доля обработанных кадров и текущий этап
+    Processing {
+        progress: f32,
+        message: String,
+    },
+}
+
+/// Сообщение фонового потока реконструкции окну
+pub(crate) enum ProgressEvent {
+    /// Начат этап подготовки, до покадровой обработки
+    Stage(String),
+    /// Обработан кадр `frame` из `total`; `points` - точек в его облаке (треков в режиме
+    /// одной камеры), `None` - кадр уже был обработан раньше и пропущен
+    Frame {
+        frame: usize,
+        total: usize,
+        points: Option<usize>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use lib_cv::board::BoardConfig;
+    use opencv::objdetect::PredefinedDictionaryType;
+
+    use super::*;
+
+    #[test]
+    fn board_differing_from_calibration_is_reported() {
+        let dir = std::env::temp_dir().join(format!("model_board_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let calibration_file = dir.join("camera_parameters.yml");
+        let calibration_board = BoardConfig::new().into_config();
+        calibration_board
+            .save(CharucoBoardConfig::path_for_calibration(&calibration_file))
+            .unwrap();
+
+        let data = CalibrationData::new(calibration_file, Vec::new());
+        assert_eq!(data.board.as_ref(), Some(&calibration_board));
+        assert_eq!(data.board_mismatch(&calibration_board), None);
+        let other_board = BoardConfig::new()
+            .dict(PredefinedDictionaryType::DICT_5X5_100)
+            .into_config();
+        let warning = data.board_mismatch(&other_board).unwrap();
+        assert!(warning.contains("DICT_5X5_100"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn settings_select_visibility_mode() {
+        let settings: ReconstructionSettings = toml::from_str("").unwrap();
+        assert_eq!(settings.visibility, VisibilityMode::AllCameras);
+        let settings: ReconstructionSettings =
+            toml::from_str("visibility = { MinViews = 2 }").unwrap();
+        assert_eq!(settings.visibility, VisibilityMode::MinViews(2));
+    }
+
+    #[test]
+    fn settings_without_subpixel_keep_previous_results() {
+        assert!(!ReconstructionSettings::default().subpixel_refinement);
+        let settings: ReconstructionSettings = toml::from_str("camera_mask = true").unwrap();
+        assert!(settings.camera_mask);
+        assert!(!settings.subpixel_refinement);
+    }
 }
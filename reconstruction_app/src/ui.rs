@@ -1,16 +1,54 @@
-use crate::{app::ReconstructionApp, model::PipelineState};
+use std::path::PathBuf;
+
+use crate::{
+    app::ReconstructionApp,
+    model::{PipelineState, ProjectLayout, ReconstructionSettings},
+};
 use eframe::egui;
+use lib_cv::board::LengthUnit;
+use lib_cv::fusion::FusionParams;
+use lib_cv::reconstruction::{CameraTopology, VisibilityMode};
+use lib_cv::registration::StabilizationParams;
+use lib_cv::tracking::{StaticTrackFilter, TrajectorySmoothing};
 use log::error;
 
 pub struct UiRenderer;
 
 impl UiRenderer {
     pub(crate) fn render_content(app: &mut ReconstructionApp, ctx: &egui::Context) {
-        egui::CentralPanel::default().show(ctx, |ui| match app.pipeline_state {
+        egui::CentralPanel::default().show(ctx, |ui| match &app.pipeline_state {
             PipelineState::FolderSetup => Self::render_folder_setup(app, ui),
             PipelineState::FetchProject => app.fetch_project(),
             PipelineState::SetupMenu => Self::render_setup_menu(app, ui),
-            PipelineState::ReadyToProcess => todo!(),
+            PipelineState::Processing { progress, message } => {
+                let (progress, message) = (*progress, message.clone());
+                Self::render_processing(app, ui, progress, &message)
+            }
+        });
+    }
+
+    fn render_processing(
+        app: &mut ReconstructionApp,
+        ui: &mut egui::Ui,
+        progress: f32,
+        message: &str,
+    ) {
+        ui.vertical_centered(|ui| {
+            ui.label(egui::RichText::new("Идёт реконструкция").size(18.0));
+            ui.add(
+                egui::ProgressBar::new(progress)
+                    .text(message)
+                    .show_percentage(),
+            );
+            let cancelling = app.is_cancelling();
+            let button = egui::Button::new(egui::RichText::new("Отмена").size(18.0))
+                .min_size(egui::vec2(140.0, 40.0));
+            if ui.add_enabled(!cancelling, button).clicked() {
+                app.cancel_pipeline();
+            }
+            if cancelling {
+                ui.label("Остановка после текущего кадра, готовые облака точек сохраняются");
+            }
         });
     }
 
@@ -39,10 +77,12 @@ impl UiRenderer {
 
     fn render_setup_menu(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
         ui.vertical_centered(|ui| {
-            ui.label(egui::RichText::new(format!(
-                "Путь проекта теперь установлен в {}",
-                app.resources.project_path.as_ref().unwrap().display()
-            )))
+            if let Some(project_path) = &app.resources.project_path {
+                ui.label(egui::RichText::new(format!(
+                    "Путь проекта теперь установлен в {}",
+                    project_path.display()
+                )));
+            }
         });
 
         ui.columns(2, |columns| {
@@ -50,9 +90,449 @@ impl UiRenderer {
             Self::render_video_setup(app, &mut columns[1]);
         });
 
+        Self::render_topology_setup(app, ui);
+        Self::render_output_layout_setup(app, ui);
+        Self::render_error_stats_setup(app, ui);
+        ui.checkbox(
+            &mut app.settings.resume,
+            "Продолжить прерванную реконструкцию (пропускать готовые кадры)",
+        );
+        ui.checkbox(
+            &mut app.settings.undistort_frames,
+            "Исправлять дисторсию кадров перед поиском признаков",
+        );
+        ui.checkbox(
+            &mut app.settings.subpixel_refinement,
+            "Субпиксельное уточнение сопоставленных точек",
+        );
+        Self::render_board_frame_setup(app, ui);
+        ui.checkbox(
+            &mut app.settings.keep_descriptors,
+            "Сохранять дескрипторы треков для повторного сопоставления",
+        )
+        .on_hover_text(
+            "Дескрипторы SIFT камеры 0 для точек первого облака сохраняются в track_descriptors.yml",
+        );
+        ui.checkbox(
+            &mut app.settings.export_correspondences,
+            "Сохранять наблюдения треков по камерам (CSV)",
+        )
+        .on_hover_text(
+            "correspondences_{кадр}.csv рядом с облаками: track_id, cam_id, x, y для внешнего bundle adjustment",
+        );
+        Self::render_visibility_setup(app, ui);
+        Self::render_camera_mask_setup(app, ui);
+        Self::render_static_track_setup(app, ui);
+        Self::render_smoothing_setup(app, ui);
+        Self::render_stabilization_setup(app, ui);
+        Self::render_reproj_filter_setup(app, ui);
+        Self::render_color_filter_setup(app, ui);
+        Self::render_color_camera_setup(app, ui);
+        Self::render_fusion_setup(app, ui);
+        Self::render_debug_video_setup(app, ui);
+        Self::render_processing_params_setup(app, ui);
+
         Self::button_start_reconstruction(app, ui);
     }
 
+    /// Папки выходных данных. Входные файлы (параметры камер, видео) к этому моменту
+    /// уже прочитаны из проекта, поэтому здесь не меняются
+    fn render_output_layout_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let layout = &mut app.resources.layout;
+        ui.collapsing(
+            "Расположение выходных данных",
+            |ui| {
+                ui.label("Относительные пути считаются от папки проекта");
+                for (label, path) in [
+                    ("Облака точек", &mut layout.point_clouds_dir),
+                    ("Отчёты и отладочное видео", &mut layout.reports_dir),
+                ] {
+                    ui.horizontal(|ui| {
+                        ui.label(label);
+                        let mut text = path.to_string_lossy().into_owned();
+                        if ui.text_edit_singleline(&mut text).changed() {
+                            *path = PathBuf::from(text);
+                        }
+                    });
+                }
+                if ui.button("По умолчанию").clicked() {
+                    let default = ProjectLayout::default();
+                    layout.point_clouds_dir = default.point_clouds_dir;
+                    layout.reports_dir = default.reports_dir;
+                }
+            },
+        );
+    }
+
+    /// Параметры SIFT, сопоставления, оптического потока и выходных облаков. Сохраняются
+    /// в файл настроек проекта при запуске реконструкции или по кнопке
+    fn render_processing_params_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        ui.collapsing("Параметры обработки", |ui| {
+            let settings = &mut app.settings;
+            ui.label("SIFT");
+            let sift = &mut settings.sift;
+            ui.horizontal(|ui| {
+                ui.label("Макс. признаков (0 - без ограничения):");
+                ui.add(egui::DragValue::new(&mut sift.nfeatures).range(0..=100_000));
+                ui.label("Слоёв в октаве:");
+                ui.add(egui::DragValue::new(&mut sift.n_octave_layers).range(1..=8));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Порог контраста:");
+                ui.add(
+                    egui::DragValue::new(&mut sift.contrast_threshold)
+                        .range(0.001..=0.2)
+                        .speed(0.001),
+                );
+                ui.label("Порог краёв:");
+                ui.add(
+                    egui::DragValue::new(&mut sift.edge_threshold)
+                        .range(1.0..=50.0)
+                        .speed(0.1),
+                );
+                ui.label("Сигма:");
+                ui.add(
+                    egui::DragValue::new(&mut sift.sigma)
+                        .range(0.5..=5.0)
+                        .speed(0.01),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("Порог теста отношения:");
+                ui.add(
+                    egui::DragValue::new(&mut settings.match_ratio)
+                        .range(0.3..=1.0)
+                        .speed(0.01),
+                )
+                .on_hover_text("Чем меньше, тем меньше сопоставлений, но они надёжнее");
+            });
+
+            ui.label("Оптический поток");
+            let flow = &mut settings.optical_flow;
+            ui.horizontal(|ui| {
+                ui.label("Окно, пикс.:");
+                ui.add(egui::DragValue::new(&mut flow.win_size).range(3..=101));
+                ui.label("Уровней пирамиды:");
+                ui.add(egui::DragValue::new(&mut flow.max_level).range(0..=8));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Макс. итераций:");
+                ui.add(egui::DragValue::new(&mut flow.max_iterations).range(1..=1_000_000));
+                ui.label("Точность:");
+                ui.add(
+                    egui::DragValue::new(&mut flow.epsilon)
+                        .range(1e-9..=1.0)
+                        .speed(1e-6),
+                );
+                ui.label("Порог собств. значения:");
+                ui.add(
+                    egui::DragValue::new(&mut flow.min_eig_threshold)
+                        .range(1e-6..=1e-1)
+                        .speed(1e-5),
+                );
+            });
+
+            ui.label("Облака точек");
+            ui.horizontal(|ui| {
+                ui.label("Мин. уверенность точки:");
+                ui.add(
+                    egui::DragValue::new(&mut settings.min_confidence)
+                        .range(0.0..=1.0)
+                        .speed(0.01),
+                );
+            });
+            ui.checkbox(
+                &mut settings.ply_comments,
+                "Комментарии о происхождении в заголовке PLY",
+            );
+
+            let save = ui
+                .horizontal(|ui| {
+                    if ui.button("По умолчанию").clicked() {
+                        let default = ReconstructionSettings::default();
+                        settings.sift = default.sift;
+                        settings.match_ratio = default.match_ratio;
+                        settings.optical_flow = default.optical_flow;
+                        settings.min_confidence = default.min_confidence;
+                        settings.ply_comments = default.ply_comments;
+                    }
+                    ui.button("Сохранить в проект").clicked()
+                })
+                .inner;
+            if save && let Err(e) = app.save_settings() {
+                error!("{}", e);
+            }
+        });
+    }
+
+    fn render_error_stats_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut enabled = app.settings.error_stats_csv.is_some();
+            if ui
+                .checkbox(&mut enabled, "Сохранять ошибки перепроекции в CSV")
+                .changed()
+            {
+                app.settings.error_stats_csv =
+                    enabled.then(ReconstructionSettings::default_error_stats_csv);
+            }
+            if let Some(path) = &mut app.settings.error_stats_csv {
+                let mut text = path.to_string_lossy().into_owned();
+                if ui.text_edit_singleline(&mut text).changed() {
+                    *path = PathBuf::from(text);
+                }
+            }
+        });
+    }
+
+    fn render_debug_video_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut enabled = app.settings.debug_video.is_some();
+            if ui
+                .checkbox(&mut enabled, "Отладочное видео с треками (медленнее)")
+                .changed()
+            {
+                app.settings.debug_video =
+                    enabled.then(ReconstructionSettings::default_debug_video);
+            }
+            if let Some(path) = &mut app.settings.debug_video {
+                let mut text = path.to_string_lossy().into_owned();
+                if ui.text_edit_singleline(&mut text).changed() {
+                    *path = PathBuf::from(text);
+                }
+            }
+        });
+    }
+
+    fn render_color_camera_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let num_cameras = app
+            .resources
+            .calibration_data
+            .as_ref()
+            .map_or(1, |c| c.num_cameras.max(1));
+        app.settings.color_camera = app.settings.color_camera.min(num_cameras - 1);
+        egui::ComboBox::from_label("Камера для цвета точек")
+            .selected_text(format!("Камера {}", app.settings.color_camera + 1))
+            .show_ui(ui, |ui| {
+                for camera in 0..num_cameras {
+                    ui.selectable_value(
+                        &mut app.settings.color_camera,
+                        camera,
+                        format!("Камера {}", camera + 1),
+                    );
+                }
+            });
+    }
+
+    fn render_reproj_filter_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut enabled = app.settings.max_reproj_error.is_some();
+            if ui
+                .checkbox(
+                    &mut enabled,
+                    "Отбрасывать точки с ошибкой перепроекции больше, пикс.:",
+                )
+                .changed()
+            {
+                app.settings.max_reproj_error = enabled.then_some(3.0);
+            }
+            if let Some(max_px) = &mut app.settings.max_reproj_error {
+                ui.add(egui::DragValue::new(max_px).range(0.1..=100.0).speed(0.1));
+            }
+        });
+    }
+
+    fn render_color_filter_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut enabled = app.settings.max_color_diff.is_some();
+            if ui
+                .checkbox(
+                    &mut enabled,
+                    "Отбрасывать точки, различающиеся по цвету между камерами больше чем на:",
+                )
+                .on_hover_text("Расстояние между цветами в RGB, от 0 до 441")
+                .changed()
+            {
+                app.settings.max_color_diff = enabled.then_some(60.0);
+            }
+            if let Some(max_diff) = &mut app.settings.max_color_diff {
+                ui.add(egui::DragValue::new(max_diff).range(1.0..=441.0));
+            }
+        });
+    }
+
+    fn render_visibility_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let mut all_cameras = app.settings.visibility == VisibilityMode::AllCameras;
+        if ui
+            .checkbox(&mut all_cameras, "Только точки, видимые во всех камерах")
+            .on_hover_text(
+                "Точнее, но при широкой базе между камерами отбрасывает большую часть сцены. \
+                 Без ограничения каждая точка триангулируется по камерам, где она найдена",
+            )
+            .changed()
+        {
+            app.settings.visibility = if all_cameras {
+                VisibilityMode::AllCameras
+            } else {
+                VisibilityMode::MinViews(2)
+            };
+        }
+        if let VisibilityMode::MinViews(min_views) = &mut app.settings.visibility {
+            ui.horizontal(|ui| {
+                ui.label("Мин. камер на точку:");
+                ui.add(egui::DragValue::new(min_views).range(2..=16));
+            });
+        }
+    }
+
+    fn render_static_track_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let mut enabled = app.settings.static_track_filter.is_some();
+        if ui
+            .checkbox(&mut enabled, "Отбрасывать неподвижные треки (фон)")
+            .changed()
+        {
+            app.settings.static_track_filter = enabled.then(StaticTrackFilter::default);
+        }
+        if let Some(filter) = &mut app.settings.static_track_filter {
+            ui.horizontal(|ui| {
+                ui.label("Мин. смещение, пикс.:");
+                ui.add(egui::DragValue::new(&mut filter.min_motion_px).range(0.0..=100.0));
+                ui.label("Окно, кадров:");
+                ui.add(egui::DragValue::new(&mut filter.window).range(2..=1000));
+            });
+        }
+    }
+
+    fn render_smoothing_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let mut enabled = app.settings.trajectory_smoothing.is_some();
+        if ui
+            .checkbox(&mut enabled, "Сглаживать траектории точек во времени")
+            .on_hover_text(
+                "Положение точки трека усредняется по соседним кадрам. \
+                 Не действует в кольцевой топологии: там нет треков",
+            )
+            .changed()
+        {
+            app.settings.trajectory_smoothing = enabled.then(TrajectorySmoothing::default);
+        }
+        if let Some(smoothing) = &mut app.settings.trajectory_smoothing {
+            ui.horizontal(|ui| {
+                ui.label("Кадров до и после:");
+                ui.add(egui::DragValue::new(&mut smoothing.radius).range(1..=30));
+            });
+        }
+    }
+
+    fn render_stabilization_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let mut enabled = app.settings.stabilization.is_some();
+        if ui
+            .checkbox(&mut enabled, "Вычитать общее движение сцены (съёмка с рук)")
+            .on_hover_text(
+                "Облака переводятся в систему первого кадра: остаётся только движение объекта. \
+                 Не действует в кольцевой топологии: там нет треков",
+            )
+            .changed()
+        {
+            app.settings.stabilization = enabled.then(StabilizationParams::default);
+        }
+        if let Some(stabilization) = &mut app.settings.stabilization {
+            ui.horizontal(|ui| {
+                ui.label("Порог отклонения точки:");
+                ui.add(
+                    egui::DragValue::new(&mut stabilization.inlier_threshold)
+                        .range(0.01..=1000.0)
+                        .speed(0.1),
+                );
+                ui.label("Мин. точек сцены:");
+                ui.add(egui::DragValue::new(&mut stabilization.min_inliers).range(3..=10_000));
+            });
+        }
+    }
+
+    fn render_fusion_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let mut enabled = app.settings.fusion.is_some();
+        if ui
+            .checkbox(&mut enabled, "Сливать облака кадров в общую карту")
+            .changed()
+        {
+            app.settings.fusion = enabled.then(FusionParams::default);
+        }
+        if let Some(fusion) = &mut app.settings.fusion {
+            ui.horizontal(|ui| {
+                ui.label("Радиус слияния:");
+                ui.add(
+                    egui::DragValue::new(&mut fusion.radius)
+                        .range(0.0..=100.0)
+                        .speed(0.1),
+                );
+                ui.label("Макс. наблюдений:");
+                ui.add(egui::DragValue::new(&mut fusion.max_observations).range(1..=1000));
+            });
+        }
+    }
+
+    fn render_board_frame_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        let board_known = app
+            .resources
+            .calibration_data
+            .as_ref()
+            .is_some_and(|data| data.board.is_some());
+        ui.add_enabled(
+            board_known,
+            egui::Checkbox::new(
+                &mut app.settings.board_frame,
+                "Система координат доски (по первому кадру камеры 0)",
+            ),
+        )
+        .on_hover_text(
+            "Облака и позы камер переводятся в систему доски ChArUco. \
+             Если доска не найдена на первом кадре, остаётся система камеры 0",
+        )
+        .on_disabled_hover_text("Нет файла доски калибровки рядом с параметрами камер");
+    }
+
+    fn render_camera_mask_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        ui.checkbox(
+            &mut app.settings.camera_mask,
+            "Исключать перекрытые камеры на отдельных кадрах",
+        );
+        if app.settings.camera_mask {
+            ui.horizontal(|ui| {
+                ui.label("Мин. СКО яркости кадра:");
+                ui.add(
+                    egui::DragValue::new(&mut app.settings.min_frame_std_dev).range(0.0..=128.0),
+                );
+                ui.label("Мин. доля прослеженных точек:");
+                ui.add(
+                    egui::DragValue::new(&mut app.settings.min_tracked_ratio)
+                        .range(0.0..=1.0)
+                        .speed(0.01),
+                );
+            });
+        }
+    }
+
+    fn render_topology_setup(app: &mut ReconstructionApp, ui: &mut egui::Ui) {
+        ui.vertical_centered(|ui| {
+            egui::ComboBox::from_label("Расположение камер")
+                .selected_text(match app.topology {
+                    CameraTopology::Star => "Звезда (всё к камере 1)",
+                    CameraTopology::Ring => "Кольцо (соседние пары)",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut app.topology,
+                        CameraTopology::Star,
+                        "Звезда (всё к камере 1)",
+                    );
+                    ui.selectable_value(
+                        &mut app.topology,
+                        CameraTopology::Ring,
+                        "Кольцо (соседние пары)",
+                    );
+                });
+        });
+    }
+
     fn pick_camera_parameters_file(app: &mut ReconstructionApp) {
         if let Some(file_path) = rfd::FileDialog::new()
             .set_title("Выбрать файл параметров")
@@ -79,6 +559,57 @@ impl UiRenderer {
                 Some(calib_data) => {
                     let num_cam = calib_data.num_cameras;
                     ui.label(format!("В параметрах найдено {num_cam} камеры"));
+                    let unit = calib_data.unit();
+                    if unit == LengthUnit::Unspecified {
+                        ui.label(
+                            egui::RichText::new(
+                                "Единица длин калибровки неизвестна: координаты облаков \
+                                 будут в единицах доски",
+                            )
+                            .color(egui::Color32::YELLOW),
+                        );
+                    } else {
+                        ui.label(format!("Координаты облаков в {}", unit.label()));
+                    }
+                    if num_cam == 1 {
+                        ui.label(
+                            egui::RichText::new(
+                                "Одна камера: реконструкция невозможна, \
+                                 будут сохранены 2D треки признаков (tracks_2d.csv)",
+                            )
+                            .color(egui::Color32::YELLOW),
+                        );
+                    }
+                    match &calib_data.board {
+                        Some(board) => {
+                            ui.label(format!(
+                                "Доска калибровки: {}x{}, квадрат {}, маркер {}, {}",
+                                board.squares_x,
+                                board.squares_y,
+                                board.square_length,
+                                board.marker_length,
+                                board.dictionary
+                            ));
+                        }
+                        None => {
+                            ui.label(
+                                egui::RichText::new(
+                                    "Файл доски калибровки не найден, масштаб не проверить",
+                                )
+                                .color(egui::Color32::YELLOW),
+                            );
+                        }
+                    }
+                    if let Some(warning) = &calib_data.board_warning {
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "Доска отличается от прежней калибровки проекта: {}",
+                                warning
+                            ))
+                            .size(18.0)
+                            .color(egui::Color32::RED),
+                        );
+                    }
                     let button =
                         egui::Button::new(egui::RichText::new("Изменить параметры").size(18.0))
                             .min_size(egui::vec2(140.0, 40.0));
@@ -133,11 +664,25 @@ impl UiRenderer {
         let button = egui::Button::new(egui::RichText::new("Начать реконструкцию").size(18.0))
             .min_size(egui::vec2(140.0, 40.0));
         ui.vertical_centered(|ui| {
-            if ui.add_enabled(is_enabled, button).clicked() {
-                if let Err(e) = app.run_pipeline() {
-                    error!("Ошибка при выполнении пайплайна реконструкции: {}", e);
+            match &app.last_run {
+                Some(Ok(message)) => {
+                    ui.label(egui::RichText::new(message).color(egui::Color32::GREEN));
+                }
+                Some(Err(e)) => {
+                    ui.label(
+                        egui::RichText::new(format!("Ошибка реконструкции: {}", e))
+                            .size(18.0)
+                            .color(egui::Color32::RED),
+                    );
                 }
+                None => {}
+            }
+            if ui.add_enabled(is_enabled, button).clicked() {
+                app.start_pipeline();
             };
+            if ui.button("Просмотр облаков точек").clicked() {
+                app.open_viewer();
+            }
         });
     }
 
@@ -175,5 +720,13 @@ impl UiRenderer {
         if ui.add(button).clicked() {
             app.pick_from_4_combined_video();
         }
+        ui.checkbox(
+            &mut app.settings.keep_combined_video,
+            "Не делить видео на файлы камер",
+        )
+        .on_hover_text(
+            "Видео сохраняется в проект целиком, кадры камер вырезаются при чтении. \
+             Экономит место на диске",
+        );
     }
 }
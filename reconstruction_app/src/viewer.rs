@@ -0,0 +1,598 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use lib_cv::reconstruction::{PointCloud, load_point_cloud};
+use log::{error, info};
+
+/// Сколько точек рисуется за кадр: из больших облаков берётся каждая n-я точка
+const MAX_DRAWN_POINTS: usize = 100_000;
+/// Сторона квадрата точки на экране, пикс.
+const POINT_SIZE: f32 = 2.0;
+/// Поле зрения камеры просмотра по вертикали, рад.
+const FIELD_OF_VIEW: f32 = 0.9;
+/// Поворот вида на пиксель перетаскивания, рад.
+const ORBIT_SPEED: f32 = 0.01;
+/// Частота воспроизведения, если частота видео неизвестна
+pub(crate) const DEFAULT_PLAYBACK_FPS: f64 = 30.0;
+/// Сколько следующих облаков читается заранее, чтобы воспроизведение не ждало диска
+const PREFETCH_CLOUDS: usize = 8;
+/// Как часто окно проверяет, не готово ли облако, которое ещё читается
+const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    /// Цвет точки из облака, серый у точек без цвета
+    Rgb,
+    /// От красного (уверенность 0) до зелёного (1)
+    Confidence,
+}
+
+/// Камера просмотра: вращается вокруг точки `target` на расстоянии `distance`.
+/// При нулевых углах смотрит вдоль оси Z облака, как камера 0
+#[derive(Debug, Clone, Copy)]
+struct Orbit {
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    target: [f32; 3],
+}
+
+impl Orbit {
+    /// Вид на всё облако с центром `center` и радиусом `radius`
+    fn framing(center: [f32; 3], radius: f32) -> Self {
+        Self {
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: radius.max(f32::EPSILON) / (FIELD_OF_VIEW / 2.0).tan() * 1.2,
+            target: center,
+        }
+    }
+
+    /// Точка облака в системе камеры просмотра
+    fn view_coords(&self, p: &[f32; 3]) -> [f32; 3] {
+        let (sy, cy) = self.yaw.sin_cos();
+        let (sp, cp) = self.pitch.sin_cos();
+        let d = [
+            p[0] - self.target[0],
+            p[1] - self.target[1],
+            p[2] - self.target[2],
+        ];
+        let x = cy * d[0] + sy * d[2];
+        let z = -sy * d[0] + cy * d[2];
+        [x, cp * d[1] - sp * z, sp * d[1] + cp * z + self.distance]
+    }
+
+    /// Сдвигает центр вращения на `(dx, dy)` в плоскости экрана (в единицах облака)
+    fn pan(&mut self, dx: f32, dy: f32) {
+        let (sy, cy) = self.yaw.sin_cos();
+        let (sp, cp) = self.pitch.sin_cos();
+        let (y, z) = (cp * dy, -sp * dy);
+        self.target[0] -= cy * dx - sy * z;
+        self.target[1] -= y;
+        self.target[2] -= sy * dx + cy * z;
+    }
+}
+
+/// Облако, подготовленное к рисованию: прореженные точки и их цвета в обоих режимах
+struct ViewedCloud {
+    frame: usize,
+    total: usize,
+    points: Vec<[f32; 3]>,
+    rgb: Vec<egui::Color32>,
+    confidence: Vec<egui::Color32>,
+    center: [f32; 3],
+    radius: f32,
+}
+
+impl ViewedCloud {
+    fn new(cloud: &PointCloud) -> Self {
+        let step = cloud.points.len().div_ceil(MAX_DRAWN_POINTS).max(1);
+        let shown: Vec<_> = cloud.points.iter().step_by(step).collect();
+        let points: Vec<[f32; 3]> = shown
+            .iter()
+            .map(|p| [p.x as f32, p.y as f32, p.z as f32])
+            .collect();
+        let rgb = shown
+            .iter()
+            .map(|p| {
+                let (r, g, b) = p.color.unwrap_or((160, 160, 160));
+                egui::Color32::from_rgb(r, g, b)
+            })
+            .collect();
+        let confidence = shown
+            .iter()
+            .map(|p| {
+                let c = p.confidence.clamp(0.0, 1.0);
+                egui::Color32::from_rgb(((1.0 - c) * 255.0) as u8, (c * 255.0) as u8, 40)
+            })
+            .collect();
+
+        let n = points.len().max(1) as f32;
+        let center = points.iter().fold([0.0; 3], |acc, p| {
+            [acc[0] + p[0] / n, acc[1] + p[1] / n, acc[2] + p[2] / n]
+        });
+        // Радиус по 95-му процентилю, чтобы одиночные выбросы не отдаляли вид
+        let mut distances: Vec<f32> = points
+            .iter()
+            .map(|p| {
+                ((p[0] - center[0]).powi(2)
+                    + (p[1] - center[1]).powi(2)
+                    + (p[2] - center[2]).powi(2))
+                .sqrt()
+            })
+            .collect();
+        distances.sort_by(f32::total_cmp);
+        let radius = distances
+            .get(distances.len() * 95 / 100)
+            .copied()
+            .unwrap_or(1.0);
+        Self {
+            frame: cloud.timestamp,
+            total: cloud.points.len(),
+            points,
+            rgb,
+            confidence,
+            center,
+            radius,
+        }
+    }
+}
+
+/// Прочитанное облако или ошибка чтения
+type LoadedCloud = Result<ViewedCloud, String>;
+
+/// Фоновый поток, который читает облака из PLY и готовит их к рисованию: разбор ASCII
+/// и сортировка больших облаков в потоке окна не успевают за частотой видео.
+/// Запросы устаревшего поколения (другой папки) пропускаются. Поток завершается
+/// вместе с просмотром, когда закрывается канал запросов
+struct CloudLoader {
+    requests: Sender<(u64, usize, PathBuf)>,
+    loaded: Receiver<(u64, usize, LoadedCloud)>,
+    generation: Arc<AtomicU64>,
+}
+
+impl CloudLoader {
+    fn spawn() -> Self {
+        let (requests, jobs) = channel::<(u64, usize, PathBuf)>();
+        let (done, loaded) = channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let current = Arc::clone(&generation);
+        std::thread::spawn(move || {
+            for (generation, index, path) in jobs {
+                if generation != current.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let cloud = load_point_cloud(&path)
+                    .map(|cloud| ViewedCloud::new(&cloud))
+                    .map_err(|e| e.to_string());
+                if done.send((generation, index, cloud)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            loaded,
+            generation,
+        }
+    }
+
+    /// Начинает новое поколение: прочитанные и запрошенные раньше облака больше не нужны
+    fn restart(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// Окно просмотра облаков точек последовательности: вращение, приближение и сдвиг
+/// мышью, переход между кадрами и воспроизведение. Точки проецируются программно
+/// и рисуются одной сеткой egui
+pub(crate) struct CloudViewer {
+    /// Облака последовательности: номер кадра и файл, по возрастанию кадра
+    frames: Vec<(usize, PathBuf)>,
+    index: usize,
+    /// Показанное облако: позиция в `frames` и подготовленные точки. Пока читается
+    /// следующее, остаётся на экране предыдущее
+    cloud: Option<ViewedCloud>,
+    shown: Option<usize>,
+    loader: CloudLoader,
+    generation: u64,
+    /// Позиции, отправленные в поток чтения, и уже прочитанные облака
+    requested: BTreeSet<usize>,
+    ready: BTreeMap<usize, LoadedCloud>,
+    orbit: Option<Orbit>,
+    color_mode: ColorMode,
+    fps: f64,
+    /// Когда показано текущее облако при воспроизведении, `None` - пауза
+    playing: Option<Instant>,
+    error: Option<String>,
+}
+
+impl CloudViewer {
+    /// Просмотр облаков `point_cloud_{кадр}.ply` из папки `dir`, воспроизведение с частотой `fps`
+    pub(crate) fn new(dir: &Path, fps: f64) -> Self {
+        let mut viewer = Self {
+            frames: Vec::new(),
+            index: 0,
+            cloud: None,
+            shown: None,
+            loader: CloudLoader::spawn(),
+            generation: 0,
+            requested: BTreeSet::new(),
+            ready: BTreeMap::new(),
+            orbit: None,
+            color_mode: ColorMode::Rgb,
+            fps: if fps > 0.0 { fps } else { DEFAULT_PLAYBACK_FPS },
+            playing: None,
+            error: None,
+        };
+        viewer.open_dir(dir, None);
+        viewer
+    }
+
+    /// Облака последовательности в папке `dir`; показывается `selected` или первое облако
+    fn open_dir(&mut self, dir: &Path, selected: Option<&Path>) {
+        self.frames = list_point_clouds(dir);
+        self.index = selected
+            .and_then(|path| self.frames.iter().position(|(_, p)| p == path))
+            .unwrap_or(0);
+        self.playing = None;
+        self.orbit = None;
+        self.cloud = None;
+        self.shown = None;
+        self.generation = self.loader.restart();
+        self.requested.clear();
+        self.ready.clear();
+        if self.frames.is_empty() {
+            self.error = Some(format!("В {} нет облаков точек", dir.display()));
+            return;
+        }
+        info!(
+            "Просмотр облаков: {} кадров в {}",
+            self.frames.len(),
+            dir.display()
+        );
+        self.load_current();
+    }
+
+    /// Позиции облаков, которые стоит держать прочитанными: текущее и следующие за ним
+    fn prefetch_window(&self) -> std::ops::Range<usize> {
+        self.index..(self.index + PREFETCH_CLOUDS + 1).min(self.frames.len())
+    }
+
+    /// Запрашивает чтение текущего и следующих облаков и забывает облака вне этого окна
+    fn load_current(&mut self) {
+        let window = self.prefetch_window();
+        let requested = &mut self.requested;
+        self.ready.retain(|index, _| {
+            let keep = window.contains(index);
+            if !keep {
+                requested.remove(index);
+            }
+            keep
+        });
+        for index in window {
+            if self.requested.insert(index) {
+                let path = self.frames[index].1.clone();
+                // Поток чтения живёт, пока жив просмотр, поэтому отправка не отказывает
+                let _ = self.loader.requests.send((self.generation, index, path));
+            }
+        }
+        self.show_ready();
+    }
+
+    /// Забирает облака, прочитанные фоновым потоком
+    fn receive_loaded(&mut self) {
+        let window = self.prefetch_window();
+        while let Ok((generation, index, cloud)) = self.loader.loaded.try_recv() {
+            if generation != self.generation {
+                continue;
+            }
+            if window.contains(&index) {
+                self.ready.insert(index, cloud);
+            } else {
+                // Облако ушло из окна, пока читалось: при возврате к нему его прочитают заново
+                self.requested.remove(&index);
+            }
+        }
+        self.show_ready();
+    }
+
+    /// Показывает текущее облако, если оно уже прочитано
+    fn show_ready(&mut self) {
+        if self.shown == Some(self.index) {
+            return;
+        }
+        let Some(loaded) = self.ready.remove(&self.index) else {
+            return;
+        };
+        self.requested.remove(&self.index);
+        self.shown = Some(self.index);
+        match loaded {
+            Ok(cloud) => {
+                // Вид настраивается по первому облаку и сохраняется при переходе между кадрами
+                if self.orbit.is_none() {
+                    self.orbit = Some(Orbit::framing(cloud.center, cloud.radius));
+                }
+                self.cloud = Some(cloud);
+                self.error = None;
+            }
+            Err(e) => {
+                error!("Облако точек не прочитано: {}", e);
+                self.cloud = None;
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Текущее облако ещё читается
+    fn is_loading(&self) -> bool {
+        !self.frames.is_empty() && self.shown != Some(self.index)
+    }
+
+    fn go_to(&mut self, index: usize) {
+        if index != self.index && index < self.frames.len() {
+            self.index = index;
+            self.load_current();
+        }
+    }
+
+    /// Показывает окно; `false`, когда окно закрыто
+    pub(crate) fn show(&mut self, ctx: &egui::Context) -> bool {
+        self.receive_loaded();
+        self.advance_playback(ctx);
+        if self.is_loading() {
+            ctx.request_repaint_after(LOAD_POLL_INTERVAL);
+        }
+        let mut open = true;
+        egui::Window::new("Просмотр облаков точек")
+            .open(&mut open)
+            .default_size([720.0, 560.0])
+            .show(ctx, |ui| {
+                self.render_controls(ui);
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                self.render_cloud(ui);
+            });
+        open
+    }
+
+    /// При воспроизведении переходит к следующему облаку, когда подошло его время
+    fn advance_playback(&mut self, ctx: &egui::Context) {
+        let Some(shown_at) = self.playing else {
+            return;
+        };
+        let period = Duration::from_secs_f64(1.0 / self.fps);
+        let elapsed = shown_at.elapsed();
+        if elapsed < period {
+            ctx.request_repaint_after(period - elapsed);
+            return;
+        }
+        if self.index + 1 >= self.frames.len() {
+            self.playing = None;
+            return;
+        }
+        // Следующий кадр ещё читается: воспроизведение ждёт его, а не останавливает окно
+        if self.is_loading() {
+            return;
+        }
+        self.go_to(self.index + 1);
+        self.playing = Some(Instant::now());
+        ctx.request_repaint_after(period);
+    }
+
+    fn render_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Открыть файл").clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .add_filter("PLY", &["ply"])
+                    .set_title("Выбрать облако точек")
+                    .pick_file()
+                && let Some(dir) = path.parent()
+            {
+                self.open_dir(dir, Some(&path));
+            }
+            let play_label = if self.playing.is_some() {
+                "Пауза"
+            } else {
+                "Воспроизвести"
+            };
+            if ui
+                .add_enabled(self.frames.len() > 1, egui::Button::new(play_label))
+                .clicked()
+            {
+                self.playing = match self.playing {
+                    Some(_) => None,
+                    None => {
+                        if self.index + 1 >= self.frames.len() {
+                            self.go_to(0);
+                        }
+                        Some(Instant::now())
+                    }
+                };
+            }
+            ui.label("Кадров в секунду:");
+            ui.add(
+                egui::DragValue::new(&mut self.fps)
+                    .range(1.0..=120.0)
+                    .speed(0.5),
+            );
+            if ui.button("Сбросить вид").clicked()
+                && let Some(cloud) = &self.cloud
+            {
+                self.orbit = Some(Orbit::framing(cloud.center, cloud.radius));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.color_mode, ColorMode::Rgb, "Цвет точек");
+            ui.radio_value(&mut self.color_mode, ColorMode::Confidence, "Уверенность");
+            if self.is_loading() {
+                ui.spinner();
+            }
+            if let Some(cloud) = &self.cloud {
+                ui.label(format!(
+                    "Кадр {}: показано {} из {} точек",
+                    cloud.frame,
+                    cloud.points.len(),
+                    cloud.total
+                ));
+            }
+        });
+        if self.frames.len() > 1 {
+            let mut index = self.index;
+            ui.add(
+                egui::Slider::new(&mut index, 0..=self.frames.len() - 1)
+                    .custom_formatter(|i, _| {
+                        self.frames
+                            .get(i as usize)
+                            .map_or(String::new(), |(frame, _)| frame.to_string())
+                    })
+                    .text("кадр"),
+            );
+            if index != self.index {
+                self.playing = None;
+                self.go_to(index);
+            }
+        }
+    }
+
+    /// Облако в оставшейся части окна: левая кнопка мыши вращает вид, правая или средняя
+    /// сдвигает, колесо приближает
+    fn render_cloud(&mut self, ui: &mut egui::Ui) {
+        let (response, painter) =
+            ui.allocate_painter(ui.available_size(), egui::Sense::click_and_drag());
+        let rect = response.rect;
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+        let (Some(cloud), Some(orbit)) = (&self.cloud, &mut self.orbit) else {
+            return;
+        };
+
+        let focal = rect.height() / 2.0 / (FIELD_OF_VIEW / 2.0).tan();
+        let delta = response.drag_delta();
+        if response.dragged_by(egui::PointerButton::Primary) {
+            orbit.yaw += delta.x * ORBIT_SPEED;
+            orbit.pitch = (orbit.pitch - delta.y * ORBIT_SPEED).clamp(-1.5, 1.5);
+        } else if response.dragged_by(egui::PointerButton::Secondary)
+            || response.dragged_by(egui::PointerButton::Middle)
+        {
+            let scale = orbit.distance / focal;
+            orbit.pan(delta.x * scale, delta.y * scale);
+        }
+        if response.hovered() {
+            let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+            if scroll != 0.0 {
+                orbit.distance =
+                    (orbit.distance * (-scroll * 0.002).exp()).max(cloud.radius * 0.01);
+            }
+        }
+
+        let colors = match self.color_mode {
+            ColorMode::Rgb => &cloud.rgb,
+            ColorMode::Confidence => &cloud.confidence,
+        };
+        let near = orbit.distance * 0.01;
+        let center = rect.center();
+        let half = egui::vec2(POINT_SIZE, POINT_SIZE) / 2.0;
+        let mut mesh = egui::Mesh::default();
+        for (point, &color) in cloud.points.iter().zip(colors) {
+            let [x, y, z] = orbit.view_coords(point);
+            if z <= near {
+                continue;
+            }
+            let screen = center + egui::vec2(x, y) * (focal / z);
+            if rect.contains(screen) {
+                mesh.add_colored_rect(
+                    egui::Rect::from_min_max(screen - half, screen + half),
+                    color,
+                );
+            }
+        }
+        painter.add(egui::Shape::mesh(mesh));
+    }
+}
+
+/// Облака `point_cloud_{кадр}.ply` в папке `dir` по возрастанию кадра
+fn list_point_clouds(dir: &Path) -> Vec<(usize, PathBuf)> {
+    let Ok(entries) = dir.read_dir() else {
+        return Vec::new();
+    };
+    let mut frames: Vec<(usize, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let frame = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("point_cloud_")?
+                .strip_suffix(".ply")?
+                .parse()
+                .ok()?;
+            Some((frame, path))
+        })
+        .collect();
+    frames.sort();
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use lib_cv::reconstruction::{Point3D, save_point_cloud_with_comments};
+
+    use super::*;
+
+    fn cloud_dir(frames: &[usize]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("viewer_clouds_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for &frame in frames {
+            let cloud = PointCloud {
+                points: vec![Point3D::new(frame as f64, 0.0, 1.0, 1.0); frame + 1],
+                timestamp: frame,
+            };
+            let path = dir.join(format!("point_cloud_{}.ply", frame));
+            save_point_cloud_with_comments(&cloud, &[format!("frame {}", frame)], &path).unwrap();
+        }
+        dir
+    }
+
+    /// Ждёт, пока фоновый поток прочитает текущее облако
+    fn wait_loaded(viewer: &mut CloudViewer) {
+        let started = Instant::now();
+        while viewer.is_loading() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "облако не прочитано"
+            );
+            std::thread::sleep(Duration::from_millis(5));
+            viewer.receive_loaded();
+        }
+    }
+
+    #[test]
+    fn clouds_load_in_background_and_ahead() {
+        let dir = cloud_dir(&[3, 1, 2]);
+        let mut viewer = CloudViewer::new(&dir, 30.0);
+        assert_eq!(viewer.frames.len(), 3);
+        wait_loaded(&mut viewer);
+        assert_eq!(viewer.cloud.as_ref().unwrap().frame, 1);
+        assert!(viewer.error.is_none());
+
+        // Следующие облака уже запрошены и показываются без нового чтения
+        assert!(viewer.requested.contains(&2) || viewer.ready.contains_key(&2));
+        viewer.go_to(2);
+        wait_loaded(&mut viewer);
+        let cloud = viewer.cloud.as_ref().unwrap();
+        assert_eq!((cloud.frame, cloud.total), (3, 4));
+
+        // После возврата назад облако читается заново
+        viewer.go_to(0);
+        wait_loaded(&mut viewer);
+        assert_eq!(viewer.cloud.as_ref().unwrap().frame, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}